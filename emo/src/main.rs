@@ -1,6 +1,9 @@
 use clap::{Parser as ClapParser, Subcommand};
 use colored::*;
-use std::path::Path;
+use emo_compiler::diagnostics::MessageFormat;
+use emo_compiler::optimizer::OptLevel;
+use emo_compiler::{BuildOptions, Failed, RunOptions};
+use std::path::{Path, PathBuf};
 
 #[derive(ClapParser)]
 #[command(name = "eMo")]
@@ -21,10 +24,38 @@ enum Commands {
         file: String,
         #[arg(short, long)]
         output: Option<String>,
+        /// How to print eMo errors
+        #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+        message_format: MessageFormat,
+        /// Extra directory to search for imported modules (repeatable)
+        #[arg(long = "module-path", value_name = "DIR")]
+        module_paths: Vec<PathBuf>,
+        /// Report type errors without stopping
+        #[arg(long)]
+        allow_type_errors: bool,
+        /// Optimisation level
+        #[arg(short = 'O', value_enum, default_value_t = OptLevel::O1)]
+        opt_level: OptLevel,
     },
     /// Run a script or enter REPL
     Run {
         file: Option<String>,
+        /// Maximum depth of nested eMo function calls
+        #[arg(
+            long,
+            default_value_t = emo_compiler::interpreter::DEFAULT_MAX_CALL_DEPTH,
+            value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=emo_compiler::interpreter::MAX_CALL_DEPTH as u64)
+        )]
+        max_call_depth: usize,
+        /// How to print eMo errors
+        #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+        message_format: MessageFormat,
+        /// Extra directory to search for imported modules (repeatable)
+        #[arg(long = "module-path", value_name = "DIR")]
+        module_paths: Vec<PathBuf>,
+        /// Report type errors without stopping
+        #[arg(long)]
+        allow_type_errors: bool,
     },
     /// Enter the SadSmile shell
     Shell,
@@ -37,82 +68,95 @@ enum Commands {
 fn main() {
     let cli = Cli::parse();
 
-    if let Some(command) = cli.command {
+    let result = if let Some(command) = cli.command {
         match command {
-            Commands::Build { file, output } => {
-                handle_build(&file, output);
+            Commands::Build { file, output, message_format, module_paths, allow_type_errors, opt_level } => {
+                handle_build(&file, output, BuildOptions { message_format, module_paths, allow_type_errors, opt_level })
             }
-            Commands::Run { file } => {
-                handle_run(file);
+            Commands::Run { file, max_call_depth, message_format, module_paths, allow_type_errors } => {
+                handle_run(file, RunOptions { max_call_depth, message_format, module_paths, allow_type_errors })
             }
             Commands::Shell => {
                 sadsmile::run_repl(false);
+                Ok(())
             }
             Commands::Vibe { prompt } => {
                 println!("{} ThinkingVirus is processing your vibe: '{}'", "🧠".magenta(), prompt);
                 println!("(AI functionality coming soon in v4.1)");
+                Ok(())
             }
         }
     } else if let Some(file) = cli.file {
-        handle_auto(&file);
+        handle_auto(&file)
     } else {
         // Default to shell if no args
         sadsmile::run_repl(false);
+        Ok(())
+    };
+    if result.is_err() {
+        std::process::exit(1);
     }
 }
 
-fn handle_build(file: &str, output: Option<String>) {
+fn handle_build(file: &str, output: Option<String>, options: BuildOptions) -> Result<(), Failed> {
     let path = Path::new(file);
     let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
 
     match ext {
         "hpy" => {
             happy_cry_lang::build(file);
+            Ok(())
         }
-        "emo" => {
-            emo_compiler::compile(file, output);
-        }
+        "emo" => emo_compiler::compile_with_options(file, output, options),
         "ss" => {
             println!("{} .ss files are usually interpreted. Use 'emo run {}' instead.", "Info:".yellow(), file);
+            Ok(())
         }
         _ => {
             println!("{} Unknown file extension: .{}", "Error:".red(), ext);
+            Err(Failed)
         }
     }
 }
 
-fn handle_run(file: Option<String>) {
+fn handle_run(file: Option<String>, options: RunOptions) -> Result<(), Failed> {
     if let Some(f) = file {
         let path = Path::new(&f);
         let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
 
         match ext {
-            "emo" => {
-                emo_compiler::run(&f);
-            }
+            "emo" => emo_compiler::run_with_options(&f, options),
             "ss" => {
                 sadsmile::execute_script(&f);
+                Ok(())
             }
             "hpy" => {
                 println!("{} .hpy files must be built first. Use 'emo build {}'", "Info:".yellow(), f);
+                Err(Failed)
             }
             _ => {
                 println!("{} Cannot run file with extension: .{}", "Error:".red(), ext);
+                Err(Failed)
             }
         }
     } else {
         sadsmile::run_repl(false);
+        Ok(())
     }
 }
 
-fn handle_auto(file: &str) {
+fn handle_auto(file: &str) -> Result<(), Failed> {
     let path = Path::new(file);
     let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
 
     match ext {
         "hpy" => happy_cry_lang::build(file),
-        "emo" => emo_compiler::run(file),
+        "emo" => return emo_compiler::run(file),
         "ss" => sadsmile::execute_script(file),
-        _ => println!("{} Unrecognized file: {}", "Error:".red(), file),
+        _ => {
+            println!("{} Unrecognized file: {}", "Error:".red(), file);
+            return Err(Failed);
+        }
     }
+    Ok(())
 }
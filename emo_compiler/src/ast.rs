@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+use std::fmt;
 use std::ops::Range;
 
 pub type Span = Range<usize>;
@@ -15,20 +17,40 @@ pub enum Statement {
         source: Option<String>,
     },
     FunctionDef {
+        /// Declared with `pub`: importing modules may use it.
+        public: bool,
         name: String,
-        params: Vec<(String, String)>,
+        params: Vec<(String, Option<TypeExpr>)>,
+        return_type: Option<TypeExpr>,
         body: Vec<Spanned<Statement>>,
     },
+    /// `let name = value`, or `let mut name = value` for a binding `set` may reassign.
     Let {
         name: String,
+        mutable: bool,
+        ty: Option<TypeExpr>,
+        value: Spanned<Expression>,
+    },
+    /// `const NAME = value`: never reassigned, and folded to a literal when the value
+    /// only involves literals and other constants.
+    Const {
+        public: bool,
+        name: String,
+        ty: Option<TypeExpr>,
         value: Spanned<Expression>,
     },
     Set {
         name: String,
         value: Spanned<Expression>,
     },
+    IndexSet {
+        object: Spanned<Expression>,
+        index: Spanned<Expression>,
+        value: Spanned<Expression>,
+    },
     Return(Spanned<Expression>),
     Break,
+    Continue,
     Expression(Spanned<Expression>),
     If {
         cond: Spanned<Expression>,
@@ -43,21 +65,93 @@ pub enum Statement {
         cond: Spanned<Expression>,
         body: Vec<Spanned<Statement>>,
     },
+    ForIn {
+        var: String,
+        iterable: Spanned<Expression>,
+        body: Vec<Spanned<Statement>>,
+    },
     StructDef {
+        public: bool,
         name: String,
-        fields: Vec<(String, String)>, // name, type
+        type_params: Vec<String>,
+        fields: Vec<(String, TypeExpr)>,
     },
     EnumDef {
+        public: bool,
         name: String,
-        variants: Vec<String>, // simplified for now
+        type_params: Vec<String>,
+        variants: Vec<(String, Vec<TypeExpr>)>, // variant name and payload types
     },
     UnsafeBlock(Vec<Spanned<Statement>>),
+    Match {
+        subject: Spanned<Expression>,
+        arms: Vec<MatchArm>,
+    },
+    /// `try { ... } catch e { ... }`: an error raised by `?` anywhere in `body` runs
+    /// `handler` with the error bound to `error`, instead of leaving the function.
+    TryCatch {
+        body: Vec<Spanned<Statement>>,
+        error: String,
+        handler: Vec<Spanned<Statement>>,
+    },
+    /// `impl Name { fn method(self, ...) { ... } }`; every method is a `FunctionDef`.
+    Impl {
+        type_name: String,
+        methods: Vec<Spanned<Statement>>,
+    },
+    /// A statement that failed to parse; the parser has already reported it.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Spanned<Pattern>,
+    pub body: Vec<Spanned<Statement>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    Binding(String),
+    Literal(Expression), // Number, StringLit or Bool
+    Variant {
+        enum_name: String,
+        variant: String,
+        fields: Vec<Spanned<Pattern>>,
+    },
+    Struct {
+        name: String,
+        fields: Vec<(String, Spanned<Pattern>)>,
+    },
+}
+
+impl Pattern {
+    /// Names bound by this pattern, in source order.
+    pub fn bindings(&self, out: &mut Vec<String>) {
+        match self {
+            Pattern::Binding(name) => out.push(name.clone()),
+            Pattern::Variant { fields, .. } => {
+                for f in fields {
+                    f.node.bindings(out);
+                }
+            }
+            Pattern::Struct { fields, .. } => {
+                for (_, f) in fields {
+                    f.node.bindings(out);
+                }
+            }
+            Pattern::Wildcard | Pattern::Literal(_) => {}
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expression {
     Number(i64),
+    Float(f64),
     StringLit(String),
+    /// `"Hello {name}"`: literal text and embedded expressions, in order.
+    Interpolated(Vec<StringPart>),
     Bool(bool),
     Null,
     Identifier(String),
@@ -65,7 +159,18 @@ pub enum Expression {
         name: String,
         fields: Vec<(String, Spanned<Expression>)>,
     },
+    ListLiteral(Vec<Spanned<Expression>>),
+    MapLiteral(Vec<(Spanned<Expression>, Spanned<Expression>)>),
+    Index {
+        object: Box<Spanned<Expression>>,
+        index: Box<Spanned<Expression>>,
+    },
+    Range {
+        start: Box<Spanned<Expression>>,
+        end: Box<Spanned<Expression>>,
+    },
     BinaryOp(Box<Spanned<Expression>>, Op, Box<Spanned<Expression>>),
+    Unary(UnaryOp, Box<Spanned<Expression>>),
     Call {
         func: Box<Spanned<Expression>>,
         args: Vec<Spanned<Expression>>,
@@ -74,6 +179,29 @@ pub enum Expression {
         object: Box<Spanned<Expression>>,
         member: String,
     },
+    Lambda {
+        params: Vec<(String, Option<TypeExpr>)>,
+        return_type: Option<TypeExpr>,
+        body: Vec<Spanned<Statement>>,
+    },
+    /// `value?`: the payload of `Result.Ok` or `Option.Some`; on `Result.Err` or
+    /// `Option.None` the enclosing function returns it, or the enclosing `try` catches it.
+    Try(Box<Spanned<Expression>>),
+    /// `if` used as a value: the result of the branch taken (see `split_value`), or void without `else`.
+    If {
+        cond: Box<Spanned<Expression>>,
+        then_block: Vec<Spanned<Statement>>,
+        else_block: Option<Vec<Spanned<Statement>>>,
+    },
+    /// `match` used as a value: the result of the arm taken.
+    Match {
+        subject: Box<Spanned<Expression>>,
+        arms: Vec<MatchArm>,
+    },
+    /// `{ ... }` used as a value: runs in its own scope and results in its last expression.
+    Block(Vec<Spanned<Statement>>),
+    /// An expression that failed to parse; the parser has already reported it.
+    Error,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -88,4 +216,393 @@ pub enum Op {
     Lt,
     Gte,
     Lte,
-}
\ No newline at end of file
+    Mod,
+    /// `&&` and `||` evaluate their right operand only when needed.
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringPart {
+    Literal(String),
+    Expr(Spanned<Expression>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+/// A type annotation as written in source: `int`, `Point`, `Pair<int, str>`, `[str]`, `{str: int}`, `fn(int) -> bool`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TypeExpr {
+    Named(String),
+    Generic(String, Vec<TypeExpr>),
+    List(Box<TypeExpr>),
+    Map(Box<TypeExpr>),
+    Function(Vec<TypeExpr>, Box<TypeExpr>),
+}
+
+impl fmt::Display for TypeExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeExpr::Named(name) => write!(f, "{}", name),
+            TypeExpr::Generic(name, args) => {
+                write!(f, "{}<", name)?;
+                for (i, a) in args.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{}", a)?;
+                }
+                write!(f, ">")
+            }
+            TypeExpr::List(elem) => write!(f, "[{}]", elem),
+            TypeExpr::Map(value) => write!(f, "{{str: {}}}", value),
+            TypeExpr::Function(params, ret) => {
+                write!(f, "fn(")?;
+                for (i, p) in params.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{}", p)?;
+                }
+                write!(f, ") -> {}", ret)
+            }
+        }
+    }
+}
+
+/// Splits a block used as a value into the statements that run first and its result:
+/// the final expression statement, or `None` when the block ends otherwise and is void.
+pub fn split_value(block: &[Spanned<Statement>]) -> (&[Spanned<Statement>], Option<&Spanned<Expression>>) {
+    match block.split_last() {
+        Some((Spanned { node: Statement::Expression(result), .. }, init)) => (init, Some(result)),
+        _ => (block, None),
+    }
+}
+
+/// Whether a function body returns a value anywhere, ignoring nested functions and lambdas.
+pub fn returns_value(body: &[Spanned<Statement>]) -> bool {
+    body.iter().any(|stmt| match &stmt.node {
+        Statement::Return(expr) => !matches!(expr.node, Expression::Null),
+        Statement::If { then_block, else_block, .. } => {
+            returns_value(then_block) || else_block.as_deref().is_some_and(returns_value)
+        }
+        Statement::While { body, .. }
+        | Statement::Loop { body, .. }
+        | Statement::ForIn { body, .. }
+        | Statement::UnsafeBlock(body) => returns_value(body),
+        Statement::Match { arms, .. } => arms.iter().any(|arm| returns_value(&arm.body)),
+        Statement::TryCatch { body, handler, .. } => returns_value(body) || returns_value(handler),
+        _ => false,
+    })
+}
+
+/// Whether every path through `body` ends in a `return`, so control never falls off its
+/// end. A `loop` (or `while true`) without a `break` never finishes, so it counts too.
+pub fn always_returns(body: &[Spanned<Statement>]) -> bool {
+    body.iter().any(|stmt| match &stmt.node {
+        Statement::Return(_) => true,
+        Statement::If { then_block, else_block: Some(else_block), .. } => always_returns(then_block) && always_returns(else_block),
+        Statement::Match { arms, .. } => arms.iter().all(|arm| always_returns(&arm.body)),
+        Statement::TryCatch { body, handler, .. } => always_returns(body) && always_returns(handler),
+        Statement::UnsafeBlock(body) => always_returns(body),
+        Statement::Loop { count: None, body } => !breaks(body),
+        Statement::While { cond: Spanned { node: Expression::Bool(true), .. }, body } => !breaks(body),
+        _ => false,
+    })
+}
+
+/// Whether `body` contains a `break` out of the loop it belongs to, rather than out of a
+/// loop nested in it.
+fn breaks(body: &[Spanned<Statement>]) -> bool {
+    body.iter().any(|stmt| match &stmt.node {
+        Statement::Break => true,
+        Statement::If { then_block, else_block, .. } => breaks(then_block) || else_block.as_deref().is_some_and(breaks),
+        Statement::Match { arms, .. } => arms.iter().any(|arm| breaks(&arm.body)),
+        Statement::TryCatch { body, handler, .. } => breaks(body) || breaks(handler),
+        Statement::UnsafeBlock(body) => breaks(body),
+        _ => false,
+    })
+}
+
+/// Collects identifiers used in `body` that are not bound inside it, in first-use order.
+pub fn free_variables(body: &[Spanned<Statement>], bound: &HashSet<String>, out: &mut Vec<String>) {
+    visit_free(body, bound, false, &mut |name, _, _| {
+        if !out.iter().any(|o| o == name) {
+            out.push(name.to_string());
+        }
+    });
+}
+
+/// The places in `body` that use `name` as bound outside it.
+pub fn free_uses(body: &[Spanned<Statement>], name: &str) -> Vec<Span> {
+    let mut uses = Vec::new();
+    visit_free(body, &HashSet::new(), false, &mut |used, span, _| {
+        if used == name {
+            uses.push(span.clone());
+        }
+    });
+    uses
+}
+
+/// The uses in `body` of identifiers not bound inside it, leaving out those in lambdas and
+/// local functions, which only run when they are called.
+pub fn direct_uses(body: &[Spanned<Statement>]) -> Vec<(String, Span)> {
+    let mut uses = Vec::new();
+    visit_free(body, &HashSet::new(), false, &mut |name, span, nested| {
+        if !nested {
+            uses.push((name.to_string(), span.clone()));
+        }
+    });
+    uses
+}
+
+/// Whether a lambda or local function in `body` uses `name` as bound outside `body`.
+pub fn is_captured(body: &[Spanned<Statement>], name: &str) -> bool {
+    let mut captured = false;
+    visit_free(body, &HashSet::new(), false, &mut |used, _, nested| captured |= nested && used == name);
+    captured
+}
+
+/// Calls `f` with each use in `body` of an identifier not bound inside it, and whether the
+/// use is in a lambda or function declared in `body`, or `nested` already.
+fn visit_free(body: &[Spanned<Statement>], bound: &HashSet<String>, nested: bool, f: &mut dyn FnMut(&str, &Span, bool)) {
+    let mut bound = bound.clone();
+    for stmt in body {
+        match &stmt.node {
+            Statement::Let { name, value, .. } | Statement::Const { name, value, .. } => {
+                visit_free_in(value, &bound, nested, f);
+                bound.insert(name.clone());
+            }
+            Statement::Set { name, value } => {
+                if !bound.contains(name) {
+                    f(name, &stmt.span, nested);
+                }
+                visit_free_in(value, &bound, nested, f);
+            }
+            Statement::Return(expr) | Statement::Expression(expr) => visit_free_in(expr, &bound, nested, f),
+            Statement::IndexSet { object, index, value } => {
+                visit_free_in(object, &bound, nested, f);
+                visit_free_in(index, &bound, nested, f);
+                visit_free_in(value, &bound, nested, f);
+            }
+            Statement::If { cond, then_block, else_block } => {
+                visit_free_in(cond, &bound, nested, f);
+                visit_free(then_block, &bound, nested, f);
+                if let Some(eb) = else_block {
+                    visit_free(eb, &bound, nested, f);
+                }
+            }
+            Statement::While { cond, body } => {
+                visit_free_in(cond, &bound, nested, f);
+                visit_free(body, &bound, nested, f);
+            }
+            Statement::Loop { count, body } => {
+                if let Some(c) = count {
+                    visit_free_in(c, &bound, nested, f);
+                }
+                visit_free(body, &bound, nested, f);
+            }
+            Statement::ForIn { var, iterable, body } => {
+                visit_free_in(iterable, &bound, nested, f);
+                let mut inner = bound.clone();
+                inner.insert(var.clone());
+                visit_free(body, &inner, nested, f);
+            }
+            Statement::UnsafeBlock(body) => visit_free(body, &bound, nested, f),
+            Statement::TryCatch { body, error, handler } => {
+                visit_free(body, &bound, nested, f);
+                let mut inner = bound.clone();
+                inner.insert(error.clone());
+                visit_free(handler, &inner, nested, f);
+            }
+            Statement::Match { subject, arms } => {
+                visit_free_in(subject, &bound, nested, f);
+                for arm in arms {
+                    let mut names = Vec::new();
+                    arm.pattern.node.bindings(&mut names);
+                    let mut inner = bound.clone();
+                    inner.extend(names);
+                    visit_free(&arm.body, &inner, nested, f);
+                }
+            }
+            Statement::FunctionDef { name, params, body, .. } => {
+                bound.insert(name.clone());
+                let mut inner = bound.clone();
+                inner.extend(params.iter().map(|(p, _)| p.clone()));
+                visit_free(body, &inner, true, f);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn visit_free_in(expr: &Spanned<Expression>, bound: &HashSet<String>, nested: bool, f: &mut dyn FnMut(&str, &Span, bool)) {
+    match &expr.node {
+        Expression::Identifier(name) if !bound.contains(name) => f(name, &expr.span, nested),
+        Expression::StructLiteral { fields, .. } => {
+            for (_, f_val) in fields {
+                visit_free_in(f_val, bound, nested, f);
+            }
+        }
+        Expression::BinaryOp(left, _, right) => {
+            visit_free_in(left, bound, nested, f);
+            visit_free_in(right, bound, nested, f);
+        }
+        Expression::Unary(_, operand) | Expression::Try(operand) => visit_free_in(operand, bound, nested, f),
+        Expression::Interpolated(parts) => {
+            for part in parts {
+                if let StringPart::Expr(e) = part {
+                    visit_free_in(e, bound, nested, f);
+                }
+            }
+        }
+        Expression::Call { func, args } => {
+            visit_free_in(func, bound, nested, f);
+            for arg in args {
+                visit_free_in(arg, bound, nested, f);
+            }
+        }
+        Expression::MemberAccess { object, .. } => visit_free_in(object, bound, nested, f),
+        Expression::ListLiteral(items) => {
+            for item in items {
+                visit_free_in(item, bound, nested, f);
+            }
+        }
+        Expression::MapLiteral(entries) => {
+            for (key, value) in entries {
+                visit_free_in(key, bound, nested, f);
+                visit_free_in(value, bound, nested, f);
+            }
+        }
+        Expression::Index { object, index } => {
+            visit_free_in(object, bound, nested, f);
+            visit_free_in(index, bound, nested, f);
+        }
+        Expression::Range { start, end } => {
+            visit_free_in(start, bound, nested, f);
+            visit_free_in(end, bound, nested, f);
+        }
+        Expression::Lambda { params, body, .. } => {
+            let mut inner = bound.clone();
+            inner.extend(params.iter().map(|(p, _)| p.clone()));
+            visit_free(body, &inner, true, f);
+        }
+        Expression::If { cond, then_block, else_block } => {
+            visit_free_in(cond, bound, nested, f);
+            visit_free(then_block, bound, nested, f);
+            if let Some(eb) = else_block {
+                visit_free(eb, bound, nested, f);
+            }
+        }
+        Expression::Match { subject, arms } => {
+            visit_free_in(subject, bound, nested, f);
+            for arm in arms {
+                let mut names = Vec::new();
+                arm.pattern.node.bindings(&mut names);
+                let mut inner = bound.clone();
+                inner.extend(names);
+                visit_free(&arm.body, &inner, nested, f);
+            }
+        }
+        Expression::Block(body) => visit_free(body, bound, nested, f),
+        _ => {}
+    }
+}
+
+/// The names of the members `body` accesses, as in `value.member`, anywhere inside it.
+pub fn member_names(body: &[Spanned<Statement>]) -> HashSet<String> {
+    let mut out = HashSet::new();
+    members_in(body, &mut out);
+    out
+}
+
+fn members_in(body: &[Spanned<Statement>], out: &mut HashSet<String>) {
+    for stmt in body {
+        match &stmt.node {
+            Statement::Let { value, .. } | Statement::Const { value, .. } | Statement::Set { value, .. }
+            | Statement::Return(value) | Statement::Expression(value) => members_in_expr(value, out),
+            Statement::IndexSet { object, index, value } => {
+                members_in_expr(object, out);
+                members_in_expr(index, out);
+                members_in_expr(value, out);
+            }
+            Statement::If { cond, then_block, else_block } => {
+                members_in_expr(cond, out);
+                members_in(then_block, out);
+                members_in(else_block.as_deref().unwrap_or_default(), out);
+            }
+            Statement::Loop { count, body } => {
+                if let Some(c) = count {
+                    members_in_expr(c, out);
+                }
+                members_in(body, out);
+            }
+            Statement::While { cond: e, body } | Statement::ForIn { iterable: e, body, .. } => {
+                members_in_expr(e, out);
+                members_in(body, out);
+            }
+            Statement::UnsafeBlock(body) | Statement::FunctionDef { body, .. } => members_in(body, out),
+            Statement::TryCatch { body, handler, .. } => {
+                members_in(body, out);
+                members_in(handler, out);
+            }
+            Statement::Match { subject, arms } => {
+                members_in_expr(subject, out);
+                for arm in arms {
+                    members_in(&arm.body, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn members_in_expr(expr: &Spanned<Expression>, out: &mut HashSet<String>) {
+    match &expr.node {
+        Expression::MemberAccess { object, member } => {
+            out.insert(member.clone());
+            members_in_expr(object, out);
+        }
+        Expression::StructLiteral { fields, .. } => fields.iter().for_each(|(_, value)| members_in_expr(value, out)),
+        Expression::ListLiteral(items) => items.iter().for_each(|item| members_in_expr(item, out)),
+        Expression::MapLiteral(entries) => {
+            for (key, value) in entries {
+                members_in_expr(key, out);
+                members_in_expr(value, out);
+            }
+        }
+        Expression::Index { object: a, index: b } | Expression::Range { start: a, end: b } | Expression::BinaryOp(a, _, b) => {
+            members_in_expr(a, out);
+            members_in_expr(b, out);
+        }
+        Expression::Unary(_, operand) | Expression::Try(operand) => members_in_expr(operand, out),
+        Expression::Interpolated(parts) => {
+            for part in parts {
+                if let StringPart::Expr(e) = part {
+                    members_in_expr(e, out);
+                }
+            }
+        }
+        Expression::Call { func, args } => {
+            members_in_expr(func, out);
+            args.iter().for_each(|arg| members_in_expr(arg, out));
+        }
+        Expression::Lambda { body, .. } | Expression::Block(body) => members_in(body, out),
+        Expression::If { cond, then_block, else_block } => {
+            members_in_expr(cond, out);
+            members_in(then_block, out);
+            members_in(else_block.as_deref().unwrap_or_default(), out);
+        }
+        Expression::Match { subject, arms } => {
+            members_in_expr(subject, out);
+            for arm in arms {
+                members_in(&arm.body, out);
+            }
+        }
+        _ => {}
+    }
+}
@@ -1,4 +1,7 @@
-use crate::ast::{Statement, Expression, Op, Spanned};
+use crate::ast::{Op, UnaryOp};
+use crate::ir::{Arm, Callee, Const, Function, FunctionKind, Operand, Pattern, Program, Rvalue, Scope, Stmt, Type, TypeDef, TypeDefKind, Var};
+use crate::numeric::IntKind;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -10,10 +13,46 @@ pub enum Dimension {
     Default      // .emo
 }
 
+/// Enclosing C constructs that capture `break`. A `break` inside a match `switch`
+/// must still leave the surrounding loop, so such loops get a `goto` label on demand.
+enum Breakable {
+    Loop {
+        break_label: Option<String>,
+        // Loops with a step jump to it on `continue`, so that the step still runs.
+        continue_label: Option<String>,
+        has_step: bool,
+    },
+    Switch,
+}
+
+/// What calls and closures need to know about a function of the program.
+struct FnSig {
+    params: Vec<Var>,
+    ret: Type,
+    /// Methods taking `self` receive it as a pointer.
+    method: bool,
+    /// Names of the variables a lambda captured, in environment order.
+    captures: Vec<String>,
+}
+
 pub struct CodegenC {
     output: String,
     indent_level: usize,
     dimension: Dimension,
+    type_decls: HashMap<String, TypeDef>,
+    // Typedefs for every instantiated type, emitted ahead of their first use.
+    type_defs: String,
+    emitted_types: HashSet<String>,
+    functions: HashMap<String, FnSig>,
+    // Functions used as values, which get a thunk with the closure calling convention.
+    function_values: Vec<String>,
+    in_method: bool,
+    // In `main` proper, which returns 0 to the C runtime.
+    in_main: bool,
+    breakable: Vec<Breakable>,
+    // Enclosing `try` blocks as (error variable, catch label), innermost last.
+    catches: Vec<(String, String)>,
+    next_id: usize,
 }
 
 impl CodegenC {
@@ -22,6 +61,16 @@ impl CodegenC {
             output: String::new(),
             indent_level: 0,
             dimension,
+            type_decls: HashMap::new(),
+            type_defs: String::new(),
+            emitted_types: HashSet::new(),
+            functions: HashMap::new(),
+            function_values: Vec::new(),
+            in_method: false,
+            in_main: false,
+            breakable: Vec::new(),
+            catches: Vec::new(),
+            next_id: 0,
         }
     }
 
@@ -29,9 +78,13 @@ impl CodegenC {
         "    ".repeat(self.indent_level)
     }
 
-    pub fn generate(&mut self, statements: &[Spanned<Statement>]) -> String {
+    fn line(&mut self, text: &str) {
+        writeln!(self.output, "{}{}", self.indent(), text).unwrap();
+    }
+
+    pub fn generate(&mut self, program: &Program) -> String {
         writeln!(self.output, "#include \"emo_runtime.h\"").unwrap();
-        
+
         match self.dimension {
             Dimension::SadSmile => writeln!(self.output, "#define EMO_UNSAFE 1").unwrap(),
             Dimension::Shadow => writeln!(self.output, "#define EMO_METAPROGRAMMING 1").unwrap(),
@@ -39,354 +92,921 @@ impl CodegenC {
             _ => {}
         }
 
-        writeln!(self.output, "").unwrap();
-        
-        // First pass: Generate Struct and Enum definitions
-        for stmt in statements {
-            match &stmt.node {
-                Statement::StructDef { name, fields } => {
-                    writeln!(self.output, "typedef struct {{").unwrap();
-                    for (f_name, f_type) in fields {
-                        let c_type = match f_type.as_str() {
-                            "str" => "emo_str",
-                            "int" => "emo_int",
-                            "bool" => "emo_bool",
-                            _ => "emo_int", // Default or custom struct type
-                        };
-                        writeln!(self.output, "    {} {}", c_type, f_name).unwrap();
-                    }
-                    writeln!(self.output, "}} {}", name).unwrap();
-                    writeln!(self.output, "").unwrap();
-                }
-                Statement::EnumDef { name, variants } => {
-                    writeln!(self.output, "typedef enum {{").unwrap();
-                    for v in variants {
-                        writeln!(self.output, "    {}_{}", name, v).unwrap();
-                    }
-                    writeln!(self.output, "}} {}", name).unwrap();
-                    writeln!(self.output, "").unwrap();
+        writeln!(self.output).unwrap();
+
+        for def in &program.types {
+            self.type_decls.insert(def.name.clone(), def.clone());
+        }
+        for function in &program.functions {
+            let captures = match &function.kind {
+                FunctionKind::Lambda { captures } => captures.iter().map(|c| c.name.clone()).collect(),
+                _ => Vec::new(),
+            };
+            let method = matches!(function.kind, FunctionKind::Method);
+            self.functions.insert(function.name.clone(), FnSig { params: function.params.clone(), ret: function.ret.clone(), method, captures });
+        }
+
+        // Struct and Enum typedefs are collected into `type_defs` and spliced in here at the end.
+        let types_pos = self.output.len();
+        for def in &program.types {
+            if def.type_params.is_empty() {
+                self.c_type(&Type::Struct(def.name.clone(), Vec::new()));
+            }
+        }
+        // A lambda finds the values it captured in a heap environment of this shape.
+        for function in &program.functions {
+            if let FunctionKind::Lambda { captures } = &function.kind {
+                if captures.is_empty() {
+                    continue;
                 }
-                _ => {}
+                let fields: Vec<String> = captures.iter().map(|c| match c.scope {
+                    Scope::CapturedCell => format!("{}* {};", self.c_type(&c.ty), c.name),
+                    _ => format!("{} {};", self.c_type(&c.ty), c.name),
+                }).collect();
+                writeln!(self.type_defs, "struct {}_env {{ {} }};", function.name, fields.join(" ")).unwrap();
+                writeln!(self.type_defs).unwrap();
             }
         }
 
-        // Second pass: Function Implementations and Main Logic
-        let mut found_main = false;
+        for var in &program.globals {
+            if var.ty != Type::Void {
+                let c_type = self.c_type(&var.ty);
+                writeln!(self.output, "static {} {};", c_type, var.name).unwrap();
+            }
+        }
+        if !program.globals.is_empty() {
+            writeln!(self.output).unwrap();
+        }
 
-        for stmt in statements {
-            if let Statement::FunctionDef { name, params, body } = &stmt.node {
-                let fn_name = if self.dimension == Dimension::HappyCry && name == "main" {
-                    found_main = true;
-                    "user_logic"
-                } else {
-                    name
-                };
+        // Prototypes let functions call each other regardless of definition order.
+        for function in &program.functions {
+            if function.name != "main" {
+                let sig = self.c_signature(function);
+                writeln!(self.output, "{};", sig).unwrap();
+            }
+        }
+        writeln!(self.output).unwrap();
 
-                let ret_type = if name == "main" && self.dimension != Dimension::HappyCry { "int" } else { "void" };
-                write!(self.output, "{} {}( ", ret_type, fn_name).unwrap();
+        // Closure thunk prototypes are spliced in here once we know which functions were used as values.
+        let prototypes_pos = self.output.len();
 
-                for (i, (p_name, p_type)) in params.iter().enumerate() {
-                    let c_type = match p_type.as_str() {
-                        "str" => "emo_str",
-                        "int" => "emo_int",
-                        "bool" => "emo_bool",
-                        _ => "emo_int",
-                    };
-                    write!(self.output, "{} {}", c_type, p_name).unwrap();
-                    if i < params.len() - 1 { write!(self.output, ", ").unwrap(); }
-                }
-                writeln!(self.output, ") {{ ").unwrap();
-                self.indent_level += 1;
-                
-                for s in body {
-                    self.gen_statement(s);
-                }
-                
-                if name == "main" && self.dimension != Dimension::HappyCry {
-                     writeln!(self.output, "{}return 0;", self.indent()).unwrap();
-                }
+        let has_init = !program.init.is_empty();
+        if has_init {
+            writeln!(self.output, "static void __emo_init(void) {{").unwrap();
+            self.indent_level += 1;
+            self.gen_block(&program.init);
+            self.indent_level -= 1;
+            writeln!(self.output, "}}").unwrap();
+            writeln!(self.output).unwrap();
+        }
 
-                self.indent_level -= 1;
-                writeln!(self.output, "}}").unwrap();
-                writeln!(self.output, "").unwrap();
+        let mut found_main = false;
+        for function in &program.functions {
+            found_main |= function.name == "main";
+            self.gen_function(function, has_init);
+        }
+
+        // Top-level functions used as values are wrapped in thunks matching the closure calling convention.
+        let mut prototypes = String::new();
+        for name in std::mem::take(&mut self.function_values) {
+            let Some(sig) = self.functions.get(&name) else { continue };
+            let (params, ret, method) = (sig.params.clone(), sig.ret.clone(), sig.method);
+            let mut c_params = vec!["void* __env".to_string()];
+            let mut args = Vec::new();
+            for (i, p) in params.iter().enumerate() {
+                c_params.push(format!("{} {}", self.c_type(&p.ty), p.name));
+                args.push(if i == 0 && method { format!("&{}", p.name) } else { p.name.clone() });
+            }
+            let ret = self.c_type(&ret);
+            let sig = format!("static {} {}__closure({})", ret, name, c_params.join(", "));
+            writeln!(prototypes, "{};", sig).unwrap();
+
+            writeln!(self.output, "{} {{", sig).unwrap();
+            writeln!(self.output, "    (void)__env;").unwrap();
+            if ret == "void" {
+                writeln!(self.output, "    {}({});", name, args.join(", ")).unwrap();
             } else {
-                 self.gen_statement(stmt);
+                writeln!(self.output, "    return {}({});", name, args.join(", ")).unwrap();
             }
+            writeln!(self.output, "}}").unwrap();
+            writeln!(self.output).unwrap();
+        }
+        if !prototypes.is_empty() {
+            prototypes.push('\n');
+            self.output.insert_str(prototypes_pos, &prototypes);
         }
+        let type_defs = std::mem::take(&mut self.type_defs);
+        self.output.insert_str(types_pos, &type_defs);
 
         // Handle HappyCry entry point injection
         if self.dimension == Dimension::HappyCry && found_main {
-            writeln!(self.output, "").unwrap();
+            writeln!(self.output).unwrap();
             writeln!(self.output, "// Auto-injected HappyCry Event Loop").unwrap();
             writeln!(self.output, "int main() {{").unwrap();
+            if has_init {
+                writeln!(self.output, "    __emo_init(); // Run top-level code").unwrap();
+            }
             writeln!(self.output, "    joy_init(); // Initialize UI subsystem").unwrap();
             writeln!(self.output, "    user_logic(); // Run user code").unwrap();
             writeln!(self.output, "    joy_loop(); // Enter event loop").unwrap();
             writeln!(self.output, "    return 0;").unwrap();
             writeln!(self.output, "}}").unwrap();
+        } else if !found_main {
+            // Scripts without `main` are all top-level code.
+            writeln!(self.output, "int main() {{").unwrap();
+            if has_init {
+                writeln!(self.output, "    __emo_init();").unwrap();
+            }
+            writeln!(self.output, "    return 0;").unwrap();
+            writeln!(self.output, "}}").unwrap();
         }
 
         self.output.clone()
     }
 
-    fn gen_statement(&mut self, stmt: &Spanned<Statement>) {
-        match &stmt.node {
-             Statement::StructDef { .. } | Statement::EnumDef { .. } | Statement::FunctionDef { .. } => {},
-            Statement::Let { name, value } => {
-                // Use GCC's __auto_type for type inference in C
-                write!(self.output, "{}__auto_type {} = ", self.indent(), name).unwrap();
-                self.gen_expression(value);
-                writeln!(self.output, ";").unwrap();
-            }
-            Statement::Set { name, value } => {
-                write!(self.output, "{}{} = ", self.indent(), name).unwrap();
-                self.gen_expression(value);
-                writeln!(self.output, ";").unwrap();
-            }
-            Statement::While { cond, body } => {
-                write!(self.output, "{}while (", self.indent()).unwrap();
-                self.gen_expression(cond);
-                writeln!(self.output, ") {{ ").unwrap();
-                self.indent_level += 1;
-                for s in body {
-                    self.gen_statement(s);
+    /// The C type for an IR type, emitting the typedef of a struct or enum instance on first use.
+    fn c_type(&mut self, t: &Type) -> String {
+        match t {
+            Type::Int => "emo_int".to_string(),
+            Type::Sized(kind) => kind.c_type().to_string(),
+            Type::Float => "emo_float".to_string(),
+            Type::Str => "emo_str".to_string(),
+            Type::Bool => "emo_bool".to_string(),
+            Type::Void => "void".to_string(),
+            Type::Struct(name, args) | Type::Enum(name, args) => {
+                let Some(decl) = self.type_decls.get(name) else {
+                    return "emo_int".to_string();
+                };
+                // Missing type arguments (only after a type error) fall back to int.
+                let mut args = args.clone();
+                args.resize(decl.type_params.len(), Type::Int);
+                self.instantiate_type(name, &args)
+            }
+            Type::List(_) | Type::Map(_) => "emo_coll".to_string(),
+            Type::Function(..) => "emo_closure".to_string(),
+            // Only found in generic declarations, which are instantiated before use.
+            Type::Param(_) => "emo_int".to_string(),
+        }
+    }
+
+    /// Emits the monomorphised typedef for `name<args>` and returns its C name, e.g. `Pair_int_str`.
+    fn instantiate_type(&mut self, name: &str, args: &[Type]) -> String {
+        let c_name = std::iter::once(name.to_string()).chain(args.iter().map(Type::mangled)).collect::<Vec<_>>().join("_");
+        if !self.emitted_types.insert(c_name.clone()) {
+            return c_name;
+        }
+        let decl = self.type_decls[name].clone();
+        let bindings: HashMap<String, Type> = decl.type_params.iter().cloned().zip(args.iter().cloned()).collect();
+        let mut def = String::new();
+        match &decl.kind {
+            TypeDefKind::Enum(variants) if decl.is_plain_enum() => {
+                writeln!(def, "typedef enum {{").unwrap();
+                for (v, _) in variants {
+                    writeln!(def, "    {}_{},", name, v).unwrap();
                 }
-                self.indent_level -= 1;
-                writeln!(self.output, "{}", self.indent()).unwrap();
+                writeln!(def, "}} {};", c_name).unwrap();
             }
-            Statement::If { cond, then_block, else_block } => {
-                write!(self.output, "{}if (", self.indent()).unwrap();
-                self.gen_expression(cond);
-                writeln!(self.output, ") {{ ").unwrap();
-                self.indent_level += 1;
-                for s in then_block {
-                    self.gen_statement(s);
+            TypeDefKind::Enum(variants) => {
+                // Payload enums are a tag plus a union of per-variant payload structs; the tag
+                // constants are shared by every instantiation.
+                let tag = format!("{}_Tag", name);
+                if self.emitted_types.insert(tag.clone()) {
+                    writeln!(self.type_defs, "typedef enum {{").unwrap();
+                    for (v, _) in variants {
+                        writeln!(self.type_defs, "    {}_{},", name, v).unwrap();
+                    }
+                    writeln!(self.type_defs, "}} {};", tag).unwrap();
+                    writeln!(self.type_defs).unwrap();
                 }
-                self.indent_level -= 1;
-                if let Some(eb) = else_block {
-                                        writeln!(self.output, "{}}} else {{ ", self.indent()).unwrap();
-                    
-                    self.indent_level += 1;
-                    for s in eb {
-                        self.gen_statement(s);
+                writeln!(def, "typedef struct {{").unwrap();
+                writeln!(def, "    {} tag;", tag).unwrap();
+                if variants.iter().any(|(_, payload)| !payload.is_empty()) {
+                    writeln!(def, "    union {{").unwrap();
+                    for (v, payload) in variants {
+                        if payload.is_empty() {
+                            continue;
+                        }
+                        write!(def, "        struct {{").unwrap();
+                        for (i, p_type) in payload.iter().enumerate() {
+                            write!(def, " {} _{};", self.c_type(&substitute_type(p_type, &bindings)), i).unwrap();
+                        }
+                        writeln!(def, " }} {};", v).unwrap();
                     }
-                    self.indent_level -= 1;
+                    writeln!(def, "    }} as;").unwrap();
                 }
-                writeln!(self.output, "{}", self.indent()).unwrap();
-            }
-            Statement::Loop { count, body } => {
-                if let Some(c) = count {
-                    // loop n times { ... }
-                    let loop_var = format!("_i{}", self.indent_level);
-                    write!(self.output, "{}for (int {} = 0; {} < ", self.indent(), loop_var, loop_var).unwrap();
-                    self.gen_expression(c);
-                    writeln!(self.output, "; {}", loop_var).unwrap();
-                } else {
-                    // infinite loop
-                                        writeln!(self.output, "{}while (true) {{ ", self.indent()).unwrap();
-                    
+                writeln!(def, "}} {};", c_name).unwrap();
+            }
+            TypeDefKind::Struct(fields) => {
+                writeln!(def, "typedef struct {{").unwrap();
+                for (f_name, f_type) in fields {
+                    writeln!(def, "    {} {};", self.c_type(&substitute_type(f_type, &bindings)), f_name).unwrap();
                 }
+                writeln!(def, "}} {};", c_name).unwrap();
+            }
+        }
+        // Member types were emitted by the recursive `c_type` calls above, so they precede this one.
+        writeln!(def).unwrap();
+        self.type_defs.push_str(&def);
+        c_name
+    }
+
+    /// `ret name(params)`: methods taking `self` receive a pointer to it, and lambdas their
+    /// environment first.
+    fn c_signature(&mut self, function: &Function) -> String {
+        let mut params = Vec::new();
+        if matches!(function.kind, FunctionKind::Lambda { .. }) {
+            params.push("void* __envp".to_string());
+        }
+        for (i, p) in function.params.iter().enumerate() {
+            let c_type = self.c_type(&p.ty);
+            if i == 0 && matches!(function.kind, FunctionKind::Method) {
+                params.push(format!("{}* {}", c_type, p.name));
+            } else {
+                params.push(format!("{} {}", c_type, p.name));
+            }
+        }
+        // Functions not declared `pub` get internal linkage (`static`).
+        let linkage = if function.public { "" } else { "static " };
+        format!("{}{} {}({})", linkage, self.c_type(&function.ret), function.name, params.join(", "))
+    }
+
+    fn gen_function(&mut self, function: &Function, has_init: bool) {
+        let is_main = function.name == "main";
+        let sig = match is_main {
+            true if self.dimension == Dimension::HappyCry => "void user_logic()".to_string(),
+            true => "int main()".to_string(),
+            false => self.c_signature(function),
+        };
+        writeln!(self.output, "{} {{", sig).unwrap();
+        self.indent_level += 1;
+        self.in_method = matches!(function.kind, FunctionKind::Method);
+        self.in_main = is_main && self.dimension != Dimension::HappyCry;
+        match &function.kind {
+            FunctionKind::Lambda { captures } if captures.is_empty() => self.line("(void)__envp;"),
+            FunctionKind::Lambda { .. } => self.line(&format!("struct {}_env* __env = __envp;", function.name)),
+            _ if self.in_main && has_init => self.line("__emo_init(); // Run top-level code"),
+            _ => {}
+        }
+
+        self.gen_block(&function.body);
+
+        if self.in_main {
+            self.line("return 0;");
+        }
+        self.in_method = false;
+        self.in_main = false;
+        self.indent_level -= 1;
+        writeln!(self.output, "}}").unwrap();
+        writeln!(self.output).unwrap();
+    }
+
+    /// The C lvalue for a variable, redirecting captured variables through the lambda environment.
+    fn var(&self, var: &Var) -> String {
+        match var.scope {
+            Scope::Captured => format!("__env->{}", var.name),
+            Scope::Cell => format!("(*{})", var.name),
+            Scope::CapturedCell => format!("(*__env->{})", var.name),
+            Scope::Local if var.name == "self" && self.in_method => "(*self)".to_string(),
+            _ => var.name.clone(),
+        }
+    }
+
+    fn operand(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Var(var) => self.var(var),
+            Operand::Const(c) => c_const(c),
+        }
+    }
+
+    fn operands(&self, operands: &[Operand]) -> String {
+        operands.iter().map(|op| self.operand(op)).collect::<Vec<_>>().join(", ")
+    }
+
+    fn gen_block(&mut self, body: &[Stmt]) {
+        for stmt in body {
+            self.gen_statement(stmt);
+        }
+    }
+
+    fn gen_nested(&mut self, body: &[Stmt]) {
+        self.indent_level += 1;
+        self.gen_block(body);
+        self.indent_level -= 1;
+    }
+
+    fn gen_statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Let(var, value) => {
+                let c_type = self.c_type(&var.ty);
+                match value {
+                    Some(value) if var.scope == Scope::Cell => {
+                        let value = self.rvalue(value, &var.ty);
+                        self.line(&format!("{}* {} = malloc(sizeof({}));", c_type, var.name, c_type));
+                        self.line(&format!("*{} = {};", var.name, value));
+                    }
+                    Some(value) => {
+                        let value = self.rvalue(value, &var.ty);
+                        self.line(&format!("{} {} = {};", c_type, var.name, value));
+                    }
+                    None => self.line(&format!("{} {};", c_type, var.name)),
+                }
+            }
+            Stmt::Assign(var, value) => {
+                let value = self.rvalue(value, &var.ty);
+                self.line(&format!("{} = {};", self.var(var), value));
+            }
+            Stmt::IndexSet { object, index, value } => {
+                let text = format!("emo_index_set({}, EMO_SLOT({}), {});", self.operand(object), self.operand(index), self.write_slot(value));
+                self.line(&text);
+            }
+            Stmt::Eval(value) => {
+                let value = self.rvalue(value, &Type::Void);
+                self.line(&format!("{};", value));
+            }
+            Stmt::If { cond, then_block, else_block } if then_block.is_empty() && !else_block.is_empty() => {
+                self.line(&format!("if (!{}) {{", self.operand(cond)));
+                self.gen_nested(else_block);
+                self.line("}");
+            }
+            Stmt::If { cond, then_block, else_block } => {
+                self.line(&format!("if ({}) {{", self.operand(cond)));
+                self.gen_nested(then_block);
+                if !else_block.is_empty() {
+                    self.line("} else {");
+                    self.gen_nested(else_block);
+                }
+                self.line("}");
+            }
+            Stmt::Loop { body, step } => {
+                self.line("while (true) {");
                 self.indent_level += 1;
-                for s in body {
-                    self.gen_statement(s);
+                self.breakable.push(Breakable::Loop { break_label: None, continue_label: None, has_step: !step.is_empty() });
+                if step.is_empty() {
+                    self.gen_block(body);
+                } else {
+                    self.line("{");
+                    self.gen_nested(body);
+                    self.line("}");
                 }
+                let Some(Breakable::Loop { break_label, continue_label, .. }) = self.breakable.pop() else {
+                    unreachable!("loops pop their own entry");
+                };
+                if let Some(label) = continue_label {
+                    self.line(&format!("{}:;", label));
+                }
+                self.gen_block(step);
                 self.indent_level -= 1;
-                writeln!(self.output, "{}", self.indent()).unwrap();
+                self.line("}");
+                if let Some(label) = break_label {
+                    self.line(&format!("{}:;", label));
+                }
             }
-            Statement::UnsafeBlock(body) => {
-                writeln!(self.output, "{}{{ // unsafe", self.indent()).unwrap();
-                self.indent_level += 1;
-                for s in body {
-                    self.gen_statement(s);
+            Stmt::Break => {
+                if matches!(self.breakable.last(), Some(Breakable::Switch)) {
+                    let label = self.loop_label(true);
+                    self.line(&format!("goto {};", label));
+                } else {
+                    self.line("break;");
+                }
+            }
+            Stmt::Continue => {
+                let has_step = self.breakable.iter().rev().find_map(|b| match b {
+                    Breakable::Loop { has_step, .. } => Some(*has_step),
+                    Breakable::Switch => None,
+                });
+                if has_step == Some(true) {
+                    let label = self.loop_label(false);
+                    self.line(&format!("goto {};", label));
+                } else {
+                    self.line("continue;");
                 }
+            }
+            Stmt::Return(None) if self.in_main => self.line("return 0;"),
+            Stmt::Return(None) => self.line("return;"),
+            Stmt::Return(Some(value)) => self.line(&format!("return {};", self.operand(value))),
+            Stmt::Match { subject, arms } => self.gen_match(subject, arms),
+            Stmt::Try { body, error, handler } => {
+                // A `Throw` in the body stores the error and jumps to the handler.
+                let id = self.next_id;
+                self.next_id += 1;
+                let c_error = self.c_type(&error.ty);
+                self.line("{");
+                self.indent_level += 1;
+                self.line(&format!("{} {};", c_error, error.name));
+                self.line("{");
+                self.catches.push((self.var(error), format!("__catch{}", id)));
+                self.gen_nested(body);
+                self.catches.pop();
+                self.line("}");
+                self.line(&format!("goto __try_end{};", id));
+                self.line(&format!("__catch{}: {{", id));
+                self.gen_nested(handler);
+                self.line("}");
+                self.line(&format!("__try_end{}:;", id));
                 self.indent_level -= 1;
-                writeln!(self.output, "{}", self.indent()).unwrap();
+                self.line("}");
             }
-            Statement::Return(expr) => {
-                write!(self.output, "{}return ", self.indent()).unwrap();
-                self.gen_expression(expr);
-                writeln!(self.output, ";").unwrap();
+            Stmt::Throw(error) => match self.catches.last() {
+                Some((slot, label)) => {
+                    let text = format!("{} = {}; goto {};", slot, self.operand(error), label);
+                    self.line(&text);
+                }
+                None => self.line("emo_panic(\"uncaught error\");"),
+            },
+            Stmt::Panic(message) => self.line(&format!("emo_panic({});", c_string(message))),
+            Stmt::Block(body) => {
+                self.line("{");
+                self.gen_nested(body);
+                self.line("}");
             }
-            Statement::Break => {
-                writeln!(self.output, "{}break;", self.indent()).unwrap();
+        }
+    }
+
+    /// A label in the innermost loop: just past it, for breaking out through a `switch`, or
+    /// before its step, for continuing.
+    fn loop_label(&mut self, past_loop: bool) -> String {
+        let id = self.next_id;
+        let Some(Breakable::Loop { break_label, continue_label, .. }) = self.breakable.iter_mut().rev().find(|b| matches!(b, Breakable::Loop { .. })) else {
+            return "_brk_outside_loop".to_string();
+        };
+        let (label, prefix) = if past_loop { (break_label, "_brk") } else { (continue_label, "_cont") };
+        let label = label.get_or_insert_with(|| format!("{}{}", prefix, id)).clone();
+        self.next_id += 1;
+        label
+    }
+
+    /// Matches on enum variants become a `switch` over the `Name_Variant` constants, each case
+    /// trying the arms that can apply to it in source order; other matches become an if-chain.
+    fn gen_match(&mut self, subject: &Operand, arms: &[Arm]) {
+        let m = self.operand(subject);
+        let switch_enum = arms.iter().find_map(|arm| match &arm.pattern {
+            Pattern::Variant { enum_name, .. } => match self.type_decls.get(enum_name) {
+                Some(decl @ TypeDef { kind: TypeDefKind::Enum(variants), .. }) => Some((enum_name.clone(), decl.is_plain_enum(), variants.clone())),
+                _ => None,
+            },
+            _ => None,
+        });
+        match switch_enum {
+            Some((enum_name, plain, variants)) => {
+                let scrutinee = if plain { m.clone() } else { format!("{}.tag", m) };
+                self.line(&format!("switch ({}) {{", scrutinee));
+                self.breakable.push(Breakable::Switch);
+                for (variant, _) in &variants {
+                    let applicable: Vec<&Arm> = arms.iter().filter(|arm| match &arm.pattern {
+                        Pattern::Variant { variant: v, .. } => v == variant,
+                        _ => true,
+                    }).collect();
+                    self.line(&format!("case {}_{}: {{", enum_name, variant));
+                    self.indent_level += 1;
+                    self.gen_arm_chain(&m, &applicable, true);
+                    self.line("break;");
+                    self.indent_level -= 1;
+                    self.line("}");
+                }
+                self.breakable.pop();
+                self.line("}");
             }
-            Statement::Expression(expr) => {
-                write!(self.output, "{}", self.indent()).unwrap();
-                self.gen_expression(expr);
-                writeln!(self.output, ";").unwrap();
+            None => {
+                let arms: Vec<&Arm> = arms.iter().collect();
+                self.gen_arm_chain(&m, &arms, false);
             }
-            _ => {}
         }
     }
 
-    fn gen_expression(&mut self, expr: &Spanned<Expression>) {
-        match &expr.node {
-            Expression::Number(n) => write!(self.output, "{}", n).unwrap(),
-            Expression::StringLit(s) => write!(self.output, "\"{}\"", s).unwrap(),
-            Expression::Bool(b) => write!(self.output, "{}", if *b { "true" } else { "false" }).unwrap(),
-            Expression::Null => write!(self.output, "0").unwrap(),
-            Expression::Identifier(name) => write!(self.output, "{}", name).unwrap(),
-            Expression::StructLiteral { name, fields } => {
-                                write!(self.output, "({}){{ ", name).unwrap();
-                
-                for (i, (f_name, f_val)) in fields.iter().enumerate() {
-                    write!(self.output, ".{} = ", f_name).unwrap();
-                    self.gen_expression(f_val);
-                    if i < fields.len() - 1 { write!(self.output, ", ").unwrap(); }
+    /// `if (test) { bindings; body } else if ...`, ending in a panic unless some arm always matches.
+    /// Inside a `switch` case the top-level variant test is already known to hold.
+    fn gen_arm_chain(&mut self, m: &str, arms: &[&Arm], in_case: bool) {
+        let mut first = true;
+        for arm in arms {
+            let mut tests = Vec::new();
+            let mut bindings = Vec::new();
+            self.pattern_tests(&arm.pattern, m, in_case, &mut tests, &mut bindings);
+            let keyword = if first { "" } else { "else " };
+            if tests.is_empty() {
+                self.line(&format!("{}{{", keyword));
+            } else {
+                self.line(&format!("{}if ({}) {{", keyword, tests.join(" && ")));
+            }
+            self.indent_level += 1;
+            for (var, path) in bindings {
+                let c_type = self.c_type(&var.ty);
+                self.line(&format!("{} {} = {};", c_type, var.name, path));
+            }
+            self.gen_block(&arm.body);
+            self.indent_level -= 1;
+            self.line("}");
+            if tests.is_empty() {
+                return;
+            }
+            first = false;
+        }
+        let keyword = if first { "" } else { "else " };
+        self.line(&format!("{}emo_panic(\"no match arm matched\");", keyword));
+    }
+
+    /// Collects the C conditions a value at `path` must meet to match `pattern`, and the bindings it makes.
+    fn pattern_tests(&self, pattern: &Pattern, path: &str, skip_top: bool, tests: &mut Vec<String>, bindings: &mut Vec<(Var, String)>) {
+        match pattern {
+            Pattern::Wildcard | Pattern::Literal(Const::Null) => {}
+            Pattern::Binding(var) => bindings.push((var.clone(), path.to_string())),
+            Pattern::Literal(c @ Const::Str(_)) => tests.push(format!("strcmp({}, {}) == 0", path, c_const(c))),
+            Pattern::Literal(c) => tests.push(format!("{} == {}", path, c_const(c))),
+            Pattern::Variant { enum_name, variant, fields } => {
+                let plain = self.type_decls.get(enum_name).is_some_and(|decl| decl.is_plain_enum());
+                if !skip_top {
+                    if plain {
+                        tests.push(format!("{} == {}_{}", path, enum_name, variant));
+                    } else {
+                        tests.push(format!("{}.tag == {}_{}", path, enum_name, variant));
+                    }
+                }
+                for (i, f) in fields.iter().enumerate() {
+                    self.pattern_tests(f, &format!("{}.as.{}._{}", path, variant, i), false, tests, bindings);
                 }
-                write!(self.output, " }}").unwrap();
             }
-            Expression::BinaryOp(left, op, right) => {
-                write!(self.output, "(").unwrap();
-                self.gen_expression(left);
+            Pattern::Struct { fields } => {
+                for (f_name, f) in fields {
+                    self.pattern_tests(f, &format!("{}.{}", path, f_name), false, tests, bindings);
+                }
+            }
+        }
+    }
+
+    fn is_plain_enum(&self, name: &str) -> bool {
+        self.type_decls.get(name).is_some_and(|decl| decl.is_plain_enum())
+    }
+
+    /// The C expression computing `value` for a variable of type `dest`, or void when the
+    /// value is discarded.
+    fn rvalue(&mut self, value: &Rvalue, dest: &Type) -> String {
+        match value {
+            Rvalue::Use(operand) => self.operand(operand),
+            Rvalue::Binary(op, left, right) => self.binary(op, left, right, dest),
+            Rvalue::Unary(UnaryOp::Neg, operand) if is_integer(&operand.ty()) => {
+                let t = self.integer_type(dest, &operand.ty());
+                format!("emo_checked_sub({}, 0, {})", t, self.operand(operand))
+            }
+            Rvalue::Unary(op, operand) => {
                 let op_str = match op {
-                    Op::Plus => "+",
-                    Op::Minus => "-",
-                    Op::Mul => "*",
-                    Op::Div => "/",
-                    Op::Eq => "==",
-                    Op::NotEq => "!=",
-                    Op::Gt => ">",
-                    Op::Lt => "<",
-                    Op::Gte => ">=",
-                    Op::Lte => "<=",
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "!",
                 };
-                                write!(self.output, " {} ", op_str).unwrap();
-                
-                self.gen_expression(right);
-                write!(self.output, ")").unwrap();
-            }
-            Expression::Call { func, args } => {
-                let mut is_log = false;
-                if let Expression::Identifier(name) = &func.node {
-                    if name == "log" {
-                        is_log = true;
-                    } else {
-                        write!(self.output, "{}(", name).unwrap();
-                    }
-                } else if let Expression::MemberAccess { object, member } = &func.node {
-                    if let Expression::Identifier(obj_name) = &object.node {
-                        match obj_name.as_str() {
-                            "math" => {
-                                match member.as_str() {
-                                    "sqrt" => write!(self.output, "(emo_int)sqrt((double)").unwrap(),
-                                    "pow" => write!(self.output, "(emo_int)pow((double)").unwrap(),
-                                    "sin" => write!(self.output, "(emo_int)sin((double)").unwrap(),
-                                    "cos" => write!(self.output, "(emo_int)cos((double)").unwrap(),
-                                    _ => write!(self.output, "{}_{}(", obj_name, member).unwrap(),
-                                }
-                            },
-                            "json" => {
-                                match member.as_str() {
-                                    "parse" => write!(self.output, "0; // json.parse not yet in C\n").unwrap(),
-                                    "stringify" => write!(self.output, "\"{}\" ; // json.stringify not yet in C\n", "{}").unwrap(),
-                                    _ => write!(self.output, "{}_{}(", obj_name, member).unwrap(),
-                                }
-                                return; // Early return for these stubs
-                            },
-                            "mind" => {
-                                match member.as_str() {
-                                    "spawn_model" => write!(self.output, "mind_spawn_model(").unwrap(),
-                                    "think" => write!(self.output, "model_think(NULL, ").unwrap(), // Global think
-                                    _ => write!(self.output, "{}_{}(", obj_name, member).unwrap(),
-                                }
-                            },
-                            "void" => {
-                                match member.as_str() {
-                                    "absorb" => write!(self.output, "void_absorb(").unwrap(),
-                                    "synthesize_lib" => write!(self.output, "void_synthesize_lib(").unwrap(),
-                                    _ => write!(self.output, "{}_{}(", obj_name, member).unwrap(),
-                                }
-                            },
-                            "time" => {
-                                match member.as_str() {
-                                    "now" => write!(self.output, "time(NULL)").unwrap(),
-                                    "sleep" => write!(self.output, "time_sleep_ms(").unwrap(),
-                                    _ => write!(self.output, "{}_{}(", obj_name, member).unwrap(),
-                                }
-                            },
-                            "net" => {
-                                match member.as_str() {
-                                    "fetch" => write!(self.output, "net_fetch(").unwrap(),
-                                    _ => write!(self.output, "{}_{}(", obj_name, member).unwrap(),
-                                }
-                            },
-                            _ => {
-                                // Potential model method call
-                                match member.as_str() {
-                                    "train" | "save" | "think" => {
-                                        write!(self.output, "model_{}(", member).unwrap();
-                                        self.gen_expression(object);
-                                        if !args.is_empty() { write!(self.output, ", ").unwrap(); }
-                                    }
-                                    _ => {
-                                        // Check if it starts with uppercase (Enum)
-                                        if obj_name.chars().next().unwrap().is_uppercase() {
-                                            write!(self.output, "{}_{}", obj_name, member).unwrap();
-                                        } else {
-                                            self.gen_expression(object);
-                                            write!(self.output, ".{}", member).unwrap();
-                                        }
-                                    }
-                                }
-                                if matches!(member.as_str(), "train" | "save" | "think") { /* arguments will follow */ }
-                            }
-                        }
-                    } else {
-                        self.gen_expression(func);
-                        write!(self.output, "(").unwrap();
-                    }
+                format!("({}{})", op_str, self.operand(operand))
+            }
+            Rvalue::Convert(operand) => {
+                // Floats convert with a plain cast, truncating toward zero; integers must fit.
+                let target = self.c_type(dest);
+                if *dest == Type::Float || operand.ty() == Type::Float {
+                    format!("(({})({}))", target, self.operand(operand))
                 } else {
-                    self.gen_expression(func);
-                    write!(self.output, "(").unwrap();
+                    format!("emo_convert({}, \"{}\", ({}))", target, dest, self.operand(operand))
                 }
-
-                if is_log {
-                    for (_i, arg) in args.iter().enumerate() {
-                        self.gen_expression(arg);
-                        write!(self.output, "); ").unwrap();
-                    }
-                    write!(self.output, "log_newline")
+            }
+            Rvalue::Call(callee, args) => self.call(callee, args, dest),
+            Rvalue::Struct(fields) => {
+                let c_name = self.c_type(dest);
+                let fields: Vec<String> = fields.iter().map(|(name, value)| format!(".{} = {}", name, self.operand(value))).collect();
+                if fields.is_empty() {
+                    format!("(({}){{ 0 }})", c_name)
                 } else {
-                    // Skip arg generation if it was already handled for models (first arg)
-                    // Wait, my logic for model methods above is a bit messy with arg handling.
-                    // Let's fix it.
-                    let _is_model_method = if let Expression::MemberAccess { member, .. } = &func.node {
-                         matches!(member.as_str(), "train" | "save" | "think")
-                    } else { false };
-                    
-                    // We only want to skip if it's NOT a model method or if we handled it differently.
-                    // Actually, let's just use a standard way.
-                    
-                    for (_i, arg) in args.iter().enumerate() {
-                        self.gen_expression(arg);
-                        if _i < args.len() - 1 {
-                             write!(self.output, ", ").unwrap();
-                        }
-                    }
-                    write!(self.output, ")")
+                    format!("(({}){{ {} }})", c_name, fields.join(", "))
+                }
+            }
+            Rvalue::Variant(variant, args) => {
+                let Type::Enum(enum_name, _) = dest else {
+                    return "0".to_string();
+                };
+                if self.is_plain_enum(enum_name) {
+                    return format!("{}_{}", enum_name, variant);
                 }
-                .unwrap();
-            }
-            Expression::MemberAccess { object, member } => {
-                // Check if this is likely an enum access: EnumName.Variant
-                // For a professional compiler, we'd check the type of `object`.
-                // Here we'll check if object is an identifier starting with Uppercase.
-                let is_enum = if let Expression::Identifier(name) = &object.node {
-                    name.chars().next().map_or(false, |c| c.is_uppercase())
+                let c_name = self.c_type(dest);
+                if args.is_empty() {
+                    format!("(({}){{ .tag = {}_{} }})", c_name, enum_name, variant)
                 } else {
-                    false
+                    format!("(({}){{ .tag = {}_{}, .as.{} = {{ {} }} }})", c_name, enum_name, variant, variant, self.operands(args))
+                }
+            }
+            Rvalue::Field(object, field) => format!("{}.{}", self.operand(object), field),
+            Rvalue::IsVariant(value, variant) => match value.ty() {
+                Type::Enum(enum_name, _) if self.is_plain_enum(&enum_name) => format!("({} == {}_{})", self.operand(value), enum_name, variant),
+                Type::Enum(enum_name, _) => format!("({}.tag == {}_{})", self.operand(value), enum_name, variant),
+                _ => "false".to_string(),
+            },
+            Rvalue::Payload(value, variant, n) => format!("{}.as.{}._{}", self.operand(value), variant, n),
+            Rvalue::List(items) => {
+                let mut text = format!("emo_list_of({}", items.len());
+                for item in items {
+                    let slot = self.write_slot(item);
+                    write!(text, ", {}", slot).unwrap();
+                }
+                text + ")"
+            }
+            Rvalue::Map(entries) => {
+                let mut text = format!("emo_map_of({}", entries.len());
+                for (key, value) in entries {
+                    let slot = self.write_slot(value);
+                    write!(text, ", (emo_str)({}), {}", self.operand(key), slot).unwrap();
+                }
+                text + ")"
+            }
+            Rvalue::Index(object, index) => {
+                let slot = format!("emo_index({}, EMO_SLOT({}))", self.operand(object), self.operand(index));
+                self.read_slot(slot, dest)
+            }
+            Rvalue::Range(start, end) => format!("emo_range({}, {})", self.operand(start), self.operand(end)),
+            Rvalue::Interpolate(parts) => {
+                let mut text = format!("emo_str_join({}", parts.len());
+                for part in parts {
+                    match part.ty() {
+                        Type::Str => write!(text, ", {}", self.operand(part)).unwrap(),
+                        Type::Bool => write!(text, ", emo_bool_to_str({})", self.operand(part)).unwrap(),
+                        _ => write!(text, ", emo_to_str({})", self.operand(part)).unwrap(),
+                    }
+                }
+                text + ")"
+            }
+            Rvalue::Closure(function, captures) if captures.is_empty() => {
+                format!("((emo_closure){{ .fn = (void*){}, .env = NULL }})", function)
+            }
+            Rvalue::Closure(function, captures) => {
+                // The environment holds copies of the captured values, taken as the closure is made,
+                // except that cells are shared.
+                let names = self.functions.get(function).map(|sig| sig.captures.clone()).unwrap_or_default();
+                let mut text = format!("({{ struct {}_env* __e = malloc(sizeof(struct {}_env));", function, function);
+                for (name, value) in names.iter().zip(captures) {
+                    let value = match value {
+                        Operand::Var(var) if var.scope == Scope::Cell => var.name.clone(),
+                        Operand::Var(var) if var.scope == Scope::CapturedCell => format!("__env->{}", var.name),
+                        value => self.operand(value),
+                    };
+                    write!(text, " __e->{} = {};", name, value).unwrap();
+                }
+                write!(text, " (emo_closure){{ .fn = (void*){}, .env = __e }}; }})", function).unwrap();
+                text
+            }
+            Rvalue::FunctionRef(function) => {
+                if !self.function_values.contains(function) {
+                    self.function_values.push(function.clone());
+                }
+                format!("((emo_closure){{ .fn = (void*){}__closure, .env = NULL }})", function)
+            }
+        }
+    }
+
+    fn binary(&mut self, op: &Op, left: &Operand, right: &Operand, dest: &Type) -> String {
+        let (l, r) = (self.operand(left), self.operand(right));
+        let left_type = left.ty();
+        match op {
+            Op::Plus if left_type == Type::Str => format!("emo_str_concat({}, {})", l, r),
+            Op::Eq | Op::NotEq | Op::Gt | Op::Lt | Op::Gte | Op::Lte if left_type == Type::Str => {
+                format!("(strcmp({}, {}) {} 0)", l, r, c_op(op))
+            }
+            Op::Plus | Op::Minus | Op::Mul | Op::Div | Op::Mod | Op::Shl | Op::Shr
+                if is_integer(&left_type) && is_integer(&right.ty()) =>
+            {
+                let checked = match op {
+                    Op::Plus => "add",
+                    Op::Minus => "sub",
+                    Op::Mul => "mul",
+                    Op::Div => "div",
+                    Op::Mod => "rem",
+                    Op::Shl => "shl",
+                    _ => "shr",
                 };
+                format!("emo_checked_{}({}, {}, {})", checked, self.integer_type(dest, &left_type), l, r)
+            }
+            Op::Mod => format!("emo_mod({}, {})", l, r),
+            _ => format!("({} {} {})", l, c_op(op), r),
+        }
+    }
+
+    /// The C type integer arithmetic is checked in: that of the result, or else of the operand.
+    fn integer_type(&mut self, dest: &Type, operand: &Type) -> String {
+        if is_integer(dest) {
+            self.c_type(dest)
+        } else if is_integer(operand) {
+            self.c_type(operand)
+        } else {
+            "emo_int".to_string()
+        }
+    }
+
+    /// Whether values of type `t` are too big for a collection slot and live on the heap.
+    fn is_boxed(&self, t: &Type) -> bool {
+        match t {
+            Type::Struct(..) | Type::Function(..) => true,
+            Type::Enum(name, _) => !self.is_plain_enum(name),
+            _ => false,
+        }
+    }
+
+    /// `value` as the `emo_int` a collection slot holds.
+    fn write_slot(&mut self, value: &Operand) -> String {
+        let t = value.ty();
+        let value = self.operand(value);
+        match t {
+            Type::Float => format!("emo_float_slot({})", value),
+            _ if self.is_boxed(&t) => format!("EMO_BOX({})", value),
+            _ => format!("EMO_SLOT({})", value),
+        }
+    }
+
+    /// How `emo_print_slot` in the runtime prints a slot holding a `t`.
+    fn slot_format(&self, t: &Type) -> String {
+        match t {
+            Type::Sized(IntKind::U64) => "u".to_string(),
+            Type::Int | Type::Sized(_) => "i".to_string(),
+            Type::Enum(name, _) if self.is_plain_enum(name) => "i".to_string(),
+            Type::Float => "f".to_string(),
+            Type::Str => "s".to_string(),
+            Type::Bool => "b".to_string(),
+            Type::List(elem) => format!("l{}", self.slot_format(elem)),
+            Type::Map(value) => format!("m{}", self.slot_format(value)),
+            _ => "?".to_string(),
+        }
+    }
+
+    /// Reads a collection slot, which holds an `emo_int`, as a value of type `t`.
+    fn read_slot(&mut self, slot: String, t: &Type) -> String {
+        match t {
+            Type::Str => format!("EMO_STR({})", slot),
+            Type::List(_) | Type::Map(_) => format!("((emo_coll)(intptr_t)({}))", slot),
+            Type::Float => format!("emo_slot_float({})", slot),
+            Type::Bool | Type::Sized(_) => format!("(({})({}))", self.c_type(t), slot),
+            _ if self.is_boxed(t) => format!("EMO_UNBOX({}, {})", self.c_type(t), slot),
+            _ => slot,
+        }
+    }
 
-                if is_enum {
-                    if let Expression::Identifier(obj_name) = &object.node {
-                        write!(self.output, "{}_{}", obj_name, member).unwrap();
+    fn call(&mut self, callee: &Callee, args: &[Operand], dest: &Type) -> String {
+        match callee {
+            Callee::Function(name) | Callee::Extern(name) => format!("{}({})", name, self.operands(args)),
+            Callee::Method(name) => {
+                let receiver = args.first().map(|a| self.operand(a)).unwrap_or_default();
+                let mut c_args = vec![format!("&{}", receiver)];
+                c_args.extend(args.iter().skip(1).map(|a| self.operand(a)));
+                format!("{}({})", name, c_args.join(", "))
+            }
+            Callee::Closure(f) => {
+                // The code pointer receives the captured environment first.
+                let (mut params, ret) = match f.ty() {
+                    Type::Function(params, ret) => (params, *ret),
+                    _ => (Vec::new(), dest.clone()),
+                };
+                for arg in args.iter().skip(params.len()) {
+                    params.push(arg.ty());
+                }
+                let mut c_params = vec!["void*".to_string()];
+                for p in &params {
+                    c_params.push(self.c_type(p));
+                }
+                let f = self.operand(f);
+                let mut c_args = vec![format!("{}.env", f)];
+                c_args.extend(args.iter().map(|a| self.operand(a)));
+                format!("(({} (*)({})){}.fn)({})", self.c_type(&ret), c_params.join(", "), f, c_args.join(", "))
+            }
+            Callee::Native(module, name) => self.native_call(module, name, args),
+            Callee::Collection(method) => {
+                let c_args = self.operands(args);
+                match method.as_str() {
+                    // Collections store every value in an emo_int slot.
+                    "push" => {
+                        let slot = self.write_slot(&args[1]);
+                        format!("emo_coll_push({}, {})", self.operand(&args[0]), slot)
                     }
-                } else {
-                    self.gen_expression(object);
-                    write!(self.output, ".{}", member).unwrap();
+                    "pop" | "remove" => {
+                        let slot = format!("emo_coll_{}({})", method, c_args);
+                        self.read_slot(slot, dest)
+                    }
+                    _ => format!("emo_coll_{}({})", method, c_args),
                 }
             }
+            Callee::Overflow(name) => {
+                let operand_type = args.first().map_or(Type::Int, Operand::ty);
+                let t = self.integer_type(dest, &operand_type);
+                format!("emo_{}({}, {})", name, t, self.operands(args))
+            }
+        }
+    }
+
+    fn native_call(&mut self, module: &str, name: &str, args: &[Operand]) -> String {
+        match (module, name) {
+            ("sys", "log") => {
+                // Collections do not know their element type, so it is passed along.
+                let mut parts: Vec<String> = args.iter().map(|a| match a.ty() {
+                    t @ (Type::List(_) | Type::Map(_)) => format!("log_coll({}, \"{}\")", self.operand(a), self.slot_format(&t)),
+                    _ => format!("log_any({})", self.operand(a)),
+                }).collect();
+                parts.push("log_newline()".to_string());
+                format!("({})", parts.join(", "))
+            }
+            ("math", "sqrt" | "pow" | "sin" | "cos") => {
+                let args: Vec<String> = args.iter().map(|a| format!("(emo_float){}", self.operand(a))).collect();
+                format!("{}({})", name, args.join(", "))
+            }
+            ("time", "now") => "time(NULL)".to_string(),
+            ("time", "sleep") => format!("time_sleep_ms({})", self.operands(args)),
+            ("str", "to_int") => self.result_call("emo_str_parse_int", Type::Int, args),
+            ("sys", "read_file") | ("net", "fetch") => self.result_call(&format!("{}_{}", module, name), Type::Str, args),
+            ("str", _) => format!("emo_str_{}({})", name, self.operands(args)),
+            // The global `mind.think` has no model of its own.
+            ("mind", "think") => format!("model_think(NULL, {})", self.operands(args)),
+            _ => format!("{}_{}({})", module, name, self.operands(args)),
         }
     }
+
+    /// A call to a runtime function that stores an `ok` value through the pointer after
+    /// `args` and returns NULL, or returns an error message, as a `Result<ok, str>`.
+    fn result_call(&mut self, function: &str, ok: Type, args: &[Operand]) -> String {
+        let result = self.c_type(&Type::Enum("Result".to_string(), vec![ok.clone(), Type::Str]));
+        let ok = self.c_type(&ok);
+        let id = self.next_id;
+        self.next_id += 1;
+        let args = self.operands(args);
+        format!(
+            "({{ {ok} __v{id}; emo_str __e{id} = {function}({args}, &__v{id}); __e{id} ? ({result}){{ .tag = Result_Err, .as.Err = {{ __e{id} }} }} : ({result}){{ .tag = Result_Ok, .as.Ok = {{ __v{id} }} }}; }})"
+        )
+    }
+}
+
+fn is_integer(t: &Type) -> bool {
+    matches!(t, Type::Int | Type::Sized(_))
+}
+
+fn c_op(op: &Op) -> &'static str {
+    match op {
+        Op::Plus => "+",
+        Op::Minus => "-",
+        Op::Mul => "*",
+        Op::Div => "/",
+        Op::Eq => "==",
+        Op::NotEq => "!=",
+        Op::Gt => ">",
+        Op::Lt => "<",
+        Op::Gte => ">=",
+        Op::Lte => "<=",
+        Op::Mod => "%",
+        Op::And => "&&",
+        Op::Or => "||",
+        Op::BitAnd => "&",
+        Op::BitOr => "|",
+        Op::BitXor => "^",
+        Op::Shl => "<<",
+        Op::Shr => ">>",
+    }
+}
+
+/// A constant as a C expression; negative numbers are parenthesised so they can follow `-`.
+fn c_const(c: &Const) -> String {
+    match c {
+        // The literal 9223372036854775808 does not fit in int64_t.
+        Const::Int(i64::MIN) => "INT64_MIN".to_string(),
+        Const::Int(n) if *n < 0 => format!("({})", n),
+        Const::Int(n) => n.to_string(),
+        // `INFINITY` and `NAN` are `float`s, which `_Generic` would not treat as `emo_float`.
+        Const::Float(x) if x.is_nan() => "((emo_float)NAN)".to_string(),
+        Const::Float(x) if x.is_infinite() && *x > 0.0 => "((emo_float)INFINITY)".to_string(),
+        Const::Float(x) if x.is_infinite() => "(-(emo_float)INFINITY)".to_string(),
+        Const::Float(x) if x.is_sign_negative() => format!("({:?})", x),
+        Const::Float(x) => format!("{:?}", x),
+        // Likewise `true` and `false` are `int`s.
+        Const::Bool(b) => format!("((emo_bool){})", b),
+        Const::Str(s) => c_string(s),
+        Const::Null => "0".to_string(),
+    }
+}
+
+/// A C string literal for `s`. `?` is escaped too, since `-std=c11` reads trigraphs.
+fn c_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '?' => out.push_str("\\?"),
+            c if (c as u32) < 0x20 || c == '\u{7f}' => write!(out, "\\{:03o}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Replaces type parameters with the arguments of an instantiation.
+fn substitute_type(t: &Type, bindings: &HashMap<String, Type>) -> Type {
+    match t {
+        Type::Param(name) => bindings.get(name).cloned().unwrap_or(Type::Int),
+        Type::Struct(name, args) => Type::Struct(name.clone(), args.iter().map(|a| substitute_type(a, bindings)).collect()),
+        Type::Enum(name, args) => Type::Enum(name.clone(), args.iter().map(|a| substitute_type(a, bindings)).collect()),
+        Type::List(elem) => Type::List(Box::new(substitute_type(elem, bindings))),
+        Type::Map(value) => Type::Map(Box::new(substitute_type(value, bindings))),
+        Type::Function(params, ret) => Type::Function(
+            params.iter().map(|p| substitute_type(p, bindings)).collect(),
+            Box::new(substitute_type(ret, bindings)),
+        ),
+        _ => t.clone(),
+    }
 }
@@ -0,0 +1,256 @@
+//! Turns parse, type and runtime errors into diagnostics: `file:line:column` positions,
+//! the offending source lines with carets, secondary labels and stable error codes.
+
+use crate::lexer::Span;
+use colored::*;
+
+/// Stable identifiers for every kind of error, printed as `E0001`, `E0002`, ...
+/// New codes are only ever appended so existing numbers keep their meaning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Syntax,
+    TypeMismatch,
+    ArityMismatch,
+    UndefinedName,
+    UnknownType,
+    UnknownMember,
+    InfiniteType,
+    NonExhaustiveMatch,
+    UnreachableArm,
+    DuplicateDefinition,
+    Unsupported,
+    Runtime,
+    CallDepthExceeded,
+    NoMatchingArm,
+    DivisionByZero,
+    ModuleNotFound,
+    ImportCycle,
+    PrivateItem,
+    ImmutableAssignment,
+    UncaughtError,
+    IntegerOverflow,
+    MissingField,
+    /// `break` or `continue` outside a loop, or `return` outside a function.
+    MisplacedControlFlow,
+    /// A file that could not be read or written.
+    Io,
+    /// The C compiler rejected the generated code.
+    NativeCompilation,
+    /// A function that returns a value can also reach the end of its body.
+    MissingReturn,
+    /// A type left generic where compiled code needs a concrete one.
+    AmbiguousType,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Syntax => "E0001",
+            ErrorCode::TypeMismatch => "E0002",
+            ErrorCode::ArityMismatch => "E0003",
+            ErrorCode::UndefinedName => "E0004",
+            ErrorCode::UnknownType => "E0005",
+            ErrorCode::UnknownMember => "E0006",
+            ErrorCode::InfiniteType => "E0007",
+            ErrorCode::NonExhaustiveMatch => "E0008",
+            ErrorCode::UnreachableArm => "E0009",
+            ErrorCode::DuplicateDefinition => "E0010",
+            ErrorCode::Unsupported => "E0011",
+            ErrorCode::Runtime => "E0012",
+            ErrorCode::CallDepthExceeded => "E0013",
+            ErrorCode::NoMatchingArm => "E0014",
+            ErrorCode::DivisionByZero => "E0015",
+            ErrorCode::ModuleNotFound => "E0016",
+            ErrorCode::ImportCycle => "E0017",
+            ErrorCode::PrivateItem => "E0018",
+            ErrorCode::ImmutableAssignment => "E0019",
+            ErrorCode::UncaughtError => "E0020",
+            ErrorCode::IntegerOverflow => "E0021",
+            ErrorCode::MissingField => "E0022",
+            ErrorCode::MisplacedControlFlow => "E0023",
+            ErrorCode::Io => "E0024",
+            ErrorCode::NativeCompilation => "E0025",
+            ErrorCode::MissingReturn => "E0026",
+            ErrorCode::AmbiguousType => "E0027",
+        }
+    }
+}
+
+/// A secondary span with a note, drawn with `-` under the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    pub message: String,
+    /// Runtime errors raised outside any expression have no location.
+    pub span: Option<Span>,
+    pub labels: Vec<Label>,
+}
+
+impl Diagnostic {
+    pub fn new(code: ErrorCode, message: impl Into<String>, span: Option<Span>) -> Self {
+        Self { code, message: message.into(), span, labels: Vec::new() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MessageFormat {
+    /// Source snippets with carets, for people.
+    #[default]
+    Human,
+    /// One JSON object per diagnostic, for editors and tools.
+    Json,
+}
+
+struct SourceFile {
+    name: String,
+    source: String,
+    /// Offset of the file's first byte in program-wide spans.
+    base: usize,
+    line_starts: Vec<usize>,
+}
+
+/// Maps program-wide byte offsets to files and 1-based lines and columns. Every file of
+/// a linked program owns a distinct range of offsets, so a span alone says where it is.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A map holding just `source`, at offset 0.
+    pub fn single(name: &str, source: &str) -> Self {
+        let mut map = Self::new();
+        map.add_file(name, source);
+        map
+    }
+
+    /// Registers a file and returns the base offset its spans must start from.
+    pub fn add_file(&mut self, name: &str, source: &str) -> usize {
+        // One spare offset per file keeps end-of-file spans inside their own file.
+        let base = self.files.last().map_or(0, |f| f.base + f.source.len() + 1);
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        self.files.push(SourceFile { name: name.to_string(), source: source.to_string(), base, line_starts });
+        base
+    }
+
+    fn file(&self, offset: usize) -> &SourceFile {
+        let index = self.files.partition_point(|f| f.base <= offset).saturating_sub(1);
+        &self.files[index]
+    }
+
+    /// File name, 1-based line and column; columns count characters, not bytes.
+    pub fn locate(&self, offset: usize) -> (&str, usize, usize) {
+        let file = self.file(offset);
+        let offset = (offset - file.base).min(file.source.len());
+        let line = file.line_starts.partition_point(|&start| start <= offset) - 1;
+        let column = file.source[file.line_starts[line]..offset].chars().count() + 1;
+        (&file.name, line + 1, column)
+    }
+
+    fn line_text(&self, offset: usize, line: usize) -> &str {
+        let file = self.file(offset);
+        let start = file.line_starts[line - 1];
+        let end = file.line_starts.get(line).map_or(file.source.len(), |&next| next - 1);
+        file.source[start..end].trim_end_matches('\r')
+    }
+}
+
+/// Prints a diagnostic to stderr in the requested format.
+pub fn emit(diagnostic: &Diagnostic, map: &SourceMap, format: MessageFormat) {
+    match format {
+        MessageFormat::Human => eprintln!("{}", render(diagnostic, map)),
+        MessageFormat::Json => eprintln!("{}", to_json(diagnostic, map)),
+    }
+}
+
+/// Renders in the familiar compiler layout:
+///
+/// ```text
+/// error[E0002]: Type mismatch: int and str
+///  --> main.emo:3:17
+///   |
+/// 3 |     let x = 1 + "a";
+///   |                 ^^^
+///   |             - int
+/// ```
+pub fn render(diagnostic: &Diagnostic, map: &SourceMap) -> String {
+    let mut out = format!(
+        "{}{}",
+        format!("error[{}]", diagnostic.code.as_str()).red().bold(),
+        format!(": {}", diagnostic.message).bold()
+    );
+    let Some(span) = &diagnostic.span else {
+        return out;
+    };
+
+    let (file, line, column) = map.locate(span.start);
+    let mut marks: Vec<(&Span, char, &str)> = vec![(span, '^', "")];
+    // Labels in other files than the primary span would need snippets of their own.
+    marks.extend(diagnostic.labels.iter()
+        .filter(|l| map.locate(l.span.start).0 == file)
+        .map(|l| (&l.span, '-', l.message.as_str())));
+    let mut lines: Vec<usize> = marks.iter().map(|(s, _, _)| map.locate(s.start).1).collect();
+    lines.sort_unstable();
+    lines.dedup();
+
+    let width = lines.last().map_or(1, |l| l.to_string().len());
+    let gutter = " ".repeat(width);
+    let bar = "|".blue().bold();
+    out.push_str(&format!("\n{}{} {}:{}:{}", gutter, "-->".blue().bold(), file, line, column));
+    out.push_str(&format!("\n{} {}", gutter, bar));
+    for line in lines {
+        let text = map.line_text(span.start, line);
+        out.push_str(&format!("\n{} {} {}", format!("{:>width$}", line, width = width).blue().bold(), bar, text));
+        for (mark_span, marker, message) in marks.iter().filter(|(s, _, _)| map.locate(s.start).1 == line) {
+            let (_, _, start_col) = map.locate(mark_span.start);
+            // Spans running past the end of the line are underlined up to its end.
+            let (_, end_line, end_col) = map.locate(mark_span.end);
+            let end_col = if end_line == line { end_col } else { text.chars().count() + 1 };
+            let underline = marker.to_string().repeat(end_col.saturating_sub(start_col).max(1));
+            let underline = if *marker == '^' { underline.red().bold() } else { underline.blue().bold() };
+            out.push_str(format!("\n{} {} {}{} {}", gutter, bar, " ".repeat(start_col - 1), underline, message).trim_end());
+        }
+    }
+    out
+}
+
+/// A single-line JSON object: code, message, primary span and labels, each span with
+/// its file, byte offsets within that file and 1-based line/column positions.
+pub fn to_json(diagnostic: &Diagnostic, map: &SourceMap) -> String {
+    let span_json = |span: &Span| {
+        let (file, line, column) = map.locate(span.start);
+        let (_, end_line, end_column) = map.locate(span.end);
+        let base = map.file(span.start).base;
+        serde_json::json!({
+            "file": file,
+            "start": span.start - base,
+            "end": span.end - base,
+            "line": line,
+            "column": column,
+            "end_line": end_line,
+            "end_column": end_column,
+        })
+    };
+    serde_json::json!({
+        "severity": "error",
+        "code": diagnostic.code.as_str(),
+        "message": diagnostic.message,
+        "span": diagnostic.span.as_ref().map(span_json),
+        "labels": diagnostic.labels.iter()
+            .map(|l| serde_json::json!({ "message": l.message, "span": span_json(&l.span) }))
+            .collect::<Vec<_>>(),
+    })
+    .to_string()
+}
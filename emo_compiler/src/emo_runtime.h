@@ -2,6 +2,7 @@
 #define EMO_RUNTIME_H
 
 #include <stdio.h>
+#include <stdarg.h>
 #include <stdint.h>
 #include <stdbool.h>
 #include <stdlib.h>
@@ -9,12 +10,55 @@
 #include <math.h>
 #include <time.h>
 #include <unistd.h>
+#include <ctype.h>
+#include <errno.h>
 
 // eMo Types
 typedef int64_t emo_int;
+typedef double emo_float;
 typedef const char* emo_str;
 typedef bool emo_bool;
 
+// First-class functions: a code pointer plus its heap-allocated captured environment.
+// The code pointer always takes the environment as its first argument.
+typedef struct {
+    void* fn;
+    void* env;
+} emo_closure;
+
+// Growable collections. Lists and maps share one representation so that
+// indexing can dispatch at runtime; every slot holds an emo_int. Integers are
+// stored as they are, floats by their bits, pointers (strings, other
+// collections) through intptr_t, and structs and closures boxed on the heap.
+typedef enum { EMO_LIST, EMO_MAP } emo_coll_kind;
+
+typedef struct {
+    emo_coll_kind kind;
+    size_t len;
+    size_t cap;
+    emo_str* keys; // maps only
+    emo_int* items;
+} emo_coll_data;
+
+typedef emo_coll_data* emo_coll;
+
+#define EMO_SLOT(x) ((emo_int)(intptr_t)(x))
+#define EMO_STR(x) ((emo_str)(intptr_t)(x))
+#define EMO_BOX(x) ({ __typeof__(x) _v = (x); void* _p = malloc(sizeof _v); memcpy(_p, &_v, sizeof _v); (emo_int)(intptr_t)_p; })
+#define EMO_UNBOX(T, x) (*(T*)(intptr_t)(x))
+
+static inline emo_int emo_float_slot(emo_float x) {
+    emo_int slot;
+    memcpy(&slot, &x, sizeof slot);
+    return slot;
+}
+
+static inline emo_float emo_slot_float(emo_int slot) {
+    emo_float x;
+    memcpy(&x, &slot, sizeof x);
+    return x;
+}
+
 // Model state for Dimension C
 typedef struct {
     emo_str model_type;
@@ -27,12 +71,43 @@ static inline void log_int(emo_int x) {
     printf("%lld ", (long long)x);
 }
 
+static inline void log_uint(uint64_t x) {
+    printf("%llu ", (unsigned long long)x);
+}
+
+// Shortest digits that read back as x, always with a '.' or an exponent so floats
+// print differently from ints. Matches format_float in the interpreter.
+static inline void emo_format_float(char* out, size_t cap, emo_float x) {
+    if (isnan(x)) { snprintf(out, cap, "NaN"); return; }
+    if (isinf(x)) { snprintf(out, cap, x > 0 ? "inf" : "-inf"); return; }
+    char sci[32];
+    int digits = 0;
+    for (; digits < 17; digits++) {
+        snprintf(sci, sizeof sci, "%.*e", digits, x);
+        if (strtod(sci, NULL) == x) break;
+    }
+    char* e = strchr(sci, 'e');
+    int exp = atoi(e + 1);
+    if (exp < -5 || exp >= 16) {
+        *e = '\0';
+        snprintf(out, cap, "%se%d", sci, exp);
+        return;
+    }
+    snprintf(out, cap, "%.*f", digits - exp > 1 ? digits - exp : 1, x);
+}
+
+static inline void log_float(emo_float x) {
+    char buf[64];
+    emo_format_float(buf, sizeof buf, x);
+    printf("%s ", buf);
+}
+
 static inline void log_str(emo_str x) {
     printf("%s ", x);
 }
 
 static inline void log_bool(emo_bool x) {
-    printf("%s\n", x ? "true" : "false");
+    printf("%s ", x ? "true" : "false");
 }
 
 static inline void sys_log(emo_str x) {
@@ -47,14 +122,428 @@ static inline emo_int sys_poll() {
     return 0; // Simulation
 }
 
+// Reads the file at `path` into `*out`; returns NULL on success, otherwise the error message for `Result.Err`.
+static inline emo_str sys_read_file(emo_str path, emo_str* out) {
+    FILE* f = fopen(path, "rb");
+    char* content = NULL;
+    size_t len = 0;
+    if (f) {
+        fseek(f, 0, SEEK_END);
+        long size = ftell(f);
+        rewind(f);
+        content = size < 0 ? NULL : malloc((size_t)size + 1);
+        if (content) len = fread(content, 1, (size_t)size, f);
+        if (ferror(f)) {
+            free(content);
+            content = NULL;
+        }
+        fclose(f);
+    }
+    if (!content) {
+        const char* reason = strerror(errno);
+        char* msg = malloc(strlen(reason) + 11);
+        sprintf(msg, "IO Error: %s", reason);
+        return msg;
+    }
+    content[len] = '\0';
+    *out = content;
+    return NULL;
+}
+
 static inline void time_sleep_ms(emo_int ms) {
     usleep(ms * 1000);
 }
 
+// Collections
+static inline void emo_panic(emo_str msg) {
+    fprintf(stderr, "eMo runtime error: %s\n", msg);
+    exit(1);
+}
+
+static inline emo_coll emo_coll_new(emo_coll_kind kind) {
+    emo_coll c = calloc(1, sizeof(emo_coll_data));
+    c->kind = kind;
+    return c;
+}
+
+static inline void emo_coll_reserve(emo_coll c, size_t n) {
+    if (n <= c->cap) return;
+    size_t cap = c->cap ? c->cap * 2 : 4;
+    while (cap < n) cap *= 2;
+    c->items = realloc(c->items, cap * sizeof(emo_int));
+    if (c->kind == EMO_MAP) c->keys = realloc(c->keys, cap * sizeof(emo_str));
+    c->cap = cap;
+}
+
+static inline void emo_coll_push(emo_coll c, emo_int value) {
+    emo_coll_reserve(c, c->len + 1);
+    c->items[c->len++] = value;
+}
+
+static inline emo_int emo_coll_pop(emo_coll c) {
+    if (c->len == 0) emo_panic("pop from empty list");
+    return c->items[--c->len];
+}
+
+static inline emo_int emo_coll_len(emo_coll c) {
+    return (emo_int)c->len;
+}
+
+static inline emo_coll emo_list_of(size_t n, ...) {
+    emo_coll c = emo_coll_new(EMO_LIST);
+    va_list args;
+    va_start(args, n);
+    for (size_t i = 0; i < n; i++) emo_coll_push(c, va_arg(args, emo_int));
+    va_end(args);
+    return c;
+}
+
+static inline long emo_map_find(emo_coll c, emo_str key) {
+    for (size_t i = 0; i < c->len; i++) {
+        if (strcmp(c->keys[i], key) == 0) return (long)i;
+    }
+    return -1;
+}
+
+static inline void emo_map_put(emo_coll c, emo_str key, emo_int value) {
+    long i = emo_map_find(c, key);
+    if (i >= 0) {
+        c->items[i] = value;
+        return;
+    }
+    emo_coll_reserve(c, c->len + 1);
+    c->keys[c->len] = key;
+    c->items[c->len++] = value;
+}
+
+// Arguments alternate key (emo_str) and value (emo_int).
+static inline emo_coll emo_map_of(size_t n, ...) {
+    emo_coll c = emo_coll_new(EMO_MAP);
+    va_list args;
+    va_start(args, n);
+    for (size_t i = 0; i < n; i++) {
+        emo_str key = va_arg(args, emo_str);
+        emo_map_put(c, key, va_arg(args, emo_int));
+    }
+    va_end(args);
+    return c;
+}
+
+static inline emo_int emo_index(emo_coll c, emo_int key) {
+    if (c->kind == EMO_MAP) {
+        long i = emo_map_find(c, (emo_str)(intptr_t)key);
+        if (i < 0) emo_panic("key not found in map");
+        return c->items[i];
+    }
+    if (key < 0 || (size_t)key >= c->len) emo_panic("list index out of bounds");
+    return c->items[key];
+}
+
+static inline void emo_index_set(emo_coll c, emo_int key, emo_int value) {
+    if (c->kind == EMO_MAP) {
+        emo_map_put(c, (emo_str)(intptr_t)key, value);
+        return;
+    }
+    if (key < 0 || (size_t)key >= c->len) emo_panic("list index out of bounds");
+    c->items[key] = value;
+}
+
+static inline emo_coll emo_coll_keys(emo_coll c) {
+    emo_coll out = emo_coll_new(EMO_LIST);
+    for (size_t i = 0; i < c->len; i++) emo_coll_push(out, EMO_SLOT(c->keys[i]));
+    return out;
+}
+
+static inline emo_coll emo_coll_values(emo_coll c) {
+    emo_coll out = emo_coll_new(EMO_LIST);
+    for (size_t i = 0; i < c->len; i++) emo_coll_push(out, c->items[i]);
+    return out;
+}
+
+static inline emo_bool emo_coll_contains(emo_coll c, emo_str key) {
+    return emo_map_find(c, key) >= 0;
+}
+
+static inline emo_int emo_coll_remove(emo_coll c, emo_str key) {
+    long i = emo_map_find(c, key);
+    if (i < 0) return 0;
+    emo_int value = c->items[i];
+    for (size_t j = (size_t)i + 1; j < c->len; j++) {
+        c->keys[j - 1] = c->keys[j];
+        c->items[j - 1] = c->items[j];
+    }
+    c->len--;
+    return value;
+}
+
+static inline emo_coll emo_range(emo_int from, emo_int to) {
+    emo_coll out = emo_coll_new(EMO_LIST);
+    for (emo_int i = from; i < to; i++) emo_coll_push(out, i);
+    return out;
+}
+
+// Snapshot used by `for x in c`: list items, or the keys of a map.
+static inline emo_coll emo_coll_iter(emo_coll c) {
+    return c->kind == EMO_MAP ? emo_coll_keys(c) : emo_coll_values(c);
+}
+
+#define EMO_CALL1(f, x) (((emo_int (*)(void*, emo_int))(f).fn)((f).env, (x)))
+
+static inline emo_coll emo_coll_map(emo_coll c, emo_closure f) {
+    emo_coll out = emo_coll_new(EMO_LIST);
+    for (size_t i = 0; i < c->len; i++) emo_coll_push(out, EMO_CALL1(f, c->items[i]));
+    return out;
+}
+
+static inline emo_coll emo_coll_filter(emo_coll c, emo_closure f) {
+    emo_coll out = emo_coll_new(EMO_LIST);
+    for (size_t i = 0; i < c->len; i++) {
+        if (EMO_CALL1(f, c->items[i])) emo_coll_push(out, c->items[i]);
+    }
+    return out;
+}
+
+static inline void emo_coll_each(emo_coll c, emo_closure f) {
+    for (size_t i = 0; i < c->len; i++) EMO_CALL1(f, c->items[i]);
+}
+
+// Prints a slot holding a value of the element type `elem` describes: 'i' int, 'u' u64,
+// 'f' float, 's' str, 'b' bool, or 'l' and 'm' for lists and maps followed by the
+// description of their own elements. Anything else has no printed form in compiled code.
+static inline void emo_print_slot(emo_int slot, emo_str elem) {
+    char buf[64];
+    switch (*elem) {
+    case 'i': printf("%lld", (long long)slot); break;
+    case 'u': printf("%llu", (unsigned long long)slot); break;
+    case 'f': emo_format_float(buf, sizeof buf, emo_slot_float(slot)); printf("%s", buf); break;
+    case 's': printf("\"%s\"", EMO_STR(slot)); break;
+    case 'b': printf("%s", slot ? "true" : "false"); break;
+    case 'l':
+    case 'm': {
+        emo_coll c = (emo_coll)(intptr_t)slot;
+        printf(c->kind == EMO_MAP ? "{" : "[");
+        for (size_t i = 0; i < c->len; i++) {
+            if (i) printf(", ");
+            if (c->kind == EMO_MAP) printf("\"%s\": ", c->keys[i]);
+            emo_print_slot(c->items[i], elem + 1);
+        }
+        printf(c->kind == EMO_MAP ? "}" : "]");
+        break;
+    }
+    default: printf("<value>");
+    }
+}
+
+// `c` is a list or map, as `elem` says, of elements described by the rest of `elem`.
+static inline void log_coll(emo_coll c, emo_str elem) {
+    emo_print_slot(EMO_SLOT(c), elem);
+    printf(" ");
+}
+
+// Strings. Results are freshly allocated and never freed, like collections.
+static inline emo_str emo_str_concat(emo_str a, emo_str b) {
+    size_t la = strlen(a), lb = strlen(b);
+    char* out = malloc(la + lb + 1);
+    memcpy(out, a, la);
+    memcpy(out + la, b, lb + 1);
+    return out;
+}
+
+// Joins n strings; used for interpolated literals.
+static inline emo_str emo_str_join(int n, ...) {
+    va_list args;
+    size_t len = 0;
+    va_start(args, n);
+    for (int i = 0; i < n; i++) len += strlen(va_arg(args, emo_str));
+    va_end(args);
+    char* out = malloc(len + 1);
+    char* p = out;
+    va_start(args, n);
+    for (int i = 0; i < n; i++) {
+        emo_str part = va_arg(args, emo_str);
+        size_t l = strlen(part);
+        memcpy(p, part, l);
+        p += l;
+    }
+    va_end(args);
+    *p = '\0';
+    return out;
+}
+
+static inline emo_str emo_int_to_str(emo_int x) {
+    char* out = malloc(24);
+    snprintf(out, 24, "%lld", (long long)x);
+    return out;
+}
+
+static inline emo_str emo_uint_to_str(uint64_t x) {
+    char* out = malloc(24);
+    snprintf(out, 24, "%llu", (unsigned long long)x);
+    return out;
+}
+
+static inline emo_str emo_float_to_str(emo_float x) {
+    char* out = malloc(32);
+    emo_format_float(out, 32, x);
+    return out;
+}
+
+static inline emo_str emo_bool_to_str(emo_bool x) {
+    return x ? "true" : "false";
+}
+
+static inline emo_str emo_str_id(emo_str x) {
+    return x;
+}
+
+#define emo_to_str(x) _Generic((x), \
+    char*: emo_str_id, \
+    const char*: emo_str_id, \
+    double: emo_float_to_str, \
+    bool: emo_bool_to_str, \
+    uint64_t: emo_uint_to_str, \
+    default: emo_int_to_str \
+)(x)
+
+// Length in characters: UTF-8 continuation bytes are not counted.
+static inline emo_int emo_str_len(emo_str s) {
+    emo_int n = 0;
+    for (; *s; s++) {
+        if (((unsigned char)*s & 0xC0) != 0x80) n++;
+    }
+    return n;
+}
+
+static inline emo_str emo_str_slice(emo_str s, size_t len) {
+    char* out = malloc(len + 1);
+    memcpy(out, s, len);
+    out[len] = '\0';
+    return out;
+}
+
+static inline emo_coll emo_str_split(emo_str s, emo_str sep) {
+    size_t sep_len = strlen(sep);
+    if (sep_len == 0) emo_panic("str.split separator must not be empty");
+    emo_coll out = emo_coll_new(EMO_LIST);
+    emo_str hit;
+    while ((hit = strstr(s, sep)) != NULL) {
+        emo_coll_push(out, EMO_SLOT(emo_str_slice(s, (size_t)(hit - s))));
+        s = hit + sep_len;
+    }
+    emo_coll_push(out, EMO_SLOT(emo_str_slice(s, strlen(s))));
+    return out;
+}
+
+static inline emo_str emo_str_trim(emo_str s) {
+    while (*s && isspace((unsigned char)*s)) s++;
+    size_t len = strlen(s);
+    while (len && isspace((unsigned char)s[len - 1])) len--;
+    return emo_str_slice(s, len);
+}
+
+static inline emo_bool emo_str_contains(emo_str s, emo_str needle) {
+    return strstr(s, needle) != NULL;
+}
+
+static inline emo_str emo_str_replace(emo_str s, emo_str from, emo_str to) {
+    size_t from_len = strlen(from), to_len = strlen(to), count = 0;
+    if (from_len == 0) emo_panic("str.replace pattern must not be empty");
+    for (emo_str p = s; (p = strstr(p, from)) != NULL; p += from_len) count++;
+    char* out = malloc(strlen(s) + count * to_len + 1);
+    char* o = out;
+    emo_str hit;
+    while ((hit = strstr(s, from)) != NULL) {
+        memcpy(o, s, (size_t)(hit - s));
+        o += hit - s;
+        memcpy(o, to, to_len);
+        o += to_len;
+        s = hit + from_len;
+    }
+    strcpy(o, s);
+    return out;
+}
+
+static inline emo_str emo_str_upper(emo_str s) {
+    char* out = (char*)emo_str_slice(s, strlen(s));
+    for (char* p = out; *p; p++) *p = (char)toupper((unsigned char)*p);
+    return out;
+}
+
+static inline emo_str emo_str_lower(emo_str s) {
+    char* out = (char*)emo_str_slice(s, strlen(s));
+    for (char* p = out; *p; p++) *p = (char)tolower((unsigned char)*p);
+    return out;
+}
+
+// Parses `s` into `*out`; returns NULL on success, otherwise the error message for `Result.Err`.
+static inline emo_str emo_str_parse_int(emo_str s, emo_int* out) {
+    char* end;
+    errno = 0;
+    long long v = strtoll(s, &end, 10);
+    if (*s == '\0' || isspace((unsigned char)*s) || *end != '\0' || errno == ERANGE) {
+        size_t cap = strlen(s) + 40;
+        char* msg = malloc(cap);
+        snprintf(msg, cap, "str.to_int: '%s' is not an integer", s);
+        return msg;
+    }
+    *out = (emo_int)v;
+    return NULL;
+}
+
+// `%` for ints, fmod for floats.
+static inline emo_int emo_imod(emo_int a, emo_int b) {
+    return a % b;
+}
+
+#define emo_mod(a, b) _Generic((a) + (b), double: fmod, default: emo_imod)((a), (b))
+
+// Integer arithmetic at the width of `T`, matching the interpreter: overflow, division by zero
+// and out-of-range shift amounts are runtime errors. The builtins compute the exact result first.
+#define EMO_SIGNED(T) ((T)-1 < (T)0)
+#define EMO_MAX(T) ((T)(EMO_SIGNED(T) ? ((uint64_t)1 << (sizeof(T) * 8 - 1)) - 1 : ~(uint64_t)0))
+#define EMO_MIN(T) ((T)(EMO_SIGNED(T) ? -EMO_MAX(T) - 1 : 0))
+#define emo_checked_add(T, a, b) ({ T _r; if (__builtin_add_overflow((a), (b), &_r)) emo_panic("Integer overflow"); _r; })
+#define emo_checked_sub(T, a, b) ({ T _r; if (__builtin_sub_overflow((a), (b), &_r)) emo_panic("Integer overflow"); _r; })
+#define emo_checked_mul(T, a, b) ({ T _r; if (__builtin_mul_overflow((a), (b), &_r)) emo_panic("Integer overflow"); _r; })
+#define emo_checked_div(T, a, b) ({ T _a = (a), _b = (b), _r; \
+    if (_b == 0) emo_panic("Division by zero"); \
+    if (EMO_SIGNED(T) && _b == (T)-1 && __builtin_sub_overflow((T)0, _a, &_r)) emo_panic("Integer overflow"); \
+    (T)(_a / _b); })
+// `MIN % -1` is 0, though C leaves it undefined.
+#define emo_checked_rem(T, a, b) ({ T _a = (a), _b = (b); \
+    if (_b == 0) emo_panic("Division by zero"); \
+    (T)(EMO_SIGNED(T) && _b == (T)-1 ? 0 : _a % _b); })
+#define emo_checked_shl(T, a, b) ({ T _a = (a); emo_int _b = (b); \
+    if (_b < 0 || _b >= (emo_int)sizeof(T) * 8) emo_panic("Shift amount out of range"); \
+    (T)((uint64_t)_a << _b); })
+#define emo_checked_shr(T, a, b) ({ T _a = (a); emo_int _b = (b); \
+    if (_b < 0 || _b >= (emo_int)sizeof(T) * 8) emo_panic("Shift amount out of range"); \
+    (T)(_a >> _b); })
+
+#define emo_wrapping_add(T, a, b) ({ T _r; (void)__builtin_add_overflow((a), (b), &_r); _r; })
+#define emo_wrapping_sub(T, a, b) ({ T _r; (void)__builtin_sub_overflow((a), (b), &_r); _r; })
+#define emo_wrapping_mul(T, a, b) ({ T _r; (void)__builtin_mul_overflow((a), (b), &_r); _r; })
+#define emo_saturating_add(T, a, b) ({ T _a = (a), _b = (b), _r; \
+    if (__builtin_add_overflow(_a, _b, &_r)) _r = _b > 0 ? EMO_MAX(T) : EMO_MIN(T); _r; })
+#define emo_saturating_sub(T, a, b) ({ T _a = (a), _b = (b), _r; \
+    if (__builtin_sub_overflow(_a, _b, &_r)) _r = _b > 0 ? EMO_MIN(T) : EMO_MAX(T); _r; })
+#define emo_saturating_mul(T, a, b) ({ T _a = (a), _b = (b), _r; \
+    if (__builtin_mul_overflow(_a, _b, &_r)) _r = (_a < 0) != (_b < 0) ? EMO_MIN(T) : EMO_MAX(T); _r; })
+
+// `T(x)` for an integer `x`: a runtime error unless the value fits in `T`.
+#define emo_convert(T, name, x) ({ __auto_type _x = (x); T _r = (T)_x; \
+    if ((__typeof__(_x))_r != _x || (_r < 0) != (_x < 0)) emo_panic("Value out of range for " name); _r; })
+
+#define math_abs(x) _Generic((x), double: fabs, default: llabs)(x)
+#define math_min(a, b) ({ __auto_type _a = (a); __auto_type _b = (b); _a < _b ? _a : _b; })
+#define math_max(a, b) ({ __auto_type _a = (a); __auto_type _b = (b); _a > _b ? _a : _b; })
+
 // Polymorphic log macro using C11 _Generic
 #define log_any(x) _Generic((x), \
     int64_t: log_int, \
     int: log_int, \
+    uint64_t: log_uint, \
+    double: log_float, \
     char*: log_str, \
     const char*: log_str, \
     bool: log_bool, \
@@ -98,18 +587,11 @@ static inline void void_synthesize_lib(emo_str name, emo_str source) {
 
 // Net Library Implementation (Simplified for Prototype)
 
-static inline emo_str net_fetch(emo_str url) {
-
+// Like `sys_read_file`, but every fetch succeeds with the same page.
+static inline emo_str net_fetch(emo_str url, emo_str* out) {
     printf("[NET] Fetching %s...\n", url);
-
-    return "<html>eMo Unified System (Native Mode)</html>";
-
-}
-
-
-
-    // Return a copy on heap or just the static buffer (unsafe but works for simple demo)
-    return strdup(buffer); 
+    *out = "<html>eMo Unified System (Native Mode)</html>";
+    return NULL;
 }
 
 // Dimension B: HappyCry UI
@@ -1,4 +1,4 @@
-use crate::ast::{Statement, Expression, Op, Spanned};
+use crate::ast::{Statement, Expression, Op, Pattern, Spanned, StringPart, TypeExpr, UnaryOp};
 
 pub struct Formatter {
     indent_level: usize,
@@ -31,13 +31,10 @@ impl Formatter {
                     out.push_str(&format!(" from \"{}\"", src));
                 }
             }
-            Statement::FunctionDef { name, params, body } => {
-                out.push_str(&format!("fn {}(", name));
-                for (i, (p_name, p_type)) in params.iter().enumerate() {
-                    out.push_str(&format!("{}: {}", p_name, p_type));
-                    if i < params.len() - 1 { out.push_str(", "); }
-                }
-                out.push_str(") {\n");
+            Statement::FunctionDef { public, name, params, return_type, body } => {
+                out.push_str(&format!("{}fn {}", visibility(*public), name));
+                out.push_str(&self.format_signature(params, return_type));
+                out.push_str(" {\n");
                 self.indent_level += 1;
                 for s in body {
                     out.push_str(&self.indent());
@@ -48,14 +45,33 @@ impl Formatter {
                 out.push_str(&self.indent());
                 out.push('}');
             }
-            Statement::Let { name, value } => {
-                out.push_str(&format!("let {} = ", name));
+            Statement::Let { name, mutable, ty, value } => {
+                let keyword = if *mutable { "let mut" } else { "let" };
+                match ty {
+                    Some(t) => out.push_str(&format!("{} {}: {} = ", keyword, name, t)),
+                    None => out.push_str(&format!("{} {} = ", keyword, name)),
+                }
+                out.push_str(&self.format_expression(value));
+            }
+            Statement::Const { public, name, ty, value } => {
+                match ty {
+                    Some(t) => out.push_str(&format!("{}const {}: {} = ", visibility(*public), name, t)),
+                    None => out.push_str(&format!("{}const {} = ", visibility(*public), name)),
+                }
                 out.push_str(&self.format_expression(value));
             }
             Statement::Set { name, value } => {
                 out.push_str(&format!("set {} to ", name));
                 out.push_str(&self.format_expression(value));
             }
+            Statement::IndexSet { object, index, value } => {
+                out.push_str(&format!(
+                    "{}[{}] = {}",
+                    self.format_expression(object),
+                    self.format_expression(index),
+                    self.format_expression(value)
+                ));
+            }
             Statement::Return(expr) => {
                 out.push_str("return ");
                 out.push_str(&self.format_expression(expr));
@@ -63,6 +79,9 @@ impl Formatter {
             Statement::Break => {
                 out.push_str("break");
             }
+            Statement::Continue => {
+                out.push_str("continue");
+            }
             Statement::Expression(expr) => {
                 out.push_str(&self.format_expression(expr));
             }
@@ -124,8 +143,22 @@ impl Formatter {
                 out.push_str(&self.indent());
                 out.push('}');
             }
-            Statement::StructDef { name, fields } => {
-                out.push_str(&format!("struct {} {{ \n", name));
+            Statement::ForIn { var, iterable, body } => {
+                out.push_str(&format!("for {} in ", var));
+                out.push_str(&self.format_expression(iterable));
+                out.push_str(" {\n");
+                self.indent_level += 1;
+                for s in body {
+                    out.push_str(&self.indent());
+                    out.push_str(&self.format_statement(s));
+                    out.push('\n');
+                }
+                self.indent_level -= 1;
+                out.push_str(&self.indent());
+                out.push('}');
+            }
+            Statement::StructDef { public, name, type_params, fields } => {
+                out.push_str(&format!("{}struct {}{} {{ \n", visibility(*public), name, self.format_type_params(type_params)));
                 self.indent_level += 1;
                 for (f_name, f_type) in fields {
                     out.push_str(&self.indent());
@@ -135,12 +168,17 @@ impl Formatter {
                 out.push_str(&self.indent());
                 out.push('}');
             }
-            Statement::EnumDef { name, variants } => {
-                out.push_str(&format!("enum {} {{ \n", name));
+            Statement::EnumDef { public, name, type_params, variants } => {
+                out.push_str(&format!("{}enum {}{} {{ \n", visibility(*public), name, self.format_type_params(type_params)));
                 self.indent_level += 1;
-                for v in variants {
+                for (v, payload) in variants {
                     out.push_str(&self.indent());
-                    out.push_str(&format!("{},\n", v));
+                    if payload.is_empty() {
+                        out.push_str(&format!("{},\n", v));
+                    } else {
+                        let payload: Vec<String> = payload.iter().map(|t| t.to_string()).collect();
+                        out.push_str(&format!("{}({}),\n", v, payload.join(", ")));
+                    }
                 }
                 self.indent_level -= 1;
                 out.push_str(&self.indent());
@@ -158,14 +196,103 @@ impl Formatter {
                 out.push_str(&self.indent());
                 out.push('}');
             }
+            Statement::TryCatch { body, error, handler } => {
+                out.push_str("try {\n");
+                self.indent_level += 1;
+                for s in body {
+                    out.push_str(&self.indent());
+                    out.push_str(&self.format_statement(s));
+                    out.push('\n');
+                }
+                self.indent_level -= 1;
+                out.push_str(&self.indent());
+                out.push_str(&format!("}} catch {} {{\n", error));
+                self.indent_level += 1;
+                for s in handler {
+                    out.push_str(&self.indent());
+                    out.push_str(&self.format_statement(s));
+                    out.push('\n');
+                }
+                self.indent_level -= 1;
+                out.push_str(&self.indent());
+                out.push('}');
+            }
+            Statement::Impl { type_name, methods } => {
+                out.push_str(&format!("impl {} {{\n", type_name));
+                self.indent_level += 1;
+                for m in methods {
+                    out.push_str(&self.indent());
+                    out.push_str(&self.format_statement(m));
+                    out.push('\n');
+                }
+                self.indent_level -= 1;
+                out.push_str(&self.indent());
+                out.push('}');
+            }
+            Statement::Error => {}
+            Statement::Match { subject, arms } => {
+                out.push_str(&format!("match {} {{\n", self.format_expression(subject)));
+                self.indent_level += 1;
+                for arm in arms {
+                    out.push_str(&self.indent());
+                    out.push_str(&format!("{} => {{\n", self.format_pattern(&arm.pattern)));
+                    self.indent_level += 1;
+                    for s in &arm.body {
+                        out.push_str(&self.indent());
+                        out.push_str(&self.format_statement(s));
+                        out.push('\n');
+                    }
+                    self.indent_level -= 1;
+                    out.push_str(&self.indent());
+                    out.push_str("}\n");
+                }
+                self.indent_level -= 1;
+                out.push_str(&self.indent());
+                out.push('}');
+            }
         }
         out
     }
 
+    fn format_pattern(&mut self, pattern: &Spanned<Pattern>) -> String {
+        match &pattern.node {
+            Pattern::Wildcard => "_".to_string(),
+            Pattern::Binding(name) => name.clone(),
+            Pattern::Literal(lit) => self.format_expression(&Spanned { node: lit.clone(), span: pattern.span.clone() }),
+            Pattern::Variant { enum_name, variant, fields } => {
+                if fields.is_empty() {
+                    format!("{}.{}", enum_name, variant)
+                } else {
+                    let fields: Vec<String> = fields.iter().map(|f| self.format_pattern(f)).collect();
+                    format!("{}.{}({})", enum_name, variant, fields.join(", "))
+                }
+            }
+            Pattern::Struct { name, fields } => {
+                let fields: Vec<String> = fields.iter().map(|(f_name, f)| match &f.node {
+                    Pattern::Binding(b) if b == f_name => f_name.clone(),
+                    _ => format!("{}: {}", f_name, self.format_pattern(f)),
+                }).collect();
+                format!("{} {{ {} }}", name, fields.join(", "))
+            }
+        }
+    }
+
     fn format_expression(&mut self, expr: &Spanned<Expression>) -> String {
         match &expr.node {
             Expression::Number(n) => n.to_string(),
+            Expression::Float(x) => format!("{:?}", x),
             Expression::StringLit(s) => format!("\"{}\"", s),
+            Expression::Interpolated(parts) => {
+                let mut out = String::from("\"");
+                for part in parts {
+                    match part {
+                        StringPart::Literal(s) => out.push_str(&s.replace('{', "\\{")),
+                        StringPart::Expr(e) => out.push_str(&format!("{{{}}}", self.format_expression(e))),
+                    }
+                }
+                out.push('"');
+                out
+            }
             Expression::Bool(b) => b.to_string(),
             Expression::Null => "null".to_string(),
             Expression::Identifier(name) => name.clone(),
@@ -179,6 +306,25 @@ impl Formatter {
                 out.push_str(" }");
                 out
             }
+            Expression::ListLiteral(items) => {
+                let items: Vec<String> = items.iter().map(|i| self.format_expression(i)).collect();
+                format!("[{}]", items.join(", "))
+            }
+            Expression::MapLiteral(entries) => {
+                if entries.is_empty() {
+                    return "{}".to_string();
+                }
+                let entries: Vec<String> = entries.iter()
+                    .map(|(k, v)| format!("{}: {}", self.format_expression(k), self.format_expression(v)))
+                    .collect();
+                format!("{{ {} }}", entries.join(", "))
+            }
+            Expression::Index { object, index } => {
+                format!("{}[{}]", self.format_expression(object), self.format_expression(index))
+            }
+            Expression::Range { start, end } => {
+                format!("{}..{}", self.format_expression(start), self.format_expression(end))
+            }
             Expression::BinaryOp(left, op, right) => {
                 let op_str = match op {
                     Op::Plus => "+",
@@ -191,9 +337,29 @@ impl Formatter {
                     Op::Lt => "<",
                     Op::Gte => ">=",
                     Op::Lte => "<=",
+                    Op::Mod => "%",
+                    Op::And => "&&",
+                    Op::Or => "||",
+                    Op::BitAnd => "&",
+                    Op::BitOr => "|",
+                    Op::BitXor => "^",
+                    Op::Shl => "<<",
+                    Op::Shr => ">>",
                 };
                 format!("({} {} {})", self.format_expression(left), op_str, self.format_expression(right))
             }
+            Expression::Unary(op, operand) => {
+                let op_str = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "!",
+                };
+                format!("{}{}", op_str, self.format_expression(operand))
+            }
+            Expression::Try(operand) => match operand.node {
+                // `-x?` would apply the `?` first.
+                Expression::Unary(..) => format!("({})?", self.format_expression(operand)),
+                _ => format!("{}?", self.format_expression(operand)),
+            },
             Expression::Call { func, args } => {
                 let mut out = self.format_expression(func);
                 out.push('(');
@@ -207,6 +373,82 @@ impl Formatter {
             Expression::MemberAccess { object, member } => {
                 format!("{}.{}", self.format_expression(object), member)
             }
+            Expression::Lambda { params, return_type, body } => {
+                let mut out = "fn".to_string();
+                out.push_str(&self.format_signature(params, return_type));
+                out.push_str(" {\n");
+                self.indent_level += 1;
+                for s in body {
+                    out.push_str(&self.indent());
+                    out.push_str(&self.format_statement(s));
+                    out.push('\n');
+                }
+                self.indent_level -= 1;
+                out.push_str(&self.indent());
+                out.push('}');
+                out
+            }
+            Expression::If { cond, then_block, else_block } => {
+                let mut out = format!("if {} {}", self.format_expression(cond), self.format_block(then_block));
+                if let Some(eb) = else_block {
+                    out.push_str(" else ");
+                    out.push_str(&self.format_block(eb));
+                }
+                out
+            }
+            Expression::Match { subject, arms } => {
+                let mut out = format!("match {} {{\n", self.format_expression(subject));
+                self.indent_level += 1;
+                for arm in arms {
+                    let pattern = self.format_pattern(&arm.pattern);
+                    out.push_str(&format!("{}{} => {}\n", self.indent(), pattern, self.format_block(&arm.body)));
+                }
+                self.indent_level -= 1;
+                out.push_str(&self.indent());
+                out.push('}');
+                out
+            }
+            Expression::Block(body) => self.format_block(body),
+            // Files with syntax errors are never formatted.
+            Expression::Error => String::new(),
+        }
+    }
+
+    /// `{`, the statements one level deeper, and `}` at the current level.
+    fn format_block(&mut self, body: &[Spanned<Statement>]) -> String {
+        let mut out = "{\n".to_string();
+        self.indent_level += 1;
+        for s in body {
+            out.push_str(&self.indent());
+            out.push_str(&self.format_statement(s));
+            out.push('\n');
+        }
+        self.indent_level -= 1;
+        out.push_str(&self.indent());
+        out.push('}');
+        out
+    }
+
+    fn format_signature(&self, params: &[(String, Option<TypeExpr>)], return_type: &Option<TypeExpr>) -> String {
+        let params: Vec<String> = params.iter().map(|(p_name, p_type)| match p_type {
+            Some(t) => format!("{}: {}", p_name, t),
+            None => p_name.clone(),
+        }).collect();
+        match return_type {
+            Some(t) => format!("({}) -> {}", params.join(", "), t),
+            None => format!("({})", params.join(", ")),
         }
     }
+
+    fn format_type_params(&self, type_params: &[String]) -> String {
+        if type_params.is_empty() {
+            String::new()
+        } else {
+            format!("<{}>", type_params.join(", "))
+        }
+    }
+}
+
+fn visibility(public: bool) -> &'static str {
+    if public { "pub " } else { "" }
 }
@@ -1,6 +1,9 @@
-use crate::ast::{Statement, Expression, Op, Spanned};
+use crate::ast::{split_value, MatchArm, Statement, Expression, Op, Pattern, Spanned, Span, StringPart, TypeExpr, UnaryOp};
+use crate::diagnostics::{Diagnostic, ErrorCode};
+use crate::natives::{self, NATIVE_FUNCTIONS, NATIVE_GLOBALS};
+use crate::numeric::{arith, overflow_helper, ArithError, IntKind, Overflow, OVERFLOW_HELPERS, SIZED_INTS};
 use crate::shadow_synthesizer::ShadowSynthesizer;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::fs;
@@ -12,14 +15,18 @@ use serde_json;
 #[derive(Clone)]
 pub enum Value {
     Number(i64),
+    /// A value of a sized integer type, always within its range.
+    Sized(IntKind, i128),
+    Float(f64),
     String(String),
     Bool(bool),
     Pointer(usize),
     Library(Rc<libloading::Library>),
     Function {
         name: String,
-        params: Vec<(String, String)>,
+        params: Vec<(String, Option<TypeExpr>)>,
         body: Vec<Spanned<Statement>>,
+        closure: Rc<RefCell<Environment>>,
     },
     NativeFn(String),
     BoundNativeFn {
@@ -27,6 +34,18 @@ pub enum Value {
         name: String,
     },
     Object(HashMap<String, Value>),
+    /// An instance of a user-defined struct; the name selects its `impl` methods.
+    Struct {
+        name: String,
+        fields: HashMap<String, Value>,
+    },
+    /// A method looked up on a struct value, e.g. `p.area`, with `self` already bound.
+    BoundMethod {
+        receiver: Box<Value>,
+        method: Box<Value>,
+    },
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<Vec<(String, Value)>>>),
     Model {
         model_type: String,
         focus: String,
@@ -39,6 +58,13 @@ pub enum Value {
     EnumVariant {
         enum_name: String,
         variant_name: String,
+        payload: Vec<Value>,
+    },
+    /// A payload-carrying variant before it is applied, e.g. `Option.Some`.
+    VariantConstructor {
+        enum_name: String,
+        variant_name: String,
+        arity: usize,
     },
     Null,
 }
@@ -47,6 +73,8 @@ impl std::fmt::Debug for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Value::Number(n) => write!(f, "Number({})", n),
+            Value::Sized(kind, n) => write!(f, "Sized({}, {})", kind.name(), n),
+            Value::Float(x) => write!(f, "Float({})", format_float(*x)),
             Value::String(s) => write!(f, "String({:?})", s),
             Value::Bool(b) => write!(f, "Bool({})", b),
             Value::Pointer(p) => write!(f, "Pointer(0x{:x})", p),
@@ -55,9 +83,15 @@ impl std::fmt::Debug for Value {
             Value::NativeFn(name) => write!(f, "NativeFn({})", name),
             Value::BoundNativeFn { name, .. } => write!(f, "BoundNativeFn({})", name),
             Value::Object(map) => write!(f, "Object({:?})", map),
+            Value::Struct { name, fields } => write!(f, "{}({:?})", name, fields),
+            Value::BoundMethod { method, .. } => write!(f, "BoundMethod({:?})", method),
+            Value::List(items) => write!(f, "List({:?})", items.borrow()),
+            Value::Map(entries) => write!(f, "Map({:?})", entries.borrow()),
             Value::Model { model_type, focus, .. } => write!(f, "Model({}, focus: {})", model_type, focus),
             Value::Type { name, .. } => write!(f, "Type({})", name),
-            Value::EnumVariant { enum_name, variant_name } => write!(f, "EnumVariant({}::{})", enum_name, variant_name),
+            Value::EnumVariant { enum_name, variant_name, payload } if payload.is_empty() => write!(f, "EnumVariant({}::{})", enum_name, variant_name),
+            Value::EnumVariant { enum_name, variant_name, payload } => write!(f, "EnumVariant({}::{}{:?})", enum_name, variant_name, payload),
+            Value::VariantConstructor { enum_name, variant_name, .. } => write!(f, "VariantConstructor({}::{})", enum_name, variant_name),
             Value::Null => write!(f, "Null"),
         }
     }
@@ -67,10 +101,19 @@ impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Sized(ka, a), Value::Sized(kb, b)) => ka == kb && a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
             (Value::String(a), Value::String(b)) => a == b,
             (Value::Bool(a), Value::Bool(b)) => a == b,
             (Value::Pointer(a), Value::Pointer(b)) => a == b,
             (Value::Library(a), Value::Library(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => *a.borrow() == *b.borrow(),
+            (Value::Map(a), Value::Map(b)) => *a.borrow() == *b.borrow(),
+            (Value::Struct { name: n1, fields: f1 }, Value::Struct { name: n2, fields: f2 }) => n1 == n2 && f1 == f2,
+            (
+                Value::EnumVariant { enum_name: e1, variant_name: v1, payload: p1 },
+                Value::EnumVariant { enum_name: e2, variant_name: v2, payload: p2 },
+            ) => e1 == e2 && v1 == v2 && p1 == p2,
             (Value::Null, Value::Null) => true,
             _ => false,
         }
@@ -79,8 +122,8 @@ impl PartialEq for Value {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TypeDef {
-    Struct { fields: Vec<(String, String)> },
-    Enum { variants: Vec<String> },
+    Struct { fields: Vec<(String, TypeExpr)> },
+    Enum { variants: Vec<(String, Vec<TypeExpr>)> },
 }
 
 #[derive(Debug, Clone, Default)]
@@ -94,9 +137,131 @@ impl PartialEq for ModelState {
     }
 }
 
+/// `Result.Ok(value)`, for natives that can fail.
+fn result_ok(value: Value) -> Value {
+    Value::EnumVariant { enum_name: "Result".to_string(), variant_name: "Ok".to_string(), payload: vec![value] }
+}
+
+/// `Result.Err(message)`: a failure eMo code can handle instead of a runtime error.
+fn result_err(message: String) -> Value {
+    Value::EnumVariant { enum_name: "Result".to_string(), variant_name: "Err".to_string(), payload: vec![Value::String(message)] }
+}
+
+impl Value {
+    /// Renders a value the way `sys.log` prints it.
+    pub fn display(&self) -> String {
+        match self {
+            Value::String(s) => s.clone(),
+            Value::Number(n) => n.to_string(),
+            Value::Sized(_, n) => n.to_string(),
+            Value::Float(x) => format_float(*x),
+            Value::Bool(b) => b.to_string(),
+            Value::Pointer(p) => format!("0x{:x}", p),
+            Value::Null => "null".to_string(),
+            Value::List(items) => {
+                let items: Vec<String> = items.borrow().iter().map(|v| v.display_nested()).collect();
+                format!("[{}]", items.join(", "))
+            }
+            Value::Map(entries) => {
+                let entries: Vec<String> = entries.borrow().iter()
+                    .map(|(k, v)| format!("{:?}: {}", k, v.display_nested()))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            _ => format!("{:?}", self),
+        }
+    }
+
+    /// Like `display`, but quotes strings so they stay distinguishable inside collections.
+    fn display_nested(&self) -> String {
+        match self {
+            Value::String(s) => format!("{:?}", s),
+            _ => self.display(),
+        }
+    }
+}
+
+/// Shortest digits that read back as `x`, always with a `.` or exponent so floats stay
+/// distinguishable from ints. `emo_format_float` in emo_runtime.h produces the same text.
+pub fn format_float(x: f64) -> String {
+    if !x.is_finite() {
+        return x.to_string();
+    }
+    let sci = format!("{:e}", x);
+    let exp: i32 = sci.split_once('e').and_then(|(_, e)| e.parse().ok()).unwrap_or(0);
+    if !(-5..16).contains(&exp) {
+        return sci;
+    }
+    let s = x.to_string();
+    if s.contains('.') { s } else { format!("{}.0", s) }
+}
+
+/// The type and value of an `int` or sized integer, so both share one arithmetic path.
+fn as_integer(v: &Value) -> Option<(IntKind, i128)> {
+    match v {
+        Value::Number(n) => Some((IntKind::I64, *n as i128)),
+        Value::Sized(kind, n) => Some((*kind, *n)),
+        _ => None,
+    }
+}
+
+/// Wraps an in-range result back up as a value of `kind`.
+fn integer_value(kind: IntKind, n: i128) -> Value {
+    match kind {
+        IntKind::I64 => Value::Number(n as i64),
+        _ => Value::Sized(kind, n),
+    }
+}
+
+/// `l op r` on integers, which must share a type except for a shift amount.
+/// Arithmetic is checked: overflow and division by zero are runtime errors, as in C.
+fn integer_op(lk: IntKind, l: i128, op: &Op, rk: IntKind, r: i128) -> Result<Value, RuntimeError> {
+    match op {
+        Op::Shl | Op::Shr => {
+            let shifted = u32::try_from(r).ok().filter(|r| *r < lk.bits()).map(|r| match op {
+                Op::Shl => lk.wrap(l << r),
+                _ => l >> r,
+            });
+            shifted.map(|v| integer_value(lk, v)).ok_or_else(|| format!("Shift amount {} is out of range", r).into())
+        }
+        _ if lk != rk => Err(format!("Cannot mix {} and {} operands; convert one with {}()", lk.name(), rk.name(), lk.name()).into()),
+        Op::Plus | Op::Minus | Op::Mul | Op::Div | Op::Mod => Ok(integer_value(lk, arith(lk, l, op, r, Overflow::Checked)?)),
+        Op::BitAnd => Ok(integer_value(lk, l & r)),
+        Op::BitOr => Ok(integer_value(lk, l | r)),
+        Op::BitXor => Ok(integer_value(lk, l ^ r)),
+        Op::Gt => Ok(Value::Bool(l > r)),
+        Op::Lt => Ok(Value::Bool(l < r)),
+        Op::Gte => Ok(Value::Bool(l >= r)),
+        Op::Lte => Ok(Value::Bool(l <= r)),
+        Op::Eq => Ok(Value::Bool(l == r)),
+        Op::NotEq => Ok(Value::Bool(l != r)),
+        Op::And | Op::Or => Err("Invalid operation".to_string().into()),
+    }
+}
+
+/// `name(value)` for an integer type: any integer that fits.
+fn convert_int(kind: IntKind, name: &str, value: Option<&Value>) -> Result<Value, String> {
+    match value.and_then(as_integer) {
+        Some((_, n)) if kind.contains(n) => Ok(integer_value(kind, n)),
+        Some((_, n)) => Err(format!("Value {} is out of range for {}", n, name)),
+        None => Err(format!("{} expects an integer", name)),
+    }
+}
+
+fn number_as_f64(v: &Value) -> Option<f64> {
+    match v {
+        Value::Number(n) => Some(*n as f64),
+        Value::Sized(_, n) => Some(*n as f64),
+        Value::Float(x) => Some(*x),
+        _ => None,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Environment {
     values: HashMap<String, Value>,
+    /// Names bound with `let mut`; every other binding is immutable.
+    mutable: HashSet<String>,
     enclosing: Option<Rc<RefCell<Environment>>>,
 }
 
@@ -104,6 +269,7 @@ impl Environment {
     pub fn new() -> Self {
         Self {
             values: HashMap::new(),
+            mutable: HashSet::new(),
             enclosing: None,
         }
     }
@@ -111,11 +277,18 @@ impl Environment {
     pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
         Self {
             values: HashMap::new(),
+            mutable: HashSet::new(),
             enclosing: Some(enclosing),
         }
     }
 
     pub fn define(&mut self, name: String, value: Value) {
+        self.mutable.remove(&name);
+        self.values.insert(name, value);
+    }
+
+    pub fn define_mutable(&mut self, name: String, value: Value) {
+        self.mutable.insert(name.clone());
         self.values.insert(name, value);
     }
 
@@ -129,27 +302,97 @@ impl Environment {
         }
     }
 
-    pub fn assign(&mut self, name: &str, value: Value) -> Result<(), String> {
+    pub fn assign(&mut self, name: &str, value: Value) -> Result<(), RuntimeError> {
         if self.values.contains_key(name) {
+            if !self.mutable.contains(name) {
+                return Err(RuntimeError::new(ErrorCode::ImmutableAssignment, format!("Cannot assign to immutable binding '{}'", name), None));
+            }
             self.values.insert(name.to_string(), value);
             Ok(())
         } else if let Some(ref enclosing) = self.enclosing {
             enclosing.borrow_mut().assign(name, value)
         } else {
-            Err(format!("Undefined variable '{}'", name))
+            Err(RuntimeError::new(ErrorCode::UndefinedName, format!("Undefined variable '{}'", name), None))
         }
     }
 }
 
+/// A runtime failure. Errors raised without a location take the span of the innermost
+/// statement or expression being evaluated as they propagate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl RuntimeError {
+    pub fn new(code: ErrorCode, message: impl Into<String>, span: Option<Span>) -> Self {
+        Self { code, message: message.into(), span }
+    }
+
+    fn at(mut self, span: &Span) -> Self {
+        self.span.get_or_insert_with(|| span.clone());
+        self
+    }
+}
+
+impl From<String> for RuntimeError {
+    fn from(message: String) -> Self {
+        Self::new(ErrorCode::Runtime, message, None)
+    }
+}
+
+impl From<ArithError> for RuntimeError {
+    fn from(e: ArithError) -> Self {
+        match e {
+            ArithError::Overflow => Self::new(ErrorCode::IntegerOverflow, "Integer overflow", None),
+            ArithError::DivisionByZero => Self::new(ErrorCode::DivisionByZero, "Division by zero", None),
+        }
+    }
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<RuntimeError> for Diagnostic {
+    fn from(e: RuntimeError) -> Self {
+        Diagnostic::new(e.code, e.message, e.span)
+    }
+}
+
 pub enum ExecResult {
     Ok,
     Return(Value),
     Break,
+    Continue,
 }
 
+/// Default limit on nested eMo function calls before the interpreter gives up.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/// The largest call depth the interpreter accepts; see `run_with_options` for why.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
 pub struct Interpreter {
     pub globals: Rc<RefCell<Environment>>,
     pub backpack: Rc<RefCell<HashMap<String, Value>>>,
+    pub max_call_depth: usize,
+    call_depth: usize,
+    /// Methods from `impl` blocks, keyed by struct name and then method name.
+    methods: HashMap<String, HashMap<String, Value>>,
+    /// The `Result.Err` or `Option.None` a `?` is passing to its function or `try` block.
+    /// It unwinds as an `UncaughtError`, which only carries a message, since values
+    /// cannot leave the interpreter thread.
+    propagating: Option<Value>,
+    /// The `return`, `break` or `continue` leaving a block used as a value. It unwinds as a
+    /// runtime error to the statement around the expression, which then acts on it.
+    leaving: Option<ExecResult>,
+    /// Integer literals the type checker gave a sized type, by span; the rest are `int`s.
+    sized_literals: HashMap<Span, IntKind>,
 }
 
 impl Interpreter {
@@ -157,102 +400,80 @@ impl Interpreter {
         let globals = Rc::new(RefCell::new(Environment::new()));
         let backpack = Rc::new(RefCell::new(HashMap::new()));
         
-        let mut sys_methods = HashMap::new();
-        // ... (existing sys methods)
-        
-        // Add Backpack methods to sys
-        let _bp_capture = backpack.clone();
-        sys_methods.insert("hold".to_string(), Value::NativeFn("sys.hold".to_string()));
-        sys_methods.insert("release".to_string(), Value::NativeFn("sys.release".to_string()));
-        
-        sys_methods.insert("log".to_string(), Value::NativeFn("sys.log".to_string()));
-        sys_methods.insert("poll".to_string(), Value::NativeFn("sys.poll".to_string()));
-        sys_methods.insert("read_file".to_string(), Value::NativeFn("sys.read_file".to_string()));
-        sys_methods.insert("write_file".to_string(), Value::NativeFn("sys.write_file".to_string()));
-        sys_methods.insert("append_file".to_string(), Value::NativeFn("sys.append_file".to_string()));
-        sys_methods.insert("alloc".to_string(), Value::NativeFn("sys.alloc".to_string()));
-        sys_methods.insert("free".to_string(), Value::NativeFn("sys.free".to_string()));
-        sys_methods.insert("peek".to_string(), Value::NativeFn("sys.peek".to_string()));
-        sys_methods.insert("poke".to_string(), Value::NativeFn("sys.poke".to_string()));
-        sys_methods.insert("load_lib".to_string(), Value::NativeFn("sys.load_lib".to_string()));
-        sys_methods.insert("call_ffi".to_string(), Value::NativeFn("sys.call_ffi".to_string()));
-        globals.borrow_mut().define("sys".to_string(), Value::Object(sys_methods));
-
-        let mut joy_methods = HashMap::new();
-        joy_methods.insert("say".to_string(), Value::NativeFn("joy.say".to_string()));
-        globals.borrow_mut().define("joy".to_string(), Value::Object(joy_methods));
-
-        let mut math_methods = HashMap::new();
-        math_methods.insert("sqrt".to_string(), Value::NativeFn("math.sqrt".to_string()));
-        math_methods.insert("pow".to_string(), Value::NativeFn("math.pow".to_string()));
-        math_methods.insert("sin".to_string(), Value::NativeFn("math.sin".to_string()));
-        math_methods.insert("cos".to_string(), Value::NativeFn("math.cos".to_string()));
-        math_methods.insert("abs".to_string(), Value::NativeFn("math.abs".to_string()));
-        math_methods.insert("min".to_string(), Value::NativeFn("math.min".to_string()));
-        math_methods.insert("max".to_string(), Value::NativeFn("math.max".to_string()));
-        globals.borrow_mut().define("math".to_string(), Value::Object(math_methods));
-
-        let mut json_methods = HashMap::new();
-        json_methods.insert("parse".to_string(), Value::NativeFn("json.parse".to_string()));
-        json_methods.insert("stringify".to_string(), Value::NativeFn("json.stringify".to_string()));
-        globals.borrow_mut().define("json".to_string(), Value::Object(json_methods));
-
-        let mut mind_methods = HashMap::new();
-        mind_methods.insert("think".to_string(), Value::NativeFn("mind.think".to_string()));
-        mind_methods.insert("spawn_model".to_string(), Value::NativeFn("mind.spawn_model".to_string()));
-        globals.borrow_mut().define("mind".to_string(), Value::Object(mind_methods));
-
-        let mut net_methods = HashMap::new();
-        net_methods.insert("fetch".to_string(), Value::NativeFn("net.fetch".to_string()));
-        globals.borrow_mut().define("net".to_string(), Value::Object(net_methods));
-
-        let mut void_methods = HashMap::new();
-        void_methods.insert("absorb".to_string(), Value::NativeFn("void.absorb".to_string()));
-        void_methods.insert("synthesize_lib".to_string(), Value::NativeFn("void.synthesize_lib".to_string()));
-        globals.borrow_mut().define("void".to_string(), Value::Object(void_methods));
-
-        let mut time_methods = HashMap::new();
-        time_methods.insert("now".to_string(), Value::NativeFn("time.now".to_string()));
-        time_methods.insert("sleep".to_string(), Value::NativeFn("time.sleep".to_string()));
-        globals.borrow_mut().define("time".to_string(), Value::Object(time_methods));
-
-        let mut os_methods = HashMap::new();
-        os_methods.insert("exit".to_string(), Value::NativeFn("os.exit".to_string()));
-        os_methods.insert("env".to_string(), Value::NativeFn("os.env".to_string()));
-        os_methods.insert("args".to_string(), Value::NativeFn("os.args".to_string()));
-        os_methods.insert("name".to_string(), Value::NativeFn("os.name".to_string()));
-        globals.borrow_mut().define("os".to_string(), Value::Object(os_methods));
-
-        let mut random_methods = HashMap::new();
-        random_methods.insert("int".to_string(), Value::NativeFn("random.int".to_string()));
-        globals.borrow_mut().define("random".to_string(), Value::Object(random_methods));
-
-        globals.borrow_mut().define("log".to_string(), Value::NativeFn("sys.log".to_string()));
-
-        Self { globals, backpack }
-    }
-
-    pub fn interpret(&mut self, statements: Vec<Spanned<Statement>>) -> Result<(), String> {
+        // Every native module is an object of `NativeFn`s that `call_native` dispatches by name.
+        for module in natives::modules() {
+            let functions = NATIVE_FUNCTIONS.iter()
+                .filter(|f| f.module == module)
+                .map(|f| (f.name.to_string(), Value::NativeFn(f.qualified_name())))
+                .collect();
+            globals.borrow_mut().define(module.to_string(), Value::Object(functions));
+        }
+        for (name, module, function) in NATIVE_GLOBALS {
+            globals.borrow_mut().define(name.to_string(), Value::NativeFn(format!("{}.{}", module, function)));
+        }
+        let sized = SIZED_INTS.iter().map(|kind| kind.name());
+        for name in ["float", "int", "i64", "byte"].into_iter().chain(sized).chain(OVERFLOW_HELPERS.iter().copied()) {
+            globals.borrow_mut().define(name.to_string(), Value::NativeFn(name.to_string()));
+        }
+
+        Self { globals, backpack, max_call_depth: DEFAULT_MAX_CALL_DEPTH, call_depth: 0, methods: HashMap::new(), propagating: None, leaving: None, sized_literals: HashMap::new() }
+    }
+
+    pub fn with_max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = depth;
+        self
+    }
+
+    /// Uses the types `TypeChecker::sized_literals` gave integer literals.
+    pub fn with_sized_literals(mut self, literals: HashMap<Span, IntKind>) -> Self {
+        self.sized_literals = literals;
+        self
+    }
+
+    pub fn interpret(&mut self, statements: Vec<Spanned<Statement>>) -> Result<(), RuntimeError> {
         for stmt in &statements {
             match &stmt.node {
-                Statement::FunctionDef { name, params, body } => {
-                     let func = Value::Function { name: name.clone(), params: params.clone(), body: body.clone() };
+                Statement::FunctionDef { name, params, body, .. } => {
+                     let func = Value::Function {
+                         name: name.clone(),
+                         params: params.clone(),
+                         body: body.clone(),
+                         closure: self.globals.clone(),
+                     };
                      self.globals.borrow_mut().define(name.clone(), func);
                 }
-                Statement::StructDef { name, fields } => {
+                Statement::StructDef { name, fields, .. } => {
                     let def = Value::Type { 
                         name: name.clone(), 
                         definition: TypeDef::Struct { fields: fields.clone() } 
                     };
                     self.globals.borrow_mut().define(name.clone(), def);
                 }
-                Statement::EnumDef { name, variants } => {
+                Statement::EnumDef { name, variants, .. } => {
                     let mut variant_map = HashMap::new();
-                    for v in variants {
-                        variant_map.insert(v.clone(), Value::EnumVariant { enum_name: name.clone(), variant_name: v.clone() });
+                    for (v, payload) in variants {
+                        let value = if payload.is_empty() {
+                            Value::EnumVariant { enum_name: name.clone(), variant_name: v.clone(), payload: Vec::new() }
+                        } else {
+                            Value::VariantConstructor { enum_name: name.clone(), variant_name: v.clone(), arity: payload.len() }
+                        };
+                        variant_map.insert(v.clone(), value);
                     }
                     self.globals.borrow_mut().define(name.clone(), Value::Object(variant_map));
                 }
+                Statement::Impl { type_name, methods } => {
+                    let table = self.methods.entry(type_name.clone()).or_default();
+                    for method in methods {
+                        if let Statement::FunctionDef { name, params, body, .. } = &method.node {
+                            table.insert(name.clone(), Value::Function {
+                                name: format!("{}.{}", type_name, name),
+                                params: params.clone(),
+                                body: body.clone(),
+                                closure: self.globals.clone(),
+                            });
+                        }
+                    }
+                }
                 _ => {}
             }
         }
@@ -275,13 +496,42 @@ impl Interpreter {
         }
     }
 
-    fn execute(&mut self, stmt: &Spanned<Statement>, env: Rc<RefCell<Environment>>) -> Result<ExecResult, String> {
+    fn execute(&mut self, stmt: &Spanned<Statement>, env: Rc<RefCell<Environment>>) -> Result<ExecResult, RuntimeError> {
+        let result = self.execute_statement(stmt, env);
+        if let Some(leaving) = self.leaving.take() {
+            return Ok(leaving);
+        }
+        result.map_err(|e| e.at(&stmt.span))
+    }
+
+    fn execute_statement(&mut self, stmt: &Spanned<Statement>, env: Rc<RefCell<Environment>>) -> Result<ExecResult, RuntimeError> {
         match &stmt.node {
             Statement::Import { .. } => Ok(ExecResult::Ok),
-            Statement::FunctionDef { .. } => Ok(ExecResult::Ok),
+            // Methods are registered up front by `interpret`.
+            Statement::Impl { .. } => Ok(ExecResult::Ok),
+            Statement::FunctionDef { name, params, body, .. } => {
+                // Top-level functions are hoisted by `interpret`; nested ones close over their block.
+                let func = Value::Function {
+                    name: name.clone(),
+                    params: params.clone(),
+                    body: body.clone(),
+                    closure: env.clone(),
+                };
+                env.borrow_mut().define(name.clone(), func);
+                Ok(ExecResult::Ok)
+            },
             Statement::StructDef { .. } => Ok(ExecResult::Ok),
             Statement::EnumDef { .. } => Ok(ExecResult::Ok),
-            Statement::Let { name, value } => {
+            Statement::Let { name, mutable, value, .. } => {
+                let val = self.evaluate(value, env.clone())?;
+                if *mutable {
+                    env.borrow_mut().define_mutable(name.clone(), val);
+                } else {
+                    env.borrow_mut().define(name.clone(), val);
+                }
+                Ok(ExecResult::Ok)
+            },
+            Statement::Const { name, value, .. } => {
                 let val = self.evaluate(value, env.clone())?;
                 env.borrow_mut().define(name.clone(), val);
                 Ok(ExecResult::Ok)
@@ -301,15 +551,24 @@ impl Interpreter {
                     Ok(ExecResult::Ok)
                 }
             },
-            Statement::Loop { count: _, body } => {
-                 loop {
-                     let res = self.execute_block(body, env.clone())?;
-                     match res {
-                         ExecResult::Break => return Ok(ExecResult::Ok),
-                         ExecResult::Return(v) => return Ok(ExecResult::Return(v)),
-                         ExecResult::Ok => {},
-                     }
-                 }
+            Statement::Loop { count, body } => {
+                let limit = match count {
+                    Some(c) => match self.evaluate(c, env.clone())? {
+                        Value::Number(n) => Some(n),
+                        _ => return Err("Loop count must be a number".to_string().into()),
+                    },
+                    None => None,
+                };
+                let mut i = 0;
+                while limit.is_none_or(|n| i < n) {
+                    i += 1;
+                    match self.execute_block(body, env.clone())? {
+                        ExecResult::Break => break,
+                        ExecResult::Return(v) => return Ok(ExecResult::Return(v)),
+                        ExecResult::Ok | ExecResult::Continue => {},
+                    }
+                }
+                Ok(ExecResult::Ok)
             },
             Statement::While { cond, body } => {
                 loop {
@@ -321,12 +580,69 @@ impl Interpreter {
                     match res {
                         ExecResult::Break => break,
                         ExecResult::Return(v) => return Ok(ExecResult::Return(v)),
-                        ExecResult::Ok => {},
+                        ExecResult::Ok | ExecResult::Continue => {},
+                    }
+                }
+                Ok(ExecResult::Ok)
+            },
+            Statement::ForIn { var, iterable, body } => {
+                // Ranges are walked lazily; collections are snapshotted so the body may mutate them.
+                let items: Box<dyn Iterator<Item = Value>> = match &iterable.node {
+                    Expression::Range { start, end } => {
+                        let (from, to) = self.eval_range_bounds(start, end, env.clone())?;
+                        Box::new((from..to).map(Value::Number))
+                    }
+                    _ => match self.evaluate(iterable, env.clone())? {
+                        Value::List(items) => Box::new(items.borrow().clone().into_iter()),
+                        Value::Map(entries) => {
+                            let keys: Vec<Value> = entries.borrow().iter().map(|(k, _)| Value::String(k.clone())).collect();
+                            Box::new(keys.into_iter())
+                        }
+                        other => return Err(format!("Cannot iterate over {:?}", other).into()),
+                    },
+                };
+                for item in items {
+                    let iter_env = Rc::new(RefCell::new(Environment::with_enclosing(env.clone())));
+                    iter_env.borrow_mut().define(var.clone(), item);
+                    match self.execute_block(body, iter_env)? {
+                        ExecResult::Break => break,
+                        ExecResult::Return(v) => return Ok(ExecResult::Return(v)),
+                        ExecResult::Ok | ExecResult::Continue => {},
                     }
                 }
                 Ok(ExecResult::Ok)
             },
+            Statement::Match { subject, arms } => {
+                let (arm, arm_env) = self.select_arm(subject, arms, env)?;
+                self.execute_block(&arm.body, arm_env)
+            },
             Statement::Break => Ok(ExecResult::Break),
+            Statement::Continue => Ok(ExecResult::Continue),
+            Statement::IndexSet { object, index, value } => {
+                let obj = self.evaluate(object, env.clone())?;
+                let idx = self.evaluate(index, env.clone())?;
+                let val = self.evaluate(value, env)?;
+                match (obj, idx) {
+                    (Value::List(items), Value::Number(i)) => {
+                        let mut items = items.borrow_mut();
+                        let len = items.len();
+                        let slot = usize::try_from(i).ok().and_then(|i| items.get_mut(i))
+                            .ok_or_else(|| format!("List index {} out of bounds for length {}", i, len))?;
+                        *slot = val;
+                    }
+                    (Value::Map(entries), Value::String(key)) => {
+                        let mut entries = entries.borrow_mut();
+                        match entries.iter_mut().find(|(k, _)| *k == key) {
+                            Some((_, slot)) => *slot = val,
+                            None => entries.push((key, val)),
+                        }
+                    }
+                    (Value::List(_), _) => return Err("List indices must be numbers".to_string().into()),
+                    (Value::Map(_), _) => return Err("Map keys must be strings".to_string().into()),
+                    _ => return Err("Only lists and maps support index assignment".to_string().into()),
+                }
+                Ok(ExecResult::Ok)
+            },
             Statement::Return(expr) => {
                 let val = self.evaluate(expr, env)?;
                 Ok(ExecResult::Return(val))
@@ -338,10 +654,75 @@ impl Interpreter {
             Statement::UnsafeBlock(body) => {
                 self.execute_block(body, env)
             }
+            Statement::TryCatch { body, error, handler } => match self.execute_block(body, env.clone()) {
+                Err(e) if e.code == ErrorCode::UncaughtError => {
+                    // `Result.Err(e)` hands `e` to the handler; `Option.None` has nothing to give.
+                    let caught = match self.propagating.take() {
+                        Some(Value::EnumVariant { payload, .. }) => payload.into_iter().next().unwrap_or(Value::Null),
+                        _ => Value::Null,
+                    };
+                    let handler_env = Rc::new(RefCell::new(Environment::with_enclosing(env)));
+                    handler_env.borrow_mut().define(error.clone(), caught);
+                    self.execute_block(handler, handler_env)
+                }
+                result => result,
+            },
+            Statement::Error => Err(RuntimeError::new(ErrorCode::Syntax, "Cannot run a statement that failed to parse", None)),
+        }
+    }
+
+    /// Tests `value` against `pattern`, collecting the variables it binds.
+    /// The first arm whose pattern matches the subject, with an environment holding its bindings.
+    fn select_arm<'a>(&mut self, subject: &Spanned<Expression>, arms: &'a [MatchArm], env: Rc<RefCell<Environment>>) -> Result<(&'a MatchArm, Rc<RefCell<Environment>>), RuntimeError> {
+        let value = self.evaluate(subject, env.clone())?;
+        for arm in arms {
+            let mut bindings = Vec::new();
+            if self.match_pattern(&arm.pattern.node, &value, &mut bindings) {
+                let arm_env = Rc::new(RefCell::new(Environment::with_enclosing(env)));
+                for (name, v) in bindings {
+                    arm_env.borrow_mut().define(name, v);
+                }
+                return Ok((arm, arm_env));
+            }
+        }
+        Err(RuntimeError::new(
+            ErrorCode::NoMatchingArm,
+            format!("No match arm matched {}", value.display()),
+            Some(subject.span.clone()),
+        ))
+    }
+
+    fn match_pattern(&self, pattern: &Pattern, value: &Value, bindings: &mut Vec<(String, Value)>) -> bool {
+        match (pattern, value) {
+            (Pattern::Wildcard, _) => true,
+            (Pattern::Binding(name), _) => {
+                bindings.push((name.clone(), value.clone()));
+                true
+            }
+            (Pattern::Literal(Expression::Number(n)), Value::Number(v)) => n == v,
+            (Pattern::Literal(Expression::Number(n)), Value::Sized(_, v)) => *n as i128 == *v,
+            (Pattern::Literal(Expression::StringLit(s)), Value::String(v)) => s == v,
+            (Pattern::Literal(Expression::Bool(b)), Value::Bool(v)) => b == v,
+            (
+                Pattern::Variant { enum_name, variant, fields },
+                Value::EnumVariant { enum_name: value_enum, variant_name, payload },
+            ) => {
+                enum_name == value_enum
+                    && variant == variant_name
+                    && fields.len() == payload.len()
+                    && fields.iter().zip(payload).all(|(f, v)| self.match_pattern(&f.node, v, bindings))
+            }
+            (Pattern::Struct { name, fields }, Value::Struct { name: value_name, fields: values }) => {
+                name == value_name
+                    && fields.iter().all(|(f_name, f_pattern)| {
+                        values.get(f_name).is_some_and(|v| self.match_pattern(&f_pattern.node, v, bindings))
+                    })
+            }
+            _ => false,
         }
     }
 
-    fn execute_block(&mut self, statements: &[Spanned<Statement>], env: Rc<RefCell<Environment>>) -> Result<ExecResult, String> {
+    fn execute_block(&mut self, statements: &[Spanned<Statement>], env: Rc<RefCell<Environment>>) -> Result<ExecResult, RuntimeError> {
         let block_env = Rc::new(RefCell::new(Environment::with_enclosing(env)));
         for stmt in statements {
             let res = self.execute(stmt, block_env.clone())?;
@@ -352,27 +733,126 @@ impl Interpreter {
         Ok(ExecResult::Ok)
     }
 
-    fn evaluate(&mut self, expr: &Spanned<Expression>, env: Rc<RefCell<Environment>>) -> Result<Value, String> {
+    /// Runs a block used as a value in a scope of its own and evaluates its result; see `split_value`.
+    fn evaluate_block(&mut self, block: &[Spanned<Statement>], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+        let (init, result) = split_value(block);
+        let block_env = Rc::new(RefCell::new(Environment::with_enclosing(env)));
+        for stmt in init {
+            let res = self.execute(stmt, block_env.clone())?;
+            if !matches!(res, ExecResult::Ok) {
+                self.leaving = Some(res);
+                return Err(RuntimeError::new(ErrorCode::Runtime, "Cannot leave a block outside any function or loop", None));
+            }
+        }
+        match result {
+            Some(result) => self.evaluate(result, block_env),
+            None => Ok(Value::Null),
+        }
+    }
+
+    fn evaluate(&mut self, expr: &Spanned<Expression>, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+        self.evaluate_expression(expr, env).map_err(|e| e.at(&expr.span))
+    }
+
+    fn evaluate_expression(&mut self, expr: &Spanned<Expression>, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
         match &expr.node {
-            Expression::Number(n) => Ok(Value::Number(*n)),
+            Expression::Number(n) => Ok(match self.sized_literals.get(&expr.span) {
+                Some(kind) => integer_value(*kind, *n as i128),
+                None => Value::Number(*n),
+            }),
+            Expression::Float(x) => Ok(Value::Float(*x)),
             Expression::StringLit(s) => Ok(Value::String(s.clone())),
+            Expression::Interpolated(parts) => {
+                let mut out = String::new();
+                for part in parts {
+                    match part {
+                        StringPart::Literal(s) => out.push_str(s),
+                        StringPart::Expr(e) => out.push_str(&self.evaluate(e, env.clone())?.display()),
+                    }
+                }
+                Ok(Value::String(out))
+            },
             Expression::Bool(b) => Ok(Value::Bool(*b)),
             Expression::Null => Ok(Value::Null),
             Expression::Identifier(name) => {
-                env.borrow().get(name).ok_or_else(|| format!("Undefined variable '{}'", name))
+                env.borrow().get(name).ok_or_else(|| RuntimeError::new(ErrorCode::UndefinedName, format!("Undefined variable '{}'", name), None))
             },
-            Expression::StructLiteral { name: _, fields } => {
+            Expression::StructLiteral { name, fields } => {
                 let mut vals = HashMap::new();
                 for (f_name, f_expr) in fields {
                     vals.insert(f_name.clone(), self.evaluate(f_expr, env.clone())?);
                 }
-                Ok(Value::Object(vals))
+                Ok(Value::Struct { name: name.clone(), fields: vals })
+            },
+            Expression::ListLiteral(items) => {
+                let mut vals = Vec::new();
+                for item in items {
+                    vals.push(self.evaluate(item, env.clone())?);
+                }
+                Ok(Value::List(Rc::new(RefCell::new(vals))))
+            },
+            Expression::MapLiteral(entries) => {
+                let mut vals: Vec<(String, Value)> = Vec::new();
+                for (k_expr, v_expr) in entries {
+                    let Value::String(key) = self.evaluate(k_expr, env.clone())? else {
+                        return Err("Map keys must be strings".to_string().into());
+                    };
+                    let val = self.evaluate(v_expr, env.clone())?;
+                    match vals.iter_mut().find(|(k, _)| *k == key) {
+                        Some((_, slot)) => *slot = val,
+                        None => vals.push((key, val)),
+                    }
+                }
+                Ok(Value::Map(Rc::new(RefCell::new(vals))))
+            },
+            Expression::Index { object, index } => {
+                let obj = self.evaluate(object, env.clone())?;
+                let idx = self.evaluate(index, env)?;
+                match (obj, idx) {
+                    (Value::List(items), Value::Number(i)) => {
+                        let items = items.borrow();
+                        usize::try_from(i).ok().and_then(|i| items.get(i)).cloned()
+                            .ok_or_else(|| format!("List index {} out of bounds for length {}", i, items.len()).into())
+                    }
+                    (Value::Map(entries), Value::String(key)) => {
+                        entries.borrow().iter().find(|(k, _)| *k == key).map(|(_, v)| v.clone())
+                            .ok_or_else(|| format!("Key '{}' not found in map", key).into())
+                    }
+                    (Value::Object(map) | Value::Struct { fields: map, .. }, Value::String(key)) => {
+                        map.get(&key).cloned().ok_or_else(|| RuntimeError::new(ErrorCode::UnknownMember, format!("Member '{}' not found", key), None))
+                    }
+                    (Value::List(_), _) => Err("List indices must be numbers".to_string().into()),
+                    (Value::Map(_), _) => Err("Map keys must be strings".to_string().into()),
+                    _ => Err("Only lists and maps can be indexed".to_string().into()),
+                }
+            },
+            Expression::Range { start, end } => {
+                let (from, to) = self.eval_range_bounds(start, end, env)?;
+                Ok(Value::List(Rc::new(RefCell::new((from..to).map(Value::Number).collect()))))
+            },
+            Expression::BinaryOp(left, op @ (Op::And | Op::Or), right) => {
+                let l = self.evaluate(left, env.clone())?;
+                if self.is_truthy(&l) == matches!(op, Op::Or) {
+                    return Ok(Value::Bool(matches!(op, Op::Or)));
+                }
+                let r = self.evaluate(right, env)?;
+                Ok(Value::Bool(self.is_truthy(&r)))
             },
             Expression::BinaryOp(left, op, right) => {
                 let l = self.evaluate(left, env.clone())?;
                 let r = self.evaluate(right, env.clone())?;
                 self.apply_op(l, op, r)
             },
+            Expression::Unary(op, operand) => {
+                let v = self.evaluate(operand, env)?;
+                match (op, v) {
+                    (UnaryOp::Neg, Value::Number(n)) => n.checked_neg().map(Value::Number).ok_or_else(|| ArithError::Overflow.into()),
+                    (UnaryOp::Neg, Value::Sized(kind, n)) => Ok(Value::Sized(kind, arith(kind, 0, &Op::Minus, n, Overflow::Checked)?)),
+                    (UnaryOp::Neg, Value::Float(x)) => Ok(Value::Float(-x)),
+                    (UnaryOp::Neg, _) => Err("Only numbers can be negated".to_string().into()),
+                    (UnaryOp::Not, v) => Ok(Value::Bool(!self.is_truthy(&v))),
+                }
+            },
             Expression::Call { func, args } => {
                 let callee = self.evaluate(func, env.clone())?;
                 let mut arg_vals = Vec::new();
@@ -380,53 +860,200 @@ impl Interpreter {
                     arg_vals.push(self.evaluate(arg, env.clone())?);
                 }
                 
-                match callee {
-                    Value::NativeFn(name) => self.call_native(&name, arg_vals),
-                    Value::BoundNativeFn { receiver, name } => self.call_bound_native(*receiver, &name, arg_vals),
-                    Value::Function { body, .. } => {
-                        let fn_env = Rc::new(RefCell::new(Environment::with_enclosing(self.globals.clone())));
-                        match self.execute_block(&body, fn_env)? {
-                            ExecResult::Return(v) => Ok(v),
-                            _ => Ok(Value::Null),
-                        }
-                    },
-                    _ => Err("Trying to call non-function".to_string()),
-                }
+                self.call_value(callee, arg_vals, expr.span.clone())
             },
             Expression::MemberAccess { object, member } => {
                 let obj = self.evaluate(object, env)?;
                 if let Value::Object(map) = obj {
-                    map.get(member).cloned().ok_or_else(|| format!("Member '{}' not found", member))
+                    map.get(member).cloned().ok_or_else(|| RuntimeError::new(ErrorCode::UnknownMember, format!("Member '{}' not found", member), None))
+                } else if let Value::Struct { name, fields } = &obj {
+                    if let Some(v) = fields.get(member) {
+                        return Ok(v.clone());
+                    }
+                    let method = self.methods.get(name).and_then(|m| m.get(member)).cloned()
+                        .ok_or_else(|| RuntimeError::new(ErrorCode::UnknownMember, format!("Struct '{}' has no member '{}'", name, member), None))?;
+                    Ok(Value::BoundMethod { receiver: Box::new(obj.clone()), method: Box::new(method) })
+                } else if let Value::Type { name, .. } = &obj {
+                    self.methods.get(name).and_then(|m| m.get(member)).cloned()
+                        .ok_or_else(|| RuntimeError::new(ErrorCode::UnknownMember, format!("Type '{}' has no associated function '{}'", name, member), None))
+                } else if let Value::List(_) = obj {
+                    match member.as_str() {
+                        "len" | "push" | "pop" | "map" | "filter" | "each" => Ok(Value::BoundNativeFn {
+                            receiver: Box::new(obj.clone()),
+                            name: format!("list.{}", member),
+                        }),
+                        _ => Err(format!("List has no member '{}'", member).into()),
+                    }
+                } else if let Value::Map(_) = obj {
+                    match member.as_str() {
+                        "len" | "keys" | "values" | "contains" | "remove" => Ok(Value::BoundNativeFn {
+                            receiver: Box::new(obj.clone()),
+                            name: format!("map.{}", member),
+                        }),
+                        _ => Err(format!("Map has no member '{}'", member).into()),
+                    }
                 } else if let Value::Model { .. } = obj {
                     match member.as_str() {
                         "train" => Ok(Value::BoundNativeFn { receiver: Box::new(obj.clone()), name: "model.train".to_string() }),
                         "save" => Ok(Value::BoundNativeFn { receiver: Box::new(obj.clone()), name: "model.save".to_string() }),
                         "think" => Ok(Value::BoundNativeFn { receiver: Box::new(obj.clone()), name: "model.think".to_string() }),
-                        _ => Err(format!("Model has no member '{}'", member)),
+                        _ => Err(format!("Model has no member '{}'", member).into()),
                     }
                 } else {
-                    Err("Only objects and models have members".to_string())
+                    Err("Only objects and models have members".to_string().into())
                 }
             },
+            Expression::Lambda { params, body, .. } => Ok(Value::Function {
+                name: "<lambda>".to_string(),
+                params: params.clone(),
+                body: body.clone(),
+                closure: env,
+            }),
+            Expression::Try(operand) => {
+                let value = self.evaluate(operand, env)?;
+                let Value::EnumVariant { enum_name, variant_name, payload } = &value else {
+                    return Err("The ? operator expects a Result or Option".to_string().into());
+                };
+                match (enum_name.as_str(), variant_name.as_str()) {
+                    ("Result", "Ok") | ("Option", "Some") => Ok(payload[0].clone()),
+                    ("Result", "Err") | ("Option", "None") => {
+                        let message = match payload.first() {
+                            Some(error) => format!("Uncaught error: {}", error.display()),
+                            None => format!("Uncaught error: {}.{}", enum_name, variant_name),
+                        };
+                        self.propagating = Some(value);
+                        Err(RuntimeError::new(ErrorCode::UncaughtError, message, None))
+                    }
+                    _ => Err("The ? operator expects a Result or Option".to_string().into()),
+                }
+            }
+            Expression::If { cond, then_block, else_block } => {
+                let cond_val = self.evaluate(cond, env.clone())?;
+                if self.is_truthy(&cond_val) {
+                    self.evaluate_block(then_block, env)
+                } else if let Some(else_block) = else_block {
+                    self.evaluate_block(else_block, env)
+                } else {
+                    Ok(Value::Null)
+                }
+            }
+            Expression::Match { subject, arms } => {
+                let (arm, arm_env) = self.select_arm(subject, arms, env)?;
+                self.evaluate_block(&arm.body, arm_env)
+            }
+            Expression::Block(body) => self.evaluate_block(body, env),
+            Expression::Error => Err(RuntimeError::new(ErrorCode::Syntax, "Cannot evaluate an expression that failed to parse", None)),
         }
     }
 
-    fn apply_op(&self, left: Value, op: &Op, right: Value) -> Result<Value, String> {
+    fn eval_range_bounds(&mut self, start: &Spanned<Expression>, end: &Spanned<Expression>, env: Rc<RefCell<Environment>>) -> Result<(i64, i64), RuntimeError> {
+        match (self.evaluate(start, env.clone())?, self.evaluate(end, env)?) {
+            (Value::Number(from), Value::Number(to)) => Ok((from, to)),
+            _ => Err("Range bounds must be numbers".to_string().into()),
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, args: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+        match callee {
+            Value::NativeFn(name) => self.call_native(&name, args).map_err(RuntimeError::from),
+            Value::BoundNativeFn { receiver, name } => self.call_bound_native(*receiver, &name, args, span),
+            func @ Value::Function { .. } => self.call_function(func, args, span),
+            Value::BoundMethod { receiver, method } => {
+                let mut full_args = Vec::with_capacity(args.len() + 1);
+                full_args.push(*receiver);
+                full_args.extend(args);
+                self.call_function(*method, full_args, span)
+            }
+            Value::VariantConstructor { enum_name, variant_name, arity } => {
+                if args.len() != arity {
+                    return Err(RuntimeError::new(
+                        ErrorCode::ArityMismatch,
+                        format!("Variant '{}.{}' expects {} value(s) but got {}", enum_name, variant_name, arity, args.len()),
+                        Some(span),
+                    ));
+                }
+                Ok(Value::EnumVariant { enum_name, variant_name, payload: args })
+            }
+            _ => Err("Trying to call non-function".to_string().into()),
+        }
+    }
+
+    fn call_function(&mut self, func: Value, args: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+        let Value::Function { name, params, body, closure } = func else {
+            return Err("Trying to call non-function".to_string().into());
+        };
+        if args.len() != params.len() {
+            return Err(RuntimeError::new(
+                ErrorCode::ArityMismatch,
+                format!("Function '{}' expects {} argument(s) but got {}", name, params.len(), args.len()),
+                Some(span),
+            ));
+        }
+        if self.call_depth >= self.max_call_depth {
+            return Err(RuntimeError::new(
+                ErrorCode::CallDepthExceeded,
+                format!("Maximum call depth of {} exceeded in '{}'", self.max_call_depth, name),
+                Some(span),
+            ));
+        }
+
+        let fn_env = Rc::new(RefCell::new(Environment::with_enclosing(closure)));
+        for ((p_name, _), arg) in params.iter().zip(args) {
+            fn_env.borrow_mut().define(p_name.clone(), arg);
+        }
+
+        self.call_depth += 1;
+        let result = self.execute_block(&body, fn_env);
+        self.call_depth -= 1;
+
+        // A `?` in the body returns its `Err` or `None` from this call.
+        if let Err(e) = &result {
+            if e.code == ErrorCode::UncaughtError {
+                if let Some(value) = self.propagating.take() {
+                    return Ok(value);
+                }
+            }
+        }
+        match result? {
+            ExecResult::Return(v) => Ok(v),
+            _ => Ok(Value::Null),
+        }
+    }
+
+    fn apply_op(&self, left: Value, op: &Op, right: Value) -> Result<Value, RuntimeError> {
+        if let (Some((lk, l)), Some((rk, r))) = (as_integer(&left), as_integer(&right)) {
+            return integer_op(lk, l, op, rk, r);
+        }
         match (left, op, right) {
-            (Value::Number(l), Op::Plus, Value::Number(r)) => Ok(Value::Number(l + r)),
-            (Value::Number(l), Op::Minus, Value::Number(r)) => Ok(Value::Number(l - r)),
-            (Value::Number(l), Op::Mul, Value::Number(r)) => Ok(Value::Number(l * r)),
-            (Value::Number(l), Op::Div, Value::Number(r)) => Ok(Value::Number(l / r)),
-            (Value::Number(l), Op::Gt, Value::Number(r)) => Ok(Value::Bool(l > r)),
-            (Value::Number(l), Op::Lt, Value::Number(r)) => Ok(Value::Bool(l < r)),
-            (Value::Number(l), Op::Gte, Value::Number(r)) => Ok(Value::Bool(l >= r)),
-            (Value::Number(l), Op::Lte, Value::Number(r)) => Ok(Value::Bool(l <= r)),
-            (Value::Number(l), Op::Eq, Value::Number(r)) => Ok(Value::Bool(l == r)),
-            (Value::Number(l), Op::NotEq, Value::Number(r)) => Ok(Value::Bool(l != r)),
-            (Value::EnumVariant { enum_name: e1, variant_name: v1 }, Op::Eq, Value::EnumVariant { enum_name: e2, variant_name: v2 }) => {
-                Ok(Value::Bool(e1 == e2 && v1 == v2))
+            (Value::Float(l), op, Value::Float(r)) => match op {
+                Op::Plus => Ok(Value::Float(l + r)),
+                Op::Minus => Ok(Value::Float(l - r)),
+                Op::Mul => Ok(Value::Float(l * r)),
+                Op::Div => Ok(Value::Float(l / r)),
+                Op::Mod => Ok(Value::Float(l % r)),
+                Op::Gt => Ok(Value::Bool(l > r)),
+                Op::Lt => Ok(Value::Bool(l < r)),
+                Op::Gte => Ok(Value::Bool(l >= r)),
+                Op::Lte => Ok(Value::Bool(l <= r)),
+                Op::Eq => Ok(Value::Bool(l == r)),
+                Op::NotEq => Ok(Value::Bool(l != r)),
+                _ => Err("Bitwise and logical operators are not defined for floats".to_string().into()),
+            },
+            (Value::Number(_) | Value::Sized(..), _, Value::Float(_)) | (Value::Float(_), _, Value::Number(_) | Value::Sized(..)) => {
+                Err("Cannot mix int and float operands; convert one with float() or int()".to_string().into())
+            }
+            (Value::String(l), op, Value::String(r)) => match op {
+                Op::Plus => Ok(Value::String(l + &r)),
+                Op::Eq => Ok(Value::Bool(l == r)),
+                Op::NotEq => Ok(Value::Bool(l != r)),
+                Op::Gt => Ok(Value::Bool(l > r)),
+                Op::Lt => Ok(Value::Bool(l < r)),
+                Op::Gte => Ok(Value::Bool(l >= r)),
+                Op::Lte => Ok(Value::Bool(l <= r)),
+                _ => Err("Strings only support + and comparisons".to_string().into()),
             },
-            _ => Err("Invalid operation".to_string()),
+            (l @ Value::EnumVariant { .. }, Op::Eq, r @ Value::EnumVariant { .. }) => Ok(Value::Bool(l == r)),
+            _ => Err("Invalid operation".to_string().into()),
         }
     }
 
@@ -434,6 +1061,8 @@ impl Interpreter {
         match val {
             Value::Bool(b) => *b,
             Value::Number(n) => *n != 0,
+            Value::Sized(_, n) => *n != 0,
+            Value::Float(x) => *x != 0.0,
             Value::Null => false,
             _ => true,
         }
@@ -447,18 +1076,15 @@ impl Interpreter {
                 if let Some(i) = n.as_i64() {
                     Value::Number(i)
                 } else if let Some(f) = n.as_f64() {
-                    Value::Number(f as i64) // eMo currently only supports i64 numbers? I should check.
+                    Value::Float(f)
                 } else {
                     Value::Null
                 }
             }
             serde_json::Value::String(s) => Value::String(s),
             serde_json::Value::Array(a) => {
-                let mut map = HashMap::new();
-                for (i, v) in a.into_iter().enumerate() {
-                    map.insert(i.to_string(), self.json_to_value(v));
-                }
-                Value::Object(map)
+                let items = a.into_iter().map(|v| self.json_to_value(v)).collect();
+                Value::List(Rc::new(RefCell::new(items)))
             }
             serde_json::Value::Object(o) => {
                 let mut map = HashMap::new();
@@ -475,19 +1101,85 @@ impl Interpreter {
             Value::Null => serde_json::Value::Null,
             Value::Bool(b) => serde_json::Value::Bool(b),
             Value::Number(n) => serde_json::Value::Number(n.into()),
+            // Every sized value fits in an i64 or a u64.
+            Value::Sized(_, n) => i64::try_from(n).map(serde_json::Number::from)
+                .or_else(|_| u64::try_from(n).map(serde_json::Number::from))
+                .map_or(serde_json::Value::Null, serde_json::Value::Number),
+            Value::Float(x) => serde_json::Number::from_f64(x).map_or(serde_json::Value::Null, serde_json::Value::Number),
             Value::String(s) => serde_json::Value::String(s),
-            Value::Object(map) => {
+            Value::Object(map) | Value::Struct { fields: map, .. } => {
                 let mut obj = serde_json::Map::new();
                 for (k, val) in map {
                     obj.insert(k, self.value_to_json(val));
                 }
                 serde_json::Value::Object(obj)
             }
+            Value::List(items) => {
+                serde_json::Value::Array(items.borrow().iter().map(|v| self.value_to_json(v.clone())).collect())
+            }
+            Value::Map(entries) => {
+                let mut obj = serde_json::Map::new();
+                for (k, val) in entries.borrow().iter() {
+                    obj.insert(k.clone(), self.value_to_json(val.clone()));
+                }
+                serde_json::Value::Object(obj)
+            }
             _ => serde_json::Value::Null,
         }
     }
 
+    /// The `str` module. Case mapping and trimming are ASCII-only and `len` counts
+    /// characters, matching the `emo_str_*` helpers in emo_runtime.h.
+    fn call_str(&self, name: &str, args: &[Value]) -> Result<Value, String> {
+        let strs: Vec<&str> = args.iter().map(|a| match a {
+            Value::String(s) => Ok(s.as_str()),
+            other => Err(format!("{} expects strings but got {}", name, other.display())),
+        }).collect::<Result<_, _>>()?;
+        let arity = match name {
+            "str.split" | "str.contains" => 2,
+            "str.replace" => 3,
+            _ => 1,
+        };
+        if strs.len() != arity {
+            return Err(format!("{} expects {} argument(s) but got {}", name, arity, strs.len()));
+        }
+        match name {
+            "str.len" => Ok(Value::Number(strs[0].chars().count() as i64)),
+            "str.split" => {
+                if strs[1].is_empty() {
+                    return Err("str.split separator must not be empty".to_string());
+                }
+                let parts = strs[0].split(strs[1]).map(|p| Value::String(p.to_string())).collect();
+                Ok(Value::List(Rc::new(RefCell::new(parts))))
+            }
+            "str.trim" => Ok(Value::String(strs[0].trim_matches(|c: char| c.is_ascii_whitespace()).to_string())),
+            "str.contains" => Ok(Value::Bool(strs[0].contains(strs[1]))),
+            "str.replace" => {
+                if strs[1].is_empty() {
+                    return Err("str.replace pattern must not be empty".to_string());
+                }
+                Ok(Value::String(strs[0].replace(strs[1], strs[2])))
+            }
+            "str.upper" => Ok(Value::String(strs[0].to_ascii_uppercase())),
+            "str.lower" => Ok(Value::String(strs[0].to_ascii_lowercase())),
+            _ => Ok(match strs[0].parse() {
+                Ok(n) => result_ok(Value::Number(n)),
+                Err(_) => result_err(format!("str.to_int: '{}' is not an integer", strs[0])),
+            }),
+        }
+    }
+
     fn call_native(&self, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        if let Some(kind) = IntKind::from_name(name) {
+            return convert_int(kind, name, args.first());
+        }
+        if let Some((op, overflow)) = overflow_helper(name) {
+            return match (args.first().and_then(as_integer), args.get(1).and_then(as_integer)) {
+                // Wrapping and saturating arithmetic cannot fail.
+                (Some((lk, l)), Some((rk, r))) if lk == rk => Ok(integer_value(lk, arith(lk, l, &op, r, overflow).unwrap_or_default())),
+                _ => Err(format!("{} expects two integers of the same type", name)),
+            };
+        }
         match name {
             "mind.spawn_model" => {
                 let model_type = if let Some(Value::String(s)) = args.get(0) { s.clone() } else { "Generic".to_string() };
@@ -523,68 +1215,55 @@ impl Interpreter {
             "sys.log" | "joy.say" => {
                 let output = args.iter().map(|v| {
                     match v {
-                        Value::String(s) => s.clone(),
-                        Value::Number(n) => n.to_string(),
-                        Value::Pointer(p) => format!("0x{:x}", p),
+                        Value::String(_) | Value::Number(_) | Value::Sized(..) | Value::Float(_) | Value::Pointer(_) | Value::List(_) | Value::Map(_) => v.display(),
                         _ => format!("{:?}", v),
                     }
                 }).collect::<Vec<_>>().join(" ");
                 println!("[STDOUT] {}", output);
                 Ok(Value::Null)
             },
-            "math.sqrt" => {
-                if let Some(Value::Number(n)) = args.get(0) {
-                    Ok(Value::Number((*n as f64).sqrt() as i64))
-                } else {
-                    Err("math.sqrt expects a number".to_string())
-                }
+            "math.sqrt" | "math.sin" | "math.cos" => {
+                let Some(x) = args.first().and_then(number_as_f64) else {
+                    return Err(format!("{} expects a number", name));
+                };
+                Ok(Value::Float(match name {
+                    "math.sqrt" => x.sqrt(),
+                    "math.sin" => x.sin(),
+                    _ => x.cos(),
+                }))
             },
             "math.pow" => {
-                if let (Some(Value::Number(base)), Some(Value::Number(exp))) = (args.get(0), args.get(1)) {
-                    Ok(Value::Number((*base as f64).powf(*exp as f64) as i64))
+                if let (Some(base), Some(exp)) = (args.first().and_then(number_as_f64), args.get(1).and_then(number_as_f64)) {
+                    Ok(Value::Float(base.powf(exp)))
                 } else {
                     Err("math.pow expects two numbers".to_string())
                 }
             },
-            "math.sin" => {
-                if let Some(Value::Number(n)) = args.get(0) {
-                    Ok(Value::Number((*n as f64).to_radians().sin() as i64))
-                } else {
-                    Err("math.sin expects a number".to_string())
-                }
+            "math.abs" => match args.first() {
+                Some(Value::Number(n)) => Ok(Value::Number(n.abs())),
+                Some(Value::Float(x)) => Ok(Value::Float(x.abs())),
+                _ => Err("math.abs expects a number".to_string()),
             },
-            "math.cos" => {
-                if let Some(Value::Number(n)) = args.get(0) {
-                    Ok(Value::Number((*n as f64).to_radians().cos() as i64))
-                } else {
-                    Err("math.cos expects a number".to_string())
+            "math.min" | "math.max" => {
+                let pick_min = name == "math.min";
+                match (args.first(), args.get(1)) {
+                    (Some(Value::Number(a)), Some(Value::Number(b))) => Ok(Value::Number(if pick_min { *a.min(b) } else { *a.max(b) })),
+                    (Some(Value::Float(a)), Some(Value::Float(b))) => Ok(Value::Float(if pick_min { a.min(*b) } else { a.max(*b) })),
+                    _ => Err(format!("{} expects two numbers of the same type", name)),
                 }
             },
-            "math.abs" => {
-                if let Some(Value::Number(n)) = args.get(0) {
-                    Ok(Value::Number(n.abs()))
-                } else {
-                    Err("math.abs expects a number".to_string())
-                }
-            },
-            "math.min" => {
-                if let (Some(Value::Number(a)), Some(Value::Number(b))) = (args.get(0), args.get(1)) {
-                    Ok(Value::Number(*a.min(b)))
-                } else {
-                    Err("math.min expects two numbers".to_string())
-                }
-            },
-            "math.max" => {
-                if let (Some(Value::Number(a)), Some(Value::Number(b))) = (args.get(0), args.get(1)) {
-                    Ok(Value::Number(*a.max(b)))
-                } else {
-                    Err("math.max expects two numbers".to_string())
-                }
+            "float" => args.first().and_then(number_as_f64).map(Value::Float).ok_or_else(|| "float expects a number".to_string()),
+            "int" | "i64" => match args.first() {
+                // Truncates toward zero, like a C cast.
+                Some(Value::Float(x)) => Ok(Value::Number(*x as i64)),
+                value => convert_int(IntKind::I64, name, value),
             },
             "json.parse" => {
                 if let Some(Value::String(s)) = args.get(0) {
-                    let j: serde_json::Value = serde_json::from_str(s).map_err(|e| format!("JSON Error: {}", e))?;
-                    Ok(self.json_to_value(j))
+                    Ok(match serde_json::from_str(s) {
+                        Ok(j) => result_ok(self.json_to_value(j)),
+                        Err(e) => result_err(format!("JSON Error: {}", e)),
+                    })
                 } else {
                     Err("json.parse expects a string".to_string())
                 }
@@ -640,16 +1319,10 @@ impl Interpreter {
             },
             "net.fetch" => {
                 if let Some(Value::String(url)) = args.get(0) {
-                    match reqwest::blocking::get(url) {
-                        Ok(response) => {
-                            let content = response.text().map_err(|e| format!("Net Error: {}", e))?;
-                            Ok(Value::String(content))
-                        },
-                        Err(_) => {
-                            println!("[NET] Warning: Could not reach {}. Using cached/placeholder response.", url);
-                            Ok(Value::String("<html>eMo Offline Mode</html>".to_string()))
-                        }
-                    }
+                    Ok(match reqwest::blocking::get(url).and_then(|response| response.text()) {
+                        Ok(content) => result_ok(Value::String(content)),
+                        Err(e) => result_err(format!("Net Error: {}", e)),
+                    })
                 } else {
                     Err("net.fetch expects a string URL".to_string())
                 }
@@ -686,15 +1359,15 @@ impl Interpreter {
                 }
             },
             "os.args" => {
-                let mut map = HashMap::new();
-                for (i, arg) in std::env::args().enumerate() {
-                    map.insert(i.to_string(), Value::String(arg));
-                }
-                Ok(Value::Object(map))
+                let args = std::env::args().map(Value::String).collect();
+                Ok(Value::List(Rc::new(RefCell::new(args))))
             },
             "os.name" => {
                 Ok(Value::String(std::env::consts::OS.to_string()))
             },
+            "str.len" | "str.split" | "str.trim" | "str.contains" | "str.replace" | "str.upper" | "str.lower" | "str.to_int" => {
+                self.call_str(name, &args)
+            },
             "random.int" => {
                 let min = if let Some(Value::Number(n)) = args.get(0) { *n } else { 0 };
                 let max = if let Some(Value::Number(n)) = args.get(1) { *n } else { 100 };
@@ -726,29 +1399,35 @@ impl Interpreter {
             },
             "sys.read_file" => {
                 if let Some(Value::String(path)) = args.get(0) {
-                    let content = fs::read_to_string(path).map_err(|e| format!("IO Error: {}", e))?;
-                    Ok(Value::String(content))
+                    Ok(match fs::read_to_string(path) {
+                        Ok(content) => result_ok(Value::String(content)),
+                        Err(e) => result_err(format!("IO Error: {}", e)),
+                    })
                 } else {
                     Err("sys.read_file expects a string argument".to_string())
                 }
             },
             "sys.write_file" => {
                 if let (Some(Value::String(path)), Some(Value::String(content))) = (args.get(0), args.get(1)) {
-                    fs::write(path, content).map_err(|e| format!("IO Error: {}", e))?;
-                    Ok(Value::Null)
+                    Ok(match fs::write(path, content) {
+                        Ok(()) => result_ok(Value::Null),
+                        Err(e) => result_err(format!("IO Error: {}", e)),
+                    })
                 } else {
                     Err("sys.write_file expects two string arguments (path, content)".to_string())
                 }
             },
             "sys.append_file" => {
                 if let (Some(Value::String(path)), Some(Value::String(content))) = (args.get(0), args.get(1)) {
-                    let mut file = fs::OpenOptions::new()
+                    let appended = fs::OpenOptions::new()
                         .append(true)
                         .create(true)
                         .open(path)
-                        .map_err(|e| format!("IO Error: {}", e))?;
-                    file.write_all(content.as_bytes()).map_err(|e| format!("IO Error: {}", e))?;
-                    Ok(Value::Null)
+                        .and_then(|mut file| file.write_all(content.as_bytes()));
+                    Ok(match appended {
+                        Ok(()) => result_ok(Value::Null),
+                        Err(e) => result_err(format!("IO Error: {}", e)),
+                    })
                 } else {
                     Err("sys.append_file expects two string arguments (path, content)".to_string())
                 }
@@ -836,15 +1515,71 @@ impl Interpreter {
         }
     }
 
-    fn call_bound_native(&self, receiver: Value, name: &str, args: Vec<Value>) -> Result<Value, String> {
+    fn call_bound_native(&mut self, receiver: Value, name: &str, args: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
         match (receiver, name) {
+            (Value::List(items), "list.len") => Ok(Value::Number(items.borrow().len() as i64)),
+            (Value::List(items), "list.push") => {
+                if let Some(val) = args.into_iter().next() {
+                    items.borrow_mut().push(val);
+                    Ok(Value::Null)
+                } else {
+                    Err("list.push expects a value".to_string().into())
+                }
+            },
+            (Value::List(items), "list.pop") => items.borrow_mut().pop().ok_or_else(|| "pop from empty list".to_string().into()),
+            (Value::List(items), "list.map" | "list.filter" | "list.each") => {
+                let Some(callback) = args.into_iter().next() else {
+                    return Err(format!("{} expects a function", name).into());
+                };
+                // Snapshot so the callback may safely mutate the list it iterates.
+                let snapshot = items.borrow().clone();
+                let mut result = Vec::new();
+                for item in snapshot {
+                    let out = self.call_value(callback.clone(), vec![item.clone()], span.clone())?;
+                    match name {
+                        "list.map" => result.push(out),
+                        "list.filter" if self.is_truthy(&out) => result.push(item),
+                        _ => {}
+                    }
+                }
+                if name == "list.each" {
+                    Ok(Value::Null)
+                } else {
+                    Ok(Value::List(Rc::new(RefCell::new(result))))
+                }
+            },
+            (Value::Map(entries), "map.len") => Ok(Value::Number(entries.borrow().len() as i64)),
+            (Value::Map(entries), "map.keys") => {
+                let keys = entries.borrow().iter().map(|(k, _)| Value::String(k.clone())).collect();
+                Ok(Value::List(Rc::new(RefCell::new(keys))))
+            },
+            (Value::Map(entries), "map.values") => {
+                let values = entries.borrow().iter().map(|(_, v)| v.clone()).collect();
+                Ok(Value::List(Rc::new(RefCell::new(values))))
+            },
+            (Value::Map(entries), "map.contains") => {
+                if let Some(Value::String(key)) = args.first() {
+                    Ok(Value::Bool(entries.borrow().iter().any(|(k, _)| k == key)))
+                } else {
+                    Err("map.contains expects a string key".to_string().into())
+                }
+            },
+            (Value::Map(entries), "map.remove") => {
+                if let Some(Value::String(key)) = args.first() {
+                    let mut entries = entries.borrow_mut();
+                    let removed = entries.iter().position(|(k, _)| k == key).map(|i| entries.remove(i).1);
+                    Ok(removed.unwrap_or(Value::Null))
+                } else {
+                    Err("map.remove expects a string key".to_string().into())
+                }
+            },
             (Value::Model { model_type, state, .. }, "model.train") => {
                 if let Some(Value::String(path)) = args.get(0) {
                     println!("[MIND] Training {} on {}...", model_type, path);
                     state.borrow_mut().trained_data.push(path.clone());
                     Ok(Value::Null)
                 } else {
-                    Err("model.train expects a string path".to_string())
+                    Err("model.train expects a string path".to_string().into())
                 }
             },
             (Value::Model { model_type: _, .. }, "model.save") => {
@@ -852,17 +1587,17 @@ impl Interpreter {
                     println!("[MIND] Saving model to {}...", path);
                     Ok(Value::Null)
                 } else {
-                    Err("model.save expects a string path".to_string())
+                    Err("model.save expects a string path".to_string().into())
                 }
             },
             (Value::Model { model_type, .. }, "model.think") => {
                 if let Some(Value::String(prompt)) = args.get(0) {
                     Ok(Value::String(format!("[{}] After processing your request '{}', I conclude that eMo is the future.", model_type, prompt)))
                 } else {
-                    Err("model.think expects a string prompt".to_string())
+                    Err("model.think expects a string prompt".to_string().into())
                 }
             },
-            _ => Err(format!("Unknown bound native function {}", name)),
+            _ => Err(format!("Unknown bound native function {}", name).into()),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,559 @@
+//! The typed intermediate representation that code generation works from.
+//!
+//! `lowering` builds it from the checked AST. Every value in it has a resolved type, every
+//! intermediate result is held in a named temporary, literals are values rather than source
+//! text and lambdas are lifted to functions of their own. Control flow stays structured
+//! (blocks, `if`, `loop`, `match`), so a backend can print it back as nested code.
+
+use crate::ast::{Op, UnaryOp};
+use crate::numeric::IntKind;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    /// A fixed-width integer; never `IntKind::I64`, which is `Int`.
+    Sized(IntKind),
+    Float,
+    Str,
+    Bool,
+    Void,
+    Struct(String, Vec<Type>), // name and type arguments
+    Enum(String, Vec<Type>),
+    List(Box<Type>),
+    Map(Box<Type>), // keys are always Str
+    Function(Vec<Type>, Box<Type>),
+    /// A type parameter; only found in the members of a generic `TypeDef`.
+    Param(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    /// Struct and enum declarations, generic ones with their type parameters left in.
+    pub types: Vec<TypeDef>,
+    /// Variables declared by the script's top-level code.
+    pub globals: Vec<Var>,
+    /// Top-level functions, `impl` methods and lifted lambdas.
+    pub functions: Vec<Function>,
+    /// The script's top-level code, which runs before `main`.
+    pub init: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TypeDef {
+    pub name: String,
+    pub type_params: Vec<String>,
+    pub kind: TypeDefKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum TypeDefKind {
+    Struct(Vec<(String, Type)>),
+    /// Variants and their payload types.
+    Enum(Vec<(String, Vec<Type>)>),
+}
+
+impl TypeDef {
+    /// Enums without type parameters or payloads, which need no tag and union.
+    pub fn is_plain_enum(&self) -> bool {
+        matches!(&self.kind, TypeDefKind::Enum(variants) if self.type_params.is_empty() && variants.iter().all(|(_, payload)| payload.is_empty()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    /// Unique in the program: methods are `Struct_method` and lambdas `__emo_lambda_N`.
+    pub name: String,
+    pub params: Vec<Var>,
+    pub ret: Type,
+    pub body: Vec<Stmt>,
+    /// Declared `pub`, or a method of a `pub` struct.
+    pub public: bool,
+    pub kind: FunctionKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum FunctionKind {
+    Plain,
+    /// A method taking `self`, its first parameter, by reference, so that it can change it.
+    Method,
+    /// A lifted lambda, called with the values of the variables it captured.
+    Lambda { captures: Vec<Var> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Var {
+    pub name: String,
+    pub ty: Type,
+    pub scope: Scope,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// A parameter, local variable or temporary of the enclosing function.
+    Local,
+    /// A variable of the script's top-level code.
+    Global,
+    /// A variable captured by the enclosing lambda, read from its environment.
+    Captured,
+    /// A mutable local variable that lambdas capture, kept in a heap cell they share with
+    /// the function so that each sees what the others assign.
+    Cell,
+    /// A `Cell` captured by the enclosing lambda, whose environment holds the cell.
+    CapturedCell,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Const {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Null,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Const(Const),
+    Var(Var),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Callee {
+    /// A top-level function or a method that does not take `self`.
+    Function(String),
+    /// A method taking `self`, which is the first argument and must be a variable.
+    Method(String),
+    /// A closure value.
+    Closure(Operand),
+    /// A function of a native module, such as `math.sqrt`.
+    Native(String, String),
+    /// A list or map method; the collection is the first argument. `iter` is the snapshot
+    /// a `for` loop walks: the items of a list or the keys of a map.
+    Collection(String),
+    /// `wrapping_add` and the other overflow helpers.
+    Overflow(String),
+    /// A function of the backend's runtime, such as the model functions of `mind`.
+    Extern(String),
+}
+
+/// A computation whose result is stored in a variable or discarded. Values such as struct
+/// literals and conversions take their type from the variable they are stored in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rvalue {
+    Use(Operand),
+    /// `&&` and `||` only appear with operands that need no code of their own to evaluate.
+    Binary(Op, Operand, Operand),
+    Unary(UnaryOp, Operand),
+    /// A numeric conversion such as `float(x)` or `u8(x)`.
+    Convert(Operand),
+    Call(Callee, Vec<Operand>),
+    Struct(Vec<(String, Operand)>),
+    /// An enum variant and its payload.
+    Variant(String, Vec<Operand>),
+    Field(Operand, String),
+    /// Whether an enum value is the given variant.
+    IsVariant(Operand, String),
+    /// Field `n` of the payload of an enum value known to be the given variant.
+    Payload(Operand, String, usize),
+    List(Vec<Operand>),
+    Map(Vec<(Operand, Operand)>),
+    Index(Operand, Operand),
+    Range(Operand, Operand),
+    /// The parts of an interpolated string, each converted to text and joined.
+    Interpolate(Vec<Operand>),
+    /// A lifted lambda with the values of its captures, in the order it lists them.
+    Closure(String, Vec<Operand>),
+    /// A top-level function used as a value.
+    FunctionRef(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    /// Declares a variable, with its initial value if it has one.
+    Let(Var, Option<Rvalue>),
+    Assign(Var, Rvalue),
+    IndexSet { object: Operand, index: Operand, value: Operand },
+    /// Evaluates a call for its effects.
+    Eval(Rvalue),
+    If { cond: Operand, then_block: Vec<Stmt>, else_block: Vec<Stmt> },
+    /// Runs `body` until a `Break`. `step` runs after the body and on `Continue`.
+    Loop { body: Vec<Stmt>, step: Vec<Stmt> },
+    Break,
+    Continue,
+    Return(Option<Operand>),
+    /// Runs the first arm whose pattern matches `subject`; a runtime error if none does.
+    Match { subject: Operand, arms: Vec<Arm> },
+    /// Runs `body`, where a `Throw` stores its error in `error` and runs `handler` instead.
+    Try { body: Vec<Stmt>, error: Var, handler: Vec<Stmt> },
+    Throw(Operand),
+    /// Stops the program with a runtime error.
+    Panic(String),
+    /// A nested scope.
+    Block(Vec<Stmt>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    /// Declares the variable and binds the matched value to it.
+    Binding(Var),
+    Literal(Const),
+    Variant { enum_name: String, variant: String, fields: Vec<Pattern> },
+    Struct { fields: Vec<(String, Pattern)> },
+}
+
+impl Type {
+    /// Identifier-safe spelling of a type argument, for instance names like
+    /// `Option_Pair_int_str`.
+    pub fn mangled(&self) -> String {
+        match self {
+            Type::Int => "int".to_string(),
+            Type::Sized(kind) => kind.name().to_string(),
+            Type::Float => "float".to_string(),
+            Type::Str => "str".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Void => "void".to_string(),
+            Type::Struct(name, args) | Type::Enum(name, args) => {
+                std::iter::once(name.clone()).chain(args.iter().map(Type::mangled)).collect::<Vec<_>>().join("_")
+            }
+            // Collections and closures share one C representation whatever their element types.
+            Type::List(_) => "list".to_string(),
+            Type::Map(_) => "map".to_string(),
+            Type::Function(..) => "fn".to_string(),
+            Type::Param(name) => name.clone(),
+        }
+    }
+}
+
+impl Const {
+    pub fn ty(&self) -> Type {
+        match self {
+            Const::Int(_) | Const::Null => Type::Int,
+            Const::Float(_) => Type::Float,
+            Const::Bool(_) => Type::Bool,
+            Const::Str(_) => Type::Str,
+        }
+    }
+}
+
+impl Operand {
+    pub fn ty(&self) -> Type {
+        match self {
+            Operand::Const(c) => c.ty(),
+            Operand::Var(v) => v.ty.clone(),
+        }
+    }
+}
+
+impl Rvalue {
+    /// The operands the value is computed from, including a called closure.
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Rvalue::Use(op) | Rvalue::Unary(_, op) | Rvalue::Convert(op) | Rvalue::Field(op, _) | Rvalue::IsVariant(op, _) | Rvalue::Payload(op, _, _) => vec![op],
+            Rvalue::Binary(_, left, right) | Rvalue::Index(left, right) | Rvalue::Range(left, right) => vec![left, right],
+            Rvalue::Call(callee, args) => {
+                let mut ops: Vec<&mut Operand> = args.iter_mut().collect();
+                if let Callee::Closure(f) = callee {
+                    ops.insert(0, f);
+                }
+                ops
+            }
+            Rvalue::Struct(fields) => fields.iter_mut().map(|(_, value)| value).collect(),
+            Rvalue::Variant(_, ops) | Rvalue::List(ops) | Rvalue::Interpolate(ops) | Rvalue::Closure(_, ops) => ops.iter_mut().collect(),
+            Rvalue::Map(entries) => entries.iter_mut().flat_map(|(key, value)| [key, value]).collect(),
+            Rvalue::FunctionRef(_) => Vec::new(),
+        }
+    }
+}
+
+impl Stmt {
+    /// The value computed by a `Let`, `Assign` or `Eval`.
+    pub fn value(&self) -> Option<&Rvalue> {
+        match self {
+            Stmt::Let(_, Some(value)) | Stmt::Assign(_, value) | Stmt::Eval(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The operands the statement reads itself, not counting those of nested blocks.
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Stmt::Let(_, Some(value)) | Stmt::Assign(_, value) | Stmt::Eval(value) => value.operands_mut(),
+            Stmt::IndexSet { object, index, value } => vec![object, index, value],
+            Stmt::If { cond: op, .. } | Stmt::Match { subject: op, .. } | Stmt::Return(Some(op)) | Stmt::Throw(op) => vec![op],
+            _ => Vec::new(),
+        }
+    }
+
+    /// The blocks nested directly in the statement.
+    pub fn blocks(&self) -> Vec<&Vec<Stmt>> {
+        match self {
+            Stmt::If { then_block, else_block, .. } => vec![then_block, else_block],
+            Stmt::Loop { body, step } => vec![body, step],
+            Stmt::Match { arms, .. } => arms.iter().map(|arm| &arm.body).collect(),
+            Stmt::Try { body, handler, .. } => vec![body, handler],
+            Stmt::Block(body) => vec![body],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn blocks_mut(&mut self) -> Vec<&mut Vec<Stmt>> {
+        match self {
+            Stmt::If { then_block, else_block, .. } => vec![then_block, else_block],
+            Stmt::Loop { body, step } => vec![body, step],
+            Stmt::Match { arms, .. } => arms.iter_mut().map(|arm| &mut arm.body).collect(),
+            Stmt::Try { body, handler, .. } => vec![body, handler],
+            Stmt::Block(body) => vec![body],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Pattern {
+    /// The variables the pattern declares.
+    pub fn bindings(&self, out: &mut Vec<Var>) {
+        match self {
+            Pattern::Binding(var) => out.push(var.clone()),
+            Pattern::Variant { fields, .. } => fields.iter().for_each(|p| p.bindings(out)),
+            Pattern::Struct { fields } => fields.iter().for_each(|(_, p)| p.bindings(out)),
+            Pattern::Wildcard | Pattern::Literal(_) => {}
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |types: &[Type]| types.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(", ");
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Sized(kind) => write!(f, "{}", kind.name()),
+            Type::Float => write!(f, "float"),
+            Type::Str => write!(f, "str"),
+            Type::Bool => write!(f, "bool"),
+            Type::Void => write!(f, "void"),
+            Type::Struct(name, args) | Type::Enum(name, args) if args.is_empty() => write!(f, "{}", name),
+            Type::Struct(name, args) | Type::Enum(name, args) => write!(f, "{}<{}>", name, join(args)),
+            Type::List(elem) => write!(f, "[{}]", elem),
+            Type::Map(value) => write!(f, "{{str: {}}}", value),
+            Type::Function(params, ret) => write!(f, "fn({}) -> {}", join(params), ret),
+            Type::Param(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl fmt::Display for Const {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Const::Int(n) => write!(f, "{}", n),
+            Const::Float(x) => write!(f, "{:?}", x),
+            Const::Bool(b) => write!(f, "{}", b),
+            Const::Str(s) => write!(f, "{:?}", s),
+            Const::Null => write!(f, "null"),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Const(c) => write!(f, "{}", c),
+            Operand::Var(v) if matches!(v.scope, Scope::Captured | Scope::CapturedCell) => write!(f, "env.{}", v.name),
+            Operand::Var(v) => write!(f, "{}", v.name),
+        }
+    }
+}
+
+fn list<T: fmt::Display>(items: &[T]) -> String {
+    items.iter().map(|item| item.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn op_symbol(op: &Op) -> &'static str {
+    match op {
+        Op::Plus => "+",
+        Op::Minus => "-",
+        Op::Mul => "*",
+        Op::Div => "/",
+        Op::Mod => "%",
+        Op::Eq => "==",
+        Op::NotEq => "!=",
+        Op::Gt => ">",
+        Op::Lt => "<",
+        Op::Gte => ">=",
+        Op::Lte => "<=",
+        Op::And => "&&",
+        Op::Or => "||",
+        Op::BitAnd => "&",
+        Op::BitOr => "|",
+        Op::BitXor => "^",
+        Op::Shl => "<<",
+        Op::Shr => ">>",
+    }
+}
+
+impl fmt::Display for Callee {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Callee::Function(name) | Callee::Method(name) | Callee::Overflow(name) | Callee::Extern(name) => write!(f, "{}", name),
+            Callee::Closure(callee) => write!(f, "{}", callee),
+            Callee::Native(module, name) => write!(f, "{}.{}", module, name),
+            Callee::Collection(method) => write!(f, ".{}", method),
+        }
+    }
+}
+
+impl fmt::Display for Rvalue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rvalue::Use(op) => write!(f, "{}", op),
+            Rvalue::Binary(op, left, right) => write!(f, "{} {} {}", left, op_symbol(op), right),
+            Rvalue::Unary(UnaryOp::Neg, operand) => write!(f, "-{}", operand),
+            Rvalue::Unary(UnaryOp::Not, operand) => write!(f, "!{}", operand),
+            Rvalue::Convert(operand) => write!(f, "convert {}", operand),
+            Rvalue::Call(callee, args) => write!(f, "call {}({})", callee, list(args)),
+            Rvalue::Struct(fields) => {
+                let fields: Vec<String> = fields.iter().map(|(name, value)| format!("{}: {}", name, value)).collect();
+                write!(f, "{{ {} }}", fields.join(", "))
+            }
+            Rvalue::Variant(variant, args) if args.is_empty() => write!(f, "{}", variant),
+            Rvalue::Variant(variant, args) => write!(f, "{}({})", variant, list(args)),
+            Rvalue::Field(object, field) => write!(f, "{}.{}", object, field),
+            Rvalue::IsVariant(value, variant) => write!(f, "{} is {}", value, variant),
+            Rvalue::Payload(value, variant, n) => write!(f, "{} as {}.{}", value, variant, n),
+            Rvalue::List(items) => write!(f, "[{}]", list(items)),
+            Rvalue::Map(entries) => {
+                let entries: Vec<String> = entries.iter().map(|(key, value)| format!("{}: {}", key, value)).collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+            Rvalue::Index(object, index) => write!(f, "{}[{}]", object, index),
+            Rvalue::Range(start, end) => write!(f, "{}..{}", start, end),
+            Rvalue::Interpolate(parts) => write!(f, "concat({})", list(parts)),
+            Rvalue::Closure(function, captures) => write!(f, "closure {}({})", function, list(captures)),
+            Rvalue::FunctionRef(function) => write!(f, "fn {}", function),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Binding(var) => write!(f, "{}: {}", var.name, var.ty),
+            Pattern::Literal(c) => write!(f, "{}", c),
+            Pattern::Variant { enum_name, variant, fields } if fields.is_empty() => write!(f, "{}.{}", enum_name, variant),
+            Pattern::Variant { enum_name, variant, fields } => write!(f, "{}.{}({})", enum_name, variant, list(fields)),
+            Pattern::Struct { fields } => {
+                let fields: Vec<String> = fields.iter().map(|(name, p)| format!("{}: {}", name, p)).collect();
+                write!(f, "{{ {} }}", fields.join(", "))
+            }
+        }
+    }
+}
+
+/// Writes `stmts` one per line, `depth` levels in, and nested blocks further in.
+fn write_block(f: &mut fmt::Formatter, stmts: &[Stmt], depth: usize) -> fmt::Result {
+    let indent = "    ".repeat(depth);
+    for stmt in stmts {
+        match stmt {
+            Stmt::Let(var, None) => writeln!(f, "{}let {}: {}", indent, var.name, var.ty)?,
+            Stmt::Let(var, Some(value)) if var.scope == Scope::Cell => writeln!(f, "{}let cell {}: {} = {}", indent, var.name, var.ty, value)?,
+            Stmt::Let(var, Some(value)) => writeln!(f, "{}let {}: {} = {}", indent, var.name, var.ty, value)?,
+            Stmt::Assign(var, value) => writeln!(f, "{}{} = {}", indent, Operand::Var(var.clone()), value)?,
+            Stmt::IndexSet { object, index, value } => writeln!(f, "{}{}[{}] = {}", indent, object, index, value)?,
+            Stmt::Eval(value) => writeln!(f, "{}{}", indent, value)?,
+            Stmt::If { cond, then_block, else_block } => {
+                writeln!(f, "{}if {} {{", indent, cond)?;
+                write_block(f, then_block, depth + 1)?;
+                if !else_block.is_empty() {
+                    writeln!(f, "{}}} else {{", indent)?;
+                    write_block(f, else_block, depth + 1)?;
+                }
+                writeln!(f, "{}}}", indent)?;
+            }
+            Stmt::Loop { body, step } => {
+                writeln!(f, "{}loop {{", indent)?;
+                write_block(f, body, depth + 1)?;
+                if !step.is_empty() {
+                    writeln!(f, "{}}} step {{", indent)?;
+                    write_block(f, step, depth + 1)?;
+                }
+                writeln!(f, "{}}}", indent)?;
+            }
+            Stmt::Break => writeln!(f, "{}break", indent)?,
+            Stmt::Continue => writeln!(f, "{}continue", indent)?,
+            Stmt::Return(None) => writeln!(f, "{}return", indent)?,
+            Stmt::Return(Some(value)) => writeln!(f, "{}return {}", indent, value)?,
+            Stmt::Match { subject, arms } => {
+                writeln!(f, "{}match {} {{", indent, subject)?;
+                for arm in arms {
+                    writeln!(f, "{}    {} => {{", indent, arm.pattern)?;
+                    write_block(f, &arm.body, depth + 2)?;
+                    writeln!(f, "{}    }}", indent)?;
+                }
+                writeln!(f, "{}}}", indent)?;
+            }
+            Stmt::Try { body, error, handler } => {
+                writeln!(f, "{}try {{", indent)?;
+                write_block(f, body, depth + 1)?;
+                writeln!(f, "{}}} catch {}: {} {{", indent, error.name, error.ty)?;
+                write_block(f, handler, depth + 1)?;
+                writeln!(f, "{}}}", indent)?;
+            }
+            Stmt::Throw(error) => writeln!(f, "{}throw {}", indent, error)?,
+            Stmt::Panic(message) => writeln!(f, "{}panic {:?}", indent, message)?,
+            Stmt::Block(body) => {
+                writeln!(f, "{}{{", indent)?;
+                write_block(f, body, depth + 1)?;
+                writeln!(f, "{}}}", indent)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for def in &self.types {
+            let params = if def.type_params.is_empty() { String::new() } else { format!("<{}>", def.type_params.join(", ")) };
+            match &def.kind {
+                TypeDefKind::Struct(fields) => {
+                    let fields: Vec<String> = fields.iter().map(|(name, t)| format!("{}: {}", name, t)).collect();
+                    writeln!(f, "struct {}{} {{ {} }}", def.name, params, fields.join(", "))?;
+                }
+                TypeDefKind::Enum(variants) => {
+                    let variants: Vec<String> = variants.iter()
+                        .map(|(name, payload)| if payload.is_empty() { name.clone() } else { format!("{}({})", name, list(payload)) })
+                        .collect();
+                    writeln!(f, "enum {}{} {{ {} }}", def.name, params, variants.join(", "))?;
+                }
+            }
+        }
+        for var in &self.globals {
+            writeln!(f, "global {}: {}", var.name, var.ty)?;
+        }
+        for function in &self.functions {
+            let params: Vec<String> = function.params.iter().map(|p| format!("{}: {}", p.name, p.ty)).collect();
+            let public = if function.public { "pub " } else { "" };
+            write!(f, "{}fn {}({}) -> {}", public, function.name, params.join(", "), function.ret)?;
+            if let FunctionKind::Lambda { captures } = &function.kind {
+                let captures: Vec<String> = captures.iter().map(|c| format!("{}: {}", c.name, c.ty)).collect();
+                write!(f, " captures({})", captures.join(", "))?;
+            }
+            writeln!(f, " {{")?;
+            write_block(f, &function.body, 1)?;
+            writeln!(f, "}}")?;
+        }
+        if !self.init.is_empty() {
+            writeln!(f, "init {{")?;
+            write_block(f, &self.init, 1)?;
+            writeln!(f, "}}")?;
+        }
+        Ok(())
+    }
+}
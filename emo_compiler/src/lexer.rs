@@ -9,6 +9,28 @@ pub struct SpannedToken {
     pub span: Span,
 }
 
+/// Resolves backslash escapes in the body of a string literal.
+pub fn unescape(s: &str) -> String {
+    let mut res = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => res.push('\n'),
+                Some('t') => res.push('\t'),
+                Some('r') => res.push('\r'),
+                Some('\\') => res.push('\\'),
+                Some('"') => res.push('"'),
+                Some(other) => res.push(other),
+                None => res.push('\\'),
+            }
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
 #[derive(Logos, Debug, PartialEq, Clone)]
 #[logos(skip r"[ \t\n\f]+")] // Skip whitespace
 #[logos(skip r"//[^\n]*")]   // Skip comments
@@ -20,6 +42,8 @@ pub enum Token {
     Fn,
     #[token("let")]
     Let,
+    #[token("mut")]
+    Mut,
     #[token("set")]
     Set,
     #[token("to")]
@@ -34,6 +58,10 @@ pub enum Token {
     Loop,
     #[token("while")]
     While,
+    #[token("for")]
+    For,
+    #[token("in")]
+    In,
     #[token("do")]
     Do,
     #[token("const")]
@@ -42,6 +70,8 @@ pub enum Token {
     Return,
     #[token("break")]
     Break,
+    #[token("continue")]
+    Continue,
     #[token("true")]
     True,
     #[token("false")]
@@ -56,6 +86,16 @@ pub enum Token {
     From,
     #[token("null")]
     Null,
+    #[token("match")]
+    Match,
+    #[token("impl")]
+    Impl,
+    #[token("pub")]
+    Pub,
+    #[token("try")]
+    Try,
+    #[token("catch")]
+    Catch,
 
     // Brackets
     #[token("{")]
@@ -94,14 +134,38 @@ pub enum Token {
     Star,
     #[token("/")]
     Slash,
+    #[token("%")]
+    Percent,
+    #[token("&&")]
+    AndAnd,
+    #[token("||")]
+    OrOr,
+    #[token("!")]
+    Bang,
+    #[token("&")]
+    Amp,
+    #[token("|")]
+    Pipe,
+    #[token("^")]
+    Caret,
+    #[token("<<")]
+    Shl,
+    #[token(">>")]
+    Shr,
     #[token("->")]
     Arrow,
+    #[token("=>")]
+    FatArrow,
     #[token(":")]
     Colon,
     #[token(",")]
     Comma,
     #[token(".")]
     Dot,
+    #[token("..")]
+    DotDot,
+    #[token("?")]
+    Question,
     #[token("exec")]
     Exec,
 
@@ -111,28 +175,14 @@ pub enum Token {
 
     #[regex(r#""([^"\\]|\\.)*""#, |lex| {
         let s = lex.slice();
-        let s = &s[1..s.len()-1];
-        let mut res = String::new();
-        let mut chars = s.chars();
-        while let Some(c) = chars.next() {
-            if c == '\\' {
-                match chars.next() {
-                    Some('n') => res.push('\n'),
-                    Some('t') => res.push('\t'),
-                    Some('r') => res.push('\r'),
-                    Some('\\') => res.push('\\'),
-                    Some('"') => res.push('"'),
-                    Some(other) => res.push(other),
-                    None => res.push('\\'),
-                }
-            } else {
-                res.push(c);
-            }
-        }
-        res
+        unescape(&s[1..s.len()-1])
     })]
     StringLit(String),
 
     #[regex("[0-9]+", |lex| lex.slice().parse().ok())]
     Integer(i64),
+
+    // A digit is required after the point so that `0..10` still lexes as a range.
+    #[regex(r"[0-9]+\.[0-9]+([eE][+-]?[0-9]+)?|[0-9]+[eE][+-]?[0-9]+", |lex| lex.slice().parse().ok())]
+    Float(f64),
 }
\ No newline at end of file
@@ -2,44 +2,118 @@ pub mod lexer;
 pub mod ast;
 pub mod parser;
 pub mod interpreter;
+pub mod ir;
+pub mod lowering;
+pub mod optimizer;
 pub mod codegen_c;
 pub mod formatter;
 pub mod shadow_synthesizer;
 pub mod type_checker;
+pub mod diagnostics;
+pub mod modules;
+pub mod numeric;
+pub mod natives;
 
 use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use colored::*;
 use codegen_c::Dimension;
+use diagnostics::{Diagnostic, ErrorCode, MessageFormat, SourceMap};
 
-pub fn compile(file: &str, output: Option<String>) {
-    println!("{} Building {}...", "   Building".green().bold(), file);
-    
-    let content = match fs::read_to_string(file) {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("{} Could not read file: {}", "Error:".red().bold(), e);
-            return;
-        }
-    };
+#[derive(Default)]
+pub struct BuildOptions {
+    pub message_format: MessageFormat,
+    /// Extra directories searched for imported modules.
+    pub module_paths: Vec<PathBuf>,
+    /// Report type errors but generate C anyway.
+    pub allow_type_errors: bool,
+    pub opt_level: optimizer::OptLevel,
+}
 
-    let mut parser = parser::Parser::new(&content);
-    let ast = match parser.parse() {
-        Ok(ast) => ast,
+/// A build, run or format that stopped on an error, which has already been reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Failed;
+
+/// Prints `error` against the source it came from.
+fn report_error(sources: &SourceMap, error: impl Into<Diagnostic>, format: MessageFormat) {
+    diagnostics::emit(&error.into(), sources, format);
+}
+
+/// Prints an error that has no place in the eMo source, such as an unreadable file.
+fn report_failure(code: ErrorCode, message: String, format: MessageFormat) {
+    match format {
+        MessageFormat::Human => eprintln!("{} {}", "Error:".red().bold(), message),
+        MessageFormat::Json => diagnostics::emit(&Diagnostic::new(code, message, None), &SourceMap::new(), format),
+    }
+}
+
+/// Prints a closing summary such as the error count. JSON output leaves it out, so
+/// that stderr holds nothing but diagnostics.
+fn report_summary(message: String, format: MessageFormat) {
+    if format == MessageFormat::Human {
+        eprintln!("{}", message);
+    }
+}
+
+/// Type-checks `ast`, printing every error found. Returns the checker, which holds the
+/// inferred types, and the number of errors.
+fn check_types(ast: &[ast::Spanned<ast::Statement>], sources: &SourceMap, format: MessageFormat) -> (type_checker::TypeChecker, usize) {
+    println!("{} Checking types...", "   Checking".magenta().bold());
+    let mut tc = type_checker::TypeChecker::new();
+    let errors = tc.check_program(ast).err().unwrap_or_default();
+    let count = errors.len();
+    for e in errors {
+        report_error(sources, e, format);
+    }
+    (tc, count)
+}
+
+/// Prints that `count` type errors are being ignored, as `--allow-type-errors` asked.
+fn warn_allowed_type_errors(count: usize, format: MessageFormat) {
+    if count > 0 {
+        report_summary(format!("{} Continuing despite {} type error(s) (--allow-type-errors)", "Warning:".yellow().bold(), count), format);
+    }
+}
+
+/// Loads `file` with its imports, printing any read error.
+fn load_program(file: &str, module_paths: Vec<PathBuf>, format: MessageFormat) -> Option<modules::LinkedProgram> {
+    match modules::ModuleLoader::new(module_paths).load(file) {
+        Ok(program) => Some(program),
         Err(e) => {
-            // report_error(&content, file, &e.message, e.span);
-            eprintln!("{} {}: {}", "Error:".red().bold(), file, e.message);
-            return;
+            report_failure(ErrorCode::Io, format!("Could not read file: {}", e), format);
+            None
         }
+    }
+}
+
+pub fn compile(file: &str, output: Option<String>) -> Result<(), Failed> {
+    compile_with_options(file, output, BuildOptions::default())
+}
+
+pub fn compile_with_options(file: &str, output: Option<String>, options: BuildOptions) -> Result<(), Failed> {
+    println!("{} Building {}...", "   Building".green().bold(), file);
+    
+    let Some(program) = load_program(file, options.module_paths, options.message_format) else {
+        return Err(Failed);
     };
+    let ast = program.statements;
+    let mut error_count = program.errors.len();
+    for e in program.errors {
+        report_error(&program.sources, e, options.message_format);
+    }
 
-    println!("{} Checking types...", "   Checking".magenta().bold());
-    let mut tc = type_checker::TypeChecker::new();
-    if let Err(e) = tc.check_program(&ast) {
-        eprintln!("{} {}: {}", "Type Error:".red().bold(), file, e.message);
-        // In a real compiler, we might stop here.
-        // For now, we log it and continue to see what else we catch.
-        // return; 
+    // Statements that failed to parse are error nodes, so the rest still gets checked.
+    let (tc, type_errors) = check_types(&ast, &program.sources, options.message_format);
+    if options.allow_type_errors {
+        warn_allowed_type_errors(type_errors, options.message_format);
+    } else {
+        error_count += type_errors;
+    }
+
+    if error_count > 0 {
+        report_summary(format!("{} Could not build {} due to {} error(s)", "Error:".red().bold(), file, error_count), options.message_format);
+        return Err(Failed);
     }
 
     let dimension = if file.ends_with(".ss") {
@@ -54,85 +128,172 @@ pub fn compile(file: &str, output: Option<String>) {
         Dimension::Default
     };
 
-    let mut codegen = codegen_c::CodegenC::new(dimension);
-    let c_code = codegen.generate(&ast);
+    let mut program = match lowering::lower(&ast, tc.types(), tc.generic_vars()) {
+        Ok(program) => program,
+        Err(errors) => {
+            let count = errors.len();
+            for e in errors {
+                report_error(&program.sources, e, options.message_format);
+            }
+            report_summary(format!("{} Could not build {} due to {} error(s)", "Error:".red().bold(), file, count), options.message_format);
+            return Err(Failed);
+        }
+    };
+    optimizer::optimize(&mut program, options.opt_level);
+    let c_code = codegen_c::CodegenC::new(dimension).generate(&program);
 
+    // The runtime header goes next to the C file, where its `#include` looks for it.
     let c_file = format!("{}.c", file);
-    fs::write(&c_file, c_code).expect("Failed to write C file");
-
-    let runtime_h = include_str!("emo_runtime.h");
-    fs::write("emo_runtime.h", runtime_h).expect("Failed to write runtime header");
+    let runtime_h = Path::new(&c_file).with_file_name("emo_runtime.h");
+    let written = fs::write(&c_file, c_code).and_then(|_| fs::write(&runtime_h, include_str!("emo_runtime.h")));
+    if let Err(e) = written {
+        report_failure(ErrorCode::Io, format!("Could not write C file: {}", e), options.message_format);
+        return Err(Failed);
+    }
 
     let out_file = output.unwrap_or_else(|| file.replace(".emo", ""));
     
     println!("{} Compiling C code with GCC...", "   Compiling".blue().bold());
-    let status = Command::new("gcc")
+    let gcc = Command::new("gcc")
         .arg("-std=c11")
         .arg(&c_file)
         .arg("-o")
         .arg(&out_file)
-        .status()
-        .expect("Failed to execute GCC");
+        .output();
+    let gcc = match gcc {
+        Ok(gcc) => gcc,
+        Err(e) => {
+            report_failure(ErrorCode::NativeCompilation, format!("Could not run GCC: {}", e), options.message_format);
+            return Err(Failed);
+        }
+    };
+    let gcc_messages = String::from_utf8_lossy(&gcc.stderr);
+    if options.message_format == MessageFormat::Human {
+        eprint!("{}", gcc_messages);
+    }
 
-    if status.success() {
+    if gcc.status.success() {
         println!("{} Build successful: {}", "   Finished".green().bold(), out_file);
         let _ = fs::remove_file(c_file);
-        let _ = fs::remove_file("emo_runtime.h");
+        let _ = fs::remove_file(runtime_h);
 
         // Merge Feature: If HappyCry dimension, and it has a UI/Web component, 
         // we might want to suggest starting the server.
         if dimension == Dimension::HappyCry {
             println!("{} HappyCry project detected. Run with './{}' to start the fluid interface.", "   Hint:".cyan().bold(), out_file);
         }
+        Ok(())
     } else {
-        eprintln!("{} Native compilation failed", "Error:".red().bold());
+        let message = match options.message_format {
+            MessageFormat::Human => "Native compilation failed".to_string(),
+            MessageFormat::Json => format!("Native compilation failed:\n{}", gcc_messages.trim_end()),
+        };
+        report_failure(ErrorCode::NativeCompilation, message, options.message_format);
+        Err(Failed)
     }
 }
 
-pub fn run(file: &str) {
-    println!("{} Interpreting {}...", "   Running".cyan().bold(), file);
-    let content = match fs::read_to_string(file) {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("{} Could not read file: {}", "Error:".red().bold(), e);
-            return;
+/// Host stack the interpreter thread reserves for each eMo call it allows, on top of
+/// `INTERPRETER_BASE_STACK`. A call to a small function takes about 60 KiB in a debug
+/// build, so this leaves room for deeper expressions inside each call; a thread for
+/// `interpreter::MAX_CALL_DEPTH` calls reserves about 1.3 GiB.
+const INTERPRETER_STACK_PER_CALL: usize = 128 * 1024;
+const INTERPRETER_BASE_STACK: usize = 64 * 1024 * 1024;
+
+pub struct RunOptions {
+    pub max_call_depth: usize,
+    pub message_format: MessageFormat,
+    /// Extra directories searched for imported modules.
+    pub module_paths: Vec<PathBuf>,
+    /// Report type errors but interpret the program anyway.
+    pub allow_type_errors: bool,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            max_call_depth: interpreter::DEFAULT_MAX_CALL_DEPTH,
+            message_format: MessageFormat::default(),
+            module_paths: Vec::new(),
+            allow_type_errors: false,
         }
+    }
+}
+
+pub fn run(file: &str) -> Result<(), Failed> {
+    run_with_options(file, RunOptions::default())
+}
+
+pub fn run_with_options(file: &str, options: RunOptions) -> Result<(), Failed> {
+    println!("{} Interpreting {}...", "   Running".cyan().bold(), file);
+    let Some(program) = load_program(file, options.module_paths, options.message_format) else {
+        return Err(Failed);
     };
-    let mut parser = parser::Parser::new(&content);
-    let ast = match parser.parse() {
-        Ok(ast) => ast,
-        Err(e) => {
-            eprintln!("{} {}: {}", "Error:".red().bold(), file, e.message);
-            return;
+    if !program.errors.is_empty() {
+        for e in program.errors {
+            report_error(&program.sources, e, options.message_format);
         }
-    };
-    let mut interpreter = interpreter::Interpreter::new();
-    if let Err(e) = interpreter.interpret(ast) {
-        eprintln!("{} Interpretation error: {}", "Error:".red().bold(), e);
+        return Err(Failed);
+    }
+    let ast = program.statements;
+    let (tc, type_errors) = check_types(&ast, &program.sources, options.message_format);
+    if options.allow_type_errors {
+        warn_allowed_type_errors(type_errors, options.message_format);
+    } else if type_errors > 0 {
+        report_summary(format!("{} Could not run {} due to {} error(s)", "Error:".red().bold(), file, type_errors), options.message_format);
+        return Err(Failed);
+    }
+    let max_call_depth = options.max_call_depth;
+    if max_call_depth > interpreter::MAX_CALL_DEPTH {
+        let message = format!("A call depth of {} is more than the interpreter supports ({})", max_call_depth, interpreter::MAX_CALL_DEPTH);
+        report_failure(ErrorCode::CallDepthExceeded, message, options.message_format);
+        return Err(Failed);
+    }
+    let sized_literals = tc.sized_literals();
+    let handle = std::thread::Builder::new()
+        .name("emo-interpreter".into())
+        .stack_size(INTERPRETER_BASE_STACK + max_call_depth * INTERPRETER_STACK_PER_CALL)
+        .spawn(move || {
+            let mut interpreter = interpreter::Interpreter::new().with_max_call_depth(max_call_depth).with_sized_literals(sized_literals);
+            interpreter.interpret(ast)
+        })
+        .expect("Failed to spawn interpreter thread");
+    match handle.join() {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => {
+            report_error(&program.sources, e, options.message_format);
+            Err(Failed)
+        }
+        // The panic message has already been printed by the thread.
+        Err(_) => Err(Failed),
     }
 }
 
-pub fn format_file(file: &str) {
+pub fn format_file(file: &str) -> Result<(), Failed> {
     let content = match fs::read_to_string(file) {
         Ok(c) => c,
         Err(e) => {
             eprintln!("{} Could not read file: {}", "Error:".red().bold(), e);
-            return;
+            return Err(Failed);
         }
     };
     let mut parser = parser::Parser::new(&content);
     let ast = match parser.parse() {
         Ok(ast) => ast,
-        Err(e) => {
-            eprintln!("{} {}: {}", "Error:".red().bold(), file, e.message);
-            return;
+        Err(errors) => {
+            let sources = SourceMap::single(file, &content);
+            for e in errors {
+                report_error(&sources, e, MessageFormat::Human);
+            }
+            return Err(Failed);
         }
     };
     let mut formatter = formatter::Formatter::new();
     let formatted = formatter.format(&ast);
     if let Err(e) = fs::write(file, formatted) {
         eprintln!("{} Could not write file: {}", "Error:".red().bold(), e);
-    } else {
-        println!("{} Formatted {}", "   Formatted".green().bold(), file);
+        return Err(Failed);
     }
+    println!("{} Formatted {}", "   Formatted".green().bold(), file);
+    Ok(())
 }
@@ -0,0 +1,1175 @@
+//! Lowers the checked AST to the IR in `ir`.
+//!
+//! Every type comes from the type checker's record of the program, so nothing is inferred
+//! here. A generic function, or a polymorphic local function or lambda, is lowered once for
+//! each combination of types it is used at, and a type left generic is an error. Expressions are flattened
+//! into temporaries in evaluation order, loops get explicit counters, `?` becomes an explicit
+//! test and lambdas are lifted out with the variables they capture.
+
+use crate::ast::{free_uses, free_variables, is_captured, split_value, Expression, MatchArm, Op, Span, Spanned, Statement, StringPart, TypeExpr};
+use crate::ir::{Arm, Callee, Const, Function, FunctionKind, Operand, Pattern, Program, Rvalue, Scope, Stmt, Type, TypeDef, TypeDefKind, Var};
+use crate::diagnostics::{Diagnostic, ErrorCode};
+use crate::natives::{self, NativeFn};
+use crate::numeric::{IntKind, OVERFLOW_HELPERS};
+use crate::{ast, type_checker};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+/// Lowers a program that type-checked, given the types `TypeChecker::types` recorded for it
+/// and `TypeChecker::generic_vars`. Fails on what type-checks but cannot be compiled, such
+/// as natives only the interpreter has.
+pub fn lower(program: &[Spanned<Statement>], types: HashMap<Span, type_checker::Type>, generic_vars: HashSet<usize>) -> Result<Program, Vec<Diagnostic>> {
+    Lowering::new(types, generic_vars).lower_program(program)
+}
+
+/// What the type variables of a generic function or binding stand for in one instance.
+type Subst = HashMap<usize, type_checker::Type>;
+
+/// Names a variable or function cannot have in C: keywords, and what the C library and
+/// the runtime declare that the generated code may call.
+const RESERVED: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum", "extern",
+    "float", "for", "goto", "if", "inline", "int", "long", "register", "restrict", "return", "short", "signed",
+    "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned", "void", "volatile", "while",
+    "bool", "true", "false", "NULL", "INFINITY", "NAN", "main", "user_logic",
+    "abs", "ceil", "cos", "exit", "exp", "fabs", "floor", "fmod", "free", "index", "llabs", "log", "malloc",
+    "pow", "printf", "rand", "round", "sin", "sqrt", "strcmp", "strlen", "tan", "time",
+];
+
+/// Prefixes of the runtime's own names.
+const RESERVED_PREFIXES: &[&str] = &["__", "emo_", "EMO_", "log_", "math_", "mind_", "model_", "net_", "joy_", "sys_", "time_", "void_"];
+
+pub(crate) fn is_reserved(name: &str) -> bool {
+    RESERVED.contains(&name) || RESERVED_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// The built-in numeric conversions, such as `float(x)` and `u8(x)`.
+fn is_conversion(name: &str) -> bool {
+    matches!(name, "float" | "int" | "i64") || IntKind::from_name(name).is_some()
+}
+
+/// The IR type for a type the checker inferred.
+fn ir_type(t: &type_checker::Type) -> Type {
+    use type_checker::Type as Checked;
+    match t {
+        Checked::Int | Checked::Variable(_) => Type::Int,
+        Checked::Sized(kind) => Type::Sized(*kind),
+        Checked::Float => Type::Float,
+        Checked::Str => Type::Str,
+        Checked::Bool => Type::Bool,
+        Checked::Void => Type::Void,
+        Checked::Struct(name, args) => Type::Struct(name.clone(), args.iter().map(ir_type).collect()),
+        Checked::Enum(name, args) => Type::Enum(name.clone(), args.iter().map(ir_type).collect()),
+        Checked::List(elem) => Type::List(Box::new(ir_type(elem))),
+        Checked::Map(value) => Type::Map(Box::new(ir_type(value))),
+        Checked::Function { params, return_type } => Type::Function(params.iter().map(ir_type).collect(), Box::new(ir_type(return_type))),
+    }
+}
+
+/// Binds the type variables in `generic` to the parts of `used` in the same place.
+fn bind_vars(generic: &type_checker::Type, used: &type_checker::Type, out: &mut Subst) {
+    use type_checker::Type as Checked;
+    match (generic, used) {
+        (Checked::Variable(v), t) => {
+            out.entry(*v).or_insert_with(|| t.clone());
+        }
+        (Checked::List(a), Checked::List(b)) | (Checked::Map(a), Checked::Map(b)) => bind_vars(a, b, out),
+        (Checked::Struct(_, a), Checked::Struct(_, b)) | (Checked::Enum(_, a), Checked::Enum(_, b)) => {
+            for (a, b) in a.iter().zip(b) {
+                bind_vars(a, b, out);
+            }
+        }
+        (Checked::Function { params: p1, return_type: r1 }, Checked::Function { params: p2, return_type: r2 }) => {
+            for (a, b) in p1.iter().zip(p2) {
+                bind_vars(a, b, out);
+            }
+            bind_vars(r1, r2, out);
+        }
+        _ => {}
+    }
+}
+
+/// The scope key of the `n`th instance of the polymorphic binding `name`.
+fn instance_key(name: &str, n: usize) -> String {
+    if n == 0 { name.to_string() } else { format!("{}#{}", name, n) }
+}
+
+/// The constant a literal expression or pattern stands for.
+fn literal(expr: &Expression) -> Option<Const> {
+    match expr {
+        Expression::Number(n) => Some(Const::Int(*n)),
+        Expression::Float(x) => Some(Const::Float(*x)),
+        Expression::StringLit(s) => Some(Const::Str(s.clone())),
+        Expression::Bool(b) => Some(Const::Bool(*b)),
+        Expression::Null => Some(Const::Null),
+        _ => None,
+    }
+}
+
+/// The parameter and result types of a function type, padded to `arity` parameters.
+fn function_type(t: Type, arity: usize) -> (Vec<Type>, Type) {
+    let (mut params, ret) = match t {
+        Type::Function(params, ret) => (params, *ret),
+        _ => (Vec::new(), Type::Int),
+    };
+    params.resize(arity, Type::Int);
+    (params, ret)
+}
+
+/// What is in scope while lowering one function, lambda or the top-level code.
+struct FunctionState {
+    scopes: Vec<HashMap<String, Var>>,
+    /// Names already declared in the function; C allows none to be declared twice.
+    names: HashSet<String>,
+    next_temp: usize,
+    ret: Type,
+    /// Enclosing `try` blocks, which `?` throws to instead of returning.
+    tries: usize,
+}
+
+impl FunctionState {
+    fn new(ret: Type) -> Self {
+        Self { scopes: vec![HashMap::new()], names: HashSet::new(), next_temp: 0, ret, tries: 0 }
+    }
+}
+
+struct Lowering {
+    types: HashMap<Span, type_checker::Type>,
+    generic_vars: HashSet<usize>,
+    /// Top-level functions with a polymorphic type, and methods of generic structs as
+    /// `Struct.method`, with that type.
+    generic: HashMap<String, type_checker::Type>,
+    /// Instances of generic functions as (function, type arguments, name in the IR).
+    instances: Vec<(String, Vec<Type>, String)>,
+    /// Instances still to be lowered, with what their type variables stand for.
+    pending: Vec<(String, String, Subst)>,
+    /// What the type variables stand for in the instance being lowered.
+    subst: Subst,
+    /// Uses of polymorphic `let`s and local functions, with the scope key of the instance each refers to.
+    instance_uses: HashMap<Span, String>,
+    /// Top-level polymorphic `let`s, with the scope key and type variables of each instance.
+    global_instances: HashMap<String, Vec<(String, Subst)>>,
+    /// The first place a type was still generic, reported once lowering is done.
+    open: RefCell<Option<(Span, type_checker::Type)>>,
+    structs: HashSet<String>,
+    enums: HashSet<String>,
+    /// Enums without type parameters or payloads, which compiled code stores as plain integers.
+    plain_enums: HashSet<String>,
+    /// Top-level functions, and methods of generic structs as `Struct.method`, with the
+    /// names they have in the IR.
+    functions: HashMap<String, String>,
+    // `impl` members as (struct, name), and whether each takes `self`.
+    methods: HashMap<(String, String), bool>,
+    /// Variables of the top-level code, which functions may use too.
+    globals: HashMap<String, Var>,
+    lifted: Vec<Function>,
+    next_lambda: usize,
+    state: FunctionState,
+    errors: Vec<Diagnostic>,
+}
+
+impl Lowering {
+    fn new(types: HashMap<Span, type_checker::Type>, generic_vars: HashSet<usize>) -> Self {
+        Self {
+            types,
+            generic_vars,
+            generic: HashMap::new(),
+            instances: Vec::new(),
+            pending: Vec::new(),
+            subst: HashMap::new(),
+            instance_uses: HashMap::new(),
+            global_instances: HashMap::new(),
+            open: RefCell::new(None),
+            structs: HashSet::new(),
+            enums: HashSet::new(),
+            plain_enums: HashSet::new(),
+            functions: HashMap::new(),
+            methods: HashMap::new(),
+            globals: HashMap::new(),
+            lifted: Vec::new(),
+            next_lambda: 0,
+            state: FunctionState::new(Type::Void),
+            errors: Vec::new(),
+        }
+    }
+
+    fn lower_program(mut self, program: &[Spanned<Statement>]) -> Result<Program, Vec<Diagnostic>> {
+        for stmt in program {
+            match &stmt.node {
+                Statement::StructDef { name, .. } => {
+                    self.structs.insert(name.clone());
+                }
+                Statement::EnumDef { name, type_params, variants, .. } => {
+                    self.enums.insert(name.clone());
+                    if type_params.is_empty() && variants.iter().all(|(_, payload)| payload.is_empty()) {
+                        self.plain_enums.insert(name.clone());
+                    }
+                }
+                Statement::Impl { type_name, methods } => {
+                    for method in methods {
+                        if let Statement::FunctionDef { name, params, .. } = &method.node {
+                            let takes_self = params.first().is_some_and(|(p, _)| p == "self");
+                            self.methods.insert((type_name.clone(), name.clone()), takes_self);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut types = Vec::new();
+        let mut taken: HashSet<String> = HashSet::new();
+        for stmt in program {
+            match &stmt.node {
+                Statement::StructDef { name, type_params, fields, .. } => {
+                    let fields = fields.iter().map(|(f, t)| (f.clone(), self.declared_type(t, type_params))).collect();
+                    types.push(TypeDef { name: name.clone(), type_params: type_params.clone(), kind: TypeDefKind::Struct(fields) });
+                }
+                Statement::EnumDef { name, type_params, variants, .. } => {
+                    let variants = variants.iter()
+                        .map(|(v, payload)| (v.clone(), payload.iter().map(|t| self.declared_type(t, type_params)).collect()))
+                        .collect();
+                    types.push(TypeDef { name: name.clone(), type_params: type_params.clone(), kind: TypeDefKind::Enum(variants) });
+                }
+                Statement::FunctionDef { name, .. } if !self.functions.contains_key(name) => {
+                    let c_name = if name == "main" { name.clone() } else { unique_name(name, |n| taken.contains(n) || is_reserved(n)) };
+                    taken.insert(c_name.clone());
+                    self.functions.insert(name.clone(), c_name);
+                    let signature = self.types.get(&stmt.span).filter(|t| self.is_generic(t)).cloned();
+                    if let Some(signature) = signature {
+                        self.generic.insert(name.clone(), signature);
+                    }
+                }
+                // Methods of a generic struct are instantiated like generic functions.
+                Statement::Impl { type_name, methods } => {
+                    for method in methods {
+                        let Statement::FunctionDef { name, .. } = &method.node else { continue };
+                        let Some(signature) = self.types.get(&method.span).filter(|t| self.is_generic(t)).cloned() else { continue };
+                        let key = format!("{}.{}", type_name, name);
+                        self.functions.insert(key.clone(), format!("{}_{}", type_name, name));
+                        self.generic.insert(key, signature);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let mut globals = Vec::new();
+        for (i, stmt) in program.iter().enumerate() {
+            if let Statement::Let { name, value, .. } | Statement::Const { name, value, .. } = &stmt.node {
+                if self.globals.contains_key(name) || self.global_instances.contains_key(name) {
+                    continue;
+                }
+                if let Some(signature) = self.polymorphic(stmt) {
+                    // A global for each combination of types the rest of the program uses it at.
+                    let uses = [&program[..i], &program[i + 1..]].into_iter().flat_map(|part| free_uses(part, name)).collect();
+                    let mut instances = Vec::new();
+                    for (n, (subst, uses)) in self.instantiations(&signature, uses).into_iter().enumerate() {
+                        let key = instance_key(name, n);
+                        let c_name = unique_name(name, |n| taken.contains(n) || is_reserved(n));
+                        taken.insert(c_name.clone());
+                        self.subst = subst.clone();
+                        let var = Var { name: c_name, ty: self.ty(&value.span), scope: Scope::Global };
+                        self.globals.insert(key.clone(), var.clone());
+                        globals.push(var);
+                        self.instance_uses.extend(uses.into_iter().map(|span| (span, key.clone())));
+                        instances.push((key, subst));
+                    }
+                    self.subst.clear();
+                    self.global_instances.insert(name.clone(), instances);
+                    continue;
+                }
+                let c_name = unique_name(name, |n| taken.contains(n) || is_reserved(n));
+                taken.insert(c_name.clone());
+                let var = Var { name: c_name, ty: self.ty(&value.span), scope: Scope::Global };
+                self.globals.insert(name.clone(), var.clone());
+                globals.push(var);
+            }
+        }
+
+        let mut functions = Vec::new();
+        for stmt in program {
+            match &stmt.node {
+                // Generic functions are lowered where they are used.
+                Statement::FunctionDef { name, .. } if self.generic.contains_key(name) => {}
+                Statement::FunctionDef { public, name, params, body, .. } => {
+                    let c_name = self.functions[name].clone();
+                    functions.push(self.lower_function(c_name, &stmt.span, params, body, *public || name == "main", FunctionKind::Plain));
+                }
+                Statement::Impl { type_name, methods } => {
+                    // Methods go wherever their struct goes.
+                    let public = program.iter().any(|s| matches!(&s.node, Statement::StructDef { public: true, name, .. } if name == type_name));
+                    for method in methods {
+                        let Statement::FunctionDef { name, params, body, .. } = &method.node else { continue };
+                        if self.generic.contains_key(&format!("{}.{}", type_name, name)) {
+                            continue;
+                        }
+                        let kind = if self.methods[&(type_name.clone(), name.clone())] { FunctionKind::Method } else { FunctionKind::Plain };
+                        functions.push(self.lower_function(format!("{}_{}", type_name, name), &method.span, params, body, public, kind));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // The top-level code, whose own `let`s and `const`s assign the globals.
+        self.state = FunctionState::new(Type::Void);
+        let mut init = Vec::new();
+        for stmt in program {
+            match &stmt.node {
+                Statement::FunctionDef { .. } | Statement::StructDef { .. } | Statement::EnumDef { .. }
+                | Statement::Impl { .. } | Statement::Import { .. } => {}
+                Statement::Let { name, value, .. } | Statement::Const { name, value, .. } if self.global_instances.contains_key(name) => {
+                    for (key, subst) in self.global_instances[name].clone() {
+                        self.subst = subst;
+                        let value = self.rvalue(value, &mut init);
+                        init.push(Stmt::Assign(self.globals[&key].clone(), value));
+                    }
+                    self.subst.clear();
+                }
+                Statement::Let { name, value, .. } | Statement::Const { name, value, .. } => {
+                    let value = self.rvalue(value, &mut init);
+                    let global = self.globals[name].clone();
+                    if global.ty == Type::Void {
+                        self.effect(value, Type::Void, &mut init);
+                    } else {
+                        init.push(Stmt::Assign(global, value));
+                    }
+                }
+                _ => self.statement(stmt, &mut init),
+            }
+        }
+
+        // Instances of generic functions and methods, which may use further ones.
+        while !self.pending.is_empty() {
+            let (name, instance, subst) = self.pending.remove(0);
+            let Some((stmt, public, kind)) = self.generic_definition(program, &name) else { continue };
+            let Statement::FunctionDef { params, body, .. } = &stmt.node else { continue };
+            self.subst = subst;
+            functions.push(self.lower_function(instance, &stmt.span, params, body, public, kind));
+        }
+        self.subst.clear();
+
+        functions.append(&mut self.lifted);
+        if let Some((span, t)) = self.open.take() {
+            let message = format!("The type {} is generic here, but compiled code needs a concrete type; add a type annotation", t);
+            self.errors.push(Diagnostic::new(ErrorCode::AmbiguousType, message, Some(span)));
+        }
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        Ok(Program { types, globals, functions, init })
+    }
+
+    /// The type of a member of a struct or enum declared with `type_params`.
+    fn declared_type(&self, t: &TypeExpr, type_params: &[String]) -> Type {
+        match t {
+            TypeExpr::Named(name) if type_params.contains(name) => Type::Param(name.clone()),
+            TypeExpr::Named(name) => match name.as_str() {
+                "int" | "i64" => Type::Int,
+                "float" => Type::Float,
+                "str" => Type::Str,
+                "bool" => Type::Bool,
+                "void" => Type::Void,
+                _ => IntKind::from_name(name).map_or_else(|| self.named_type(name, Vec::new()), Type::Sized),
+            },
+            TypeExpr::Generic(name, args) => self.named_type(name, args.iter().map(|a| self.declared_type(a, type_params)).collect()),
+            TypeExpr::List(elem) => Type::List(Box::new(self.declared_type(elem, type_params))),
+            TypeExpr::Map(value) => Type::Map(Box::new(self.declared_type(value, type_params))),
+            TypeExpr::Function(params, ret) => Type::Function(
+                params.iter().map(|p| self.declared_type(p, type_params)).collect(),
+                Box::new(self.declared_type(ret, type_params)),
+            ),
+        }
+    }
+
+    fn named_type(&self, name: &str, args: Vec<Type>) -> Type {
+        if self.enums.contains(name) {
+            Type::Enum(name.to_string(), args)
+        } else if self.structs.contains(name) {
+            Type::Struct(name.to_string(), args)
+        } else {
+            Type::Int
+        }
+    }
+
+    /// The type the checker recorded at `span`, in the instance being lowered. One still
+    /// generic is noted, as compiled code cannot have it.
+    fn ty(&self, span: &Span) -> Type {
+        let Some(t) = self.types.get(span) else { return Type::Int };
+        let t = self.apply(t);
+        if self.is_generic(&t) {
+            let mut open = self.open.borrow_mut();
+            if open.as_ref().is_none_or(|(first, _)| span.start < first.start) {
+                *open = Some((span.clone(), t.clone()));
+            }
+        }
+        ir_type(&t)
+    }
+
+    /// `t` with the type variables of the instance being lowered replaced.
+    fn apply(&self, t: &type_checker::Type) -> type_checker::Type {
+        type_checker::substitute(t, &self.subst)
+    }
+
+    fn is_generic(&self, t: &type_checker::Type) -> bool {
+        let mut vars = Vec::new();
+        type_checker::free_type_vars(t, &mut vars);
+        vars.iter().any(|v| self.generic_vars.contains(v))
+    }
+
+    /// The polymorphic type of `stmt`, if it is an immutable `let` of a lambda or a local function.
+    fn polymorphic(&self, stmt: &Spanned<Statement>) -> Option<type_checker::Type> {
+        let span = match &stmt.node {
+            Statement::Let { mutable: false, value, .. } | Statement::Const { value, .. } if matches!(value.node, Expression::Lambda { .. }) => &value.span,
+            Statement::FunctionDef { .. } => &stmt.span,
+            _ => return None,
+        };
+        self.types.get(span).map(|t| self.apply(t)).filter(|t| self.is_generic(t))
+    }
+
+    /// Groups the `uses` of a binding of the polymorphic type `signature` by the types each
+    /// needs it at, with what its type variables stand for there.
+    fn instantiations(&self, signature: &type_checker::Type, uses: Vec<Span>) -> Vec<(Subst, Vec<Span>)> {
+        let mut vars = Vec::new();
+        type_checker::free_type_vars(signature, &mut vars);
+        vars.retain(|v| self.generic_vars.contains(v));
+        let mut groups: Vec<(Vec<Type>, Subst, Vec<Span>)> = Vec::new();
+        for span in uses {
+            let mut subst = HashMap::new();
+            if let Some(used) = self.types.get(&span) {
+                bind_vars(signature, &self.apply(used), &mut subst);
+            }
+            subst.retain(|v, _| vars.contains(v));
+            let args: Vec<Type> = vars.iter().map(|v| subst.get(v).map_or(Type::Int, ir_type)).collect();
+            match groups.iter_mut().find(|(a, ..)| *a == args) {
+                Some((.., spans)) => spans.push(span),
+                None => groups.push((args, subst, vec![span])),
+            }
+        }
+        groups.into_iter().map(|(_, subst, spans)| (subst, spans)).collect()
+    }
+
+    /// The generic function or method `name`, as `function_ref` takes it, with whether it is
+    /// public and what kind of function it lowers to.
+    fn generic_definition<'p>(&self, program: &'p [Spanned<Statement>], name: &str) -> Option<(&'p Spanned<Statement>, bool, FunctionKind)> {
+        let Some((type_name, method)) = name.split_once('.') else {
+            let stmt = program.iter().find(|stmt| matches!(&stmt.node, Statement::FunctionDef { name: n, .. } if n == name))?;
+            let Statement::FunctionDef { public, .. } = &stmt.node else { return None };
+            return Some((stmt, *public, FunctionKind::Plain));
+        };
+        // Methods go wherever their struct goes.
+        let public = program.iter().any(|s| matches!(&s.node, Statement::StructDef { public: true, name, .. } if name == type_name));
+        let kind = if self.methods[&(type_name.to_string(), method.to_string())] { FunctionKind::Method } else { FunctionKind::Plain };
+        let stmt = program.iter().find_map(|stmt| match &stmt.node {
+            Statement::Impl { type_name: t, methods } if t == type_name => {
+                methods.iter().find(|m| matches!(&m.node, Statement::FunctionDef { name: n, .. } if n == method))
+            }
+            _ => None,
+        })?;
+        Some((stmt, public, kind))
+    }
+
+    /// The IR name of the top-level function `name` used at `span`. A generic function gets
+    /// an instance for the types it is used at, to be lowered later if it is a new one.
+    fn function_ref(&mut self, name: &str, span: &Span) -> String {
+        let used = self.types.get(span).cloned();
+        self.instance_ref(name, used)
+    }
+
+    /// Like `function_ref`, for a function or method `name` of the type `used`.
+    fn instance_ref(&mut self, name: &str, used: Option<type_checker::Type>) -> String {
+        let c_name = self.functions[name].clone();
+        let Some(signature) = self.generic.get(name).cloned() else { return c_name };
+        let mut subst = HashMap::new();
+        if let Some(used) = &used {
+            bind_vars(&signature, &self.apply(used), &mut subst);
+        }
+        let mut vars = Vec::new();
+        type_checker::free_type_vars(&signature, &mut vars);
+        let args: Vec<Type> = vars.iter().map(|v| subst.get(v).map_or(Type::Int, ir_type)).collect();
+        if let Some((.., instance)) = self.instances.iter().find(|(f, a, _)| f == name && *a == args) {
+            return instance.clone();
+        }
+        let spelled = std::iter::once(c_name).chain(args.iter().map(Type::mangled)).collect::<Vec<_>>().join("_");
+        let instance = unique_name(&spelled, |n| self.is_taken(n) || is_reserved(n));
+        self.instances.push((name.to_string(), args, instance.clone()));
+        self.pending.push((name.to_string(), instance.clone(), subst));
+        instance
+    }
+
+    fn lower_function(&mut self, name: String, span: &Span, params: &[(String, Option<TypeExpr>)], body: &[Spanned<Statement>], public: bool, kind: FunctionKind) -> Function {
+        let (param_types, ret) = function_type(self.ty(span), params.len());
+        self.state = FunctionState::new(ret.clone());
+        let params = params.iter().zip(param_types).map(|((p, _), t)| self.declare(p, t)).collect();
+        let mut stmts = Vec::new();
+        self.statements(body, &mut stmts);
+        Function { name, params, ret, body: stmts, public, kind }
+    }
+
+    /// Declares a variable of the current function in the innermost scope, under a name
+    /// no other declaration in the function, global or function has.
+    fn declare(&mut self, name: &str, ty: Type) -> Var {
+        self.declare_in(name, name, ty, Scope::Local)
+    }
+
+    /// Declares a variable of `scope` named after `name`, under the scope key `key`.
+    fn declare_in(&mut self, key: &str, name: &str, ty: Type, scope: Scope) -> Var {
+        let c_name = unique_name(name, |n| self.is_taken(n) || is_reserved(n));
+        self.state.names.insert(c_name.clone());
+        let var = Var { name: c_name, ty, scope };
+        if let Some(scope) = self.state.scopes.last_mut() {
+            scope.insert(key.to_string(), var.clone());
+        }
+        var
+    }
+
+    fn is_taken(&self, name: &str) -> bool {
+        self.state.names.contains(name)
+            || self.functions.values().any(|f| f == name)
+            || self.instances.iter().any(|(.., instance)| instance == name)
+            || self.globals.values().any(|g| g.name == name)
+    }
+
+    /// A fresh temporary of the current function.
+    fn new_temp(&mut self, ty: Type) -> Var {
+        loop {
+            let name = format!("_t{}", self.state.next_temp);
+            self.state.next_temp += 1;
+            if !self.is_taken(&name) {
+                self.state.names.insert(name.clone());
+                return Var { name, ty, scope: Scope::Local };
+            }
+        }
+    }
+
+    /// Stores `value` in a new temporary, or just evaluates it if it is void.
+    fn temp(&mut self, ty: Type, value: Rvalue, out: &mut Vec<Stmt>) -> Operand {
+        if ty == Type::Void {
+            out.push(Stmt::Eval(value));
+            return Operand::Const(Const::Null);
+        }
+        let var = self.new_temp(ty);
+        out.push(Stmt::Let(var.clone(), Some(value)));
+        Operand::Var(var)
+    }
+
+    /// Evaluates `value` only for what it does: calls are kept, plain values dropped,
+    /// and anything else kept in an unused temporary, as it may still fail at run time.
+    fn effect(&mut self, value: Rvalue, ty: Type, out: &mut Vec<Stmt>) {
+        match value {
+            Rvalue::Use(_) => {}
+            Rvalue::Call(..) => out.push(Stmt::Eval(value)),
+            value => {
+                self.temp(ty, value, out);
+            }
+        }
+    }
+
+    /// The variable `name` refers to, if it is one.
+    fn lookup(&self, name: &str) -> Option<Var> {
+        self.lookup_local(name).or_else(|| self.globals.get(name).cloned())
+    }
+
+    fn lookup_local(&self, name: &str) -> Option<Var> {
+        self.state.scopes.iter().rev().find_map(|scope| scope.get(name).cloned())
+    }
+
+    fn statements(&mut self, body: &[Spanned<Statement>], out: &mut Vec<Stmt>) {
+        self.statements_in(body, body.len(), out);
+    }
+
+    /// Lowers the first `count` statements of `body`. The rest are only searched for uses of
+    /// polymorphic bindings.
+    fn statements_in(&mut self, body: &[Spanned<Statement>], count: usize, out: &mut Vec<Stmt>) {
+        for (i, stmt) in body[..count].iter().enumerate() {
+            let rest = &body[i + 1..];
+            match &stmt.node {
+                // Lambdas that capture a variable which may change share it with the function.
+                Statement::Let { name, mutable: true, value, .. } if is_captured(rest, name) => self.binding(name, value, Scope::Cell, out),
+                _ if self.polymorphic_binding(stmt, rest, out) => {}
+                _ => self.statement(stmt, out),
+            }
+        }
+    }
+
+    /// Lowers a polymorphic `let` or local function to a closure for each combination of
+    /// types the statements after it, `rest`, use it at. Returns whether `stmt` was one.
+    fn polymorphic_binding(&mut self, stmt: &Spanned<Statement>, rest: &[Spanned<Statement>], out: &mut Vec<Stmt>) -> bool {
+        let Some(signature) = self.polymorphic(stmt) else { return false };
+        let (name, own, span, params, body) = match &stmt.node {
+            Statement::Let { name, value, .. } | Statement::Const { name, value, .. } => match &value.node {
+                Expression::Lambda { params, body, .. } => (name, None, &value.span, params, body),
+                _ => return false,
+            },
+            Statement::FunctionDef { name, params, body, .. } => (name, Some(name.as_str()), &stmt.span, params, body),
+            _ => return false,
+        };
+        for (n, (subst, uses)) in self.instantiations(&signature, free_uses(rest, name)).into_iter().enumerate() {
+            let key = instance_key(name, n);
+            let outer = self.subst.clone();
+            self.subst.extend(subst);
+            let closure = self.lambda(own, span, params, body);
+            let var = self.declare_in(&key, name, self.ty(span), Scope::Local);
+            out.push(Stmt::Let(var, Some(closure)));
+            self.subst = outer;
+            self.instance_uses.extend(uses.into_iter().map(|span| (span, key.clone())));
+        }
+        true
+    }
+
+    /// Lowers `body` in a scope of its own.
+    fn block(&mut self, body: &[Spanned<Statement>]) -> Vec<Stmt> {
+        self.value_block(body, None)
+    }
+
+    /// Lowers a block in a scope of its own; with a `target`, the block is used as a value
+    /// and its result is assigned to it.
+    fn value_block(&mut self, body: &[Spanned<Statement>], target: Option<&Var>) -> Vec<Stmt> {
+        let mut out = Vec::new();
+        self.state.scopes.push(HashMap::new());
+        let (init, result) = if target.is_some() { split_value(body) } else { (body, None) };
+        self.statements_in(body, init.len(), &mut out);
+        if let (Some(target), Some(result)) = (target, result) {
+            let value = self.rvalue(result, &mut out);
+            out.push(Stmt::Assign(target.clone(), value));
+        }
+        self.state.scopes.pop();
+        out
+    }
+
+    fn statement(&mut self, stmt: &Spanned<Statement>, out: &mut Vec<Stmt>) {
+        match &stmt.node {
+            Statement::Let { name, value, .. } | Statement::Const { name, value, .. } => self.binding(name, value, Scope::Local, out),
+            Statement::Set { name, value } => {
+                let ty = self.ty(&value.span);
+                let value = self.rvalue(value, out);
+                match self.lookup(name) {
+                    Some(var) => out.push(Stmt::Assign(var, value)),
+                    None => self.effect(value, ty, out),
+                }
+            }
+            Statement::IndexSet { object, index, value } => {
+                let object = self.operand(object, out);
+                let index = self.operand(index, out);
+                let value = self.operand(value, out);
+                out.push(Stmt::IndexSet { object, index, value });
+            }
+            Statement::Return(expr) if self.state.ret == Type::Void => {
+                if !matches!(expr.node, Expression::Null) {
+                    let ty = self.ty(&expr.span);
+                    let value = self.rvalue(expr, out);
+                    self.effect(value, ty, out);
+                }
+                out.push(Stmt::Return(None));
+            }
+            Statement::Return(expr) => {
+                let value = self.operand(expr, out);
+                out.push(Stmt::Return(Some(value)));
+            }
+            Statement::Break => out.push(Stmt::Break),
+            Statement::Continue => out.push(Stmt::Continue),
+            Statement::Expression(expr) => {
+                let ty = self.ty(&expr.span);
+                let value = self.rvalue(expr, out);
+                self.effect(value, ty, out);
+            }
+            Statement::If { cond, then_block, else_block } => {
+                let cond = self.operand(cond, out);
+                let then_block = self.block(then_block);
+                let else_block = else_block.as_ref().map_or_else(Vec::new, |eb| self.block(eb));
+                out.push(Stmt::If { cond, then_block, else_block });
+            }
+            Statement::While { cond, body } => {
+                let mut inner = Vec::new();
+                let cond = self.operand(cond, &mut inner);
+                inner.push(Stmt::If { cond, then_block: Vec::new(), else_block: vec![Stmt::Break] });
+                inner.extend(self.block(body));
+                out.push(Stmt::Loop { body: inner, step: Vec::new() });
+            }
+            Statement::Loop { count: None, body } => {
+                let body = self.block(body);
+                out.push(Stmt::Loop { body, step: Vec::new() });
+            }
+            Statement::Loop { count: Some(count), body } => {
+                // The count is evaluated once, before the first iteration.
+                let count = self.stable_operand(count, out);
+                self.counted_loop(Operand::Const(Const::Int(0)), count, out, |this, _, inner| inner.extend(this.block(body)));
+            }
+            Statement::ForIn { var, iterable, body } => {
+                if let Expression::Range { start, end } = &iterable.node {
+                    let start = self.operand(start, out);
+                    let end = self.stable_operand(end, out);
+                    self.counted_loop(start, end, out, |this, i, inner| {
+                        this.state.scopes.push(HashMap::new());
+                        let item = this.declare(var, Type::Int);
+                        inner.push(Stmt::Let(item, Some(Rvalue::Use(i))));
+                        this.statements(body, inner);
+                        this.state.scopes.pop();
+                    });
+                } else {
+                    // Iterate a snapshot (list items or map keys), matching the interpreter.
+                    let iterable = self.operand(iterable, out);
+                    let item_type = match iterable.ty() {
+                        Type::List(elem) => *elem,
+                        Type::Map(_) => Type::Str,
+                        _ => Type::Int,
+                    };
+                    let items = self.temp(Type::List(Box::new(item_type.clone())), Rvalue::Call(Callee::Collection("iter".to_string()), vec![iterable]), out);
+                    let len = self.temp(Type::Int, Rvalue::Call(Callee::Collection("len".to_string()), vec![items.clone()]), out);
+                    self.counted_loop(Operand::Const(Const::Int(0)), len, out, |this, i, inner| {
+                        this.state.scopes.push(HashMap::new());
+                        let item = this.declare(var, item_type);
+                        inner.push(Stmt::Let(item, Some(Rvalue::Index(items, i))));
+                        this.statements(body, inner);
+                        this.state.scopes.pop();
+                    });
+                }
+            }
+            Statement::UnsafeBlock(body) => {
+                let body = self.block(body);
+                out.push(Stmt::Block(body));
+            }
+            Statement::Match { subject, arms } => self.lower_match(subject, arms, None, out),
+            Statement::TryCatch { body, error, handler } => {
+                self.state.tries += 1;
+                let body = self.block(body);
+                self.state.tries -= 1;
+                self.state.scopes.push(HashMap::new());
+                let error = self.declare(error, self.ty(&stmt.span));
+                let mut handler_stmts = Vec::new();
+                self.statements(handler, &mut handler_stmts);
+                self.state.scopes.pop();
+                out.push(Stmt::Try { body, error, handler: handler_stmts });
+            }
+            Statement::FunctionDef { name, params, body, .. } => {
+                let closure = self.lambda(Some(name), &stmt.span, params, body);
+                let var = self.declare(name, self.ty(&stmt.span));
+                out.push(Stmt::Let(var, Some(closure)));
+            }
+            // Declarations only appear at the top level, where `lower_program` handles them.
+            Statement::StructDef { .. } | Statement::EnumDef { .. } | Statement::Impl { .. } | Statement::Import { .. } | Statement::Error => {}
+        }
+    }
+
+    /// Declares `name` in the innermost scope as a variable of `scope` that starts as `value`.
+    fn binding(&mut self, name: &str, value: &Spanned<Expression>, scope: Scope, out: &mut Vec<Stmt>) {
+        let ty = self.ty(&value.span);
+        let value = self.rvalue(value, out);
+        if ty == Type::Void {
+            self.effect(value, ty, out);
+            return;
+        }
+        let var = self.declare_in(name, name, ty, scope);
+        out.push(Stmt::Let(var, Some(value)));
+    }
+
+    /// Counts a fresh temporary from `start` up to `end`, exclusive, running `body` with its
+    /// value each time. `end` is read on every iteration, so it must not change meanwhile.
+    fn counted_loop(&mut self, start: Operand, end: Operand, out: &mut Vec<Stmt>, body: impl FnOnce(&mut Self, Operand, &mut Vec<Stmt>)) {
+        let i = self.new_temp(Type::Int);
+        out.push(Stmt::Let(i.clone(), Some(Rvalue::Use(start))));
+        let mut inner = Vec::new();
+        let more = self.temp(Type::Bool, Rvalue::Binary(Op::Lt, Operand::Var(i.clone()), end), &mut inner);
+        inner.push(Stmt::If { cond: more, then_block: Vec::new(), else_block: vec![Stmt::Break] });
+        body(self, Operand::Var(i.clone()), &mut inner);
+        let step = vec![Stmt::Assign(i.clone(), Rvalue::Binary(Op::Plus, Operand::Var(i), Operand::Const(Const::Int(1))))];
+        out.push(Stmt::Loop { body: inner, step });
+    }
+
+    /// An operand for `expr` that keeps its value while a loop runs: variables are copied.
+    fn stable_operand(&mut self, expr: &Spanned<Expression>, out: &mut Vec<Stmt>) -> Operand {
+        match self.operand(expr, out) {
+            Operand::Var(var) if matches!(expr.node, Expression::Identifier(_)) => self.temp(var.ty.clone(), Rvalue::Use(Operand::Var(var)), out),
+            operand => operand,
+        }
+    }
+
+    fn lower_match(&mut self, subject: &Spanned<Expression>, arms: &[MatchArm], target: Option<&Var>, out: &mut Vec<Stmt>) {
+        let subject = self.operand(subject, out);
+        let arms = arms.iter().map(|arm| {
+            self.state.scopes.push(HashMap::new());
+            let pattern = self.pattern(&arm.pattern);
+            let body = self.value_block(&arm.body, target);
+            self.state.scopes.pop();
+            Arm { pattern, body }
+        }).collect();
+        out.push(Stmt::Match { subject, arms });
+    }
+
+    /// Declares the variables `pattern` binds in the innermost scope.
+    fn pattern(&mut self, pattern: &Spanned<ast::Pattern>) -> Pattern {
+        match &pattern.node {
+            ast::Pattern::Wildcard => Pattern::Wildcard,
+            ast::Pattern::Binding(name) => Pattern::Binding(self.declare(name, self.ty(&pattern.span))),
+            ast::Pattern::Literal(lit) => literal(lit).map_or(Pattern::Wildcard, Pattern::Literal),
+            ast::Pattern::Variant { enum_name, variant, fields } => Pattern::Variant {
+                enum_name: enum_name.clone(),
+                variant: variant.clone(),
+                fields: fields.iter().map(|f| self.pattern(f)).collect(),
+            },
+            ast::Pattern::Struct { fields, .. } => Pattern::Struct {
+                fields: fields.iter().map(|(name, f)| (name.clone(), self.pattern(f))).collect(),
+            },
+        }
+    }
+
+    /// `expr` as an operand, storing its value in a temporary unless it already is one.
+    fn operand(&mut self, expr: &Spanned<Expression>, out: &mut Vec<Stmt>) -> Operand {
+        match self.rvalue(expr, out) {
+            Rvalue::Use(operand) => operand,
+            value => {
+                let ty = self.ty(&expr.span);
+                self.temp(ty, value, out)
+            }
+        }
+    }
+
+    fn operands(&mut self, exprs: &[Spanned<Expression>], out: &mut Vec<Stmt>) -> Vec<Operand> {
+        exprs.iter().map(|e| self.operand(e, out)).collect()
+    }
+
+    /// Lowers `expr`, pushing the code that computes its parts to `out`, and returns the
+    /// last step of the computation.
+    fn rvalue(&mut self, expr: &Spanned<Expression>, out: &mut Vec<Stmt>) -> Rvalue {
+        match &expr.node {
+            Expression::Number(_) | Expression::Float(_) | Expression::StringLit(_) | Expression::Bool(_) | Expression::Null => {
+                Rvalue::Use(Operand::Const(literal(&expr.node).unwrap_or(Const::Null)))
+            }
+            // Programs with syntax errors never reach lowering.
+            Expression::Error => Rvalue::Use(Operand::Const(Const::Null)),
+            Expression::Interpolated(parts) => {
+                let mut operands = Vec::new();
+                for part in parts {
+                    match part {
+                        StringPart::Literal(s) => operands.push(Operand::Const(Const::Str(s.clone()))),
+                        StringPart::Expr(e) => operands.push(self.operand(e, out)),
+                    }
+                }
+                Rvalue::Interpolate(operands)
+            }
+            Expression::Identifier(name) => match self.lookup(self.instance_uses.get(&expr.span).unwrap_or(name)) {
+                Some(var) => Rvalue::Use(Operand::Var(var)),
+                None if self.functions.contains_key(name) => Rvalue::FunctionRef(self.function_ref(name, &expr.span)),
+                // Native functions are not values in compiled code.
+                None => Rvalue::Use(Operand::Const(Const::Null)),
+            },
+            Expression::StructLiteral { fields, .. } => {
+                let fields = fields.iter().map(|(name, value)| (name.clone(), self.operand(value, out))).collect();
+                Rvalue::Struct(fields)
+            }
+            Expression::ListLiteral(items) => Rvalue::List(self.operands(items, out)),
+            Expression::MapLiteral(entries) => {
+                let entries = entries.iter().map(|(key, value)| (self.operand(key, out), self.operand(value, out))).collect();
+                Rvalue::Map(entries)
+            }
+            Expression::Index { object, index } => {
+                let object = self.operand(object, out);
+                let index = self.operand(index, out);
+                Rvalue::Index(object, index)
+            }
+            Expression::Range { start, end } => {
+                let start = self.operand(start, out);
+                let end = self.operand(end, out);
+                Rvalue::Range(start, end)
+            }
+            Expression::BinaryOp(left, op @ (Op::And | Op::Or), right) => self.logical(left, op, right, out),
+            Expression::BinaryOp(left, op, right) => {
+                let left = self.operand(left, out);
+                let right = self.operand(right, out);
+                Rvalue::Binary(op.clone(), left, right)
+            }
+            Expression::Unary(op, operand) => Rvalue::Unary(op.clone(), self.operand(operand, out)),
+            Expression::Try(operand) => self.try_operator(operand, out),
+            Expression::If { cond, then_block, else_block } => self.branching(&expr.span, out, |this, target, out| {
+                let cond = this.operand(cond, out);
+                let then_block = this.value_block(then_block, target);
+                let else_block = else_block.as_ref().map_or_else(Vec::new, |eb| this.value_block(eb, target));
+                out.push(Stmt::If { cond, then_block, else_block });
+            }),
+            Expression::Match { subject, arms } => self.branching(&expr.span, out, |this, target, out| this.lower_match(subject, arms, target, out)),
+            Expression::Block(body) => self.branching(&expr.span, out, |this, target, out| {
+                let body = this.value_block(body, target);
+                out.push(Stmt::Block(body));
+            }),
+            Expression::Call { func, args } => self.call(expr, func, args, out),
+            Expression::MemberAccess { object, member } => {
+                if let Expression::Identifier(name) = &object.node {
+                    if self.lookup(name).is_none() {
+                        if self.enums.contains(name) {
+                            return Rvalue::Variant(member.clone(), Vec::new());
+                        }
+                        if self.structs.contains(name) {
+                            return Rvalue::FunctionRef(format!("{}_{}", name, member));
+                        }
+                        if natives::is_module(name) {
+                            return Rvalue::Use(Operand::Const(Const::Null));
+                        }
+                    }
+                }
+                Rvalue::Field(self.operand(object, out), member.clone())
+            }
+            Expression::Lambda { params, body, .. } => self.lambda(None, &expr.span, params, body),
+        }
+    }
+
+    /// `&&` and `||`. When the right operand needs code of its own, that code only runs
+    /// when the left operand does not already decide the result.
+    fn logical(&mut self, left: &Spanned<Expression>, op: &Op, right: &Spanned<Expression>, out: &mut Vec<Stmt>) -> Rvalue {
+        let left = self.operand(left, out);
+        let mut rest = Vec::new();
+        let right = self.operand(right, &mut rest);
+        if rest.is_empty() {
+            return Rvalue::Binary(op.clone(), left, right);
+        }
+        let result = self.new_temp(Type::Bool);
+        out.push(Stmt::Let(result.clone(), Some(Rvalue::Use(left))));
+        rest.push(Stmt::Assign(result.clone(), Rvalue::Use(right)));
+        let cond = Operand::Var(result.clone());
+        out.push(match op {
+            Op::And => Stmt::If { cond, then_block: rest, else_block: Vec::new() },
+            _ => Stmt::If { cond, then_block: Vec::new(), else_block: rest },
+        });
+        Rvalue::Use(Operand::Var(result))
+    }
+
+    /// An `if`, `match` or block used as a value: its result goes to a temporary that every
+    /// branch assigns, unless it is void.
+    fn branching(&mut self, span: &Span, out: &mut Vec<Stmt>, lower: impl FnOnce(&mut Self, Option<&Var>, &mut Vec<Stmt>)) -> Rvalue {
+        let ty = self.ty(span);
+        if ty == Type::Void {
+            lower(self, None, out);
+            return Rvalue::Use(Operand::Const(Const::Null));
+        }
+        let result = self.new_temp(ty);
+        out.push(Stmt::Let(result.clone(), None));
+        lower(self, Some(&result), out);
+        Rvalue::Use(Operand::Var(result))
+    }
+
+    /// `value?`: the payload of `Ok` or `Some`. Otherwise the error is thrown to the enclosing
+    /// `try`, returned if the function returns the same kind of enum, or else a runtime error.
+    fn try_operator(&mut self, operand: &Spanned<Expression>, out: &mut Vec<Stmt>) -> Rvalue {
+        let value = self.operand(operand, out);
+        let value_type = value.ty();
+        let (kind, ok, failed) = match &value_type {
+            Type::Enum(name, _) if name == "Option" => ("Option", "Some", "None"),
+            _ => ("Result", "Ok", "Err"),
+        };
+        let error_type = match &value_type {
+            Type::Enum(_, args) if args.len() == 2 => args[1].clone(),
+            _ => Type::Str,
+        };
+        let is_ok = self.temp(Type::Bool, Rvalue::IsVariant(value.clone(), ok.to_string()), out);
+        let mut failure = Vec::new();
+        let returns_same_kind = matches!(&self.state.ret, Type::Enum(name, _) if name == kind);
+        if self.state.tries > 0 && kind == "Result" {
+            let error = self.temp(error_type, Rvalue::Payload(value.clone(), failed.to_string(), 0), &mut failure);
+            failure.push(Stmt::Throw(error));
+        } else if returns_same_kind {
+            let payload = if kind == "Result" {
+                vec![self.temp(error_type, Rvalue::Payload(value.clone(), failed.to_string(), 0), &mut failure)]
+            } else {
+                Vec::new()
+            };
+            let ret = self.state.ret.clone();
+            let returned = self.temp(ret, Rvalue::Variant(failed.to_string(), payload), &mut failure);
+            failure.push(Stmt::Return(Some(returned)));
+        } else {
+            // `main`, or a function whose result type could not be pinned down.
+            failure.push(Stmt::Panic("uncaught error".to_string()));
+        }
+        out.push(Stmt::If { cond: is_ok, then_block: Vec::new(), else_block: failure });
+        Rvalue::Payload(value, ok.to_string(), 0)
+    }
+
+    /// The callee for `native`, reporting it at `span` if compiled code cannot call it with `args`.
+    fn native(&mut self, native: &NativeFn, args: &[Spanned<Expression>], span: &Span) -> Callee {
+        if !native.is_compiled() {
+            let message = format!("{} is only available in the interpreter", native.qualified_name());
+            self.errors.push(Diagnostic::new(ErrorCode::Unsupported, message, Some(span.clone())));
+        }
+        if (native.module, native.name) == ("sys", "log") {
+            // Compiled code only knows how to print scalars, strings and collections.
+            for arg in args {
+                let t = self.ty(&arg.span);
+                let printable = match &t {
+                    Type::Struct(..) | Type::Function(..) => false,
+                    Type::Enum(name, _) => self.plain_enums.contains(name),
+                    _ => true,
+                };
+                if !printable {
+                    let message = format!("log cannot print a value of type {} in compiled code", t);
+                    self.errors.push(Diagnostic::new(ErrorCode::Unsupported, message, Some(arg.span.clone())));
+                }
+            }
+        }
+        Callee::Native(native.module.to_string(), native.name.to_string())
+    }
+
+    fn call(&mut self, expr: &Spanned<Expression>, func: &Spanned<Expression>, args: &[Spanned<Expression>], out: &mut Vec<Stmt>) -> Rvalue {
+        match &func.node {
+            Expression::Identifier(name) if self.lookup(name).is_none() => {
+                let callee = if self.functions.contains_key(name) {
+                    Callee::Function(self.function_ref(name, &func.span))
+                } else if let Some(native) = natives::global(name) {
+                    self.native(native, args, &expr.span)
+                } else if is_conversion(name) && args.len() == 1 {
+                    return Rvalue::Convert(self.operand(&args[0], out));
+                } else if OVERFLOW_HELPERS.contains(&name.as_str()) {
+                    Callee::Overflow(name.clone())
+                } else {
+                    return Rvalue::Use(Operand::Const(Const::Null));
+                };
+                Rvalue::Call(callee, self.operands(args, out))
+            }
+            Expression::MemberAccess { object, member } => {
+                if let Expression::Identifier(name) = &object.node {
+                    if self.lookup(name).is_none() {
+                        let callee = if let Some(native) = natives::lookup(name, member) {
+                            Some(self.native(native, args, &expr.span))
+                        } else if self.generic.contains_key(&format!("{}.{}", name, member)) {
+                            Some(Callee::Function(self.function_ref(&format!("{}.{}", name, member), &func.span)))
+                        } else if self.structs.contains(name) {
+                            Some(Callee::Function(format!("{}_{}", name, member)))
+                        } else if self.enums.contains(name) {
+                            return Rvalue::Variant(member.clone(), self.operands(args, out));
+                        } else {
+                            None
+                        };
+                        if let Some(callee) = callee {
+                            return Rvalue::Call(callee, self.operands(args, out));
+                        }
+                    }
+                }
+                let receiver = self.types.get(&object.span).cloned();
+                let object = self.operand(object, out);
+                match object.ty() {
+                    Type::Struct(name, _) if self.generic.contains_key(&format!("{}.{}", name, member)) => {
+                        // The checker typed `object.member` without the receiver, which the method takes first.
+                        let used = match (receiver, self.types.get(&func.span)) {
+                            (Some(receiver), Some(type_checker::Type::Function { params, return_type })) => Some(type_checker::Type::Function {
+                                params: std::iter::once(receiver).chain(params.iter().cloned()).collect(),
+                                return_type: return_type.clone(),
+                            }),
+                            _ => None,
+                        };
+                        let mut operands = vec![object];
+                        operands.extend(self.operands(args, out));
+                        Rvalue::Call(Callee::Method(self.instance_ref(&format!("{}.{}", name, member), used)), operands)
+                    }
+                    Type::Struct(name, _) if self.methods.get(&(name.clone(), member.clone())) == Some(&true) => {
+                        let mut operands = vec![object];
+                        operands.extend(self.operands(args, out));
+                        Rvalue::Call(Callee::Method(format!("{}_{}", name, member)), operands)
+                    }
+                    Type::List(_) | Type::Map(_) if matches!(member.as_str(), "map" | "filter" | "each") && args.len() == 1 => {
+                        let f = self.operand(&args[0], out);
+                        self.collection_loop(member, object, f, self.ty(&expr.span), out)
+                    }
+                    Type::List(_) | Type::Map(_) => {
+                        let mut operands = vec![object];
+                        operands.extend(self.operands(args, out));
+                        Rvalue::Call(Callee::Collection(member.clone()), operands)
+                    }
+                    Type::Struct(..) => {
+                        let f = self.temp(self.ty(&func.span), Rvalue::Field(object, member.clone()), out);
+                        Rvalue::Call(Callee::Closure(f), self.operands(args, out))
+                    }
+                    // Models from `mind.spawn_model` have no eMo type of their own.
+                    _ if matches!(member.as_str(), "train" | "save" | "think") => {
+                        let mut operands = vec![object];
+                        operands.extend(self.operands(args, out));
+                        Rvalue::Call(Callee::Extern(format!("model_{}", member)), operands)
+                    }
+                    _ => {
+                        let f = self.temp(self.ty(&func.span), Rvalue::Field(object, member.clone()), out);
+                        Rvalue::Call(Callee::Closure(f), self.operands(args, out))
+                    }
+                }
+            }
+            _ => {
+                let f = self.operand(func, out);
+                Rvalue::Call(Callee::Closure(f), self.operands(args, out))
+            }
+        }
+    }
+
+    /// `map`, `filter` and `each` become loops over the list, so that the closure is called
+    /// through its own signature.
+    fn collection_loop(&mut self, method: &str, list: Operand, f: Operand, result_type: Type, out: &mut Vec<Stmt>) -> Rvalue {
+        let item_type = match list.ty() {
+            Type::List(elem) => *elem,
+            _ => Type::Int,
+        };
+        let (_, ret) = function_type(f.ty(), 1);
+        let result = (method != "each").then(|| self.temp(result_type, Rvalue::List(Vec::new()), out));
+        let len = self.temp(Type::Int, Rvalue::Call(Callee::Collection("len".to_string()), vec![list.clone()]), out);
+        self.counted_loop(Operand::Const(Const::Int(0)), len, out, |this, i, body| {
+            let item = this.temp(item_type, Rvalue::Index(list, i), body);
+            let value = this.temp(ret, Rvalue::Call(Callee::Closure(f), vec![item.clone()]), body);
+            let push = |value| Stmt::Eval(Rvalue::Call(Callee::Collection("push".to_string()), vec![result.clone().unwrap_or(Operand::Const(Const::Null)), value]));
+            match method {
+                "map" => body.push(push(value)),
+                "filter" => body.push(Stmt::If { cond: value, then_block: vec![push(item)], else_block: Vec::new() }),
+                _ => {}
+            }
+        });
+        Rvalue::Use(result.unwrap_or(Operand::Const(Const::Null)))
+    }
+
+    /// Lifts a lambda, or a function declared inside another, to a function of its own, and
+    /// returns the closure that calls it with the values of the variables it captures.
+    fn lambda(&mut self, name: Option<&str>, span: &Span, params: &[(String, Option<TypeExpr>)], body: &[Spanned<Statement>]) -> Rvalue {
+        let fn_type = self.ty(span);
+        let (param_types, ret) = function_type(fn_type.clone(), params.len());
+        let bound: HashSet<String> = params.iter().map(|(p, _)| p.clone()).collect();
+        let mut free = Vec::new();
+        free_variables(body, &bound, &mut free);
+        let recursive = name.is_some_and(|name| free.iter().any(|f| f == name));
+        let this = &*self;
+        let captured: Vec<(String, Var)> = free.into_iter()
+            .filter(|f| Some(f.as_str()) != name)
+            // A polymorphic binding is a variable for each instance, and all are captured.
+            .flat_map(|f| (0..).map_while(move |n| {
+                let key = instance_key(&f, n);
+                this.lookup_local(&key).map(|var| (key, var))
+            }))
+            .collect();
+
+        let lifted = format!("__emo_lambda_{}", self.next_lambda);
+        self.next_lambda += 1;
+        let outer = std::mem::replace(&mut self.state, FunctionState::new(ret.clone()));
+        // Inside, each captured variable is read from the environment, under its outer name.
+        let captures: Vec<Var> = captured.iter().map(|(_, var)| {
+            let scope = if matches!(var.scope, Scope::Cell | Scope::CapturedCell) { Scope::CapturedCell } else { Scope::Captured };
+            Var { scope, ..var.clone() }
+        }).collect();
+        for ((f, _), var) in captured.iter().zip(&captures) {
+            self.state.names.insert(var.name.clone());
+            self.state.scopes[0].insert(f.clone(), var.clone());
+        }
+        let params = params.iter().zip(param_types).map(|((p, _), t)| self.declare(p, t)).collect();
+        let mut stmts = Vec::new();
+        if let (true, Some(name)) = (recursive, name) {
+            let own = self.declare(name, fn_type);
+            let env = captures.iter().cloned().map(Operand::Var).collect();
+            stmts.push(Stmt::Let(own, Some(Rvalue::Closure(lifted.clone(), env))));
+        }
+        self.statements(body, &mut stmts);
+        self.state = outer;
+
+        self.lifted.push(Function { name: lifted.clone(), params, ret, body: stmts, public: false, kind: FunctionKind::Lambda { captures } });
+        Rvalue::Closure(lifted, captured.into_iter().map(|(_, var)| Operand::Var(var)).collect())
+    }
+}
+
+/// `name`, or `name_1`, `name_2`, ... if that is taken.
+pub(crate) fn unique_name(name: &str, taken: impl Fn(&str) -> bool) -> String {
+    let mut candidate = name.to_string();
+    let mut n = 0;
+    while taken(&candidate) {
+        n += 1;
+        candidate = format!("{}_{}", name, n);
+    }
+    candidate
+}
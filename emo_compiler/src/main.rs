@@ -1,5 +1,8 @@
 use clap::{Parser as ClapParser, Subcommand};
-use emo_compiler::{compile, run, format_file};
+use emo_compiler::diagnostics::MessageFormat;
+use emo_compiler::optimizer::OptLevel;
+use emo_compiler::{compile_with_options, format_file, run_with_options, BuildOptions, RunOptions};
+use std::path::PathBuf;
 
 #[derive(ClapParser)]
 #[command(name = "eMo Compiler")]
@@ -17,30 +20,82 @@ enum Commands {
         file: String,
         #[arg(short, long)]
         output: Option<String>,
+        /// How to print errors
+        #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+        message_format: MessageFormat,
+        /// Extra directory to search for imported modules (repeatable)
+        #[arg(long = "module-path", value_name = "DIR")]
+        module_paths: Vec<PathBuf>,
+        /// Report type errors without stopping
+        #[arg(long)]
+        allow_type_errors: bool,
+        /// Optimisation level
+        #[arg(short = 'O', value_enum, default_value_t = OptLevel::O1)]
+        opt_level: OptLevel,
     },
     /// Run a script directly (using interpreter)
     Run {
         file: String,
+        /// Maximum depth of nested eMo function calls
+        #[arg(
+            long,
+            default_value_t = emo_compiler::interpreter::DEFAULT_MAX_CALL_DEPTH,
+            value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=emo_compiler::interpreter::MAX_CALL_DEPTH as u64)
+        )]
+        max_call_depth: usize,
+        /// How to print errors
+        #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+        message_format: MessageFormat,
+        /// Extra directory to search for imported modules (repeatable)
+        #[arg(long = "module-path", value_name = "DIR")]
+        module_paths: Vec<PathBuf>,
+        /// Report type errors without stopping
+        #[arg(long)]
+        allow_type_errors: bool,
     },
     /// Format an eMo file
-    Fmt {
-        file: String,
-    },
+    Fmt { file: String },
 }
 
 fn main() {
     let cli = Cli::parse();
 
-    match &cli.command {
-        Commands::Build { file, output } => {
-            compile(file, output.clone());
-        }
-                Commands::Run { file } => {
-                    run(file);
-                }
-                Commands::Fmt { file } => {
-                    format_file(file);
-                }
-            }
-        }
-        
\ No newline at end of file
+    let result = match &cli.command {
+        Commands::Build {
+            file,
+            output,
+            message_format,
+            module_paths,
+            allow_type_errors,
+            opt_level,
+        } => compile_with_options(
+            file,
+            output.clone(),
+            BuildOptions {
+                message_format: *message_format,
+                module_paths: module_paths.clone(),
+                allow_type_errors: *allow_type_errors,
+                opt_level: *opt_level,
+            },
+        ),
+        Commands::Run {
+            file,
+            max_call_depth,
+            message_format,
+            module_paths,
+            allow_type_errors,
+        } => run_with_options(
+            file,
+            RunOptions {
+                max_call_depth: *max_call_depth,
+                message_format: *message_format,
+                module_paths: module_paths.clone(),
+                allow_type_errors: *allow_type_errors,
+            },
+        ),
+        Commands::Fmt { file } => format_file(file),
+    };
+    if result.is_err() {
+        std::process::exit(1);
+    }
+}
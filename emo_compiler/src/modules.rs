@@ -0,0 +1,469 @@
+//! Multi-file programs. `import geo from "lib/geo"` loads `lib/geo.emo` once and links it
+//! into the program ahead of its importer: the module's top-level items are renamed
+//! `geo__item`, and `geo.item` in the importer resolves to that name if the item is
+//! declared `pub`. Every later pass, and the single C translation unit, then sees one
+//! flat program.
+
+use crate::ast::{Expression, MatchArm, Pattern, Spanned, Statement, StringPart, TypeExpr};
+use crate::diagnostics::{Diagnostic, ErrorCode, SourceMap};
+use crate::lexer::Span;
+use crate::parser::Parser;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// `Option` and `Result`, linked ahead of every program under their own names.
+const PRELUDE: &str = include_str!("prelude.emo");
+
+/// Joins a module's prefix to its item names; C-safe, so linked names need no mangling.
+const SEPARATOR: &str = "__";
+
+/// A loaded module: the prefix its items were renamed with, the names it defines and
+/// the `pub` ones among them, which are all other files may use.
+#[derive(Clone)]
+struct Module {
+    prefix: String,
+    names: HashSet<String>,
+    exports: HashSet<String>,
+}
+
+pub struct LinkedProgram {
+    /// The prelude, then modules in dependency order, then the entry file.
+    pub statements: Vec<Spanned<Statement>>,
+    pub sources: SourceMap,
+    /// Syntax errors from every file and problems resolving imports.
+    pub errors: Vec<Diagnostic>,
+}
+
+pub struct ModuleLoader {
+    search_paths: Vec<PathBuf>,
+    sources: SourceMap,
+    /// Loaded modules by canonical path, so each is parsed and linked once.
+    modules: HashMap<PathBuf, Module>,
+    /// Files being loaded, outermost first, to detect import cycles.
+    loading: Vec<PathBuf>,
+    statements: Vec<Spanned<Statement>>,
+    errors: Vec<Diagnostic>,
+}
+
+impl ModuleLoader {
+    /// Imports resolve against the importing file's directory, then `search_paths` in
+    /// order, then the directories listed in the `EMO_PATH` environment variable.
+    pub fn new(mut search_paths: Vec<PathBuf>) -> Self {
+        if let Some(paths) = std::env::var_os("EMO_PATH") {
+            search_paths.extend(std::env::split_paths(&paths));
+        }
+        Self {
+            search_paths,
+            sources: SourceMap::new(),
+            modules: HashMap::new(),
+            loading: Vec::new(),
+            statements: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Loads `file` and everything it imports. Only failing to read `file` itself is
+    /// fatal; other problems are collected in the program's errors.
+    pub fn load(mut self, file: &str) -> std::io::Result<LinkedProgram> {
+        let source = fs::read_to_string(file)?;
+        let path = fs::canonicalize(file)?;
+        let base = self.sources.add_file("<prelude>", PRELUDE);
+        self.statements.extend(Parser::with_base(PRELUDE, base).parse_program().0);
+        self.link_file(file, &source, path, None);
+        Ok(LinkedProgram { statements: self.statements, sources: self.sources, errors: self.errors })
+    }
+
+    /// Parses one file, links the modules it imports ahead of it and appends it to the
+    /// program. The entry file has no `prefix` and keeps its names. Returns the file's
+    /// top-level names and its public ones.
+    fn link_file(&mut self, name: &str, source: &str, path: PathBuf, prefix: Option<&str>) -> (HashSet<String>, HashSet<String>) {
+        let base = self.sources.add_file(name, source);
+        let (mut statements, syntax_errors) = Parser::with_base(source, base).parse_program();
+        self.errors.extend(syntax_errors.into_iter().map(Diagnostic::from));
+
+        self.loading.push(path);
+        let mut aliases = HashMap::new();
+        for stmt in &statements {
+            if let Statement::Import { module, source: Some(target) } = &stmt.node {
+                if let Some(imported) = self.import(target, module, &stmt.span) {
+                    aliases.insert(module.clone(), imported);
+                }
+            }
+        }
+        self.loading.pop();
+
+        let names = top_level_names(&statements);
+        let exports = public_names(&statements);
+        let globals = match prefix {
+            Some(prefix) => names.iter().map(|n| (n.clone(), format!("{}{}{}", prefix, SEPARATOR, n))).collect(),
+            None => HashMap::new(),
+        };
+        let mut renamer = Renamer { globals, aliases, scopes: Vec::new(), type_params: Vec::new(), errors: &mut self.errors };
+        for stmt in &mut statements {
+            renamer.statement(stmt);
+        }
+        self.statements.extend(statements);
+        (names, exports)
+    }
+
+    fn import(&mut self, target: &str, alias: &str, span: &Span) -> Option<Module> {
+        let Some(path) = self.resolve(target) else {
+            self.errors.push(Diagnostic::new(ErrorCode::ModuleNotFound, format!("Cannot find module \"{}\"", target), Some(span.clone())));
+            return None;
+        };
+        if let Some(start) = self.loading.iter().position(|p| *p == path) {
+            let cycle: Vec<String> = self.loading[start..].iter().chain([&path]).map(|p| display_path(p)).collect();
+            self.errors.push(Diagnostic::new(ErrorCode::ImportCycle, format!("Import cycle: {}", cycle.join(" -> ")), Some(span.clone())));
+            return None;
+        }
+        if let Some(module) = self.modules.get(&path) {
+            return Some(module.clone());
+        }
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                self.errors.push(Diagnostic::new(ErrorCode::ModuleNotFound, format!("Could not read module \"{}\": {}", target, e), Some(span.clone())));
+                return None;
+            }
+        };
+        // Two different files imported under the same name still need distinct prefixes.
+        let mut prefix = alias.to_string();
+        let mut n = 1;
+        while self.modules.values().any(|m| m.prefix == prefix) {
+            n += 1;
+            prefix = format!("{}{}", alias, n);
+        }
+        let (names, exports) = self.link_file(&display_path(&path), &source, path.clone(), Some(&prefix));
+        let module = Module { prefix, names, exports };
+        self.modules.insert(path, module.clone());
+        Some(module)
+    }
+
+    /// Finds `target`, with `.emo` added when it has no extension, relative to the
+    /// importing file and then along the search path.
+    fn resolve(&self, target: &str) -> Option<PathBuf> {
+        let mut relative = PathBuf::from(target);
+        if relative.extension().is_none() {
+            relative.set_extension("emo");
+        }
+        let importer_dir = self.loading.last().and_then(|p| p.parent()).map(Path::to_path_buf).unwrap_or_default();
+        std::iter::once(importer_dir)
+            .chain(self.search_paths.iter().cloned())
+            .map(|dir| dir.join(&relative))
+            .find(|p| p.is_file())
+            .and_then(|p| fs::canonicalize(p).ok())
+    }
+}
+
+/// Paths in messages are relative to the working directory when possible.
+fn display_path(path: &Path) -> String {
+    let cwd = std::env::current_dir().ok().and_then(|d| fs::canonicalize(d).ok());
+    cwd.and_then(|cwd| path.strip_prefix(cwd).ok().map(|p| p.display().to_string()))
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+fn top_level_names(statements: &[Spanned<Statement>]) -> HashSet<String> {
+    statements.iter().filter_map(|stmt| match &stmt.node {
+        Statement::FunctionDef { name, .. }
+        | Statement::StructDef { name, .. }
+        | Statement::EnumDef { name, .. }
+        | Statement::Let { name, .. }
+        | Statement::Const { name, .. } => Some(name.clone()),
+        _ => None,
+    }).collect()
+}
+
+fn public_names(statements: &[Spanned<Statement>]) -> HashSet<String> {
+    statements.iter().filter_map(|stmt| match &stmt.node {
+        Statement::FunctionDef { public: true, name, .. }
+        | Statement::StructDef { public: true, name, .. }
+        | Statement::EnumDef { public: true, name, .. }
+        | Statement::Const { public: true, name, .. } => Some(name.clone()),
+        _ => None,
+    }).collect()
+}
+
+/// Rewrites one file's names for linking: its own top-level names (unless shadowed by a
+/// local) become their linked names, and `alias.item` becomes the imported item's.
+struct Renamer<'a> {
+    globals: HashMap<String, String>,
+    aliases: HashMap<String, Module>,
+    /// Local bindings, innermost scope last; empty at the top level.
+    scopes: Vec<HashSet<String>>,
+    /// Type parameters of the struct or enum being renamed.
+    type_params: Vec<String>,
+    errors: &'a mut Vec<Diagnostic>,
+}
+
+impl Renamer<'_> {
+    fn is_local(&self, name: &str) -> bool {
+        self.scopes.iter().any(|s| s.contains(name))
+    }
+
+    /// Binds `name` in the current scope, or renames it if it is a top-level definition.
+    fn define(&mut self, name: &mut String) {
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                scope.insert(name.clone());
+            }
+            None => self.rename_value(name),
+        }
+    }
+
+    fn rename_value(&mut self, name: &mut String) {
+        if !self.is_local(name) {
+            if let Some(linked) = self.globals.get(name.as_str()) {
+                *name = linked.clone();
+            }
+        }
+    }
+
+    /// The linked name of `alias.member`, or None if `alias` is not an imported module.
+    fn module_member(&mut self, alias: &str, member: &str, span: &Span) -> Option<String> {
+        let module = self.aliases.get(alias)?;
+        if !module.names.contains(member) {
+            self.errors.push(Diagnostic::new(ErrorCode::UnknownMember, format!("Module {} has no member {}", alias, member), Some(span.clone())));
+        } else if !module.exports.contains(member) {
+            self.errors.push(Diagnostic::new(ErrorCode::PrivateItem, format!("{} is private to module {}; declare it pub to use it here", member, alias), Some(span.clone())));
+        }
+        Some(format!("{}{}{}", module.prefix, SEPARATOR, member))
+    }
+
+    /// Type names live apart from values, so locals never shadow them.
+    fn rename_type_name(&mut self, name: &mut String, span: &Span) {
+        if self.type_params.contains(name) {
+            return;
+        }
+        if let Some((alias, member)) = name.split_once('.') {
+            if let Some(linked) = self.module_member(alias, member, span) {
+                *name = linked;
+            }
+        } else if let Some(linked) = self.globals.get(name.as_str()) {
+            *name = linked.clone();
+        }
+    }
+
+    fn block(&mut self, body: &mut [Spanned<Statement>], bound: HashSet<String>) {
+        self.scopes.push(bound);
+        for stmt in body {
+            self.statement(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn function(&mut self, params: &mut [(String, Option<TypeExpr>)], return_type: &mut Option<TypeExpr>, body: &mut [Spanned<Statement>], span: &Span) {
+        for ty in params.iter_mut().filter_map(|(_, t)| t.as_mut()).chain(return_type.as_mut()) {
+            self.type_expr(ty, span);
+        }
+        self.block(body, params.iter().map(|(p, _)| p.clone()).collect());
+    }
+
+    fn statement(&mut self, stmt: &mut Spanned<Statement>) {
+        let span = stmt.span.clone();
+        match &mut stmt.node {
+            Statement::FunctionDef { name, params, return_type, body, .. } => {
+                self.define(name);
+                self.function(params, return_type, body, &span);
+            }
+            Statement::Let { name, ty, value, .. } | Statement::Const { name, ty, value, .. } => {
+                if let Some(ty) = ty {
+                    self.type_expr(ty, &span);
+                }
+                self.expression(value);
+                self.define(name);
+            }
+            Statement::Set { name, value } => {
+                self.expression(value);
+                self.rename_value(name);
+            }
+            Statement::IndexSet { object, index, value } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+            }
+            Statement::Return(expr) | Statement::Expression(expr) => self.expression(expr),
+            Statement::If { cond, then_block, else_block } => {
+                self.expression(cond);
+                self.block(then_block, HashSet::new());
+                if let Some(else_block) = else_block {
+                    self.block(else_block, HashSet::new());
+                }
+            }
+            Statement::Loop { count, body } => {
+                if let Some(count) = count {
+                    self.expression(count);
+                }
+                self.block(body, HashSet::new());
+            }
+            Statement::While { cond, body } => {
+                self.expression(cond);
+                self.block(body, HashSet::new());
+            }
+            Statement::ForIn { var, iterable, body } => {
+                self.expression(iterable);
+                self.block(body, HashSet::from([var.clone()]));
+            }
+            Statement::StructDef { name, type_params, fields, .. } => {
+                self.define(name);
+                self.type_params = type_params.clone();
+                for (_, ty) in fields {
+                    self.type_expr(ty, &span);
+                }
+                self.type_params.clear();
+            }
+            Statement::EnumDef { name, type_params, variants, .. } => {
+                self.define(name);
+                self.type_params = type_params.clone();
+                for ty in variants.iter_mut().flat_map(|(_, payload)| payload) {
+                    self.type_expr(ty, &span);
+                }
+                self.type_params.clear();
+            }
+            Statement::UnsafeBlock(body) => self.block(body, HashSet::new()),
+            Statement::TryCatch { body, error, handler } => {
+                self.block(body, HashSet::new());
+                self.block(handler, HashSet::from([error.clone()]));
+            }
+            Statement::Match { subject, arms } => {
+                self.expression(subject);
+                self.arms(arms);
+            }
+            Statement::Impl { type_name, methods } => {
+                self.rename_type_name(type_name, &span);
+                // Method names are reached through their struct and stay as they are.
+                for method in methods {
+                    let method_span = method.span.clone();
+                    if let Statement::FunctionDef { params, return_type, body, .. } = &mut method.node {
+                        self.function(params, return_type, body, &method_span);
+                    }
+                }
+            }
+            Statement::Import { .. } | Statement::Break | Statement::Continue | Statement::Error => {}
+        }
+    }
+
+    fn expression(&mut self, expr: &mut Spanned<Expression>) {
+        match &mut expr.node {
+            Expression::Identifier(name) => self.rename_value(name),
+            Expression::MemberAccess { object, member } => {
+                let linked = match &object.node {
+                    Expression::Identifier(alias) if !self.is_local(alias) => self.module_member(alias, member, &expr.span),
+                    _ => None,
+                };
+                match linked {
+                    Some(linked) => expr.node = Expression::Identifier(linked),
+                    None => self.expression(object),
+                }
+            }
+            Expression::StructLiteral { name, fields } => {
+                self.rename_type_name(name, &expr.span);
+                for (_, value) in fields {
+                    self.expression(value);
+                }
+            }
+            Expression::Interpolated(parts) => {
+                for part in parts {
+                    if let StringPart::Expr(e) = part {
+                        self.expression(e);
+                    }
+                }
+            }
+            Expression::BinaryOp(left, _, right) => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expression::Unary(_, operand) | Expression::Try(operand) => self.expression(operand),
+            Expression::Call { func, args } => {
+                self.expression(func);
+                for arg in args {
+                    self.expression(arg);
+                }
+            }
+            Expression::ListLiteral(items) => {
+                for item in items {
+                    self.expression(item);
+                }
+            }
+            Expression::MapLiteral(entries) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+            Expression::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+            }
+            Expression::Range { start, end } => {
+                self.expression(start);
+                self.expression(end);
+            }
+            Expression::Lambda { params, return_type, body } => {
+                let span = expr.span.clone();
+                self.function(params, return_type, body, &span);
+            }
+            Expression::If { cond, then_block, else_block } => {
+                self.expression(cond);
+                self.block(then_block, HashSet::new());
+                if let Some(else_block) = else_block {
+                    self.block(else_block, HashSet::new());
+                }
+            }
+            Expression::Match { subject, arms } => {
+                self.expression(subject);
+                self.arms(arms);
+            }
+            Expression::Block(body) => self.block(body, HashSet::new()),
+            Expression::Number(_) | Expression::Float(_) | Expression::StringLit(_) | Expression::Bool(_)
+            | Expression::Null | Expression::Error => {}
+        }
+    }
+
+    fn arms(&mut self, arms: &mut [MatchArm]) {
+        for arm in arms {
+            self.pattern(&mut arm.pattern);
+            let mut names = Vec::new();
+            arm.pattern.node.bindings(&mut names);
+            self.block(&mut arm.body, names.into_iter().collect());
+        }
+    }
+
+    fn pattern(&mut self, pattern: &mut Spanned<Pattern>) {
+        let span = pattern.span.clone();
+        match &mut pattern.node {
+            Pattern::Variant { enum_name, fields, .. } => {
+                self.rename_type_name(enum_name, &span);
+                for field in fields {
+                    self.pattern(field);
+                }
+            }
+            Pattern::Struct { name, fields } => {
+                self.rename_type_name(name, &span);
+                for (_, field) in fields {
+                    self.pattern(field);
+                }
+            }
+            Pattern::Wildcard | Pattern::Binding(_) | Pattern::Literal(_) => {}
+        }
+    }
+
+    fn type_expr(&mut self, ty: &mut TypeExpr, span: &Span) {
+        match ty {
+            TypeExpr::Named(name) => self.rename_type_name(name, span),
+            TypeExpr::Generic(name, args) => {
+                self.rename_type_name(name, span);
+                for arg in args {
+                    self.type_expr(arg, span);
+                }
+            }
+            TypeExpr::List(elem) | TypeExpr::Map(elem) => self.type_expr(elem, span),
+            TypeExpr::Function(params, ret) => {
+                for param in params {
+                    self.type_expr(param, span);
+                }
+                self.type_expr(ret, span);
+            }
+        }
+    }
+}
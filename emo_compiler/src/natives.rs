@@ -0,0 +1,145 @@
+//! The native modules built into eMo (`sys`, `math`, `json`, ...) and their signatures.
+//!
+//! This table is the one list of what exists: the interpreter binds every entry to its
+//! implementation in `call_native`, and the type checker gives it the signature written here.
+
+use crate::ast::TypeExpr;
+use crate::parser::Parser;
+
+/// A native function, `module.name`. `signature` is an eMo function type in which `any`
+/// stands for a type of the caller's choosing, separately for each parameter, and `number`
+/// for one of `int`, `float` or a sized integer, the same one throughout the signature.
+pub struct NativeFn {
+    pub module: &'static str,
+    pub name: &'static str,
+    pub signature: &'static str,
+    pub arity: Arity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    /// Exactly the parameters of the signature.
+    Fixed,
+    /// The first `n` parameters; the rest may be left out.
+    AtLeast(usize),
+    /// All parameters but the last, then any number of arguments of the last one's type.
+    Variadic,
+}
+
+const fn native(module: &'static str, name: &'static str, signature: &'static str, arity: Arity) -> NativeFn {
+    NativeFn { module, name, signature, arity }
+}
+
+pub const NATIVE_FUNCTIONS: &[NativeFn] = &[
+    native("sys", "log", "fn(any) -> void", Arity::Variadic),
+    native("sys", "poll", "fn() -> int", Arity::Fixed),
+    native("sys", "hold", "fn(str, any) -> void", Arity::Fixed),
+    native("sys", "release", "fn(str) -> any", Arity::Fixed),
+    native("sys", "read_file", "fn(str) -> Result<str, str>", Arity::Fixed),
+    native("sys", "write_file", "fn(str, str) -> Result<void, str>", Arity::Fixed),
+    native("sys", "append_file", "fn(str, str) -> Result<void, str>", Arity::Fixed),
+    // Pointers and libraries have no eMo type of their own.
+    native("sys", "alloc", "fn(int) -> any", Arity::Fixed),
+    native("sys", "free", "fn(any) -> void", Arity::Fixed),
+    native("sys", "peek", "fn(any) -> int", Arity::Fixed),
+    native("sys", "poke", "fn(any, int) -> void", Arity::Fixed),
+    native("sys", "load_lib", "fn(str) -> any", Arity::Fixed),
+    native("sys", "call_ffi", "fn(any, str, any) -> int", Arity::Variadic),
+    native("joy", "say", "fn(any) -> void", Arity::Variadic),
+    native("math", "sqrt", "fn(number) -> float", Arity::Fixed),
+    native("math", "sin", "fn(number) -> float", Arity::Fixed),
+    native("math", "cos", "fn(number) -> float", Arity::Fixed),
+    native("math", "pow", "fn(number, number) -> float", Arity::Fixed),
+    native("math", "abs", "fn(number) -> number", Arity::Fixed),
+    native("math", "min", "fn(number, number) -> number", Arity::Fixed),
+    native("math", "max", "fn(number, number) -> number", Arity::Fixed),
+    native("json", "parse", "fn(str) -> Result<any, str>", Arity::Fixed),
+    native("json", "stringify", "fn(any) -> str", Arity::Fixed),
+    native("mind", "think", "fn(str) -> str", Arity::Fixed),
+    native("mind", "spawn_model", "fn(str, str) -> any", Arity::AtLeast(0)),
+    native("net", "fetch", "fn(str) -> Result<str, str>", Arity::Fixed),
+    native("void", "absorb", "fn(str) -> str", Arity::Fixed),
+    native("void", "synthesize_lib", "fn(str, str) -> str", Arity::Fixed),
+    native("time", "now", "fn() -> int", Arity::Fixed),
+    native("time", "sleep", "fn(int) -> void", Arity::Fixed),
+    native("os", "exit", "fn(int) -> void", Arity::AtLeast(0)),
+    native("os", "env", "fn(str) -> str", Arity::Fixed),
+    native("os", "args", "fn() -> [str]", Arity::Fixed),
+    native("os", "name", "fn() -> str", Arity::Fixed),
+    native("random", "int", "fn(int, int) -> int", Arity::AtLeast(0)),
+    native("str", "len", "fn(str) -> int", Arity::Fixed),
+    native("str", "split", "fn(str, str) -> [str]", Arity::Fixed),
+    native("str", "trim", "fn(str) -> str", Arity::Fixed),
+    native("str", "contains", "fn(str, str) -> bool", Arity::Fixed),
+    native("str", "replace", "fn(str, str, str) -> str", Arity::Fixed),
+    native("str", "upper", "fn(str) -> str", Arity::Fixed),
+    native("str", "lower", "fn(str) -> str", Arity::Fixed),
+    native("str", "to_int", "fn(str) -> Result<int, str>", Arity::Fixed),
+];
+
+/// Native functions also bound as plain globals, with the native each one is.
+pub const NATIVE_GLOBALS: &[(&str, &str, &str)] = &[("log", "sys", "log")];
+
+/// Natives the interpreter provides but compiled programs cannot call, as (module, name).
+pub const INTERPRETER_ONLY: &[(&str, &str)] = &[
+    ("sys", "hold"),
+    ("sys", "release"),
+    ("sys", "write_file"),
+    ("sys", "append_file"),
+    ("sys", "alloc"),
+    ("sys", "free"),
+    ("sys", "peek"),
+    ("sys", "poke"),
+    ("sys", "load_lib"),
+    ("sys", "call_ffi"),
+    ("json", "parse"),
+    ("json", "stringify"),
+    ("os", "exit"),
+    ("os", "env"),
+    ("os", "args"),
+    ("os", "name"),
+    ("random", "int"),
+];
+
+/// Every native module name, in table order.
+pub fn modules() -> impl Iterator<Item = &'static str> {
+    let mut seen = Vec::new();
+    NATIVE_FUNCTIONS.iter().map(|f| f.module).filter(move |m| {
+        let new = !seen.contains(m);
+        seen.push(*m);
+        new
+    })
+}
+
+pub fn is_module(name: &str) -> bool {
+    NATIVE_FUNCTIONS.iter().any(|f| f.module == name)
+}
+
+pub fn lookup(module: &str, name: &str) -> Option<&'static NativeFn> {
+    NATIVE_FUNCTIONS.iter().find(|f| f.module == module && f.name == name)
+}
+
+/// The native function bound to the global `name`, such as `log`.
+pub fn global(name: &str) -> Option<&'static NativeFn> {
+    NATIVE_GLOBALS.iter().find(|(global, ..)| *global == name).and_then(|(_, module, function)| lookup(module, function))
+}
+
+impl NativeFn {
+    /// `module.name`, the name `call_native` dispatches on.
+    pub fn qualified_name(&self) -> String {
+        format!("{}.{}", self.module, self.name)
+    }
+
+    /// Whether the C runtime implements this native, so compiled programs may call it.
+    pub fn is_compiled(&self) -> bool {
+        !INTERPRETER_ONLY.contains(&(self.module, self.name))
+    }
+
+    /// The parameter and result types written in `signature`.
+    pub fn signature(&self) -> (Vec<TypeExpr>, TypeExpr) {
+        match Parser::new(self.signature).parse_type() {
+            Ok(TypeExpr::Function(params, ret)) => (params, *ret),
+            _ => panic!("malformed signature for native {}: {}", self.qualified_name(), self.signature),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::ast::Op;
+
+/// An integer type. `I64` is `int` itself; the others are the sized types from `<stdint.h>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IntKind {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+pub const SIZED_INTS: &[IntKind] = &[IntKind::I8, IntKind::I16, IntKind::I32, IntKind::U8, IntKind::U16, IntKind::U32, IntKind::U64];
+
+impl IntKind {
+    /// The sized type named `name`; `byte` is `u8`. `int` and `i64` are not sized types.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "i8" => IntKind::I8,
+            "i16" => IntKind::I16,
+            "i32" => IntKind::I32,
+            "u8" | "byte" => IntKind::U8,
+            "u16" => IntKind::U16,
+            "u32" => IntKind::U32,
+            "u64" => IntKind::U64,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IntKind::I8 => "i8",
+            IntKind::I16 => "i16",
+            IntKind::I32 => "i32",
+            IntKind::I64 => "int",
+            IntKind::U8 => "u8",
+            IntKind::U16 => "u16",
+            IntKind::U32 => "u32",
+            IntKind::U64 => "u64",
+        }
+    }
+
+    pub fn c_type(self) -> &'static str {
+        match self {
+            IntKind::I8 => "int8_t",
+            IntKind::I16 => "int16_t",
+            IntKind::I32 => "int32_t",
+            IntKind::I64 => "emo_int",
+            IntKind::U8 => "uint8_t",
+            IntKind::U16 => "uint16_t",
+            IntKind::U32 => "uint32_t",
+            IntKind::U64 => "uint64_t",
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            IntKind::I8 | IntKind::U8 => 8,
+            IntKind::I16 | IntKind::U16 => 16,
+            IntKind::I32 | IntKind::U32 => 32,
+            IntKind::I64 | IntKind::U64 => 64,
+        }
+    }
+
+    pub fn signed(self) -> bool {
+        matches!(self, IntKind::I8 | IntKind::I16 | IntKind::I32 | IntKind::I64)
+    }
+
+    pub fn min(self) -> i128 {
+        if self.signed() { -(1 << (self.bits() - 1)) } else { 0 }
+    }
+
+    pub fn max(self) -> i128 {
+        if self.signed() { (1 << (self.bits() - 1)) - 1 } else { (1 << self.bits()) - 1 }
+    }
+
+    pub fn contains(self, v: i128) -> bool {
+        (self.min()..=self.max()).contains(&v)
+    }
+
+    /// Keeps the low `bits` bits of `v`, as two's complement hardware does.
+    pub fn wrap(self, v: i128) -> i128 {
+        let bits = self.bits();
+        let low = (v as u128) & ((1u128 << bits) - 1);
+        if self.signed() && low >> (bits - 1) == 1 { low as i128 - (1i128 << bits) } else { low as i128 }
+    }
+}
+
+/// What `+`, `-` and `*` do when the result does not fit the type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// The default for operators: a runtime error.
+    Checked,
+    Wrapping,
+    Saturating,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithError {
+    Overflow,
+    DivisionByZero,
+}
+
+/// `l op r` for two values of `kind`, where `op` is `+`, `-`, `*`, `/` or `%`.
+/// Division truncates toward zero and never wraps or saturates.
+pub fn arith(kind: IntKind, l: i128, op: &Op, r: i128, overflow: Overflow) -> Result<i128, ArithError> {
+    let exact = match op {
+        Op::Plus => l.checked_add(r),
+        Op::Minus => l.checked_sub(r),
+        Op::Mul => l.checked_mul(r),
+        Op::Div | Op::Mod if r == 0 => return Err(ArithError::DivisionByZero),
+        Op::Div => Some(l / r),
+        Op::Mod => Some(l % r),
+        _ => unreachable!("not an arithmetic operator"),
+    };
+    let overflow = if matches!(op, Op::Div | Op::Mod) { Overflow::Checked } else { overflow };
+    match (exact, overflow) {
+        (Some(v), _) if kind.contains(v) => Ok(v),
+        (_, Overflow::Checked) => Err(ArithError::Overflow),
+        (Some(v), Overflow::Wrapping) => Ok(kind.wrap(v)),
+        // Only a product of two 64-bit values can leave i128; its low bits are still exact.
+        (None, Overflow::Wrapping) => Ok(kind.wrap(l.wrapping_mul(r))),
+        (Some(v), Overflow::Saturating) => Ok(v.clamp(kind.min(), kind.max())),
+        (None, Overflow::Saturating) => Ok(if (l < 0) != (r < 0) { kind.min() } else { kind.max() }),
+    }
+}
+
+/// Built-in functions that add, subtract or multiply two integers of one type without the
+/// overflow check of the operators.
+pub const OVERFLOW_HELPERS: &[&str] = &["wrapping_add", "wrapping_sub", "wrapping_mul", "saturating_add", "saturating_sub", "saturating_mul"];
+
+/// The operator and overflow behaviour of an `OVERFLOW_HELPERS` function.
+pub fn overflow_helper(name: &str) -> Option<(Op, Overflow)> {
+    let (mode, op) = name.split_once('_')?;
+    let mode = match mode {
+        "wrapping" => Overflow::Wrapping,
+        "saturating" => Overflow::Saturating,
+        _ => return None,
+    };
+    let op = match op {
+        "add" => Op::Plus,
+        "sub" => Op::Minus,
+        "mul" => Op::Mul,
+        _ => return None,
+    };
+    Some((op, mode))
+}
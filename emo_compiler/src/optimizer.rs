@@ -0,0 +1,517 @@
+//! Optimisation passes over the IR, run between lowering and code generation.
+//!
+//! Every pass keeps the program's behaviour, runtime errors included: an operation on
+//! constants that would overflow or divide by zero is left for the program to report.
+
+use crate::ast::{Op, UnaryOp};
+use crate::ir::{Callee, Const, Function, FunctionKind, Operand, Program, Rvalue, Scope, Stmt, Type, Var};
+use crate::lowering::{is_reserved, unique_name};
+use crate::numeric::{self, IntKind, Overflow};
+use std::collections::{HashMap, HashSet};
+
+/// How much `emo build` optimises, chosen with `-O0`, `-O1` or `-O2`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OptLevel {
+    /// No optimisation: the IR is generated as lowered.
+    #[value(name = "0")]
+    O0,
+    /// Constant folding and propagation and removal of unreachable code.
+    #[default]
+    #[value(name = "1")]
+    O1,
+    /// `-O1`, plus inlining of small functions and removal of functions that are never used.
+    #[value(name = "2")]
+    O2,
+}
+
+/// Functions with at most this many statements, and no control flow, are inlined at `-O2`.
+const INLINE_LIMIT: usize = 8;
+
+/// Rounds of the `-O1` passes before giving up on reaching a fixed point.
+const MAX_ROUNDS: usize = 16;
+
+pub fn optimize(program: &mut Program, level: OptLevel) {
+    if level == OptLevel::O0 {
+        return;
+    }
+    simplify(program);
+    if level == OptLevel::O2 {
+        if inline_functions(program) {
+            simplify(program);
+        }
+        remove_dead_functions(program);
+    }
+}
+
+/// Runs the `-O1` passes until none of them changes anything, since each can expose work
+/// for the others.
+fn simplify(program: &mut Program) {
+    for _ in 0..MAX_ROUNDS {
+        let changed = propagate_consts(program) | fold_constants(program) | remove_unreachable(program);
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// The top-level statement lists: every function body and the script's top-level code.
+fn bodies(program: &mut Program) -> impl Iterator<Item = &mut Vec<Stmt>> {
+    program.functions.iter_mut().map(|f| &mut f.body).chain(std::iter::once(&mut program.init))
+}
+
+/// Calls `f` on every statement in `stmts`, each before the statements nested in it.
+fn walk(stmts: &mut [Stmt], f: &mut impl FnMut(&mut Stmt)) {
+    for stmt in stmts {
+        f(stmt);
+        for block in stmt.blocks_mut() {
+            walk(block, f);
+        }
+    }
+}
+
+// ---- Constant folding ----
+
+/// Replaces operations on constants with their results, and an `if` on a constant with the
+/// branch it takes. Returns whether anything changed.
+pub fn fold_constants(program: &mut Program) -> bool {
+    bodies(program).fold(false, |changed, body| fold_block(body) | changed)
+}
+
+fn fold_block(stmts: &mut Vec<Stmt>) -> bool {
+    let mut changed = false;
+    let mut out = Vec::with_capacity(stmts.len());
+    for mut stmt in stmts.drain(..) {
+        for block in stmt.blocks_mut() {
+            changed |= fold_block(block);
+        }
+        match stmt {
+            Stmt::If { cond: Operand::Const(Const::Bool(cond)), then_block, else_block } => {
+                changed = true;
+                let taken = if cond { then_block } else { else_block };
+                if !taken.is_empty() {
+                    out.push(Stmt::Block(taken));
+                }
+            }
+            Stmt::Let(var, Some(mut value)) => {
+                changed |= fold_value(&mut value, &var.ty);
+                out.push(Stmt::Let(var, Some(value)));
+            }
+            Stmt::Assign(var, mut value) => {
+                changed |= fold_value(&mut value, &var.ty);
+                out.push(Stmt::Assign(var, value));
+            }
+            stmt => out.push(stmt),
+        }
+    }
+    *stmts = out;
+    changed
+}
+
+/// Folds `value`, stored in a variable of type `dest`, if its operands are constants.
+fn fold_value(value: &mut Rvalue, dest: &Type) -> bool {
+    let folded = match value {
+        Rvalue::Binary(op, Operand::Const(left), Operand::Const(right)) => fold_binary(op, left, right, dest),
+        Rvalue::Unary(op, Operand::Const(operand)) => fold_unary(op, operand, dest),
+        Rvalue::Convert(Operand::Const(Const::Int(n))) => match dest {
+            Type::Float => Some(Const::Float(*n as f64)),
+            Type::Int => Some(Const::Int(*n)),
+            Type::Sized(kind) if kind.contains(*n as i128) => Some(Const::Int(*n)),
+            _ => None,
+        },
+        _ => None,
+    };
+    match folded {
+        Some(c) => {
+            *value = Rvalue::Use(Operand::Const(c));
+            true
+        }
+        None => false,
+    }
+}
+
+/// `left op right`, or `None` if it fails at runtime or is not worth computing here.
+/// Integer arithmetic is done at the width of the result, as the generated code does.
+fn fold_binary(op: &Op, left: &Const, right: &Const, dest: &Type) -> Option<Const> {
+    match (left, right) {
+        (Const::Int(l), Const::Int(r)) => {
+            let (l, r) = (*l as i128, *r as i128);
+            let kind = match dest {
+                Type::Sized(kind) => *kind,
+                _ => IntKind::I64,
+            };
+            let value = match op {
+                Op::Plus | Op::Minus | Op::Mul | Op::Div | Op::Mod => numeric::arith(kind, l, op, r, Overflow::Checked).ok()?,
+                Op::Shl | Op::Shr if !(0..kind.bits() as i128).contains(&r) => return None,
+                Op::Shl => kind.wrap(l << r),
+                Op::Shr => l >> r,
+                Op::BitAnd => l & r,
+                Op::BitOr => l | r,
+                Op::BitXor => l ^ r,
+                _ => return compare(op, l.cmp(&r)).map(Const::Bool),
+            };
+            if !kind.contains(value) {
+                return None;
+            }
+            i64::try_from(value).ok().map(Const::Int)
+        }
+        (Const::Float(l), Const::Float(r)) => match op {
+            Op::Plus => Some(Const::Float(l + r)),
+            Op::Minus => Some(Const::Float(l - r)),
+            Op::Mul => Some(Const::Float(l * r)),
+            Op::Div => Some(Const::Float(l / r)),
+            Op::Mod => Some(Const::Float(l % r)),
+            Op::Eq => Some(Const::Bool(l == r)),
+            Op::NotEq => Some(Const::Bool(l != r)),
+            Op::Gt => Some(Const::Bool(l > r)),
+            Op::Lt => Some(Const::Bool(l < r)),
+            Op::Gte => Some(Const::Bool(l >= r)),
+            Op::Lte => Some(Const::Bool(l <= r)),
+            _ => None,
+        },
+        (Const::Bool(l), Const::Bool(r)) => match op {
+            Op::And => Some(Const::Bool(*l && *r)),
+            Op::Or => Some(Const::Bool(*l || *r)),
+            Op::Eq => Some(Const::Bool(l == r)),
+            Op::NotEq => Some(Const::Bool(l != r)),
+            _ => None,
+        },
+        (Const::Str(l), Const::Str(r)) => match op {
+            Op::Plus => Some(Const::Str(format!("{}{}", l, r))),
+            // Strings compare byte by byte, as `strcmp` does.
+            _ => compare(op, l.as_bytes().cmp(r.as_bytes())).map(Const::Bool),
+        },
+        _ => None,
+    }
+}
+
+/// The result of comparison operator `op` for operands that compare as `ordering`.
+fn compare(op: &Op, ordering: std::cmp::Ordering) -> Option<bool> {
+    use std::cmp::Ordering::*;
+    match op {
+        Op::Eq => Some(ordering == Equal),
+        Op::NotEq => Some(ordering != Equal),
+        Op::Gt => Some(ordering == Greater),
+        Op::Lt => Some(ordering == Less),
+        Op::Gte => Some(ordering != Less),
+        Op::Lte => Some(ordering != Greater),
+        _ => None,
+    }
+}
+
+fn fold_unary(op: &UnaryOp, operand: &Const, dest: &Type) -> Option<Const> {
+    match (op, operand) {
+        (UnaryOp::Neg, Const::Int(_)) => fold_binary(&Op::Minus, &Const::Int(0), operand, dest),
+        (UnaryOp::Neg, Const::Float(x)) => Some(Const::Float(-x)),
+        (UnaryOp::Not, Const::Bool(b)) => Some(Const::Bool(!b)),
+        _ => None,
+    }
+}
+
+// ---- Constant propagation ----
+
+/// Replaces reads of variables that only ever hold one constant with that constant, and
+/// drops the variables: locals initialised with a constant and never assigned again, and
+/// globals such as those of top-level `const`s, set once before the script's top-level
+/// code runs anything else. Returns whether anything changed.
+pub fn propagate_consts(program: &mut Program) -> bool {
+    let mut changed = propagate_globals(program);
+    for body in bodies(program) {
+        changed |= propagate_locals(body);
+    }
+    changed
+}
+
+fn propagate_globals(program: &mut Program) -> bool {
+    let mut assignments: HashMap<String, usize> = HashMap::new();
+    for body in bodies(program) {
+        walk(body, &mut |stmt| {
+            if let Stmt::Assign(var, _) = stmt {
+                if var.scope == Scope::Global {
+                    *assignments.entry(var.name.clone()).or_default() += 1;
+                }
+            }
+        });
+    }
+    // Only the leading constant assignments are known to happen before any code reads them.
+    let mut consts = HashMap::new();
+    for stmt in &program.init {
+        match stmt {
+            Stmt::Assign(var, Rvalue::Use(Operand::Const(c))) if var.scope == Scope::Global => {
+                if assignments[&var.name] == 1 && c.ty() == var.ty {
+                    consts.insert(var.name.clone(), c.clone());
+                }
+            }
+            _ => break,
+        }
+    }
+    if consts.is_empty() {
+        return false;
+    }
+    for body in bodies(program) {
+        replace_reads(body, Scope::Global, &consts);
+    }
+    program.init.retain(|stmt| !matches!(stmt, Stmt::Assign(var, _) if var.scope == Scope::Global && consts.contains_key(&var.name)));
+    program.globals.retain(|var| !consts.contains_key(&var.name));
+    true
+}
+
+fn propagate_locals(body: &mut Vec<Stmt>) -> bool {
+    let mut assigned = HashSet::new();
+    let mut initialised = HashMap::new();
+    walk(body, &mut |stmt| match stmt {
+        Stmt::Assign(var, _) if var.scope == Scope::Local => {
+            assigned.insert(var.name.clone());
+        }
+        Stmt::Let(var, Some(Rvalue::Use(Operand::Const(c)))) if var.scope == Scope::Local && c.ty() == var.ty => {
+            initialised.insert(var.name.clone(), c.clone());
+        }
+        _ => {}
+    });
+    initialised.retain(|name, _| !assigned.contains(name));
+    if initialised.is_empty() {
+        return false;
+    }
+    replace_reads(body, Scope::Local, &initialised);
+    remove_lets(body, &initialised);
+    true
+}
+
+/// Replaces reads of the variables of `scope` named in `consts` with their values.
+fn replace_reads(body: &mut [Stmt], scope: Scope, consts: &HashMap<String, Const>) {
+    walk(body, &mut |stmt| {
+        for operand in stmt.operands_mut() {
+            if let Operand::Var(var) = operand {
+                if var.scope == scope {
+                    if let Some(c) = consts.get(&var.name) {
+                        *operand = Operand::Const(c.clone());
+                    }
+                }
+            }
+        }
+    });
+}
+
+fn remove_lets(stmts: &mut Vec<Stmt>, names: &HashMap<String, Const>) {
+    stmts.retain(|stmt| !matches!(stmt, Stmt::Let(var, _) if names.contains_key(&var.name)));
+    for stmt in stmts {
+        for block in stmt.blocks_mut() {
+            remove_lets(block, names);
+        }
+    }
+}
+
+// ---- Unreachable code ----
+
+/// Drops the statements that follow a `return`, `break`, `continue`, `throw` or panic in
+/// the same block, or a statement all of whose paths end in one. Returns whether anything
+/// changed.
+pub fn remove_unreachable(program: &mut Program) -> bool {
+    bodies(program).fold(false, |changed, body| truncate_block(body) | changed)
+}
+
+fn truncate_block(stmts: &mut Vec<Stmt>) -> bool {
+    let mut changed = false;
+    for stmt in stmts.iter_mut() {
+        for block in stmt.blocks_mut() {
+            changed |= truncate_block(block);
+        }
+    }
+    if let Some(end) = stmts.iter().position(diverges) {
+        if end + 1 < stmts.len() {
+            stmts.truncate(end + 1);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Whether control never reaches the statement after `stmt`. A loop is not looked into,
+/// since a `break` in it only leaves the loop.
+fn diverges(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Return(_) | Stmt::Break | Stmt::Continue | Stmt::Throw(_) | Stmt::Panic(_) => true,
+        Stmt::Block(body) => body.iter().any(diverges),
+        Stmt::If { then_block, else_block, .. } => then_block.iter().any(diverges) && else_block.iter().any(diverges),
+        // A subject that no arm matches is a runtime error.
+        Stmt::Match { arms, .. } => arms.iter().all(|arm| arm.body.iter().any(diverges)),
+        _ => false,
+    }
+}
+
+// ---- Inlining ----
+
+/// Replaces calls to small functions without control flow with their bodies, their
+/// parameters and locals renamed apart from the caller's. Returns whether anything changed.
+pub fn inline_functions(program: &mut Program) -> bool {
+    let candidates: HashMap<String, Function> =
+        program.functions.iter().filter(|f| is_inlinable(f)).map(|f| (f.name.clone(), f.clone())).collect();
+    if candidates.is_empty() {
+        return false;
+    }
+    // Inlined locals must not shadow a global or function the inlined body refers to.
+    let outer: HashSet<String> = program.functions.iter().map(|f| f.name.clone()).chain(program.globals.iter().map(|g| g.name.clone())).collect();
+    let mut changed = false;
+    for function in &mut program.functions {
+        let mut taken = outer.clone();
+        taken.extend(function.params.iter().map(|p| p.name.clone()));
+        if let FunctionKind::Lambda { captures } = &function.kind {
+            taken.extend(captures.iter().map(|c| c.name.clone()));
+        }
+        declared_names(&function.body, &mut taken);
+        changed |= inline_calls(&mut function.body, &candidates, &mut taken);
+    }
+    let mut taken = outer;
+    declared_names(&program.init, &mut taken);
+    changed |= inline_calls(&mut program.init, &candidates, &mut taken);
+    changed
+}
+
+fn is_inlinable(function: &Function) -> bool {
+    let last = function.body.len().saturating_sub(1);
+    matches!(function.kind, FunctionKind::Plain)
+        && function.name != "main"
+        && function.body.len() <= INLINE_LIMIT
+        && function.body.iter().enumerate().all(|(i, stmt)| match stmt {
+            Stmt::Let(..) | Stmt::Assign(..) | Stmt::IndexSet { .. } | Stmt::Eval(_) => true,
+            Stmt::Return(_) => i == last,
+            _ => false,
+        })
+        && !function.body.iter().any(|stmt| matches!(stmt.value(), Some(Rvalue::Call(Callee::Function(name), _)) if *name == function.name))
+}
+
+/// Adds the names of the variables declared in `stmts` to `out`.
+fn declared_names(stmts: &[Stmt], out: &mut HashSet<String>) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::Let(var, _) | Stmt::Try { error: var, .. } => {
+                out.insert(var.name.clone());
+            }
+            Stmt::Match { arms, .. } => {
+                let mut bindings = Vec::new();
+                for arm in arms {
+                    arm.pattern.bindings(&mut bindings);
+                }
+                out.extend(bindings.into_iter().map(|var| var.name));
+            }
+            _ => {}
+        }
+        for block in stmt.blocks() {
+            declared_names(block, out);
+        }
+    }
+}
+
+fn inline_calls(stmts: &mut Vec<Stmt>, candidates: &HashMap<String, Function>, taken: &mut HashSet<String>) -> bool {
+    let mut changed = false;
+    let mut out = Vec::with_capacity(stmts.len());
+    for mut stmt in stmts.drain(..) {
+        for block in stmt.blocks_mut() {
+            changed |= inline_calls(block, candidates, taken);
+        }
+        let Some(Rvalue::Call(Callee::Function(name), args)) = stmt.value() else {
+            out.push(stmt);
+            continue;
+        };
+        let Some(callee) = candidates.get(name) else {
+            out.push(stmt);
+            continue;
+        };
+        changed = true;
+        let result = expand(callee, args.clone(), taken, &mut out).map(Rvalue::Use);
+        match (stmt, result) {
+            (Stmt::Let(dest, _), result) => out.push(Stmt::Let(dest, result)),
+            (Stmt::Assign(dest, _), Some(result)) => out.push(Stmt::Assign(dest, result)),
+            _ => {}
+        }
+    }
+    *stmts = out;
+    changed
+}
+
+/// Appends the body of `callee` to `out`, its parameters bound to `args`. Returns the
+/// value it returns.
+fn expand(callee: &Function, args: Vec<Operand>, taken: &mut HashSet<String>, out: &mut Vec<Stmt>) -> Option<Operand> {
+    let mut renames = HashMap::new();
+    let mut rename = |var: &Var| {
+        let name = unique_name(&var.name, |n| taken.contains(n) || is_reserved(n));
+        taken.insert(name.clone());
+        renames.insert(var.name.clone(), name.clone());
+        Var { name, ty: var.ty.clone(), scope: var.scope }
+    };
+    for (param, arg) in callee.params.iter().zip(args) {
+        out.push(Stmt::Let(rename(param), Some(Rvalue::Use(arg))));
+    }
+    for stmt in &callee.body {
+        if let Stmt::Let(var, _) = stmt {
+            rename(var);
+        }
+    }
+
+    let mut body = callee.body.clone();
+    let result = match body.last() {
+        Some(Stmt::Return(_)) => match body.pop() {
+            Some(Stmt::Return(value)) => value,
+            _ => None,
+        },
+        _ => None,
+    };
+    let rename_var = |var: &mut Var| {
+        if matches!(var.scope, Scope::Local | Scope::Cell) {
+            if let Some(name) = renames.get(&var.name) {
+                var.name = name.clone();
+            }
+        }
+    };
+    walk(&mut body, &mut |stmt| {
+        if let Stmt::Let(var, _) | Stmt::Assign(var, _) = stmt {
+            rename_var(var);
+        }
+        for operand in stmt.operands_mut() {
+            if let Operand::Var(var) = operand {
+                rename_var(var);
+            }
+        }
+    });
+    out.extend(body);
+    result.map(|mut value| {
+        if let Operand::Var(var) = &mut value {
+            rename_var(var);
+        }
+        value
+    })
+}
+
+// ---- Dead functions ----
+
+/// Drops the functions that neither `main` nor the script's top-level code can reach, by
+/// calls or by using them as values. Returns whether anything changed.
+pub fn remove_dead_functions(program: &mut Program) -> bool {
+    let bodies: HashMap<&str, &Vec<Stmt>> = program.functions.iter().map(|f| (f.name.as_str(), &f.body)).collect();
+    let mut pending = Vec::new();
+    if bodies.contains_key("main") {
+        pending.push("main".to_string());
+    }
+    referenced_functions(&program.init, &mut pending);
+    let mut live = HashSet::new();
+    while let Some(name) = pending.pop() {
+        if let Some(body) = bodies.get(name.as_str()) {
+            if live.insert(name) {
+                referenced_functions(body, &mut pending);
+            }
+        }
+    }
+    let before = program.functions.len();
+    program.functions.retain(|f| live.contains(&f.name));
+    program.functions.len() != before
+}
+
+fn referenced_functions(stmts: &[Stmt], out: &mut Vec<String>) {
+    for stmt in stmts {
+        if let Some(Rvalue::Call(Callee::Function(name) | Callee::Method(name), _) | Rvalue::Closure(name, _) | Rvalue::FunctionRef(name)) = stmt.value() {
+            out.push(name.clone());
+        }
+        for block in stmt.blocks() {
+            referenced_functions(block, out);
+        }
+    }
+}
@@ -1,5 +1,6 @@
-use crate::lexer::{Token, SpannedToken, Span};
-use crate::ast::{Statement, Expression, Op, Spanned};
+use crate::lexer::{unescape, Token, SpannedToken, Span};
+use crate::ast::{Statement, Expression, MatchArm, Op, Pattern, Spanned, StringPart, TypeExpr, UnaryOp};
+use crate::diagnostics::{Diagnostic, ErrorCode};
 use logos::Logos;
 
 pub type ParseResult<T> = Result<T, ParseError>;
@@ -10,28 +11,52 @@ pub struct ParseError {
     pub span: Span,
 }
 
+impl From<ParseError> for Diagnostic {
+    fn from(e: ParseError) -> Self {
+        Diagnostic::new(ErrorCode::Syntax, e.message, Some(e.span))
+    }
+}
+
 pub struct Parser<'a> {
     source: &'a str,
     tokens: Vec<SpannedToken>,
     pos: usize,
+    /// Offset of `source` within the program, added to every span. Files linked into
+    /// one program each get their own range of offsets.
+    base: usize,
+    /// Errors recovered from so far, including lexer errors.
+    errors: Vec<ParseError>,
+    /// Characters the lexer rejected; they are left out of `tokens`.
+    rejected: Vec<Span>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(source: &'a str) -> Self {
+        Self::with_base(source, 0)
+    }
+
+    pub fn with_base(source: &'a str, base: usize) -> Self {
         let mut lexer = Token::lexer(source);
         let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        let mut rejected = Vec::new();
         
         while let Some(res) = lexer.next() {
-            let span = lexer.span();
+            let span = lexer.span().start + base..lexer.span().end + base;
             match res {
                 Ok(token) => tokens.push(SpannedToken { token, span }),
                 Err(_) => {
-                    // We could handle lexer errors better here
+                    let message = match lexer.slice() {
+                        s if s.starts_with('"') => "Unterminated string literal".to_string(),
+                        s => format!("Unexpected character '{}'", s),
+                    };
+                    rejected.push(span.clone());
+                    errors.push(ParseError { message, span });
                 }
             }
         }
 
-        Self { source, tokens, pos: 0 }
+        Self { source, tokens, pos: 0, base, errors, rejected }
     }
 
     fn peek(&self) -> &Token {
@@ -50,21 +75,24 @@ impl<'a> Parser<'a> {
 
     fn peek_span(&self) -> Span {
         if self.pos >= self.tokens.len() {
-            let last_pos = self.source.len();
-            last_pos..last_pos
+            self.eof()..self.eof()
         } else {
             self.tokens[self.pos].span.clone()
         }
     }
 
+    /// Offset reported for errors at the end of input.
+    fn eof(&self) -> usize {
+        self.base + self.source.len()
+    }
+
     fn is_at_end(&self) -> bool {
         self.pos >= self.tokens.len()
     }
 
     fn advance(&mut self) -> SpannedToken {
         if self.is_at_end() {
-             let last_pos = self.source.len();
-             return SpannedToken { token: Token::Identifier("EOF".into()), span: last_pos..last_pos };
+             return SpannedToken { token: Token::Identifier("EOF".into()), span: self.eof()..self.eof() };
         }
         let token = self.tokens[self.pos].clone();
         self.pos += 1;
@@ -114,12 +142,92 @@ impl<'a> Parser<'a> {
         }
     }
 
-    pub fn parse(&mut self) -> ParseResult<Vec<Spanned<Statement>>> {
+    /// Parses the whole file, or returns every syntax error in it.
+    pub fn parse(&mut self) -> Result<Vec<Spanned<Statement>>, Vec<ParseError>> {
+        let (stmts, errors) = self.parse_program();
+        if errors.is_empty() { Ok(stmts) } else { Err(errors) }
+    }
+
+    /// Parses the whole file, recovering from syntax errors at statement boundaries.
+    /// Statements that failed to parse become `Statement::Error`, so later passes can
+    /// still check the rest; the errors come back sorted by position.
+    pub fn parse_program(&mut self) -> (Vec<Spanned<Statement>>, Vec<ParseError>) {
         let mut stmts = Vec::new();
         while !self.is_at_end() {
-            stmts.push(self.parse_statement()?);
+            stmts.push(self.parse_statement_or_recover());
         }
-        Ok(stmts)
+        let mut errors = std::mem::take(&mut self.errors);
+        errors.sort_by_key(|e| e.span.start);
+        (stmts, errors)
+    }
+
+    fn parse_statement_or_recover(&mut self) -> Spanned<Statement> {
+        let start_pos = self.pos;
+        let start = self.peek_span().start;
+        match self.parse_statement() {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                self.recover_from(e);
+                // A statement that fails on its first token, such as a stray `}`, skips it.
+                if self.pos == start_pos && !self.is_at_end() {
+                    self.advance();
+                }
+                self.synchronize(start_pos);
+                self.spanned(start, Statement::Error)
+            }
+        }
+    }
+
+    /// Records `e`, unless the lexer rejected a character right before the token it is
+    /// about: that error is already reported and explains this one.
+    fn recover_from(&mut self, e: ParseError) {
+        let previous_end = if self.pos > 0 { self.tokens[self.pos - 1].span.end } else { self.base };
+        let follows_rejected = e.span.start == self.peek_span().start
+            && self.rejected.iter().any(|r| r.start >= previous_end && r.end <= e.span.start);
+        if !follows_rejected {
+            self.errors.push(e);
+        }
+    }
+
+    /// Panic-mode recovery: skips the rest of the construct that started at `start_pos`
+    /// up to the next statement boundary outside any bracket. A boundary is a statement
+    /// keyword, the `}` closing the enclosing block, or a token starting a new line.
+    /// Braces the construct opened before the error count, so a broken block is skipped
+    /// whole; parentheses and brackets left open are ignored, as they are usually the
+    /// mistake. Consumes nothing if already at a boundary.
+    fn synchronize(&mut self, start_pos: usize) {
+        let nest = |depth: usize, token: &Token| match token {
+            Token::LBrace | Token::LParen | Token::LBracket => depth + 1,
+            Token::RBrace | Token::RParen | Token::RBracket => depth.saturating_sub(1),
+            _ => depth,
+        };
+        let mut depth = self.tokens[start_pos..self.pos].iter().fold(0, |d: usize, t| match t.token {
+            Token::LBrace => d + 1,
+            Token::RBrace => d.saturating_sub(1),
+            _ => d,
+        });
+        while !self.is_at_end() {
+            if depth == 0 && self.at_statement_boundary() {
+                return;
+            }
+            depth = nest(depth, &self.advance().token);
+        }
+    }
+
+    /// Whether the token at the cursor is the first on its line.
+    fn starts_line(&self) -> bool {
+        self.pos > 0
+            && self.source[self.tokens[self.pos - 1].span.end - self.base..self.peek_span().start - self.base].contains('\n')
+    }
+
+    fn at_statement_boundary(&self) -> bool {
+        self.starts_line()
+            || matches!(
+                self.peek(),
+                Token::RBrace | Token::Import | Token::Fn | Token::Let | Token::Const | Token::Set | Token::If | Token::Loop
+                    | Token::While | Token::For | Token::Struct | Token::Enum | Token::Match | Token::Impl
+                    | Token::Try | Token::Pub | Token::Break | Token::Continue | Token::Return | Token::Unsafe
+            )
     }
 
     fn spanned<T>(&self, start: usize, node: T) -> Spanned<T> {
@@ -135,17 +243,28 @@ impl<'a> Parser<'a> {
         let start = self.peek_span().start;
         match self.peek() {
             Token::Import => self.parse_import(),
-            Token::Fn => self.parse_fn(),
+            Token::Fn if !matches!(self.tokens.get(self.pos + 1).map(|t| &t.token), Some(Token::LParen)) => self.parse_fn(),
             Token::Let => self.parse_let(),
+            Token::Const => self.parse_const(),
             Token::Set => self.parse_set(),
             Token::If => self.parse_if(),
             Token::Loop => self.parse_loop(),
             Token::While => self.parse_while(),
+            Token::For => self.parse_for(),
             Token::Struct => self.parse_struct_def(),
             Token::Enum => self.parse_enum_def(),
+            Token::Match => self.parse_match(),
+            Token::Impl => self.parse_impl(),
+            Token::Try => self.parse_try_catch(),
+            Token::Pub => self.parse_pub_item(),
             Token::Break => {
+                self.advance();
                 Ok(self.spanned(start, Statement::Break))
             }
+            Token::Continue => {
+                self.advance();
+                Ok(self.spanned(start, Statement::Continue))
+            }
             Token::Return => {
                 self.advance();
                 let expr = self.parse_expr()?;
@@ -164,6 +283,14 @@ impl<'a> Parser<'a> {
                     Ok(self.spanned(start, Statement::Set { name, value }))
                 } else {
                     let expr = self.parse_expr()?;
+                    if self.match_token(Token::Assign) {
+                        // xs[i] = value
+                        let Expression::Index { object, index } = expr.node else {
+                            return Err(ParseError { message: "Invalid assignment target".into(), span: expr.span });
+                        };
+                        let value = self.parse_expr()?;
+                        return Ok(self.spanned(start, Statement::IndexSet { object: *object, index: *index, value }));
+                    }
                     Ok(self.spanned(start, Statement::Expression(expr)))
                 }
             }
@@ -194,26 +321,149 @@ impl<'a> Parser<'a> {
         let (name, _) = self.consume_identifier("Expected function name")?;
 
         self.consume(Token::LParen, "Expected ( after function name")?;
+        let params = self.parse_params()?;
+        let return_type = self.parse_return_type()?;
+
+        let body = self.parse_block()?;
+        Ok(self.spanned(start, Statement::FunctionDef { public: false, name, params, return_type, body }))
+    }
 
+    /// `pub fn`, `pub struct`, `pub enum` or `pub const`: an item other modules may import.
+    fn parse_pub_item(&mut self) -> ParseResult<Spanned<Statement>> {
+        let start = self.advance().span.start; // pub
+        let mut item = match self.peek() {
+            Token::Fn => self.parse_fn()?,
+            Token::Struct => self.parse_struct_def()?,
+            Token::Enum => self.parse_enum_def()?,
+            Token::Const => self.parse_const()?,
+            _ => return Err(ParseError { message: "Expected fn, struct, enum or const after pub".into(), span: self.peek_span() }),
+        };
+        if let Statement::FunctionDef { public, .. }
+        | Statement::StructDef { public, .. }
+        | Statement::EnumDef { public, .. }
+        | Statement::Const { public, .. } = &mut item.node
+        {
+            *public = true;
+        }
+        item.span.start = start;
+        Ok(item)
+    }
+
+    /// Parses a parameter list up to and including the closing `)`.
+    fn parse_params(&mut self) -> ParseResult<Vec<(String, Option<TypeExpr>)>> {
         let mut params = Vec::new();
         while !self.match_token(Token::RParen) {
             let (p_name, _) = self.consume_identifier("Expected param name")?;
+            let mut p_type = None;
             if self.match_token(Token::Colon) {
-                self.consume_identifier("Expected type identifier")?;
+                p_type = Some(self.parse_type()?);
             }
-            params.push((p_name, "Any".to_string()));
+            params.push((p_name, p_type));
             self.match_token(Token::Comma);
         }
+        Ok(params)
+    }
 
-        let body = self.parse_block()?;
-        Ok(self.spanned(start, Statement::FunctionDef { name, params, body }))
+    fn parse_return_type(&mut self) -> ParseResult<Option<TypeExpr>> {
+        if self.match_token(Token::Arrow) {
+            Ok(Some(self.parse_type()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub(crate) fn parse_type(&mut self) -> ParseResult<TypeExpr> {
+        match self.peek() {
+            Token::LBracket => {
+                self.advance();
+                let elem = self.parse_type()?;
+                self.consume(Token::RBracket, "Expected ']' after list element type")?;
+                Ok(TypeExpr::List(Box::new(elem)))
+            }
+            Token::LBrace => {
+                self.advance();
+                let (key, key_span) = self.consume_identifier("Expected map key type")?;
+                if key != "str" {
+                    return Err(ParseError { message: "Map keys must be of type str".into(), span: key_span });
+                }
+                self.consume(Token::Colon, "Expected ':' after map key type")?;
+                let value = self.parse_type()?;
+                self.consume(Token::RBrace, "Expected '}' after map value type")?;
+                Ok(TypeExpr::Map(Box::new(value)))
+            }
+            Token::Fn => {
+                self.advance();
+                self.consume(Token::LParen, "Expected ( after fn in type")?;
+                let mut params = Vec::new();
+                while !self.match_token(Token::RParen) {
+                    params.push(self.parse_type()?);
+                    if !self.peek_is(Token::RParen) && !self.match_token(Token::Comma) {
+                        return Err(ParseError { message: "Expected ',' or ')' in function type".into(), span: self.peek_span() });
+                    }
+                }
+                let ret = self.parse_return_type()?.unwrap_or_else(|| TypeExpr::Named("void".into()));
+                Ok(TypeExpr::Function(params, Box::new(ret)))
+            }
+            _ => {
+                let (mut name, _) = self.consume_identifier("Expected type name")?;
+                if self.match_token(Token::Dot) {
+                    // A type from an imported module: `geo.Point`
+                    let (item, _) = self.consume_identifier("Expected type name after '.'")?;
+                    name = format!("{}.{}", name, item);
+                }
+                if !self.match_token(Token::Lt) {
+                    return Ok(TypeExpr::Named(name));
+                }
+                let mut args = Vec::new();
+                while !self.match_closing_angle() {
+                    args.push(self.parse_type()?);
+                    if !self.peek_is(Token::Gt) && !self.peek_is(Token::Shr) && !self.match_token(Token::Comma) {
+                        return Err(ParseError { message: "Expected ',' or '>' in type arguments".into(), span: self.peek_span() });
+                    }
+                }
+                Ok(TypeExpr::Generic(name, args))
+            }
+        }
+    }
+
+    /// Consumes the `>` closing a type argument list. In `Option<Pair<int, str>>` the lexer
+    /// sees a `>>`, so its first half is taken and the second left for the outer list.
+    fn match_closing_angle(&mut self) -> bool {
+        if self.peek_is(Token::Shr) {
+            let tok = &mut self.tokens[self.pos];
+            tok.token = Token::Gt;
+            tok.span.start += 1;
+            return true;
+        }
+        self.match_token(Token::Gt)
+    }
+
+    /// Optional `<A, B>` after a struct or enum name.
+    fn parse_type_params(&mut self) -> ParseResult<Vec<String>> {
+        let mut type_params = Vec::new();
+        if self.match_token(Token::Lt) {
+            while !self.match_token(Token::Gt) {
+                let (param, _) = self.consume_identifier("Expected type parameter name")?;
+                type_params.push(param);
+                if !self.peek_is(Token::Gt) && !self.match_token(Token::Comma) {
+                    return Err(ParseError { message: "Expected ',' or '>' in type parameters".into(), span: self.peek_span() });
+                }
+            }
+        }
+        Ok(type_params)
     }
 
     fn parse_block(&mut self) -> ParseResult<Vec<Spanned<Statement>>> {
         self.consume(Token::LBrace, "Expected { start of block")?;
         let mut stmts = Vec::new();
         while !self.is_at_end() && self.peek() != &Token::RBrace {
-            stmts.push(self.parse_statement()?);
+            if self.peek_is(Token::Pub) {
+                // Report it, then parse the item as if it were private.
+                let span = self.advance().span;
+                self.errors.push(ParseError { message: "pub is only allowed on top-level items".into(), span });
+                continue;
+            }
+            stmts.push(self.parse_statement_or_recover());
         }
         self.consume(Token::RBrace, "Expected } end of block")?;
         Ok(stmts)
@@ -221,15 +471,40 @@ impl<'a> Parser<'a> {
 
     fn parse_let(&mut self) -> ParseResult<Spanned<Statement>> {
         let start = self.advance().span.start; // let
-        let (name, _) = self.consume_identifier("Expected var name")?;
-        
+        let mutable = self.match_token(Token::Mut);
+        let (name, ty, value) = self.parse_binding("Expected var name")?;
+        Ok(self.spanned(start, Statement::Let { name, mutable, ty, value }))
+    }
+
+    fn parse_const(&mut self) -> ParseResult<Spanned<Statement>> {
+        let start = self.advance().span.start; // const
+        let (name, ty, value) = self.parse_binding("Expected constant name")?;
+        Ok(self.spanned(start, Statement::Const { public: false, name, ty, value }))
+    }
+
+    /// `name[: type] = value`, shared by `let` and `const`.
+    fn parse_binding(&mut self, name_error: &str) -> ParseResult<(String, Option<TypeExpr>, Spanned<Expression>)> {
+        let (name, _) = self.consume_identifier(name_error)?;
+
+        let mut ty = None;
         if self.match_token(Token::Colon) {
-             self.consume_identifier("Expected type name")?;
+            ty = Some(self.parse_type()?);
         }
 
         self.consume(Token::Assign, "Expected =")?;
-        let value = self.parse_expr()?;
-        Ok(self.spanned(start, Statement::Let { name, value }))
+        // Keep the binding when only its value is malformed, so later uses of the
+        // name do not report errors of their own.
+        let value_pos = self.pos;
+        let value_start = self.peek_span().start;
+        let value = match self.parse_expr() {
+            Ok(value) => value,
+            Err(e) => {
+                self.recover_from(e);
+                self.synchronize(value_pos);
+                self.spanned(value_start, Expression::Error)
+            }
+        };
+        Ok((name, ty, value))
     }
 
     fn parse_set(&mut self) -> ParseResult<Spanned<Statement>> {
@@ -242,6 +517,44 @@ impl<'a> Parser<'a> {
         Ok(self.spanned(start, Statement::Set { name, value }))
     }
 
+    /// A block whose value is used. A trailing `if` with an `else` or a trailing `match`
+    /// becomes an expression so that it provides that value.
+    fn parse_value_block(&mut self) -> ParseResult<Vec<Spanned<Statement>>> {
+        Ok(value_block(self.parse_block()?))
+    }
+
+    /// Whether the `{` at the cursor opens a map literal rather than a block: it is empty,
+    /// or a `:` follows its first entry before anything that can only start a statement.
+    fn at_map_literal(&self) -> bool {
+        if self.tokens.get(self.pos + 1).map(|t| &t.token) == Some(&Token::RBrace) {
+            return true;
+        }
+        let mut depth = 0usize;
+        for t in &self.tokens[self.pos + 1..] {
+            match &t.token {
+                Token::RBrace if depth == 0 => return false,
+                Token::LBrace | Token::LParen | Token::LBracket => depth += 1,
+                Token::RBrace | Token::RParen | Token::RBracket => depth = depth.saturating_sub(1),
+                Token::Colon if depth == 0 => return true,
+                Token::Let | Token::Const | Token::Set | Token::Return | Token::If | Token::Match | Token::Loop
+                | Token::While | Token::For | Token::Break | Token::Continue | Token::Try | Token::Fn if depth == 0 => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    fn parse_if_expression(&mut self) -> ParseResult<Spanned<Expression>> {
+        let start = self.advance().span.start; // if
+        let cond = self.parse_expr()?;
+        let then_block = self.parse_value_block()?;
+        let mut else_block = None;
+        if self.match_token(Token::Else) {
+            else_block = Some(self.parse_value_block()?);
+        }
+        Ok(self.spanned(start, Expression::If { cond: Box::new(cond), then_block, else_block }))
+    }
+
     fn parse_if(&mut self) -> ParseResult<Spanned<Statement>> {
         let start = self.advance().span.start; // if
         let cond = self.parse_expr()?;
@@ -256,13 +569,26 @@ impl<'a> Parser<'a> {
     fn parse_loop(&mut self) -> ParseResult<Spanned<Statement>> {
         let start = self.advance().span.start; // loop
         let mut count = None;
-        if let Token::Integer(_) = self.peek() {
+        if !self.peek_is(Token::LBrace) {
             count = Some(self.parse_expr()?);
+            // `loop 3 times { ... }` reads better and is what the formatter emits
+            if matches!(self.peek(), Token::Identifier(w) if w == "times") {
+                self.advance();
+            }
         }
         let body = self.parse_block()?;
         Ok(self.spanned(start, Statement::Loop { count, body }))
     }
 
+    fn parse_for(&mut self) -> ParseResult<Spanned<Statement>> {
+        let start = self.advance().span.start; // for
+        let (var, _) = self.consume_identifier("Expected loop variable after for")?;
+        self.consume(Token::In, "Expected 'in' after loop variable")?;
+        let iterable = self.parse_expr()?;
+        let body = self.parse_block()?;
+        Ok(self.spanned(start, Statement::ForIn { var, iterable, body }))
+    }
+
     fn parse_while(&mut self) -> ParseResult<Spanned<Statement>> {
         let start = self.advance().span.start; // while
         let cond = self.parse_expr()?;
@@ -270,38 +596,239 @@ impl<'a> Parser<'a> {
         Ok(self.spanned(start, Statement::While { cond, body }))
     }
 
+    fn parse_try_catch(&mut self) -> ParseResult<Spanned<Statement>> {
+        let start = self.advance().span.start; // try
+        let body = self.parse_block()?;
+        self.consume(Token::Catch, "Expected catch after try block")?;
+        let (error, _) = self.consume_identifier("Expected a name for the caught error")?;
+        let handler = self.parse_block()?;
+        Ok(self.spanned(start, Statement::TryCatch { body, error, handler }))
+    }
+
+    fn parse_impl(&mut self) -> ParseResult<Spanned<Statement>> {
+        let start = self.advance().span.start; // impl
+        let (type_name, _) = self.consume_identifier("Expected type name after impl")?;
+        self.consume(Token::LBrace, "Expected '{' after impl type name")?;
+
+        let mut methods = Vec::new();
+        while !self.match_token(Token::RBrace) {
+            if !self.peek_is(Token::Fn) {
+                return Err(ParseError { message: "Only methods are allowed in an impl block".into(), span: self.peek_span() });
+            }
+            methods.push(self.parse_fn()?);
+        }
+        Ok(self.spanned(start, Statement::Impl { type_name, methods }))
+    }
+
+    fn parse_match(&mut self) -> ParseResult<Spanned<Statement>> {
+        let start = self.advance().span.start; // match
+        let subject = self.parse_expr()?;
+        self.consume(Token::LBrace, "Expected '{' after match subject")?;
+
+        let mut arms = Vec::new();
+        while !self.match_token(Token::RBrace) {
+            let pattern = self.parse_pattern()?;
+            self.consume(Token::FatArrow, "Expected '=>' after match pattern")?;
+            let body = if self.peek_is(Token::LBrace) {
+                self.parse_block()?
+            } else {
+                let expr_start = self.peek_span().start;
+                let expr = self.parse_expr()?;
+                vec![self.spanned(expr_start, Statement::Expression(expr))]
+            };
+            arms.push(MatchArm { pattern, body });
+            self.match_token(Token::Comma);
+        }
+        Ok(self.spanned(start, Statement::Match { subject, arms }))
+    }
+
+    /// `_`, `name`, literals, `Enum.Variant(p, ..)` and `Struct { field: p, other }`.
+    fn parse_pattern(&mut self) -> ParseResult<Spanned<Pattern>> {
+        let start = self.peek_span().start;
+        let pattern = match self.peek().clone() {
+            Token::Integer(n) => {
+                self.advance();
+                Pattern::Literal(Expression::Number(n))
+            }
+            Token::Minus => {
+                self.advance();
+                match self.advance().token {
+                    Token::Integer(n) => Pattern::Literal(Expression::Number(-n)),
+                    _ => return Err(ParseError { message: "Expected number after '-' in pattern".into(), span: self.peek_span() }),
+                }
+            }
+            Token::StringLit(s) => {
+                self.advance();
+                Pattern::Literal(Expression::StringLit(s))
+            }
+            Token::True => {
+                self.advance();
+                Pattern::Literal(Expression::Bool(true))
+            }
+            Token::False => {
+                self.advance();
+                Pattern::Literal(Expression::Bool(false))
+            }
+            Token::Identifier(name) => {
+                self.advance();
+                if name == "_" {
+                    Pattern::Wildcard
+                } else if self.match_token(Token::Dot) {
+                    let (mut variant, _) = self.consume_identifier("Expected variant name after '.'")?;
+                    let mut enum_name = name;
+                    if self.peek_is(Token::LBrace) {
+                        // A struct from an imported module: `geo.Point { x, y }`
+                        self.advance();
+                        let fields = self.parse_struct_pattern_fields()?;
+                        return Ok(self.spanned(start, Pattern::Struct { name: format!("{}.{}", enum_name, variant), fields }));
+                    }
+                    if self.match_token(Token::Dot) {
+                        // An enum from an imported module: `geo.Shape.Circle(r)`
+                        enum_name = format!("{}.{}", enum_name, variant);
+                        variant = self.consume_identifier("Expected variant name after '.'")?.0;
+                    }
+                    let mut fields = Vec::new();
+                    if self.match_token(Token::LParen) {
+                        while !self.match_token(Token::RParen) {
+                            fields.push(self.parse_pattern()?);
+                            if !self.peek_is(Token::RParen) && !self.match_token(Token::Comma) {
+                                return Err(ParseError { message: "Expected ',' or ')' in variant pattern".into(), span: self.peek_span() });
+                            }
+                        }
+                    }
+                    Pattern::Variant { enum_name, variant, fields }
+                } else if self.match_token(Token::LBrace) {
+                    Pattern::Struct { name, fields: self.parse_struct_pattern_fields()? }
+                } else {
+                    Pattern::Binding(name)
+                }
+            }
+            _ => return Err(ParseError { message: format!("Unexpected token in pattern: {:?}", self.peek()), span: self.peek_span() }),
+        };
+        Ok(self.spanned(start, pattern))
+    }
+
+    /// The fields of a struct pattern, after its `{`.
+    fn parse_struct_pattern_fields(&mut self) -> ParseResult<Vec<(String, Spanned<Pattern>)>> {
+        let mut fields = Vec::new();
+        while !self.match_token(Token::RBrace) {
+            let (f_name, f_span) = self.consume_identifier("Expected field name in struct pattern")?;
+            let f_pattern = if self.match_token(Token::Colon) {
+                self.parse_pattern()?
+            } else {
+                // `{ x }` is shorthand for `{ x: x }`
+                Spanned { node: Pattern::Binding(f_name.clone()), span: f_span }
+            };
+            fields.push((f_name, f_pattern));
+            if !self.peek_is(Token::RBrace) && !self.match_token(Token::Comma) {
+                return Err(ParseError { message: "Expected ',' or '}' in struct pattern".into(), span: self.peek_span() });
+            }
+        }
+        Ok(fields)
+    }
+
     fn parse_struct_def(&mut self) -> ParseResult<Spanned<Statement>> {
         let start = self.advance().span.start; // struct
         let (name, _) = self.consume_identifier("Expected struct name")?;
+        let type_params = self.parse_type_params()?;
         self.consume(Token::LBrace, "Expected '{' after struct name")?;
         
         let mut fields = Vec::new();
         while !self.match_token(Token::RBrace) {
             let (f_name, _) = self.consume_identifier("Expected field name")?;
             self.consume(Token::Colon, "Expected ':' after field name")?;
-            let (f_type, _) = self.consume_identifier("Expected field type")?;
+            let f_type = self.parse_type()?;
             fields.push((f_name, f_type));
             self.match_token(Token::Comma);
         }
-        Ok(self.spanned(start, Statement::StructDef { name, fields }))
+        Ok(self.spanned(start, Statement::StructDef { public: false, name, type_params, fields }))
     }
 
     fn parse_enum_def(&mut self) -> ParseResult<Spanned<Statement>> {
         let start = self.advance().span.start; // enum
         let (name, _) = self.consume_identifier("Expected enum name")?;
+        let type_params = self.parse_type_params()?;
         self.consume(Token::LBrace, "Expected '{' after enum name")?;
         
         let mut variants = Vec::new();
         while !self.match_token(Token::RBrace) {
             let (v_name, _) = self.consume_identifier("Expected variant name")?;
-            variants.push(v_name);
+            let mut payload = Vec::new();
+            if self.match_token(Token::LParen) {
+                while !self.match_token(Token::RParen) {
+                    payload.push(self.parse_type()?);
+                    if !self.peek_is(Token::RParen) && !self.match_token(Token::Comma) {
+                        return Err(ParseError { message: "Expected ',' or ')' in variant payload".into(), span: self.peek_span() });
+                    }
+                }
+            }
+            variants.push((v_name, payload));
             self.match_token(Token::Comma);
         }
-        Ok(self.spanned(start, Statement::EnumDef { name, variants }))
+        Ok(self.spanned(start, Statement::EnumDef { public: false, name, type_params, variants }))
     }
 
     fn parse_expr(&mut self) -> ParseResult<Spanned<Expression>> {
-        self.parse_equality()
+        self.parse_range()
+    }
+
+    fn parse_range(&mut self) -> ParseResult<Spanned<Expression>> {
+        let start = self.peek_span().start;
+        let expr = self.parse_or()?;
+        if self.match_token(Token::DotDot) {
+            let end = self.parse_or()?;
+            let span = start..end.span.end;
+            return Ok(Spanned {
+                node: Expression::Range { start: Box::new(expr), end: Box::new(end) },
+                span
+            });
+        }
+        Ok(expr)
+    }
+
+    fn parse_or(&mut self) -> ParseResult<Spanned<Expression>> {
+        self.parse_binary_level(&[(Token::OrOr, Op::Or)], Self::parse_and)
+    }
+
+    fn parse_and(&mut self) -> ParseResult<Spanned<Expression>> {
+        self.parse_binary_level(&[(Token::AndAnd, Op::And)], Self::parse_equality)
+    }
+
+    // Unlike C, bitwise operators bind tighter than comparisons, so `x & 1 == 0` tests the low bit.
+    fn parse_bit_or(&mut self) -> ParseResult<Spanned<Expression>> {
+        self.parse_binary_level(&[(Token::Pipe, Op::BitOr)], Self::parse_bit_xor)
+    }
+
+    fn parse_bit_xor(&mut self) -> ParseResult<Spanned<Expression>> {
+        self.parse_binary_level(&[(Token::Caret, Op::BitXor)], Self::parse_bit_and)
+    }
+
+    fn parse_bit_and(&mut self) -> ParseResult<Spanned<Expression>> {
+        self.parse_binary_level(&[(Token::Amp, Op::BitAnd)], Self::parse_shift)
+    }
+
+    fn parse_shift(&mut self) -> ParseResult<Spanned<Expression>> {
+        self.parse_binary_level(&[(Token::Shl, Op::Shl), (Token::Shr, Op::Shr)], Self::parse_term)
+    }
+
+    /// A left-associative precedence level: `operand (op operand)*` for the given operators.
+    fn parse_binary_level(
+        &mut self,
+        ops: &[(Token, Op)],
+        operand: fn(&mut Self) -> ParseResult<Spanned<Expression>>,
+    ) -> ParseResult<Spanned<Expression>> {
+        let start = self.peek_span().start;
+        let mut expr = operand(self)?;
+        while let Some((_, op)) = ops.iter().find(|(tok, _)| self.peek_is(tok.clone())) {
+            self.advance();
+            let right = operand(self)?;
+            let span = start..right.span.end;
+            expr = Spanned {
+                node: Expression::BinaryOp(Box::new(expr), op.clone(), Box::new(right)),
+                span
+            };
+        }
+        Ok(expr)
     }
 
     fn parse_equality(&mut self) -> ParseResult<Spanned<Expression>> {
@@ -330,7 +857,7 @@ impl<'a> Parser<'a> {
 
     fn parse_comparison(&mut self) -> ParseResult<Spanned<Expression>> {
         let start = self.peek_span().start;
-        let mut expr = self.parse_term()?;
+        let mut expr = self.parse_bit_or()?;
         while !self.is_at_end() {
              match self.peek() {
                  Token::Gt | Token::Lt | Token::Gte | Token::Lte => {
@@ -341,7 +868,7 @@ impl<'a> Parser<'a> {
                          Token::Lte => Op::Lte,
                          _ => unreachable!(),
                      };
-                     let right = self.parse_term()?;
+                     let right = self.parse_bit_or()?;
                      let span = start..right.span.end;
                      expr = Spanned {
                          node: Expression::BinaryOp(Box::new(expr), op, Box::new(right)),
@@ -380,16 +907,17 @@ impl<'a> Parser<'a> {
 
     fn parse_factor(&mut self) -> ParseResult<Spanned<Expression>> {
         let start = self.peek_span().start;
-        let mut expr = self.parse_primary()?;
+        let mut expr = self.parse_unary()?;
         while !self.is_at_end() {
              match self.peek() {
-                 Token::Star | Token::Slash => {
+                 Token::Star | Token::Slash | Token::Percent => {
                      let op = match self.advance().token {
                          Token::Star => Op::Mul,
                          Token::Slash => Op::Div,
+                         Token::Percent => Op::Mod,
                          _ => unreachable!(),
                      };
-                     let right = self.parse_primary()?;
+                     let right = self.parse_unary()?;
                      let span = start..right.span.end;
                      expr = Spanned {
                          node: Expression::BinaryOp(Box::new(expr), op, Box::new(right)),
@@ -402,18 +930,91 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
+    /// Splits a string literal at its `{expr}` holes, or returns None if it has none.
+    /// Only a `{` followed by an identifier opens a hole, so text such as JSON keeps its
+    /// braces; `\{` is always a literal brace.
+    fn interpolation_parts(&self, span: &Span) -> ParseResult<Option<Vec<StringPart>>> {
+        let source: &'a str = self.source;
+        let body_start = span.start + 1;
+        let raw = &source[body_start - self.base..span.end - 1 - self.base];
+        let bytes = raw.as_bytes();
+        let mut parts = Vec::new();
+        let mut literal_start = 0;
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => i += 2,
+                b'{' if bytes.get(i + 1).is_some_and(|c| c.is_ascii_alphabetic() || *c == b'_') => {
+                    let Some(len) = raw[i + 1..].find('}') else {
+                        return Err(ParseError { message: "Unclosed '{' in string interpolation".into(), span: body_start + i..span.end });
+                    };
+                    if literal_start < i {
+                        parts.push(StringPart::Literal(unescape(&raw[literal_start..i])));
+                    }
+                    parts.push(StringPart::Expr(self.parse_embedded(&raw[i + 1..i + 1 + len], body_start + i + 1)?));
+                    i += len + 2;
+                    literal_start = i;
+                }
+                _ => i += 1,
+            }
+        }
+        if parts.is_empty() {
+            return Ok(None);
+        }
+        if literal_start < bytes.len() {
+            parts.push(StringPart::Literal(unescape(&raw[literal_start..])));
+        }
+        Ok(Some(parts))
+    }
+
+    /// Parses the expression in an interpolation hole, keeping spans relative to the whole file.
+    fn parse_embedded(&self, text: &'a str, offset: usize) -> ParseResult<Spanned<Expression>> {
+        let mut inner = Parser::with_base(text, offset);
+        if let Some(e) = inner.errors.pop() {
+            return Err(e);
+        }
+        let expr = inner.parse_expr()?;
+        if !inner.is_at_end() {
+            return Err(ParseError { message: "Expected '}' after interpolated expression".into(), span: inner.peek_span() });
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> ParseResult<Spanned<Expression>> {
+        let start = self.peek_span().start;
+        let op = match self.peek() {
+            Token::Minus => UnaryOp::Neg,
+            Token::Bang => UnaryOp::Not,
+            _ => return self.parse_primary(),
+        };
+        self.advance();
+        let operand = self.parse_unary()?;
+        Ok(self.spanned(start, Expression::Unary(op, Box::new(operand))))
+    }
+
     fn parse_primary(&mut self) -> ParseResult<Spanned<Expression>> {
         let start = self.peek_span().start;
+        if self.is_at_end() {
+            return Err(ParseError { message: "Unexpected end of input in expression".into(), span: self.peek_span() });
+        }
         let mut expr = match self.peek() {
             Token::Integer(n) => {
                 let v = *n;
                 self.advance();
                 self.spanned(start, Expression::Number(v))
             },
+            Token::Float(f) => {
+                let v = *f;
+                self.advance();
+                self.spanned(start, Expression::Float(v))
+            },
             Token::StringLit(s) => {
                 let v = s.clone();
-                self.advance();
-                self.spanned(start, Expression::StringLit(v))
+                let span = self.advance().span;
+                match self.interpolation_parts(&span)? {
+                    Some(parts) => self.spanned(start, Expression::Interpolated(parts)),
+                    None => self.spanned(start, Expression::StringLit(v)),
+                }
             },
             Token::True => {
                 self.advance();
@@ -427,32 +1028,26 @@ impl<'a> Parser<'a> {
                 self.advance();
                 self.spanned(start, Expression::Null)
             },
-            Token::Minus => {
-                self.advance();
-                let right = self.parse_primary()?;
-                self.spanned(start, Expression::BinaryOp(
-                    Box::new(self.spanned(start, Expression::Number(0))),
-                    Op::Minus,
-                    Box::new(right)
-                ))
-            },
             Token::Identifier(s) => {
-                let name = s.clone();
-                
+                let mut name = s.clone();
+                let token_at = |i: usize| self.tokens.get(self.pos + i).map(|t| &t.token);
+                // A struct from an imported module: `geo.Point { x: ... }`
+                let qualified = matches!(token_at(1), Some(Token::Dot)) && matches!(token_at(2), Some(Token::Identifier(_)));
+                let name_len = if qualified { 3 } else { 1 };
+
                 // Peek ahead to see if it's a struct literal: Name { field: ... }
-                let is_struct_lit = if self.pos + 1 < self.tokens.len() {
-                    self.tokens[self.pos + 1].token == Token::LBrace && 
-                    self.pos + 2 < self.tokens.len() && 
-                    matches!(self.tokens[self.pos + 2].token, Token::Identifier(_)) &&
-                    self.pos + 3 < self.tokens.len() &&
-                    self.tokens[self.pos + 3].token == Token::Colon
-                } else {
-                    false
-                };
+                let is_struct_lit = token_at(name_len) == Some(&Token::LBrace)
+                    && matches!(token_at(name_len + 1), Some(Token::Identifier(_)))
+                    && token_at(name_len + 2) == Some(&Token::Colon);
 
                 self.advance();
                 
                 if is_struct_lit {
+                    if qualified {
+                        self.advance(); // .
+                        let (item, _) = self.consume_identifier("Expected struct name")?;
+                        name = format!("{}.{}", name, item);
+                    }
                     self.advance(); // {
                     let mut fields = Vec::new();
                     while !self.match_token(Token::RBrace) {
@@ -467,6 +1062,57 @@ impl<'a> Parser<'a> {
                     self.spanned(start, Expression::Identifier(name))
                 }
             },
+            Token::LBracket => {
+                self.advance(); // [
+                let mut items = Vec::new();
+                while !self.match_token(Token::RBracket) {
+                    items.push(self.parse_expr()?);
+                    if !self.peek_is(Token::RBracket) && !self.match_token(Token::Comma) {
+                        return Err(ParseError {
+                            message: "Expected ',' or ']' in list literal".into(),
+                            span: self.peek_span()
+                        });
+                    }
+                }
+                self.spanned(start, Expression::ListLiteral(items))
+            },
+            // Conditionals and blocks end the expression: nothing postfix applies to them.
+            Token::If => return self.parse_if_expression(),
+            Token::Match => {
+                let Statement::Expression(expr) = into_value(self.parse_match()?).node else {
+                    unreachable!("a match always becomes an expression");
+                };
+                return Ok(expr);
+            }
+            Token::LBrace if !self.at_map_literal() => {
+                let body = self.parse_value_block()?;
+                return Ok(self.spanned(start, Expression::Block(body)));
+            }
+            Token::LBrace => {
+                self.advance(); // {
+                let mut entries = Vec::new();
+                while !self.match_token(Token::RBrace) {
+                    let key = self.parse_expr()?;
+                    self.consume(Token::Colon, "Expected ':' after map key")?;
+                    let value = self.parse_expr()?;
+                    entries.push((key, value));
+                    if !self.peek_is(Token::RBrace) && !self.match_token(Token::Comma) {
+                        return Err(ParseError {
+                            message: "Expected ',' or '}' in map literal".into(),
+                            span: self.peek_span()
+                        });
+                    }
+                }
+                self.spanned(start, Expression::MapLiteral(entries))
+            },
+            Token::Fn => {
+                self.advance(); // fn
+                self.consume(Token::LParen, "Expected ( after fn")?;
+                let params = self.parse_params()?;
+                let return_type = self.parse_return_type()?;
+                let body = self.parse_block()?;
+                self.spanned(start, Expression::Lambda { params, return_type, body })
+            },
             Token::LParen => {
                 self.advance(); // (
                 let inner = self.parse_expr()?;
@@ -479,6 +1125,8 @@ impl<'a> Parser<'a> {
         loop {
             if self.is_at_end() { break; }
             match self.peek() {
+                // On a new line these start the next statement, such as the result of a block.
+                Token::LParen | Token::LBracket if self.starts_line() => break,
                 Token::LParen => {
                     self.advance();
                     let mut args = Vec::new();
@@ -497,6 +1145,15 @@ impl<'a> Parser<'a> {
                         span
                     };
                 },
+                Token::LBracket => {
+                    self.advance();
+                    let index = self.parse_expr()?;
+                    let end = self.consume(Token::RBracket, "Expected ']' after index")?.span.end;
+                    expr = Spanned {
+                        node: Expression::Index { object: Box::new(expr), index: Box::new(index) },
+                        span: start..end
+                    };
+                },
                 Token::Dot => {
                     self.advance();
                     let (member, m_span) = self.consume_identifier("Expected member name")?;
@@ -506,10 +1163,43 @@ impl<'a> Parser<'a> {
                         span
                     };
                 },
+                Token::Question => {
+                    let end = self.advance().span.end;
+                    expr = Spanned {
+                        node: Expression::Try(Box::new(expr)),
+                        span: start..end
+                    };
+                },
                 _ => break,
             }
         }
 
         Ok(expr)
     }
-}
\ No newline at end of file
+}
+
+/// Turns a trailing `if`/`else` or `match` statement into an expression statement, recursively,
+/// so that it gives its enclosing block a value.
+fn into_value(stmt: Spanned<Statement>) -> Spanned<Statement> {
+    let node = match stmt.node {
+        Statement::If { cond, then_block, else_block: Some(else_block) } => Expression::If {
+            cond: Box::new(cond),
+            then_block: value_block(then_block),
+            else_block: Some(value_block(else_block)),
+        },
+        Statement::Match { subject, arms } => Expression::Match {
+            subject: Box::new(subject),
+            arms: arms.into_iter().map(|arm| MatchArm { pattern: arm.pattern, body: value_block(arm.body) }).collect(),
+        },
+        node => return Spanned { node, span: stmt.span },
+    };
+    let span = stmt.span;
+    Spanned { node: Statement::Expression(Spanned { node, span: span.clone() }), span }
+}
+
+fn value_block(mut block: Vec<Spanned<Statement>>) -> Vec<Spanned<Statement>> {
+    if let Some(last) = block.pop() {
+        block.push(into_value(last));
+    }
+    block
+}
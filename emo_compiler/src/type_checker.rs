@@ -1,14 +1,23 @@
-use crate::ast::{Statement, Expression, Op, Spanned, Span};
-use std::collections::HashMap;
+use crate::ast::{always_returns, direct_uses, free_variables, member_names, returns_value, split_value, MatchArm, Statement, Expression, Op, Pattern, Spanned, Span, StringPart, TypeExpr, UnaryOp};
+use crate::diagnostics::{Diagnostic, ErrorCode, Label};
+use crate::natives::{self, Arity, NativeFn};
+use crate::numeric::{IntKind, OVERFLOW_HELPERS, SIZED_INTS};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Type {
     Int,
+    /// A fixed-width integer; never `IntKind::I64`, which is `Int`.
+    Sized(IntKind),
+    Float,
     Str,
     Bool,
     Void,
-    Struct(String),
-    Enum(String),
+    Struct(String, Vec<Type>), // name and type arguments
+    Enum(String, Vec<Type>),
+    List(Box<Type>),
+    Map(Box<Type>), // keys are always Str
     Function {
         params: Vec<Type>,
         return_type: Box<Type>,
@@ -16,35 +25,166 @@ pub enum Type {
     Variable(usize), // For HM inference
 }
 
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |types: &[Type]| types.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(", ");
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Sized(kind) => write!(f, "{}", kind.name()),
+            Type::Float => write!(f, "float"),
+            Type::Str => write!(f, "str"),
+            Type::Bool => write!(f, "bool"),
+            Type::Void => write!(f, "void"),
+            Type::Struct(name, args) | Type::Enum(name, args) if args.is_empty() => write!(f, "{}", name),
+            Type::Struct(name, args) | Type::Enum(name, args) => write!(f, "{}<{}>", name, join(args)),
+            Type::List(elem) => write!(f, "[{}]", elem),
+            Type::Map(value) => write!(f, "{{str: {}}}", value),
+            Type::Function { params, return_type } => write!(f, "fn({}) -> {}", join(params), return_type),
+            Type::Variable(v) => write!(f, "'t{}", v),
+        }
+    }
+}
+
+/// A possibly polymorphic type: `vars` are quantified and get fresh variables at every use.
+#[derive(Debug, Clone)]
+pub struct Scheme {
+    pub vars: Vec<usize>,
+    pub ty: Type,
+    pub binding: Binding,
+}
+
+/// How a name was bound, which decides whether `set` may reassign it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Immutable,
+    /// `let mut`
+    Mutable,
+    Const,
+}
+
+impl Scheme {
+    fn mono(ty: Type) -> Self {
+        Self { vars: Vec::new(), ty, binding: Binding::Immutable }
+    }
+}
+
+type TypeEnv = HashMap<String, Scheme>;
+
+/// A struct or enum declaration; member types stay unparsed until the type arguments are known.
+struct StructDecl {
+    type_params: Vec<String>,
+    fields: Vec<(String, TypeExpr)>,
+}
+
+struct EnumDecl {
+    type_params: Vec<String>,
+    variants: Vec<(String, Vec<TypeExpr>)>,
+}
+
+/// A pattern reduced to constructors for exhaustiveness checking: enum variants,
+/// `true`/`false`, a struct's single constructor, or a literal of an open-ended type.
+#[derive(Debug, Clone)]
+enum Pat {
+    Wild,
+    Ctor(String, Vec<Pat>),
+}
+
 pub struct TypeChecker {
     next_var: usize,
     substitutions: HashMap<usize, Type>,
-    env: HashMap<String, Type>,
-    structs: HashMap<String, HashMap<String, Type>>,
-    enums: HashMap<String, Vec<String>>,
+    env: TypeEnv,
+    structs: HashMap<String, StructDecl>,
+    enums: HashMap<String, EnumDecl>,
+    // The type of every expression, pattern, function and caught error, by span, for lowering.
+    types: Vec<(Span, Type)>,
+    // Variables quantified by some polymorphic type, which lowering instantiates at each use.
+    generic_vars: HashSet<usize>,
+    // Variables only an integer type can replace: the types of integer literals, until
+    // something pins them down. Whatever is left over is `int`.
+    int_vars: HashSet<usize>,
+    // Every integer literal with its value, negated after a unary minus, and its type.
+    int_literals: Vec<(Span, i128, Type)>,
+    // `impl` members by (struct, name); true for methods that take `self`.
+    // Their signatures live in `env` as `Struct.name`, which no identifier can shadow.
+    methods: HashMap<(String, String), bool>,
+    // Where `?` sends an error, innermost last: out of the enclosing function or into a `catch`.
+    try_targets: Vec<TryTarget>,
+    // Loops around the statement being checked, within the innermost function.
+    loops: usize,
+    // Errors found so far. A statement with an error is skipped and checking goes on with the next.
+    errors: Vec<TypeError>,
+}
+
+#[derive(Debug, Clone)]
+enum TryTarget {
+    /// The enclosing function's return type.
+    Return(Type),
+    /// The error type of the enclosing `try`.
+    Catch(Type),
 }
 
 #[derive(Debug)]
 pub struct TypeError {
+    pub code: ErrorCode,
     pub message: String,
     pub span: Span,
+    pub labels: Vec<Label>,
+}
+
+impl TypeError {
+    pub fn new(code: ErrorCode, message: impl Into<String>, span: Span) -> Self {
+        Self { code, message: message.into(), span, labels: Vec::new() }
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label { span, message: message.into() });
+        self
+    }
+}
+
+impl From<TypeError> for Diagnostic {
+    fn from(e: TypeError) -> Self {
+        Diagnostic { code: e.code, message: e.message, span: Some(e.span), labels: e.labels }
+    }
 }
 
 impl TypeChecker {
     pub fn new() -> Self {
+        // Native modules and `log` are typed from `natives::NATIVE_FUNCTIONS` where they are used.
         let mut env = HashMap::new();
-        // Built-in functions or globals could be added here
-        env.insert("log".to_string(), Type::Function { 
-            params: vec![Type::Int], // Simplified for now
-            return_type: Box::new(Type::Void) 
-        });
+        // The only conversions between numeric types; `int` truncates floats toward zero and the
+        // rest fail at run time on values out of range. `check_numeric_call` restricts the argument.
+        let conversions = [("float", Type::Float), ("int", Type::Int), ("i64", Type::Int), ("byte", Type::Sized(IntKind::U8))];
+        let sized = SIZED_INTS.iter().map(|kind| (kind.name(), Type::Sized(*kind)));
+        for (name, target) in conversions.into_iter().chain(sized) {
+            env.insert(name.to_string(), Scheme {
+                vars: vec![0],
+                ty: Type::Function { params: vec![Type::Variable(0)], return_type: Box::new(target) },
+                binding: Binding::Immutable,
+            });
+        }
+        for name in OVERFLOW_HELPERS {
+            env.insert(name.to_string(), Scheme {
+                vars: vec![0],
+                ty: Type::Function { params: vec![Type::Variable(0), Type::Variable(0)], return_type: Box::new(Type::Variable(0)) },
+                binding: Binding::Immutable,
+            });
+        }
 
         Self {
-            next_var: 0,
+            next_var: 1,
             substitutions: HashMap::new(),
             env,
             structs: HashMap::new(),
             enums: HashMap::new(),
+            types: Vec::new(),
+            generic_vars: HashSet::new(),
+            int_vars: HashSet::new(),
+            int_literals: Vec::new(),
+            methods: HashMap::new(),
+            try_targets: Vec::new(),
+            loops: 0,
+            errors: Vec::new(),
         }
     }
 
@@ -82,89 +222,446 @@ impl TypeChecker {
 
         match (t1, t2) {
             (Type::Variable(v), t) | (t, Type::Variable(v)) => {
+                if self.occurs(v, &t) {
+                    return Err(TypeError::new(ErrorCode::InfiniteType, format!("Infinite type: type variable 't{} occurs in {}", v, self.apply(&t)), span));
+                }
+                if self.int_vars.contains(&v) {
+                    match &t {
+                        Type::Int | Type::Sized(_) => {}
+                        Type::Variable(w) => {
+                            self.int_vars.insert(*w);
+                        }
+                        other => return Err(TypeError::new(ErrorCode::TypeMismatch, format!("Type mismatch: int and {}", self.apply(other)), span)),
+                    }
+                }
                 self.substitutions.insert(v, t);
                 Ok(())
             }
             (Type::Function { params: p1, return_type: r1 }, Type::Function { params: p2, return_type: r2 }) => {
                 if p1.len() != p2.len() {
-                    return Err(TypeError { message: "Function arity mismatch".to_string(), span });
+                    return Err(TypeError::new(ErrorCode::ArityMismatch, "Function arity mismatch", span));
                 }
                 for (a, b) in p1.iter().zip(p2.iter()) {
                     self.unify(a, b, span.clone())?;
                 }
                 self.unify(&*r1, &*r2, span)
             }
-            (a, b) => Err(TypeError {
-                message: format!("Type mismatch: {:?} and {:?}", a, b),
-                span,
-            }),
+            (Type::List(a), Type::List(b)) | (Type::Map(a), Type::Map(b)) => self.unify(&a, &b, span),
+            (Type::Struct(n1, a1), Type::Struct(n2, a2)) | (Type::Enum(n1, a1), Type::Enum(n2, a2))
+                if n1 == n2 && a1.len() == a2.len() =>
+            {
+                for (a, b) in a1.iter().zip(a2.iter()) {
+                    self.unify(a, b, span.clone())?;
+                }
+                Ok(())
+            }
+            (a, b) => Err(TypeError::new(ErrorCode::TypeMismatch, format!("Type mismatch: {} and {}", self.apply(&a), self.apply(&b)), span)),
         }
     }
 
-    pub fn check_program(&mut self, program: &[Spanned<Statement>]) -> Result<(), TypeError> {
-        // First pass: collect definitions (Structs, Enums, Functions)
+    /// Fully applies the current substitution.
+    fn apply(&self, t: &Type) -> Type {
+        match t {
+            Type::Variable(v) => match self.find(*v) {
+                Type::Variable(root) => Type::Variable(root),
+                bound => self.apply(&bound),
+            },
+            Type::List(elem) => Type::List(Box::new(self.apply(elem))),
+            Type::Map(value) => Type::Map(Box::new(self.apply(value))),
+            Type::Struct(name, args) => Type::Struct(name.clone(), args.iter().map(|a| self.apply(a)).collect()),
+            Type::Enum(name, args) => Type::Enum(name.clone(), args.iter().map(|a| self.apply(a)).collect()),
+            Type::Function { params, return_type } => Type::Function {
+                params: params.iter().map(|p| self.apply(p)).collect(),
+                return_type: Box::new(self.apply(return_type)),
+            },
+            _ => t.clone(),
+        }
+    }
+
+    fn occurs(&self, var: usize, t: &Type) -> bool {
+        let mut vars = Vec::new();
+        free_type_vars(&self.apply(t), &mut vars);
+        vars.contains(&var)
+    }
+
+    /// Quantifies every variable of `t` that is not free somewhere in `env`.
+    fn generalize(&mut self, env: &TypeEnv, t: &Type) -> Scheme {
+        let ty = self.apply(t);
+        let mut env_vars = Vec::new();
+        for scheme in env.values() {
+            let mut vars = Vec::new();
+            free_type_vars(&self.apply(&scheme.ty), &mut vars);
+            env_vars.extend(vars.into_iter().filter(|v| !scheme.vars.contains(v)));
+        }
+        let mut vars = Vec::new();
+        free_type_vars(&ty, &mut vars);
+        vars.retain(|v| !env_vars.contains(v));
+        // Integer literals nothing else constrains are `int`, not a type parameter.
+        for v in vars.iter().filter(|v| self.int_vars.contains(v)) {
+            self.substitutions.insert(*v, Type::Int);
+        }
+        vars.retain(|v| !self.int_vars.contains(v));
+        self.generic_vars.extend(&vars);
+        let ty = self.apply(&ty);
+        Scheme { vars, ty, binding: Binding::Immutable }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        if scheme.vars.is_empty() {
+            return scheme.ty.clone();
+        }
+        let fresh: HashMap<usize, Type> = scheme.vars.iter().map(|v| (*v, self.new_var())).collect();
+        substitute(&self.apply(&scheme.ty), &fresh)
+    }
+
+    /// Checks a whole program, returning every error found, in source order.
+    pub fn check_program(&mut self, program: &[Spanned<Statement>]) -> Result<(), Vec<TypeError>> {
+        // First pass: collect type declarations, so members may refer to types declared later
         for stmt in program {
             match &stmt.node {
-                Statement::StructDef { name, fields } => {
-                    let mut field_map = HashMap::new();
-                    for (f_name, f_type) in fields {
-                        field_map.insert(f_name.clone(), self.parse_type(f_type));
+                Statement::StructDef { name, type_params, fields, .. } => {
+                    self.structs.insert(name.clone(), StructDecl { type_params: type_params.clone(), fields: fields.clone() });
+                }
+                Statement::EnumDef { name, type_params, variants, .. } => {
+                    self.enums.insert(name.clone(), EnumDecl { type_params: type_params.clone(), variants: variants.clone() });
+                }
+                _ => {}
+            }
+        }
+        for stmt in program {
+            let (type_params, members): (&Vec<String>, Vec<&TypeExpr>) = match &stmt.node {
+                Statement::StructDef { type_params, fields, .. } => (type_params, fields.iter().map(|(_, t)| t).collect()),
+                Statement::EnumDef { type_params, variants, .. } => (type_params, variants.iter().flat_map(|(_, p)| p).collect()),
+                _ => continue,
+            };
+            let bindings: HashMap<String, Type> = type_params.iter().map(|p| (p.clone(), self.new_var())).collect();
+            for t in members {
+                if let Err(e) = self.parse_type_with(t, &bindings, &stmt.span) {
+                    self.errors.push(e);
+                }
+            }
+        }
+
+        // Methods get monomorphic signatures up front so functions and other methods can call them.
+        // Those of a generic struct are checked with the functions below, to be generalised like them.
+        let mut method_types = Vec::new();
+        let mut generic_methods = Vec::new();
+        for stmt in program {
+            let Statement::Impl { type_name, methods } = &stmt.node else { continue };
+            let Some(decl) = self.structs.get(type_name) else {
+                self.errors.push(TypeError::new(ErrorCode::UnknownType, format!("Undefined struct {}", type_name), stmt.span.clone()));
+                continue;
+            };
+            let generic = !decl.type_params.is_empty();
+            for method in methods {
+                match self.declare_method(type_name, method) {
+                    Ok(Some(signature)) if generic => {
+                        let Statement::FunctionDef { name, .. } = &method.node else { unreachable!() };
+                        generic_methods.push((format!("{}.{}", type_name, name), method, signature));
                     }
-                    self.structs.insert(name.clone(), field_map);
+                    Ok(Some((param_types, ret_type))) => method_types.push((method, param_types, ret_type)),
+                    Ok(None) => {}
+                    Err(e) => self.errors.push(e),
                 }
-                Statement::EnumDef { name, variants } => {
-                    self.enums.insert(name.clone(), variants.clone());
+            }
+        }
+
+        // Top-level constants come before any function body that may use them. Their
+        // values may refer to earlier constants but not to functions.
+        for stmt in program.iter().filter(|stmt| matches!(stmt.node, Statement::Const { .. })) {
+            let mut env = self.env.clone();
+            self.check_block(std::slice::from_ref(stmt), &mut env, &Type::Void);
+            self.env = env;
+        }
+
+        // Functions may use the variables a script declares at the top level. Each gets one
+        // type, which its `let` further down must agree with.
+        let mut globals = HashMap::new();
+        for stmt in program {
+            let Statement::Let { name, mutable, ty, .. } = &stmt.node else { continue };
+            if globals.contains_key(name) {
+                continue;
+            }
+            // A bad annotation is reported when the `let` itself is checked.
+            let declared = self.annotated_or_fresh(ty, &stmt.span).unwrap_or_else(|_| self.new_var());
+            let binding = if *mutable { Binding::Mutable } else { Binding::Immutable };
+            self.env.insert(name.clone(), Scheme { binding, ..Scheme::mono(declared.clone()) });
+            globals.insert(name.clone(), declared);
+        }
+
+        // Second pass: infer functions one recursive group at a time, callees first,
+        // so each function is generalised before its callers instantiate it.
+        let mut functions: Vec<(String, &Spanned<Statement>)> = program.iter()
+            .filter_map(|stmt| match &stmt.node {
+                Statement::FunctionDef { name, .. } => Some((name.clone(), stmt)),
+                _ => None,
+            })
+            .collect();
+        let mut declared = HashMap::new();
+        for (name, method, signature) in generic_methods {
+            declared.insert(functions.len(), signature);
+            functions.push((name, method));
+        }
+        for group in function_groups(&functions) {
+            let mut types = Vec::new();
+            for &i in &group {
+                if let Some(signature) = declared.remove(&i) {
+                    types.push(signature);
+                    continue;
                 }
-                Statement::FunctionDef { name, params, body: _ } => {
-                    let param_types = params.iter().map(|(_, t)| self.parse_type(t)).collect();
-                    // Assume main returns Int, others Void for now unless specified
-                    let ret_type = if name == "main" { Type::Int } else { Type::Void };
-                    self.env.insert(name.clone(), Type::Function {
-                        params: param_types,
-                        return_type: Box::new(ret_type),
-                    });
+                let (name, stmt) = &functions[i];
+                let Statement::FunctionDef { params, return_type, .. } = &stmt.node else { unreachable!() };
+                let signature = self.param_types(params, &stmt.span)
+                    .and_then(|param_types| Ok((param_types, self.annotated_or_fresh(return_type, &stmt.span)?)));
+                // A function with a broken signature is still checked, with its parameters left open.
+                let (param_types, ret_type) = signature.unwrap_or_else(|e| {
+                    self.errors.push(e);
+                    (params.iter().map(|_| self.new_var()).collect(), self.new_var())
+                });
+                let fn_type = Type::Function { params: param_types.clone(), return_type: Box::new(ret_type.clone()) };
+                self.types.push((stmt.span.clone(), fn_type.clone()));
+                self.env.insert(name.clone(), Scheme::mono(fn_type));
+                types.push((param_types, ret_type));
+            }
+
+            for (&i, (param_types, ret_type)) in group.iter().zip(types) {
+                let Statement::FunctionDef { params, return_type, body, .. } = &functions[i].1.node else { unreachable!() };
+                let env = self.env.clone();
+                if let Err(e) = self.check_function_body(params, param_types, body, &env, &ret_type, return_type.is_some()) {
+                    self.errors.push(e);
                 }
-                _ => {}
+            }
+
+            let group_types: Vec<(String, Type)> = group.iter().map(|&i| {
+                let name = &functions[i].0;
+                (name.clone(), self.env.remove(name).map(|s| s.ty).unwrap_or(Type::Void))
+            }).collect();
+            for (name, t) in group_types {
+                let env = std::mem::take(&mut self.env);
+                let scheme = self.generalize(&env, &t);
+                self.env = env;
+                self.env.insert(name, scheme);
+            }
+        }
+
+        for (method, param_types, ret_type) in method_types {
+            let Statement::FunctionDef { params, return_type, body, .. } = &method.node else { unreachable!() };
+            let env = self.env.clone();
+            if let Err(e) = self.check_function_body(params, param_types, body, &env, &ret_type, return_type.is_some()) {
+                self.errors.push(e);
             }
         }
 
-        // Second pass: check function bodies
+        // Then the script's own code, which runs before `main`, in order.
+        self.check_global_order(program);
+        let mut env = self.env.clone();
         for stmt in program {
-            if let Statement::FunctionDef { name, params, body } = &stmt.node {
-                let mut local_env = self.env.clone();
-                for (p_name, p_type) in params {
-                    local_env.insert(p_name.clone(), self.parse_type(p_type));
+            if matches!(stmt.node, Statement::FunctionDef { .. } | Statement::StructDef { .. } | Statement::EnumDef { .. }
+                | Statement::Impl { .. } | Statement::Const { .. }) {
+                continue;
+            }
+            self.check_block(std::slice::from_ref(stmt), &mut env, &Type::Void);
+            if let Statement::Let { name, value, .. } = &stmt.node {
+                if let (Some(global), Some(scheme)) = (globals.remove(name), env.get(name).cloned()) {
+                    let local = self.instantiate(&scheme);
+                    if let Err(e) = self.unify(&local, &global, value.span.clone()) {
+                        self.errors.push(e);
+                    }
                 }
-                
-                let expected_ret = if name == "main" { Type::Int } else { Type::Void };
-                self.check_block(body, &mut local_env, &expected_ret)?;
             }
         }
 
-        Ok(())
+        self.check_int_literals();
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        let mut errors = std::mem::take(&mut self.errors);
+        errors.sort_by_key(|e| e.span.start);
+        Err(errors)
+    }
+
+    /// Reports top-level code that reads a global, or calls something that does, before the
+    /// global's `let` has run. Functions see every global, but only once it is assigned.
+    fn check_global_order(&mut self, program: &[Spanned<Statement>]) {
+        let mut lets: HashMap<&str, Span> = HashMap::new();
+        // What each top-level function, or lambda a top-level `let` binds, may read when called.
+        let mut uses: HashMap<&str, Vec<String>> = HashMap::new();
+        for stmt in program {
+            let (name, params, body) = match &stmt.node {
+                Statement::FunctionDef { name, params, body, .. } => (name, params, body),
+                Statement::Let { name, value, .. } => {
+                    lets.entry(name).or_insert_with(|| stmt.span.clone());
+                    let Expression::Lambda { params, body, .. } = &value.node else { continue };
+                    (name, params, body)
+                }
+                _ => continue,
+            };
+            let bound = params.iter().map(|(p, _)| p.clone()).collect();
+            let mut free = Vec::new();
+            free_variables(body, &bound, &mut free);
+            uses.entry(name).or_insert(free);
+        }
+
+        let mut assigned: HashSet<&str> = HashSet::new();
+        for stmt in program {
+            if matches!(stmt.node, Statement::FunctionDef { .. } | Statement::StructDef { .. } | Statement::EnumDef { .. }
+                | Statement::Impl { .. } | Statement::Const { .. } | Statement::Import { .. }) {
+                continue;
+            }
+            let mut reported = HashSet::new();
+            for (name, span) in direct_uses(std::slice::from_ref(stmt)) {
+                let Some(global) = unassigned_global(&name, &uses, &lets, &assigned, &mut HashSet::new()) else { continue };
+                if !reported.insert(name.clone()) {
+                    continue;
+                }
+                let message = if global == name {
+                    format!("{} is used before its let has run", name)
+                } else {
+                    format!("Calling {} here reads {} before its let has run", name, global)
+                };
+                let declared = lets[global.as_str()].clone();
+                self.errors.push(TypeError::new(ErrorCode::UndefinedName, message, span).with_label(declared, format!("{} is assigned here", global)));
+            }
+            if let Statement::Let { name, .. } = &stmt.node {
+                assigned.insert(name);
+            }
+        }
+    }
+
+    /// Makes integer literals that nothing pinned down `int`, then checks that each literal
+    /// fits its type.
+    fn check_int_literals(&mut self) {
+        let open: Vec<usize> = self.int_vars.iter().filter_map(|v| match self.find(*v) {
+            Type::Variable(root) => Some(root),
+            _ => None,
+        }).collect();
+        for v in open {
+            self.substitutions.insert(v, Type::Int);
+        }
+        for (span, value, t) in self.int_literals.clone() {
+            let kind = match self.resolve(&t) {
+                Type::Sized(kind) => kind,
+                _ => IntKind::I64,
+            };
+            if !kind.contains(value) {
+                let message = format!("The literal {} is out of range for {}, which holds {} to {}", value, kind.name(), kind.min(), kind.max());
+                self.errors.push(TypeError::new(ErrorCode::IntegerOverflow, message, span));
+            }
+        }
+    }
+
+    /// The sized integer type of each integer literal that has one, for the interpreter,
+    /// which otherwise takes every literal as an `int`.
+    pub fn sized_literals(&self) -> HashMap<Span, IntKind> {
+        self.int_literals.iter().filter_map(|(span, _, t)| match self.resolve(t) {
+            Type::Sized(kind) => Some((span.clone(), kind)),
+            _ => None,
+        }).collect()
+    }
+
+    /// Gives a method of `type_name` its monomorphic signature, returning its parameter and
+    /// result types. The struct's type parameters stand for fresh type variables in it.
+    /// Anything but a function in the `impl` block is ignored.
+    fn declare_method(&mut self, type_name: &str, method: &Spanned<Statement>) -> Result<Option<(Vec<Type>, Type)>, TypeError> {
+        let Statement::FunctionDef { name, params, return_type, .. } = &method.node else { return Ok(None) };
+        let decl = &self.structs[type_name];
+        let type_params = decl.type_params.clone();
+        if decl.fields.iter().any(|(f, _)| f == name) {
+            return Err(TypeError::new(ErrorCode::DuplicateDefinition, format!("Method {} conflicts with a field of struct {}", name, type_name), method.span.clone()));
+        }
+        let takes_self = params.first().is_some_and(|(p, _)| p == "self");
+        if self.methods.insert((type_name.to_string(), name.clone()), takes_self).is_some() {
+            return Err(TypeError::new(ErrorCode::DuplicateDefinition, format!("Duplicate method {} for struct {}", name, type_name), method.span.clone()));
+        }
+        let args: Vec<Type> = type_params.iter().map(|_| self.new_var()).collect();
+        let bindings = type_params.into_iter().zip(args.iter().cloned()).collect();
+        let mut annotated_or_fresh = |t: &Option<TypeExpr>| match t {
+            Some(t) => self.parse_type_with(t, &bindings, &method.span),
+            None => Ok(self.new_var()),
+        };
+        let param_types = params.iter().map(|(_, t)| annotated_or_fresh(t)).collect::<Result<Vec<_>, _>>()?;
+        let ret_type = annotated_or_fresh(return_type)?;
+        if takes_self {
+            self.unify(&param_types[0], &Type::Struct(type_name.to_string(), args), method.span.clone())?;
+        }
+        let fn_type = Type::Function { params: param_types.clone(), return_type: Box::new(ret_type.clone()) };
+        self.types.push((method.span.clone(), fn_type.clone()));
+        self.env.insert(format!("{}.{}", type_name, name), Scheme::mono(fn_type));
+        Ok(Some((param_types, ret_type)))
+    }
+
+    /// The scheme a `let` or `const` binds its name to.
+    fn binding_scheme(&mut self, ty: &Option<TypeExpr>, value: &Spanned<Expression>, binding: Binding, env: &TypeEnv, span: &Span) -> Result<Scheme, TypeError> {
+        let val_type = self.infer_expression(value, env)?;
+        let declared = match ty {
+            Some(t) => {
+                let declared = self.parse_type(t, span)?;
+                self.unify(&val_type, &declared, value.span.clone())?;
+                declared
+            }
+            None => val_type,
+        };
+        // Value restriction: only lambdas are generalised, so a `let xs = []` stays monomorphic.
+        // A mutable binding keeps one type, as anything assigned to it must have it too.
+        let scheme = if matches!(value.node, Expression::Lambda { .. }) && binding != Binding::Mutable {
+            self.generalize(env, &declared)
+        } else {
+            Scheme::mono(declared)
+        };
+        Ok(Scheme { binding, ..scheme })
     }
 
-    fn check_block(&mut self, block: &[Spanned<Statement>], env: &mut HashMap<String, Type>, expected_ret: &Type) -> Result<(), TypeError> {
+    /// Checks each statement in turn, recording errors rather than stopping at the first.
+    fn check_block(&mut self, block: &[Spanned<Statement>], env: &mut TypeEnv, expected_ret: &Type) {
         for stmt in block {
-            self.check_statement(stmt, env, expected_ret)?;
+            if let Err(e) = self.check_statement(stmt, env, expected_ret) {
+                self.errors.push(e);
+                // Whatever a broken statement declares still exists, so later uses are not errors too.
+                if let Statement::Let { name, .. } | Statement::Const { name, .. } | Statement::FunctionDef { name, .. } = &stmt.node {
+                    let unknown = self.new_var();
+                    env.insert(name.clone(), Scheme::mono(unknown));
+                }
+            }
         }
-        Ok(())
     }
 
-    fn check_statement(&mut self, stmt: &Spanned<Statement>, env: &mut HashMap<String, Type>, expected_ret: &Type) -> Result<(), TypeError> {
+    fn check_statement(&mut self, stmt: &Spanned<Statement>, env: &mut TypeEnv, expected_ret: &Type) -> Result<(), TypeError> {
         match &stmt.node {
-            Statement::Let { name, value } => {
-                let val_type = self.infer_expression(value, env)?;
-                env.insert(name.clone(), val_type);
+            Statement::Let { name, mutable, ty, value } => {
+                let binding = if *mutable { Binding::Mutable } else { Binding::Immutable };
+                let scheme = self.binding_scheme(ty, value, binding, env, &stmt.span)?;
+                env.insert(name.clone(), scheme);
+            }
+            Statement::Const { name, ty, value, .. } => {
+                let scheme = self.binding_scheme(ty, value, Binding::Const, env, &stmt.span)?;
+                env.insert(name.clone(), scheme);
             }
             Statement::Set { name, value } => {
+                match env.get(name).map(|s| s.binding) {
+                    Some(Binding::Immutable) => {
+                        return Err(TypeError::new(ErrorCode::ImmutableAssignment, format!("Cannot assign to immutable variable {}; declare it with let mut", name), stmt.span.clone()));
+                    }
+                    Some(Binding::Const) => {
+                        return Err(TypeError::new(ErrorCode::ImmutableAssignment, format!("Cannot assign to constant {}", name), stmt.span.clone()));
+                    }
+                    _ => {}
+                }
                 let val_type = self.infer_expression(value, env)?;
-                if let Some(expected) = env.get(name) {
-                    self.unify(&val_type, expected, value.span.clone())?;
+                if let Some(scheme) = env.get(name).cloned() {
+                    let expected = self.instantiate(&scheme);
+                    self.unify(&val_type, &expected, value.span.clone())?;
                 } else {
-                    return Err(TypeError { message: format!("Undefined variable {}", name), span: stmt.span.clone() });
+                    return Err(TypeError::new(ErrorCode::UndefinedName, format!("Undefined variable {}", name), stmt.span.clone()));
                 }
             }
+            Statement::IndexSet { object, index, value } => {
+                let elem_type = self.infer_index(object, index, env)?;
+                let val_type = self.infer_expression(value, env)?;
+                self.unify(&val_type, &elem_type, value.span.clone())?;
+            }
+            Statement::Return(_) if self.function_return().is_none() => {
+                return Err(TypeError::new(ErrorCode::MisplacedControlFlow, "return outside a function", stmt.span.clone()));
+            }
             Statement::Return(expr) => {
                 let val_type = self.infer_expression(expr, env)?;
                 self.unify(&val_type, expected_ret, expr.span.clone())?;
@@ -172,102 +669,1045 @@ impl TypeChecker {
             Statement::If { cond, then_block, else_block } => {
                 let cond_type = self.infer_expression(cond, env)?;
                 self.unify(&cond_type, &Type::Bool, cond.span.clone())?;
-                self.check_block(then_block, &mut env.clone(), expected_ret)?;
+                self.check_block(then_block, &mut env.clone(), expected_ret);
                 if let Some(eb) = else_block {
-                    self.check_block(eb, &mut env.clone(), expected_ret)?;
+                    self.check_block(eb, &mut env.clone(), expected_ret);
                 }
             }
             Statement::While { cond, body } => {
                 let cond_type = self.infer_expression(cond, env)?;
                 self.unify(&cond_type, &Type::Bool, cond.span.clone())?;
-                self.check_block(body, &mut env.clone(), expected_ret)?;
+                self.check_loop_body(body, &mut env.clone(), expected_ret);
+            }
+            Statement::Loop { count, body } => {
+                if let Some(c) = count {
+                    let count_type = self.infer_expression(c, env)?;
+                    self.unify(&count_type, &Type::Int, c.span.clone())?;
+                }
+                self.check_loop_body(body, &mut env.clone(), expected_ret);
+            }
+            Statement::ForIn { var, iterable, body } => {
+                let iter_type = self.infer_expression(iterable, env)?;
+                let item_type = match self.resolve(&iter_type) {
+                    Type::List(elem) => *elem,
+                    Type::Map(_) => Type::Str,
+                    Type::Variable(_) => self.new_var(),
+                    other => return Err(TypeError::new(ErrorCode::TypeMismatch, format!("Type {} is not iterable", other), iterable.span.clone())),
+                };
+                let mut body_env = env.clone();
+                body_env.insert(var.clone(), Scheme::mono(item_type));
+                self.check_loop_body(body, &mut body_env, expected_ret);
             }
             Statement::Expression(expr) => {
                 self.infer_expression(expr, env)?;
             }
-            _ => {}
+            Statement::Match { subject, arms } => {
+                let subject_type = self.infer_expression(subject, env)?;
+                for arm in arms {
+                    let mut arm_env = env.clone();
+                    self.check_pattern(&arm.pattern, &subject_type, &mut arm_env)?;
+                    self.check_block(&arm.body, &mut arm_env, expected_ret);
+                }
+                self.check_exhaustive(subject, &subject_type, arms, &stmt.span)?;
+            }
+            Statement::FunctionDef { name, params, return_type, body, .. } => {
+                // Bind the name monomorphically while checking the body so nested functions can recurse.
+                let param_types = self.param_types(params, &stmt.span)?;
+                let ret_type = self.annotated_or_fresh(return_type, &stmt.span)?;
+                let fn_type = Type::Function {
+                    params: param_types.clone(),
+                    return_type: Box::new(ret_type.clone()),
+                };
+                env.insert(name.clone(), Scheme::mono(fn_type.clone()));
+                self.types.push((stmt.span.clone(), fn_type.clone()));
+                self.check_function_body(params, param_types, body, env, &ret_type, return_type.is_some())?;
+                env.remove(name);
+                let scheme = self.generalize(env, &fn_type);
+                env.insert(name.clone(), scheme);
+            }
+            Statement::TryCatch { body, error, handler } => {
+                let error_type = self.new_var();
+                self.try_targets.push(TryTarget::Catch(error_type.clone()));
+                self.check_block(body, &mut env.clone(), expected_ret);
+                self.try_targets.pop();
+                self.types.push((stmt.span.clone(), error_type.clone()));
+                let mut handler_env = env.clone();
+                handler_env.insert(error.clone(), Scheme::mono(error_type));
+                self.check_block(handler, &mut handler_env, expected_ret);
+            }
+            Statement::UnsafeBlock(body) => self.check_block(body, &mut env.clone(), expected_ret),
+            Statement::Break | Statement::Continue if self.loops == 0 => {
+                let keyword = if matches!(stmt.node, Statement::Break) { "break" } else { "continue" };
+                return Err(TypeError::new(ErrorCode::MisplacedControlFlow, format!("{} outside a loop", keyword), stmt.span.clone()));
+            }
+            Statement::Break | Statement::Continue => {}
+            // The module loader has already resolved imports of eMo files.
+            Statement::Import { .. } => {}
+            // Already reported by the parser.
+            Statement::Error => {}
+            // `check_program` handles these at the top level.
+            Statement::StructDef { name, .. } | Statement::EnumDef { name, .. } | Statement::Impl { type_name: name, .. } => {
+                return Err(TypeError::new(ErrorCode::Unsupported, format!("{} must be declared at the top level", name), stmt.span.clone()));
+            }
         }
         Ok(())
     }
 
-    fn infer_expression(&mut self, expr: &Spanned<Expression>, env: &HashMap<String, Type>) -> Result<Type, TypeError> {
+    fn check_loop_body(&mut self, body: &[Spanned<Statement>], env: &mut TypeEnv, expected_ret: &Type) {
+        self.loops += 1;
+        self.check_block(body, env, expected_ret);
+        self.loops -= 1;
+    }
+
+    /// The result type of the innermost enclosing function or lambda, if there is one.
+    fn function_return(&self) -> Option<Type> {
+        self.try_targets.iter().rev().find_map(|target| match target {
+            TryTarget::Return(ret) => Some(ret.clone()),
+            TryTarget::Catch(_) => None,
+        })
+    }
+
+    fn infer_expression(&mut self, expr: &Spanned<Expression>, env: &TypeEnv) -> Result<Type, TypeError> {
+        let t = self.infer_node(expr, env)?;
+        self.types.push((expr.span.clone(), t.clone()));
+        Ok(t)
+    }
+
+    fn infer_node(&mut self, expr: &Spanned<Expression>, env: &TypeEnv) -> Result<Type, TypeError> {
         match &expr.node {
-            Expression::Number(_) => Ok(Type::Int),
+            Expression::Number(n) => {
+                let t = self.new_var();
+                if let Type::Variable(v) = t {
+                    self.int_vars.insert(v);
+                }
+                self.int_literals.push((expr.span.clone(), *n as i128, t.clone()));
+                Ok(t)
+            }
+            Expression::Float(_) => Ok(Type::Float),
             Expression::StringLit(_) => Ok(Type::Str),
+            Expression::Interpolated(parts) => {
+                // Any value can be interpolated; the C backend picks a conversion by its type.
+                for part in parts {
+                    if let StringPart::Expr(e) = part {
+                        self.infer_expression(e, env)?;
+                    }
+                }
+                Ok(Type::Str)
+            }
             Expression::Bool(_) => Ok(Type::Bool),
             Expression::Null => Ok(self.new_var()),
+            Expression::Identifier(_) | Expression::MemberAccess { .. } if native_module(expr, env).is_some() => {
+                match native_function(expr, env) {
+                    Some(native) => Ok(self.native_type(native, None, &expr.span)?.0),
+                    None => {
+                        let (module, member) = native_module(expr, env).unwrap_or_default();
+                        Err(TypeError::new(ErrorCode::UnknownMember, format!("Module {} has no function {}", module, member), expr.span.clone()))
+                    }
+                }
+            }
             Expression::Identifier(name) => {
-                if let Some(t) = env.get(name) {
-                    Ok(t.clone())
+                if let Some(scheme) = env.get(name) {
+                    let scheme = scheme.clone();
+                    Ok(self.instantiate(&scheme))
                 } else {
-                    Err(TypeError { message: format!("Undefined identifier {}", name), span: expr.span.clone() })
+                    Err(TypeError::new(ErrorCode::UndefinedName, format!("Undefined identifier {}", name), expr.span.clone()))
+                }
+            }
+            Expression::ListLiteral(items) => {
+                let elem_type = self.new_var();
+                for item in items {
+                    let t = self.infer_expression(item, env)?;
+                    self.unify(&t, &elem_type, item.span.clone())?;
+                }
+                Ok(Type::List(Box::new(elem_type)))
+            }
+            Expression::MapLiteral(entries) => {
+                let value_type = self.new_var();
+                for (key, value) in entries {
+                    let kt = self.infer_expression(key, env)?;
+                    self.unify(&kt, &Type::Str, key.span.clone())?;
+                    let vt = self.infer_expression(value, env)?;
+                    self.unify(&vt, &value_type, value.span.clone())?;
+                }
+                Ok(Type::Map(Box::new(value_type)))
+            }
+            Expression::Index { object, index } => self.infer_index(object, index, env),
+            Expression::Range { start, end } => {
+                for bound in [start, end] {
+                    let t = self.infer_expression(bound, env)?;
+                    self.unify(&t, &Type::Int, bound.span.clone())?;
                 }
+                Ok(Type::List(Box::new(Type::Int)))
             }
             Expression::BinaryOp(left, op, right) => {
                 let lt = self.infer_expression(left, env)?;
                 let rt = self.infer_expression(right, env)?;
                 match op {
-                    Op::Plus | Op::Minus | Op::Mul | Op::Div => {
-                        self.unify(&lt, &Type::Int, left.span.clone())?;
-                        self.unify(&rt, &Type::Int, right.span.clone())?;
-                        Ok(Type::Int)
+                    Op::Plus if [&lt, &rt].iter().any(|t| self.resolve(t) == Type::Str) => {
+                        self.unify(&lt, &Type::Str, left.span.clone()).map_err(|e| self.label_operands(e, &[(left, &lt), (right, &rt)]))?;
+                        self.unify(&rt, &Type::Str, right.span.clone()).map_err(|e| self.label_operands(e, &[(left, &lt), (right, &rt)]))?;
+                        Ok(Type::Str)
+                    }
+                    Op::Plus | Op::Minus | Op::Mul | Op::Div | Op::Mod => {
+                        // Both operands share one numeric type: numeric types never mix implicitly.
+                        let numeric = self.numeric_type(&[&lt, &rt]);
+                        self.unify(&lt, &numeric, left.span.clone()).map_err(|e| self.label_operands(e, &[(left, &lt), (right, &rt)]))?;
+                        self.unify(&rt, &numeric, right.span.clone()).map_err(|e| self.label_operands(e, &[(left, &lt), (right, &rt)]))?;
+                        Ok(numeric)
+                    }
+                    Op::BitAnd | Op::BitOr | Op::BitXor => {
+                        let integer = self.integer_type(&[&lt, &rt]);
+                        self.unify(&lt, &integer, left.span.clone()).map_err(|e| self.label_operands(e, &[(left, &lt), (right, &rt)]))?;
+                        self.unify(&rt, &integer, right.span.clone()).map_err(|e| self.label_operands(e, &[(left, &lt), (right, &rt)]))?;
+                        Ok(integer)
+                    }
+                    Op::Shl | Op::Shr => {
+                        // The shift amount is always an `int`.
+                        let integer = self.integer_type(&[&lt]);
+                        self.unify(&lt, &integer, left.span.clone()).map_err(|e| self.label_operands(e, &[(left, &lt), (right, &rt)]))?;
+                        self.unify(&rt, &Type::Int, right.span.clone()).map_err(|e| self.label_operands(e, &[(left, &lt), (right, &rt)]))?;
+                        Ok(integer)
                     }
                     Op::Eq | Op::NotEq | Op::Gt | Op::Lt | Op::Gte | Op::Lte => {
-                        self.unify(&lt, &rt, expr.span.clone())?;
+                        self.unify(&lt, &rt, expr.span.clone()).map_err(|e| self.label_operands(e, &[(left, &lt), (right, &rt)]))?;
                         Ok(Type::Bool)
                     }
+                    Op::And | Op::Or => {
+                        self.unify(&lt, &Type::Bool, left.span.clone()).map_err(|e| self.label_operands(e, &[(left, &lt), (right, &rt)]))?;
+                        self.unify(&rt, &Type::Bool, right.span.clone()).map_err(|e| self.label_operands(e, &[(left, &lt), (right, &rt)]))?;
+                        Ok(Type::Bool)
+                    }
+                }
+            }
+            Expression::Unary(op, operand) => {
+                let t = self.infer_expression(operand, env)?;
+                // `-128` is in range for an `i8`, though `128` is not.
+                if let (UnaryOp::Neg, Expression::Number(_)) = (op, &operand.node) {
+                    if let Some((_, value, _)) = self.int_literals.last_mut() {
+                        *value = -*value;
+                    }
+                }
+                let result = match op {
+                    UnaryOp::Neg => self.numeric_type(&[&t]),
+                    UnaryOp::Not => Type::Bool,
+                };
+                self.unify(&t, &result, operand.span.clone())?;
+                Ok(result)
+            }
+            Expression::Try(operand) => self.infer_try(operand, &expr.span, env),
+            Expression::If { cond, then_block, else_block } => {
+                let cond_type = self.infer_expression(cond, env)?;
+                self.unify(&cond_type, &Type::Bool, cond.span.clone())?;
+                let then_type = self.infer_block(then_block, env)?;
+                let ty = match else_block {
+                    Some(eb) => {
+                        let else_type = self.infer_block(eb, env)?;
+                        let span = split_value(eb).1.map_or(expr.span.clone(), |e| e.span.clone());
+                        self.unify(&else_type, &then_type, span)?;
+                        then_type
+                    }
+                    None => Type::Void,
+                };
+                Ok(ty)
+            }
+            Expression::Match { subject, arms } => {
+                let subject_type = self.infer_expression(subject, env)?;
+                let ty = self.new_var();
+                for arm in arms {
+                    let mut arm_env = env.clone();
+                    self.check_pattern(&arm.pattern, &subject_type, &mut arm_env)?;
+                    let arm_type = self.infer_block(&arm.body, &arm_env)?;
+                    let span = split_value(&arm.body).1.map_or(arm.pattern.span.clone(), |e| e.span.clone());
+                    self.unify(&arm_type, &ty, span)?;
                 }
+                self.check_exhaustive(subject, &subject_type, arms, &expr.span)?;
+                Ok(ty)
             }
+            Expression::Block(body) => self.infer_block(body, env),
             Expression::Call { func, args } => {
-                let ft = self.infer_expression(func, env)?;
+                let native = native_function(func, env);
+                let (ft, number) = match native {
+                    Some(native) => {
+                        let (ft, number) = self.native_type(native, Some(args.len()), &expr.span)?;
+                        (ft, Some(number))
+                    }
+                    None => (self.infer_expression(func, env)?, None),
+                };
                 let mut arg_types = Vec::new();
                 for arg in args {
                     arg_types.push(self.infer_expression(arg, env)?);
                 }
                 let ret_var = self.new_var();
                 let call_type = Type::Function {
-                    params: arg_types,
+                    params: arg_types.clone(),
                     return_type: Box::new(ret_var.clone()),
                 };
-                self.unify(&ft, &call_type, expr.span.clone())?;
+                self.unify(&ft, &call_type, expr.span.clone())
+                    .map_err(|e| self.label_operands(e, &[(func, &ft)]))?;
+                if let Some(name) = numeric_builtin(func, env) {
+                    self.check_numeric_call(name, args, &arg_types)?;
+                }
+                if let (Some(native), Some(number)) = (native, number) {
+                    if !matches!(self.resolve(&number), Type::Int | Type::Float | Type::Sized(_) | Type::Variable(_)) {
+                        return Err(TypeError::new(ErrorCode::TypeMismatch, format!("{} expects numbers, found {}", native.qualified_name(), self.apply(&number)), expr.span.clone()));
+                    }
+                }
                 Ok(ret_var)
             }
+            Expression::MemberAccess { object, member } if self.enum_name(object, env).is_some() => {
+                let enum_name = self.enum_name(object, env).unwrap_or_default();
+                self.infer_variant(&enum_name, member, &expr.span)
+            }
+            Expression::MemberAccess { object, member } if self.struct_name(object, env).is_some() => {
+                let struct_name = self.struct_name(object, env).unwrap_or_default();
+                match env.get(&format!("{}.{}", struct_name, member)) {
+                    Some(scheme) => {
+                        let scheme = scheme.clone();
+                        Ok(self.instantiate(&scheme))
+                    }
+                    None => Err(TypeError::new(ErrorCode::UnknownMember, format!("Struct {} has no associated function {}", struct_name, member), expr.span.clone())),
+                }
+            }
             Expression::MemberAccess { object, member } => {
                 let ot = self.infer_expression(object, env)?;
-                match ot {
-                    Type::Struct(struct_name) => {
-                        if let Some(fields) = self.structs.get(&struct_name) {
-                            if let Some(t) = fields.get(member) {
-                                Ok(t.clone())
+                match self.resolve(&ot) {
+                    Type::List(elem) => self.list_member(*elem, member, expr.span.clone()),
+                    Type::Map(value) => self.map_member(*value, member, expr.span.clone()),
+                    Type::Struct(struct_name, args) => {
+                        if let Some(decl) = self.structs.get(&struct_name) {
+                            if let Some((_, t)) = decl.fields.iter().find(|(f_name, _)| f_name == member) {
+                                let bindings = decl.type_params.iter().cloned().zip(args).collect();
+                                self.parse_type_with(&t.clone(), &bindings, &expr.span)
+                            } else if self.methods.get(&(struct_name.clone(), member.clone())) == Some(&true) {
+                                self.method_type(&struct_name, member, &ot, &expr.span, env)
                             } else {
-                                Err(TypeError { message: format!("Struct {} has no field {}", struct_name, member), span: expr.span.clone() })
+                                Err(TypeError::new(ErrorCode::UnknownMember, format!("Struct {} has no field or method {}", struct_name, member), expr.span.clone()))
                             }
                         } else {
-                            Err(TypeError { message: format!("Undefined struct {}", struct_name), span: expr.span.clone() })
+                            Err(TypeError::new(ErrorCode::UnknownType, format!("Undefined struct {}", struct_name), expr.span.clone()))
                         }
                     }
                     _ => Ok(self.new_var()), // Could be a module access or native object
                 }
             }
-            _ => Ok(self.new_var()),
+            Expression::StructLiteral { name, fields } => {
+                let Some(decl) = self.structs.get(name) else {
+                    return Err(TypeError::new(ErrorCode::UnknownType, format!("Undefined struct {}", name), expr.span.clone()));
+                };
+                let type_params = decl.type_params.clone();
+                let declared = decl.fields.clone();
+                let args: Vec<Type> = type_params.iter().map(|_| self.new_var()).collect();
+                let bindings = type_params.into_iter().zip(args.iter().cloned()).collect();
+                for (i, (f_name, f_val)) in fields.iter().enumerate() {
+                    let Some((_, f_type)) = declared.iter().find(|(d_name, _)| d_name == f_name) else {
+                        return Err(TypeError::new(ErrorCode::UnknownMember, format!("Struct {} has no field {}", name, f_name), f_val.span.clone()));
+                    };
+                    if fields[..i].iter().any(|(earlier, _)| earlier == f_name) {
+                        return Err(TypeError::new(ErrorCode::DuplicateDefinition, format!("Field {} is given more than once", f_name), f_val.span.clone()));
+                    }
+                    let val_type = self.infer_expression(f_val, env)?;
+                    let f_type = self.parse_type_with(f_type, &bindings, &expr.span)?;
+                    self.unify(&val_type, &f_type, f_val.span.clone())?;
+                }
+                let missing: Vec<&str> = declared.iter()
+                    .filter(|(d_name, _)| !fields.iter().any(|(f_name, _)| f_name == d_name))
+                    .map(|(d_name, _)| d_name.as_str())
+                    .collect();
+                if !missing.is_empty() {
+                    return Err(TypeError::new(ErrorCode::MissingField, format!("Missing field(s) {} in struct {}", missing.join(", "), name), expr.span.clone()));
+                }
+                Ok(Type::Struct(name.clone(), args))
+            }
+            Expression::Lambda { params, return_type, body } => {
+                let param_types = self.param_types(params, &expr.span)?;
+                let ret_type = self.annotated_or_fresh(return_type, &expr.span)?;
+                self.check_function_body(params, param_types.clone(), body, env, &ret_type, return_type.is_some())?;
+                Ok(Type::Function { params: param_types, return_type: Box::new(ret_type) })
+            }
+            // Already reported by the parser; any type fits so checking can go on.
+            Expression::Error => Ok(self.new_var()),
+        }
+    }
+
+    /// The struct named by `object` in `Struct.function`, unless a variable shadows it.
+    fn struct_name(&self, object: &Spanned<Expression>, env: &TypeEnv) -> Option<String> {
+        match &object.node {
+            Expression::Identifier(name) if self.structs.contains_key(name) && !env.contains_key(name) => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    /// A method accessed on a value: its signature with `self` already applied.
+    fn method_type(&mut self, struct_name: &str, method: &str, receiver: &Type, span: &Span, env: &TypeEnv) -> Result<Type, TypeError> {
+        let scheme = env[&format!("{}.{}", struct_name, method)].clone();
+        let Type::Function { mut params, return_type } = self.instantiate(&scheme) else { unreachable!() };
+        let self_type = params.remove(0);
+        self.unify(&self_type, receiver, span.clone())?;
+        Ok(Type::Function { params, return_type })
+    }
+
+    /// The enum named by `object` in `Enum.Variant`, unless a variable shadows it.
+    fn enum_name(&self, object: &Spanned<Expression>, env: &TypeEnv) -> Option<String> {
+        match &object.node {
+            Expression::Identifier(name) if self.enums.contains_key(name) && !env.contains_key(name) => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    /// Unit variants are values of the enum; payload variants are constructor functions.
+    fn infer_variant(&mut self, enum_name: &str, variant: &str, span: &Span) -> Result<Type, TypeError> {
+        let decl = &self.enums[enum_name];
+        let Some((_, payload)) = decl.variants.iter().find(|(v, _)| v == variant) else {
+            return Err(TypeError::new(ErrorCode::UnknownMember, format!("Enum {} has no variant {}", enum_name, variant), span.clone()));
+        };
+        let payload = payload.clone();
+        let type_params = decl.type_params.clone();
+        let args: Vec<Type> = type_params.iter().map(|_| self.new_var()).collect();
+        let bindings = type_params.into_iter().zip(args.iter().cloned()).collect();
+        let enum_type = Type::Enum(enum_name.to_string(), args);
+        if payload.is_empty() {
+            return Ok(enum_type);
+        }
+        let params = payload.iter().map(|t| self.parse_type_with(t, &bindings, span)).collect::<Result<_, _>>()?;
+        Ok(Type::Function { params, return_type: Box::new(enum_type) })
+    }
+
+    /// Checks a pattern against the matched type and binds its variables in `env`.
+    fn check_pattern(&mut self, pattern: &Spanned<Pattern>, ty: &Type, env: &mut TypeEnv) -> Result<(), TypeError> {
+        let span = pattern.span.clone();
+        match &pattern.node {
+            Pattern::Wildcard => Ok(()),
+            Pattern::Binding(name) => {
+                self.types.push((span, ty.clone()));
+                env.insert(name.clone(), Scheme::mono(ty.clone()));
+                Ok(())
+            }
+            Pattern::Literal(lit) => {
+                let lit_type = self.infer_expression(&Spanned { node: lit.clone(), span: span.clone() }, env)?;
+                self.unify(&lit_type, ty, span)
+            }
+            Pattern::Variant { enum_name, variant, fields } => {
+                let Some(decl) = self.enums.get(enum_name) else {
+                    return Err(TypeError::new(ErrorCode::UnknownType, format!("Undefined enum {}", enum_name), span));
+                };
+                let Some((_, payload)) = decl.variants.iter().find(|(v, _)| v == variant) else {
+                    return Err(TypeError::new(ErrorCode::UnknownMember, format!("Enum {} has no variant {}", enum_name, variant), span));
+                };
+                if payload.len() != fields.len() {
+                    return Err(TypeError::new(ErrorCode::ArityMismatch, format!("Variant {}.{} has {} field(s) but the pattern has {}", enum_name, variant, payload.len(), fields.len()), span));
+                }
+                let payload = payload.clone();
+                let type_params = decl.type_params.clone();
+                let args: Vec<Type> = type_params.iter().map(|_| self.new_var()).collect();
+                self.unify(&Type::Enum(enum_name.clone(), args.clone()), ty, span.clone())?;
+                let bindings = type_params.into_iter().zip(args).collect();
+                for (f, f_type) in fields.iter().zip(&payload) {
+                    let f_type = self.parse_type_with(f_type, &bindings, &span)?;
+                    self.check_pattern(f, &f_type, env)?;
+                }
+                Ok(())
+            }
+            Pattern::Struct { name, fields } => {
+                let Some(decl) = self.structs.get(name) else {
+                    return Err(TypeError::new(ErrorCode::UnknownType, format!("Undefined struct {}", name), span));
+                };
+                let declared = decl.fields.clone();
+                let type_params = decl.type_params.clone();
+                let args: Vec<Type> = type_params.iter().map(|_| self.new_var()).collect();
+                self.unify(&Type::Struct(name.clone(), args.clone()), ty, span.clone())?;
+                let bindings = type_params.into_iter().zip(args).collect();
+                for (f_name, f) in fields {
+                    let Some((_, f_type)) = declared.iter().find(|(d_name, _)| d_name == f_name) else {
+                        return Err(TypeError::new(ErrorCode::UnknownMember, format!("Struct {} has no field {}", name, f_name), f.span.clone()));
+                    };
+                    let f_type = self.parse_type_with(f_type, &bindings, &span)?;
+                    self.check_pattern(f, &f_type, env)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Rejects arms that can never match and reports a value no arm covers.
+    fn check_exhaustive(&self, subject: &Spanned<Expression>, subject_type: &Type, arms: &[MatchArm], span: &Span) -> Result<(), TypeError> {
+        let types = [self.apply(subject_type)];
+        let mut rows: Vec<Vec<Pat>> = Vec::new();
+        for arm in arms {
+            let row = vec![self.lower_pattern(&arm.pattern.node)];
+            if !self.useful(&rows, &row, &types) {
+                return Err(TypeError::new(ErrorCode::UnreachableArm, "Unreachable match arm", arm.pattern.span.clone()));
+            }
+            rows.push(row);
+        }
+        if let Some(witness) = self.missing(&rows, &types) {
+            return Err(TypeError::new(ErrorCode::NonExhaustiveMatch, format!("Non-exhaustive match: {} not covered", self.format_pat(&witness[0], &types[0])), span.clone())
+                .with_label(subject.span.clone(), format!("this has type {}", types[0])));
+        }
+        Ok(())
+    }
+
+    /// The type of a block used as a value: its statements are checked in a scope of
+    /// their own and a `return` inside still leaves the enclosing function.
+    fn infer_block(&mut self, block: &[Spanned<Statement>], env: &TypeEnv) -> Result<Type, TypeError> {
+        let expected_ret = self.function_return().unwrap_or_else(|| self.new_var());
+        let (init, result) = split_value(block);
+        let mut block_env = env.clone();
+        self.check_block(init, &mut block_env, &expected_ret);
+        match (result, init.last().map(|s| &s.node)) {
+            (Some(result), _) => self.infer_expression(result, &block_env),
+            // A block that always leaves early fits wherever a value is expected.
+            (None, Some(Statement::Return(_) | Statement::Break | Statement::Continue)) => Ok(self.new_var()),
+            (None, _) => Ok(Type::Void),
         }
     }
 
-    fn parse_type(&self, t: &str) -> Type {
+    fn lower_pattern(&self, pattern: &Pattern) -> Pat {
+        match pattern {
+            Pattern::Wildcard | Pattern::Binding(_) => Pat::Wild,
+            Pattern::Literal(Expression::Bool(b)) => Pat::Ctor(b.to_string(), Vec::new()),
+            Pattern::Literal(Expression::StringLit(s)) => Pat::Ctor(format!("{:?}", s), Vec::new()),
+            Pattern::Literal(lit) => Pat::Ctor(format!("{:?}", lit), Vec::new()),
+            Pattern::Variant { variant, fields, .. } => {
+                Pat::Ctor(variant.clone(), fields.iter().map(|f| self.lower_pattern(&f.node)).collect())
+            }
+            Pattern::Struct { name, fields } => {
+                let declared = self.structs.get(name).map(|decl| decl.fields.as_slice()).unwrap_or_default();
+                let args = declared.iter().map(|(d_name, _)| {
+                    fields.iter().find(|(f_name, _)| f_name == d_name).map_or(Pat::Wild, |(_, f)| self.lower_pattern(&f.node))
+                }).collect();
+                Pat::Ctor(name.clone(), args)
+            }
+        }
+    }
+
+    /// Every constructor of a type with their field types, or `None` for open-ended types like `int`.
+    fn constructors(&self, ty: &Type) -> Option<Vec<(String, Vec<Type>)>> {
+        match ty {
+            Type::Bool => Some(vec![("true".to_string(), Vec::new()), ("false".to_string(), Vec::new())]),
+            Type::Enum(name, args) => {
+                let decl = self.enums.get(name)?;
+                let bindings = decl.type_params.iter().cloned().zip(args.iter().cloned()).collect();
+                Some(decl.variants.iter().map(|(v, payload)| {
+                    let fields = payload.iter().map(|t| self.parse_type_with(t, &bindings, &(0..0)).unwrap_or(Type::Void)).collect();
+                    (v.clone(), fields)
+                }).collect())
+            }
+            Type::Struct(name, args) => {
+                let decl = self.structs.get(name)?;
+                let bindings = decl.type_params.iter().cloned().zip(args.iter().cloned()).collect();
+                let fields = decl.fields.iter().map(|(_, t)| self.parse_type_with(t, &bindings, &(0..0)).unwrap_or(Type::Void)).collect();
+                Some(vec![(name.clone(), fields)])
+            }
+            _ => None,
+        }
+    }
+
+    /// Field types of constructor `ctor` of `ty`; literals have none.
+    fn ctor_fields(&self, ty: &Type, ctor: &str, arity: usize) -> Vec<Type> {
+        self.constructors(ty)
+            .and_then(|all| all.into_iter().find(|(c, _)| c == ctor).map(|(_, fields)| fields))
+            .unwrap_or_else(|| vec![Type::Void; arity])
+    }
+
+    /// Whether some value matched by `row` is matched by none of `rows` (Maranget's usefulness).
+    fn useful(&self, rows: &[Vec<Pat>], row: &[Pat], types: &[Type]) -> bool {
+        let Some((head, rest)) = row.split_first() else {
+            return rows.is_empty();
+        };
+        match head {
+            Pat::Ctor(c, args) => {
+                let fields = self.ctor_fields(&types[0], c, args.len());
+                let row = [args.as_slice(), rest].concat();
+                self.useful(&specialize(rows, c, args.len()), &row, &[fields.as_slice(), &types[1..]].concat())
+            }
+            Pat::Wild => match self.complete_constructors(rows, &types[0]) {
+                Some(all) => all.into_iter().any(|(c, fields)| {
+                    let row = [vec![Pat::Wild; fields.len()].as_slice(), rest].concat();
+                    self.useful(&specialize(rows, &c, fields.len()), &row, &[fields.as_slice(), &types[1..]].concat())
+                }),
+                None => self.useful(&default_rows(rows), rest, &types[1..]),
+            },
+        }
+    }
+
+    /// A value vector matched by none of `rows`, if there is one.
+    fn missing(&self, rows: &[Vec<Pat>], types: &[Type]) -> Option<Vec<Pat>> {
+        let Some((ty, rest_types)) = types.split_first() else {
+            return if rows.is_empty() { Some(Vec::new()) } else { None };
+        };
+        if let Some(all) = self.complete_constructors(rows, ty) {
+            return all.into_iter().find_map(|(c, fields)| {
+                let arity = fields.len();
+                let mut witness = self.missing(&specialize(rows, &c, arity), &[fields.as_slice(), rest_types].concat())?;
+                let rest = witness.split_off(arity);
+                Some([vec![Pat::Ctor(c, witness)], rest].concat())
+            });
+        }
+        let rest = self.missing(&default_rows(rows), rest_types)?;
+        let used: Vec<&String> = rows.iter().filter_map(|row| match &row[0] {
+            Pat::Ctor(c, _) => Some(c),
+            Pat::Wild => None,
+        }).collect();
+        // Name a constructor the rows never mention, when the type has a finite set.
+        let head = self.constructors(ty)
+            .and_then(|all| all.into_iter().find(|(c, _)| !used.contains(&c)))
+            .filter(|_| !used.is_empty())
+            .map_or(Pat::Wild, |(c, fields)| Pat::Ctor(c, vec![Pat::Wild; fields.len()]));
+        Some([vec![head], rest].concat())
+    }
+
+    /// The type's constructors, if the first column of `rows` mentions every one of them.
+    fn complete_constructors(&self, rows: &[Vec<Pat>], ty: &Type) -> Option<Vec<(String, Vec<Type>)>> {
+        let all = self.constructors(ty)?;
+        let complete = all.iter().all(|(c, _)| rows.iter().any(|row| matches!(&row[0], Pat::Ctor(rc, _) if rc == c)));
+        complete.then_some(all)
+    }
+
+    /// Notes the type of each operand the error does not already point at.
+    fn label_operands(&self, mut error: TypeError, operands: &[(&Spanned<Expression>, &Type)]) -> TypeError {
+        for (operand, ty) in operands {
+            if operand.span != error.span {
+                error = error.with_label(operand.span.clone(), format!("this is {}", self.apply(ty)));
+            }
+        }
+        error
+    }
+
+    fn format_pat(&self, pat: &Pat, ty: &Type) -> String {
+        let Pat::Ctor(c, args) = pat else {
+            return "_".to_string();
+        };
+        let fields = self.ctor_fields(ty, c, args.len());
+        let args: Vec<String> = args.iter().zip(&fields).map(|(a, t)| self.format_pat(a, t)).collect();
+        match ty {
+            Type::Enum(name, _) if args.is_empty() => format!("{}.{}", name, c),
+            Type::Enum(name, _) => format!("{}.{}({})", name, c, args.join(", ")),
+            Type::Struct(name, _) => {
+                let names: Vec<String> = self.structs.get(name).map(|decl| decl.fields.iter().map(|(f, _)| f.clone()).collect()).unwrap_or_default();
+                let fields: Vec<String> = names.iter().zip(&args).map(|(f, a)| format!("{}: {}", f, a)).collect();
+                format!("{} {{ {} }}", name, fields.join(", "))
+            }
+            _ => c.clone(),
+        }
+    }
+
+    /// The inferred type of every expression, pattern binding, function and `catch` variable,
+    /// keyed by span. Type variables are left in where inference did not pin them down.
+    pub fn types(&self) -> HashMap<Span, Type> {
+        self.types.iter().map(|(span, t)| (span.clone(), self.apply(t))).collect()
+    }
+
+    /// The type variables left in `types` that stand for a polymorphic function's type
+    /// parameters, as opposed to ones nothing constrains.
+    pub fn generic_vars(&self) -> HashSet<usize> {
+        self.generic_vars.clone()
+    }
+
+    /// Float if any operand is already known to be one, otherwise an integer type.
+    fn numeric_type(&self, operands: &[&Type]) -> Type {
+        if operands.iter().any(|t| self.resolve(t) == Type::Float) { Type::Float } else { self.integer_type(operands) }
+    }
+
+    /// The first integer type among the operands. Failing that, the type of an integer literal
+    /// among them, which the context may still pin down; otherwise Int.
+    fn integer_type(&self, operands: &[&Type]) -> Type {
+        let resolved: Vec<Type> = operands.iter().map(|t| self.resolve(t)).collect();
+        resolved.iter().find(|t| matches!(t, Type::Int | Type::Sized(_)))
+            .or_else(|| resolved.iter().find(|t| matches!(t, Type::Variable(v) if self.int_vars.contains(v))))
+            .cloned()
+            .unwrap_or(Type::Int)
+    }
+
+    /// Numeric conversions take any integer, and `int` and `float` also take floats;
+    /// the overflow helpers take two integers of one type.
+    fn check_numeric_call(&mut self, name: &str, args: &[Spanned<Expression>], arg_types: &[Type]) -> Result<(), TypeError> {
+        for (arg, t) in args.iter().zip(arg_types) {
+            let allowed = match self.resolve(t) {
+                Type::Int | Type::Sized(_) | Type::Variable(_) => true,
+                Type::Float => name == "int" || name == "float",
+                _ => false,
+            };
+            if !allowed {
+                return Err(TypeError::new(ErrorCode::TypeMismatch, format!("{} expects an integer, found {}", name, self.apply(t)), arg.span.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Follows substitutions for the outermost type constructor only.
+    fn resolve(&self, t: &Type) -> Type {
         match t {
-            "int" => Type::Int,
-            "str" => Type::Str,
-            "bool" => Type::Bool,
-            "void" => Type::Void,
-            _ => {
-                if self.structs.contains_key(t) {
-                    Type::Struct(t.to_string())
-                } else if self.enums.contains_key(t) {
-                    Type::Enum(t.to_string())
+            Type::Variable(v) => self.find(*v),
+            _ => t.clone(),
+        }
+    }
+
+    /// Type of `operand?`: the success payload. The failure goes to the innermost `try`, or else
+    /// becomes the enclosing function's result, which must be the same kind of enum.
+    fn infer_try(&mut self, operand: &Spanned<Expression>, span: &Span, env: &TypeEnv) -> Result<Type, TypeError> {
+        let t = self.infer_expression(operand, env)?;
+        // `error` is None for an Option.
+        let (value, error) = match self.resolve(&t) {
+            Type::Enum(name, args) if name == "Option" && args.len() == 1 => (args[0].clone(), None),
+            Type::Enum(name, args) if name == "Result" && args.len() == 2 => (args[0].clone(), Some(args[1].clone())),
+            Type::Variable(_) => {
+                let (value, error) = (self.new_var(), self.new_var());
+                self.unify(&t, &Type::Enum("Result".to_string(), vec![value.clone(), error.clone()]), operand.span.clone())?;
+                (value, Some(error))
+            }
+            other => {
+                return Err(TypeError::new(ErrorCode::TypeMismatch, format!("The ? operator needs a Result or Option, found {}", other), operand.span.clone()));
+            }
+        };
+        match (self.try_targets.last().cloned(), error) {
+            (Some(TryTarget::Catch(caught)), Some(error)) => self.unify(&error, &caught, operand.span.clone())?,
+            (Some(TryTarget::Catch(_)), None) => {
+                return Err(TypeError::new(ErrorCode::TypeMismatch, "? on an Option cannot be caught; convert it to a Result", span.clone()));
+            }
+            (Some(TryTarget::Return(ret)), error) => {
+                let (kind, propagated) = match error {
+                    Some(error) => ("Result", Type::Enum("Result".to_string(), vec![self.new_var(), error])),
+                    None => ("Option", Type::Enum("Option".to_string(), vec![self.new_var()])),
+                };
+                match self.resolve(&ret) {
+                    Type::Variable(_) => {}
+                    Type::Enum(name, _) if name == kind => {}
+                    other => {
+                        return Err(TypeError::new(ErrorCode::TypeMismatch, format!("The ? operator returns {} from this function, but it returns {}", kind, other), span.clone()));
+                    }
+                }
+                self.unify(&propagated, &ret, span.clone())?;
+            }
+            (None, _) => return Err(TypeError::new(ErrorCode::Unsupported, "The ? operator can only be used inside a function or try block", span.clone())),
+        }
+        Ok(value)
+    }
+
+    /// Element type of `object[index]`: lists take Int indices, maps take Str keys.
+    fn infer_index(&mut self, object: &Spanned<Expression>, index: &Spanned<Expression>, env: &TypeEnv) -> Result<Type, TypeError> {
+        let ot = self.infer_expression(object, env)?;
+        let it = self.infer_expression(index, env)?;
+        match self.resolve(&ot) {
+            Type::List(elem) => {
+                self.unify(&it, &Type::Int, index.span.clone())?;
+                Ok(*elem)
+            }
+            Type::Map(value) => {
+                self.unify(&it, &Type::Str, index.span.clone())?;
+                Ok(*value)
+            }
+            Type::Variable(_) => Ok(self.new_var()),
+            other => Err(TypeError::new(ErrorCode::TypeMismatch, format!("Type {} cannot be indexed", other), object.span.clone())),
+        }
+    }
+
+    fn list_member(&mut self, elem: Type, member: &str, span: Span) -> Result<Type, TypeError> {
+        let method = |params: Vec<Type>, ret: Type| Type::Function { params, return_type: Box::new(ret) };
+        match member {
+            "len" => Ok(method(vec![], Type::Int)),
+            "push" => Ok(method(vec![elem], Type::Void)),
+            "pop" => Ok(method(vec![], elem)),
+            "map" => {
+                let out = self.new_var();
+                Ok(method(vec![method(vec![elem], out.clone())], Type::List(Box::new(out))))
+            }
+            "filter" => Ok(method(vec![method(vec![elem.clone()], Type::Bool)], Type::List(Box::new(elem)))),
+            "each" => {
+                let ignored = self.new_var();
+                Ok(method(vec![method(vec![elem], ignored)], Type::Void))
+            }
+            _ => Err(TypeError::new(ErrorCode::UnknownMember, format!("List has no member {}", member), span)),
+        }
+    }
+
+    fn map_member(&mut self, value: Type, member: &str, span: Span) -> Result<Type, TypeError> {
+        let method = |params: Vec<Type>, ret: Type| Type::Function { params, return_type: Box::new(ret) };
+        match member {
+            "len" => Ok(method(vec![], Type::Int)),
+            "keys" => Ok(method(vec![], Type::List(Box::new(Type::Str)))),
+            "values" => Ok(method(vec![], Type::List(Box::new(value)))),
+            "contains" => Ok(method(vec![Type::Str], Type::Bool)),
+            "remove" => Ok(method(vec![Type::Str], value)),
+            _ => Err(TypeError::new(ErrorCode::UnknownMember, format!("Map has no member {}", member), span)),
+        }
+    }
+
+    /// Checks a function or lambda body against the environment it closes over.
+    /// A body that never returns a value gives the function a `Void` result, unless it
+    /// `declared` another; one that does must return on every path.
+    fn check_function_body(&mut self, params: &[(String, Option<TypeExpr>)], param_types: Vec<Type>, body: &[Spanned<Statement>], env: &TypeEnv, ret_type: &Type, declared: bool) -> Result<(), TypeError> {
+        let mut local_env = env.clone();
+        for ((p_name, _), p_type) in params.iter().zip(param_types) {
+            local_env.insert(p_name.clone(), Scheme::mono(p_type));
+        }
+        // A loop around a lambda is not one its body can break out of.
+        let loops = std::mem::take(&mut self.loops);
+        self.try_targets.push(TryTarget::Return(ret_type.clone()));
+        self.check_block(body, &mut local_env, ret_type);
+        self.try_targets.pop();
+        self.loops = loops;
+        let span = body.last().map_or(0..0, |stmt| stmt.span.clone());
+        if !returns_value(body) {
+            let ret_type = self.apply(ret_type);
+            if declared && ret_type != Type::Void {
+                let message = format!("Function is declared to return {} but never returns a value", ret_type);
+                return Err(TypeError::new(ErrorCode::MissingReturn, message, span));
+            }
+            self.unify(&ret_type, &Type::Void, span)?;
+        } else if !always_returns(body) {
+            return Err(TypeError::new(ErrorCode::MissingReturn, "Function can reach its end without returning a value", span));
+        }
+        Ok(())
+    }
+
+    /// Unannotated parameters get a fresh type variable instead of a guessed concrete type.
+    fn param_types(&mut self, params: &[(String, Option<TypeExpr>)], span: &Span) -> Result<Vec<Type>, TypeError> {
+        params.iter().map(|(_, p_type)| self.annotated_or_fresh(p_type, span)).collect()
+    }
+
+    fn annotated_or_fresh(&mut self, annotation: &Option<TypeExpr>, span: &Span) -> Result<Type, TypeError> {
+        match annotation {
+            Some(t) => self.parse_type(t, span),
+            None => Ok(self.new_var()),
+        }
+    }
+
+    fn parse_type(&self, t: &TypeExpr, span: &Span) -> Result<Type, TypeError> {
+        self.parse_type_with(t, &HashMap::new(), span)
+    }
+
+    /// Parses an annotation where the type parameters in `bindings` stand for the given types.
+    /// The type of a native function and the type its `number` stands for. Called with
+    /// `arg_count` arguments, optional parameters are dropped and a variadic one repeated to match.
+    fn native_type(&mut self, native: &NativeFn, arg_count: Option<usize>, span: &Span) -> Result<(Type, Type), TypeError> {
+        let (params, ret) = native.signature();
+        let (min, max) = match native.arity {
+            Arity::Fixed => (params.len(), Some(params.len())),
+            Arity::AtLeast(min) => (min, Some(params.len())),
+            Arity::Variadic => (params.len() - 1, None),
+        };
+        let count = arg_count.unwrap_or(params.len());
+        if count < min || max.is_some_and(|max| count > max) {
+            let expected = match max {
+                Some(max) if max == min => min.to_string(),
+                Some(max) => format!("{} to {}", min, max),
+                None => format!("at least {}", min),
+            };
+            return Err(TypeError::new(ErrorCode::ArityMismatch, format!("{} expects {} argument(s) but got {}", native.qualified_name(), expected, count), span.clone()));
+        }
+        let number = self.new_var();
+        let native_param = |this: &mut Self, t: &TypeExpr| {
+            let bindings = HashMap::from([("any".to_string(), this.new_var()), ("number".to_string(), number.clone())]);
+            this.parse_type_with(t, &bindings, span)
+        };
+        let param_types = (0..count).map(|i| native_param(self, &params[i.min(params.len() - 1)])).collect::<Result<_, _>>()?;
+        let return_type = Box::new(native_param(self, &ret)?);
+        Ok((Type::Function { params: param_types, return_type }, number))
+    }
+
+    fn parse_type_with(&self, t: &TypeExpr, bindings: &HashMap<String, Type>, span: &Span) -> Result<Type, TypeError> {
+        Ok(match t {
+            TypeExpr::Named(name) => match name.as_str() {
+                "int" | "i64" => Type::Int,
+                "float" => Type::Float,
+                "str" => Type::Str,
+                "bool" => Type::Bool,
+                "void" => Type::Void,
+                _ if bindings.contains_key(name) => bindings[name].clone(),
+                _ => match IntKind::from_name(name) {
+                    Some(kind) => Type::Sized(kind),
+                    None => self.parse_type_with(&TypeExpr::Generic(name.clone(), Vec::new()), bindings, span)?,
+                },
+            },
+            TypeExpr::Generic(name, args) => {
+                let arity = if let Some(decl) = self.structs.get(name) {
+                    decl.type_params.len()
+                } else if let Some(decl) = self.enums.get(name) {
+                    decl.type_params.len()
+                } else {
+                    return Err(TypeError::new(ErrorCode::UnknownType, format!("Unknown type {}", name), span.clone()));
+                };
+                if args.len() != arity {
+                    return Err(TypeError::new(ErrorCode::UnknownType, format!("Type {} expects {} type argument(s) but got {}", name, arity, args.len()), span.clone()));
+                }
+                let args = args.iter().map(|a| self.parse_type_with(a, bindings, span)).collect::<Result<_, _>>()?;
+                if self.structs.contains_key(name) {
+                    Type::Struct(name.clone(), args)
                 } else {
-                    Type::Int // Default
+                    Type::Enum(name.clone(), args)
                 }
             }
+            TypeExpr::List(elem) => Type::List(Box::new(self.parse_type_with(elem, bindings, span)?)),
+            TypeExpr::Map(value) => Type::Map(Box::new(self.parse_type_with(value, bindings, span)?)),
+            TypeExpr::Function(params, ret) => Type::Function {
+                params: params.iter().map(|p| self.parse_type_with(p, bindings, span)).collect::<Result<_, _>>()?,
+                return_type: Box::new(self.parse_type_with(ret, bindings, span)?),
+            },
+        })
+    }
+}
+
+/// Collects the type variables of an already applied type, in first-occurrence order.
+pub(crate) fn free_type_vars(t: &Type, out: &mut Vec<usize>) {
+    match t {
+        Type::Variable(v) if !out.contains(v) => out.push(*v),
+        Type::List(elem) | Type::Map(elem) => free_type_vars(elem, out),
+        Type::Struct(_, args) | Type::Enum(_, args) => {
+            for a in args {
+                free_type_vars(a, out);
+            }
+        }
+        Type::Function { params, return_type } => {
+            for p in params {
+                free_type_vars(p, out);
+            }
+            free_type_vars(return_type, out);
+        }
+        _ => {}
+    }
+}
+
+pub(crate) fn substitute(t: &Type, mapping: &HashMap<usize, Type>) -> Type {
+    match t {
+        Type::Variable(v) => mapping.get(v).cloned().unwrap_or_else(|| t.clone()),
+        Type::List(elem) => Type::List(Box::new(substitute(elem, mapping))),
+        Type::Map(value) => Type::Map(Box::new(substitute(value, mapping))),
+        Type::Struct(name, args) => Type::Struct(name.clone(), args.iter().map(|a| substitute(a, mapping)).collect()),
+        Type::Enum(name, args) => Type::Enum(name.clone(), args.iter().map(|a| substitute(a, mapping)).collect()),
+        Type::Function { params, return_type } => Type::Function {
+            params: params.iter().map(|p| substitute(p, mapping)).collect(),
+            return_type: Box::new(substitute(return_type, mapping)),
+        },
+        _ => t.clone(),
+    }
+}
+
+/// Rows whose first pattern matches constructor `ctor`, with its fields spliced in.
+fn specialize(rows: &[Vec<Pat>], ctor: &str, arity: usize) -> Vec<Vec<Pat>> {
+    rows.iter().filter_map(|row| match &row[0] {
+        Pat::Ctor(c, args) if c == ctor => Some([args.as_slice(), &row[1..]].concat()),
+        Pat::Ctor(..) => None,
+        Pat::Wild => Some([vec![Pat::Wild; arity].as_slice(), &row[1..]].concat()),
+    }).collect()
+}
+
+/// The module and member of `module.member` on a native module no variable shadows, or
+/// `("sys", "log")` and the like for a native global.
+fn native_module<'a>(expr: &'a Spanned<Expression>, env: &TypeEnv) -> Option<(&'a str, &'a str)> {
+    match &expr.node {
+        Expression::Identifier(name) if !env.contains_key(name) => natives::global(name).map(|native| (native.module, native.name)),
+        Expression::MemberAccess { object, member } => match &object.node {
+            Expression::Identifier(module) if natives::is_module(module) && !env.contains_key(module) => Some((module, member)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The native function `expr` names, if any.
+fn native_function(expr: &Spanned<Expression>, env: &TypeEnv) -> Option<&'static NativeFn> {
+    native_module(expr, env).and_then(|(module, member)| natives::lookup(module, member))
+}
+
+/// The name of a built-in numeric conversion or overflow helper called by `func`.
+/// Built-in schemes quantify over variable 0, which `new_var` never hands out,
+/// so a user function of the same name is not mistaken for one.
+fn numeric_builtin<'a>(func: &'a Spanned<Expression>, env: &TypeEnv) -> Option<&'a str> {
+    match &func.node {
+        Expression::Identifier(name) if env.get(name).is_some_and(|s| s.vars == [0]) => Some(name),
+        _ => None,
+    }
+}
+
+/// Rows whose first pattern is a wildcard, without it.
+fn default_rows(rows: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    rows.iter().filter(|row| matches!(row[0], Pat::Wild)).map(|row| row[1..].to_vec()).collect()
+}
+
+/// A global in `lets` not yet `assigned` that using `name` reads, directly or through the
+/// functions and lambdas in `uses` it may call.
+fn unassigned_global(name: &str, uses: &HashMap<&str, Vec<String>>, lets: &HashMap<&str, Span>, assigned: &HashSet<&str>, seen: &mut HashSet<String>) -> Option<String> {
+    if lets.contains_key(name) && !assigned.contains(name) {
+        return Some(name.to_string());
+    }
+    if !seen.insert(name.to_string()) {
+        return None;
+    }
+    uses.get(name)?.iter().find_map(|used| unassigned_global(used, uses, lets, assigned, seen))
+}
+
+/// Splits top-level functions into mutually recursive groups (Tarjan's SCCs),
+/// returned so that every group comes after the groups it calls.
+/// Methods are named `Struct.method` and may be called through any member of that name.
+fn function_groups(functions: &[(String, &Spanned<Statement>)]) -> Vec<Vec<usize>> {
+    let edges: Vec<Vec<usize>> = functions.iter().map(|(_, stmt)| {
+        let Statement::FunctionDef { params, body, .. } = &stmt.node else { unreachable!() };
+        let bound: HashSet<String> = params.iter().map(|(p, _)| p.clone()).collect();
+        let mut free = Vec::new();
+        free_variables(body, &bound, &mut free);
+        let members = member_names(body);
+        (0..functions.len()).filter(|&j| match functions[j].0.split_once('.') {
+            Some((_, method)) => members.contains(method),
+            None => free.contains(&functions[j].0),
+        }).collect()
+    }).collect();
+
+    struct Tarjan<'a> {
+        edges: &'a [Vec<usize>],
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        on_stack: Vec<bool>,
+        stack: Vec<usize>,
+        next: usize,
+        groups: Vec<Vec<usize>>,
+    }
+
+    impl Tarjan<'_> {
+        fn visit(&mut self, v: usize) {
+            self.index[v] = Some(self.next);
+            self.low[v] = self.next;
+            self.next += 1;
+            self.stack.push(v);
+            self.on_stack[v] = true;
+            for &w in &self.edges[v] {
+                match self.index[w] {
+                    None => {
+                        self.visit(w);
+                        self.low[v] = self.low[v].min(self.low[w]);
+                    }
+                    Some(w_index) if self.on_stack[w] => self.low[v] = self.low[v].min(w_index),
+                    _ => {}
+                }
+            }
+            if Some(self.low[v]) == self.index[v] {
+                let mut group = Vec::new();
+                while let Some(w) = self.stack.pop() {
+                    self.on_stack[w] = false;
+                    group.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                group.sort_unstable();
+                self.groups.push(group);
+            }
+        }
+    }
+
+    let n = functions.len();
+    let mut tarjan = Tarjan {
+        edges: &edges,
+        index: vec![None; n],
+        low: vec![0; n],
+        on_stack: vec![false; n],
+        stack: Vec::new(),
+        next: 0,
+        groups: Vec::new(),
+    };
+    for v in 0..n {
+        if tarjan.index[v].is_none() {
+            tarjan.visit(v);
         }
     }
+    tarjan.groups
 }
@@ -0,0 +1,41 @@
+//! What type-checks but cannot be lowered to compiled code.
+
+use emo_compiler::diagnostics::ErrorCode;
+use emo_compiler::lowering;
+use emo_compiler::parser::Parser;
+use emo_compiler::type_checker::TypeChecker;
+
+fn lowering_errors(source: &str) -> Vec<ErrorCode> {
+    let Ok(ast) = Parser::new(source).parse() else { panic!("does not parse: {}", source) };
+    let mut tc = TypeChecker::new();
+    assert!(tc.check_program(&ast).is_ok(), "does not type-check: {}", source);
+    match lowering::lower(&ast, tc.types(), tc.generic_vars()) {
+        Ok(_) => Vec::new(),
+        Err(errors) => errors.into_iter().map(|e| e.code).collect(),
+    }
+}
+
+#[test]
+fn interpreter_only_native_is_rejected() {
+    assert_eq!(lowering_errors("log(json.stringify(1))\n"), vec![ErrorCode::Unsupported]);
+    assert_eq!(lowering_errors("log(str.len(\"abc\"))\n"), vec![]);
+}
+
+#[test]
+fn generic_functions_are_instantiated() {
+    assert_eq!(lowering_errors("fn id(x) {\n    return x\n}\nlog(id(1.5))\nlog(id(\"s\"))\n"), vec![]);
+    assert_eq!(lowering_errors("let k = fn(x) { return x }\nlog(k(1))\nlog(k(true))\n"), vec![]);
+}
+
+#[test]
+fn type_left_generic_is_rejected() {
+    let source = "fn main() {\n    let k = fn(x) { return x }\n    let h = fn(y) { return k(y) }\n    log(h(1))\n    log(h(\"s\"))\n}\n";
+    assert_eq!(lowering_errors(source), vec![ErrorCode::AmbiguousType]);
+}
+
+#[test]
+fn log_of_a_value_compiled_code_cannot_print_is_rejected() {
+    let source = "struct P { x: int }\nenum O { S(int), N }\nlog(P { x: 1 })\nlog(O.S(1))\nlog(fn() { return 1 })\n";
+    assert_eq!(lowering_errors(source), vec![ErrorCode::Unsupported; 3]);
+    assert_eq!(lowering_errors("enum C { Red, Blue }\nlog(C.Red, 1, \"s\", [1.5], true)\n"), vec![]);
+}
@@ -0,0 +1,75 @@
+//! Tests for the module loader. Each test loads a program from a fixture directory
+//! under `modules/` and checks the linked names or the errors found while linking.
+
+use emo_compiler::ast::Statement;
+use emo_compiler::diagnostics::ErrorCode;
+use emo_compiler::modules::{LinkedProgram, ModuleLoader};
+use std::path::PathBuf;
+
+fn fixture(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("modules").join(path)
+}
+
+fn load(file: &str, search_paths: &[&str]) -> LinkedProgram {
+    let loader = ModuleLoader::new(search_paths.iter().map(|p| fixture(p)).collect());
+    loader.load(fixture(file).to_str().unwrap()).unwrap()
+}
+
+fn error_codes(program: &LinkedProgram) -> Vec<ErrorCode> {
+    program.errors.iter().map(|e| e.code).collect()
+}
+
+fn function_names(program: &LinkedProgram) -> Vec<String> {
+    program.statements.iter().filter_map(|stmt| match &stmt.node {
+        Statement::FunctionDef { name, .. } => Some(name.clone()),
+        _ => None,
+    }).collect()
+}
+
+#[test]
+fn import_cycle_is_reported() {
+    let program = load("cycle/main.emo", &[]);
+    assert_eq!(error_codes(&program), vec![ErrorCode::ImportCycle]);
+    let message = &program.errors[0].message;
+    assert!(message.contains("a.emo -> ") && message.ends_with("a.emo"), "{}", message);
+}
+
+#[test]
+fn modules_imported_under_the_same_name_get_distinct_prefixes() {
+    let program = load("alias/main.emo", &[]);
+    assert_eq!(error_codes(&program), vec![]);
+    let names = function_names(&program);
+    // `two/lib` is linked first, as `first` imports it before `main` imports `one/lib`.
+    assert!(names.contains(&"lib__name".to_string()) && names.contains(&"lib2__name".to_string()), "{:?}", names);
+    assert!(names.contains(&"first__name".to_string()), "{:?}", names);
+}
+
+#[test]
+fn importer_directory_comes_before_the_search_path() {
+    let program = load("search/local/main.emo", &["search/a"]);
+    assert_eq!(error_codes(&program), vec![]);
+    assert!(function_names(&program).contains(&"shared__from_local".to_string()));
+}
+
+#[test]
+fn search_paths_come_in_order_and_before_emo_path() {
+    // The only test that sets `EMO_PATH`, which `ModuleLoader::new` reads.
+    std::env::set_var("EMO_PATH", fixture("search/b"));
+    let from = |search_paths: &[&str]| {
+        let program = load("search/main/main.emo", search_paths);
+        assert_eq!(error_codes(&program), vec![]);
+        function_names(&program).into_iter().find(|n| n.starts_with("shared__from_")).unwrap()
+    };
+    assert_eq!(from(&[]), "shared__from_b");
+    assert_eq!(from(&["search/a"]), "shared__from_a");
+    assert_eq!(from(&["search/b", "search/a"]), "shared__from_b");
+    std::env::remove_var("EMO_PATH");
+    assert_eq!(error_codes(&load("search/main/main.emo", &[])), vec![ErrorCode::ModuleNotFound]);
+}
+
+#[test]
+fn private_item_is_not_visible_to_importers() {
+    let program = load("private/main.emo", &[]);
+    assert_eq!(error_codes(&program), vec![ErrorCode::PrivateItem]);
+    assert!(program.errors[0].message.contains("hidden is private to module lib"));
+}
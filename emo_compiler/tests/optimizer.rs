@@ -0,0 +1,69 @@
+//! Golden-output tests for the optimisation passes. Each test lowers `golden/<pass>.emo`,
+//! runs that one pass and compares the IR with `golden/<pass>.ir`. Run with
+//! `UPDATE_GOLDEN=1` to rewrite the expected files after an intended change.
+
+use emo_compiler::ir::Program;
+use emo_compiler::lowering;
+use emo_compiler::optimizer::{self, OptLevel};
+use emo_compiler::parser::Parser;
+use emo_compiler::type_checker::TypeChecker;
+use std::fs;
+use std::path::PathBuf;
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn lower(name: &str) -> Program {
+    let source = fs::read_to_string(golden_dir().join(format!("{}.emo", name))).expect("missing golden input");
+    let Ok(ast) = Parser::new(&source).parse() else { panic!("{}.emo does not parse", name) };
+    let mut tc = TypeChecker::new();
+    assert!(tc.check_program(&ast).is_ok(), "{}.emo does not type-check", name);
+    lowering::lower(&ast, tc.types(), tc.generic_vars()).expect("lowering failed")
+}
+
+fn check_pass(name: &str, pass: fn(&mut Program) -> bool) {
+    let mut program = lower(name);
+    assert!(pass(&mut program), "{} changed nothing", name);
+    let actual = program.to_string();
+    let expected_file = golden_dir().join(format!("{}.ir", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&expected_file, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&expected_file).unwrap_or_default();
+    assert_eq!(actual, expected, "IR after {} differs from {}", name, expected_file.display());
+}
+
+#[test]
+fn fold_constants() {
+    check_pass("fold_constants", optimizer::fold_constants);
+}
+
+#[test]
+fn propagate_consts() {
+    check_pass("propagate_consts", optimizer::propagate_consts);
+}
+
+#[test]
+fn remove_unreachable() {
+    check_pass("remove_unreachable", optimizer::remove_unreachable);
+}
+
+#[test]
+fn inline_functions() {
+    check_pass("inline_functions", optimizer::inline_functions);
+}
+
+#[test]
+fn remove_dead_functions() {
+    check_pass("remove_dead_functions", optimizer::remove_dead_functions);
+}
+
+#[test]
+fn o0_changes_nothing() {
+    let mut program = lower("fold_constants");
+    let before = program.to_string();
+    optimizer::optimize(&mut program, OptLevel::O0);
+    assert_eq!(program.to_string(), before);
+}
@@ -0,0 +1,22 @@
+//! Error recovery in the parser: how many errors a broken file reports, and what of it
+//! still parses.
+
+use emo_compiler::ast::Statement;
+use emo_compiler::parser::Parser;
+
+#[test]
+fn rejected_character_reports_one_error_and_keeps_the_next_line() {
+    let source = "fn f(x: int) -> int {\n    let y = @\n    return x\n}\n";
+    let (ast, errors) = Parser::new(source).parse_program();
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert_eq!(errors[0].message, "Unexpected character '@'");
+    let Statement::FunctionDef { body, .. } = &ast[0].node else { panic!("expected a function, found {:?}", ast[0].node) };
+    assert!(matches!(body.last().map(|s| &s.node), Some(Statement::Return(_))), "{:?}", body);
+}
+
+#[test]
+fn stray_closing_brace_is_skipped() {
+    let (ast, errors) = Parser::new("}\nlog(1)\n").parse_program();
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(matches!(ast.last().map(|s| &s.node), Some(Statement::Expression(_))), "{:?}", ast);
+}
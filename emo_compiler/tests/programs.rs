@@ -0,0 +1,113 @@
+//! End-to-end tests: every `programs/<name>.emo` is built to a native binary at each
+//! optimisation level, and what it prints must match `programs/<name>.out` at all of
+//! them. They need `gcc`. Run with `UPDATE_GOLDEN=1` to rewrite the expected output.
+
+use emo_compiler::optimizer::OptLevel;
+use emo_compiler::interpreter::MAX_CALL_DEPTH;
+use emo_compiler::{compile_with_options, run_with_options, BuildOptions, RunOptions};
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn programs_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("programs")
+}
+
+/// Builds `name` in a directory of its own, so that tests can build in parallel, and
+/// runs the binary there.
+fn build_and_run(name: &str, level: OptLevel) -> Output {
+    let dir = std::env::temp_dir().join(format!("emo-test-{}-{:?}", name, level));
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join(format!("{}.emo", name));
+    fs::copy(programs_dir().join(format!("{}.emo", name)), &source).unwrap();
+    let binary = dir.join(name);
+    let options = BuildOptions { opt_level: level, ..BuildOptions::default() };
+    let built = compile_with_options(source.to_str().unwrap(), Some(binary.to_str().unwrap().to_string()), options);
+    assert!(built.is_ok(), "{}.emo does not build at {:?}", name, level);
+    let run = Command::new(&binary).current_dir(&dir).output().expect("could not run the built program");
+    let _ = fs::remove_dir_all(&dir);
+    run
+}
+
+/// What the binary built from `name` prints at `level`.
+fn stdout_of(name: &str, level: OptLevel) -> String {
+    String::from_utf8_lossy(&build_and_run(name, level).stdout).into_owned()
+}
+
+fn check_program(name: &str) {
+    let actual = stdout_of(name, OptLevel::O0);
+    for level in [OptLevel::O1, OptLevel::O2] {
+        assert_eq!(stdout_of(name, level), actual, "{}.emo prints something else at {:?} than at O0", name, level);
+    }
+    let expected_file = programs_dir().join(format!("{}.out", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&expected_file, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&expected_file).unwrap_or_default();
+    assert_eq!(actual, expected, "output of {}.emo differs from {}", name, expected_file.display());
+}
+
+#[test]
+fn float_specials() {
+    check_program("float_specials");
+}
+
+#[test]
+fn native_results() {
+    check_program("native_results");
+}
+
+#[test]
+fn collection_slots() {
+    check_program("collection_slots");
+}
+
+#[test]
+fn log_collections() {
+    check_program("log_collections");
+}
+
+#[test]
+fn generic_functions() {
+    check_program("generic_functions");
+}
+
+#[test]
+fn closure_captures() {
+    check_program("closure_captures");
+}
+
+#[test]
+fn sized_literals() {
+    check_program("sized_literals");
+}
+
+#[test]
+fn pop_from_empty_list_is_a_runtime_error() {
+    let run = build_and_run("empty_pop", OptLevel::O0);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "1 \n");
+    assert_eq!(run.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("pop from empty list"));
+    let source = programs_dir().join("empty_pop.emo");
+    assert!(emo_compiler::run(source.to_str().unwrap()).is_err(), "the interpreter pops an empty list");
+}
+
+#[test]
+fn deep_recursion() {
+    check_program("deep_recursion");
+}
+
+#[test]
+fn interpreter_recurses_to_the_maximum_call_depth() {
+    let source = programs_dir().join("deep_recursion.emo");
+    let run = |max_call_depth| run_with_options(source.to_str().unwrap(), RunOptions { max_call_depth, ..RunOptions::default() });
+    assert!(run(MAX_CALL_DEPTH).is_ok());
+    assert!(run(MAX_CALL_DEPTH - 1).is_err(), "the program recurses exactly MAX_CALL_DEPTH calls deep");
+    assert!(run(MAX_CALL_DEPTH + 1).is_err(), "a call depth over the maximum is accepted");
+}
+
+#[test]
+fn generic_methods() {
+    check_program("generic_methods");
+}
@@ -0,0 +1,97 @@
+//! Whole-program checks the type checker makes beyond unification.
+
+use emo_compiler::diagnostics::ErrorCode;
+use emo_compiler::parser::Parser;
+use emo_compiler::type_checker::TypeChecker;
+
+fn check(source: &str) -> Vec<ErrorCode> {
+    let Ok(ast) = Parser::new(source).parse() else { panic!("does not parse: {}", source) };
+    match TypeChecker::new().check_program(&ast) {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors.into_iter().map(|e| e.code).collect(),
+    }
+}
+
+#[test]
+fn return_inside_if_alone_is_missing_a_return() {
+    assert_eq!(check("fn f(c: bool) -> int {\n    if c { return 1 }\n}\n"), vec![ErrorCode::MissingReturn]);
+}
+
+#[test]
+fn declared_result_with_no_return_at_all_is_missing_a_return() {
+    assert_eq!(check("fn g() -> int {\n    let a = 1\n}\n"), vec![ErrorCode::MissingReturn]);
+    assert_eq!(check("let h = fn() -> str { log(1) }\n"), vec![ErrorCode::MissingReturn]);
+    assert_eq!(check("fn v() -> void {\n    let a = 1\n}\nfn w() {\n    let a = 1\n}\n"), vec![]);
+}
+
+#[test]
+fn if_and_else_that_both_return_are_enough() {
+    assert_eq!(check("fn f(c: bool) -> int {\n    if c { return 1 } else { return 2 }\n}\n"), vec![]);
+}
+
+#[test]
+fn every_match_arm_must_return() {
+    let all = "enum Sign { Neg, Pos }\nfn f(s: Sign) -> int {\n    match s {\n        Sign.Neg => { return -1 }\n        Sign.Pos => { return 1 }\n    }\n}\n";
+    assert_eq!(check(all), vec![]);
+    let some = "enum Sign { Neg, Pos }\nfn f(s: Sign) -> int {\n    match s {\n        Sign.Neg => { return -1 }\n        Sign.Pos => log(1)\n    }\n}\n";
+    assert_eq!(check(some), vec![ErrorCode::MissingReturn]);
+}
+
+#[test]
+fn loop_only_returns_if_it_never_breaks() {
+    assert_eq!(check("fn f() -> int {\n    loop { return 1 }\n}\n"), vec![]);
+    assert_eq!(check("fn f(c: bool) -> int {\n    loop {\n        if c { break }\n        return 1\n    }\n}\n"), vec![ErrorCode::MissingReturn]);
+}
+
+#[test]
+fn integer_literals_take_the_sized_type_they_are_used_at() {
+    assert_eq!(check("let a: i8 = 100\nlet b: i8 = -128\nlet xs: [u8] = [1, 2]\n"), vec![]);
+    assert_eq!(check("fn f(x: i32) -> i32 {\n    return x * 2\n}\nlog(f(3) + 1)\nif u8(5) < 10 { log(1) }\n"), vec![]);
+    assert_eq!(check("let a: u16 = 1 + 2 * 3\nlet b: int = a\n"), vec![ErrorCode::TypeMismatch]);
+    assert_eq!(check("let s: str = 5\nlet x: float = 1\n"), vec![ErrorCode::TypeMismatch, ErrorCode::TypeMismatch]);
+}
+
+#[test]
+fn integer_literal_must_fit_its_type() {
+    assert_eq!(check("let a: i8 = 128\nlet b: u8 = -1\nlet c: u8 = 255\n"), vec![ErrorCode::IntegerOverflow, ErrorCode::IntegerOverflow]);
+    assert_eq!(check("fn f(x: u8) -> u8 {\n    return x + 300\n}\n"), vec![ErrorCode::IntegerOverflow]);
+}
+
+#[test]
+fn top_level_code_cannot_read_a_global_before_its_let() {
+    assert_eq!(check("fn show() {\n    log(str.len(greeting))\n}\nshow()\nlet greeting = \"hello\"\n"), vec![ErrorCode::UndefinedName]);
+    assert_eq!(check("fn show() {\n    log(str.len(greeting))\n}\nfn twice() {\n    show()\n    show()\n}\nlet f = fn() { twice() }\nf()\nlet greeting = \"hello\"\n"), vec![ErrorCode::UndefinedName]);
+    assert_eq!(check("log(n)\nlet n = 1\n"), vec![ErrorCode::UndefinedName]);
+    assert_eq!(check("fn show() {\n    log(str.len(greeting))\n}\nlet greeting = \"hello\"\nshow()\nlet later = fn() { log(other) }\nlet other = 2\nlater()\n"), vec![]);
+}
+
+#[test]
+fn methods_of_a_generic_struct_are_generic() {
+    let wrap = "struct Wrap<T> { v: T }\nimpl Wrap {\n    fn get(self) {\n        return self.v\n    }\n    fn new(v: T) -> Wrap<T> {\n        return Wrap { v: v }\n    }\n}\n";
+    assert_eq!(check(&format!("{}log(Wrap {{ v: 1 }}.get() + 1)\nlog(str.len(Wrap.new(\"s\").get()))\n", wrap)), vec![]);
+    assert_eq!(check(&format!("{}let n: int = Wrap.new(\"s\").get()\n", wrap)), vec![ErrorCode::TypeMismatch]);
+}
+
+#[test]
+fn unknown_type_in_a_global_annotation_is_reported_once() {
+    assert_eq!(check("let a: Foo = 1\nlog(a)\n"), vec![ErrorCode::UnknownType]);
+}
+
+#[test]
+fn let_polymorphic_function_is_used_at_two_types() {
+    assert_eq!(check("fn id(x) {\n    return x\n}\nlog(id(1) + 1)\nlog(str.len(id(\"s\")))\n"), vec![]);
+    assert_eq!(check("let id = fn(x) { return x }\nlet n: int = id(1)\nlet b: bool = id(true)\n"), vec![]);
+    assert_eq!(check("fn id(x) {\n    return x\n}\nlet n: int = id(\"s\")\n"), vec![ErrorCode::TypeMismatch]);
+}
+
+#[test]
+fn occurs_check_rejects_infinite_types() {
+    assert_eq!(check("fn f(x) {\n    return x(x)\n}\n"), vec![ErrorCode::InfiniteType]);
+    assert_eq!(check("let self_apply = fn(x) { return x(x) }\n"), vec![ErrorCode::InfiniteType]);
+}
+
+#[test]
+fn unknown_type_names_are_reported() {
+    assert_eq!(check("fn g(x: Bar) {\n    log(x)\n}\n"), vec![ErrorCode::UnknownType]);
+    assert_eq!(check("struct P { q: Qux }\nlet xs: [Baz] = []\n"), vec![ErrorCode::UnknownType, ErrorCode::UnknownType]);
+}